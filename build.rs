@@ -0,0 +1,5 @@
+fn main() {
+    // The gRPC service is generated from its definition, which takes protoc
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/cdu.proto").expect("Failed to compile proto/cdu.proto");
+}
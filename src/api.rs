@@ -0,0 +1,277 @@
+//! A small HTTP API to query the daemon and control it, for dashboards, scripts and health checks.
+//!
+//! These requests need the token in an `Authorization: Bearer <token>` header, and are only
+//! available if a token is set:
+//!
+//! - `GET /status` returns the state of the daemon.
+//! - `GET /last-change` returns when the A record was last changed, and to which IP.
+//! - `POST /force-update` makes the daemon check right away.
+//!
+//! These don't need a token, as they're meant for Docker, Kubernetes and reverse proxies:
+//!
+//! - `GET /healthz` succeeds as long as the daemon is running.
+//! - `GET /readyz` succeeds if the last successful check isn't too long ago.
+//!
+//! And `GET /metrics` returns the metrics for Prometheus, which needs the token if one is set.
+//!
+//! `GET /` serves a dashboard, which asks for the token and then shows the status, with a button
+//! to force an update. The page itself doesn't need the token, as it contains nothing but the code
+//! that talks to the requests above.
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error};
+
+use crate::daemon::Event;
+use crate::metrics;
+use crate::status::SharedStatus;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Where the API listens, and the token it's protected with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub listen: SocketAddr,
+    /// Without a token, only the health checks are available.
+    pub token: Option<String>,
+}
+
+struct ApiState {
+    token: Option<String>,
+    status: SharedStatus,
+    sender: UnboundedSender<Event>,
+}
+
+type SharedState = Arc<ApiState>;
+
+/// Starts serving the API, in the background.
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be listened on.
+pub async fn spawn(
+    options: &Options,
+    status: SharedStatus,
+    sender: UnboundedSender<Event>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(options.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", options.listen))?;
+    let app = router(ApiState {
+        token: options.token.clone(),
+        status,
+        sender,
+    });
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Stopped serving the API: {e}");
+        }
+    });
+
+    debug!("API listening on {}", options.listen);
+
+    Ok(())
+}
+
+fn router(state: ApiState) -> Router {
+    let state = Arc::new(state);
+
+    let protected = Router::new()
+        .route("/status", get(status))
+        .route("/last-change", get(last_change))
+        .route("/force-update", post(force_update))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token));
+
+    Router::new()
+        .route("/", get(|| async { Html(DASHBOARD) }))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .merge(protected)
+        .fallback(|| async { error(StatusCode::NOT_FOUND, "Not found") })
+        .layer(middleware::from_fn(log_request))
+        .with_state(state)
+}
+
+async fn log_request(request: Request, next: Next) -> Response {
+    debug!("API request: {} {}", request.method(), request.uri());
+
+    next.run(request).await
+}
+
+async fn require_token(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let Some(token) = &state.token else {
+        return error(StatusCode::FORBIDDEN, "Set a token to use the API");
+    };
+    if !is_authorized(request.headers(), token) {
+        return error(StatusCode::UNAUTHORIZED, "Missing or wrong token");
+    }
+
+    next.run(request).await
+}
+
+async fn healthz() -> Response {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+async fn readyz(State(state): State<SharedState>) -> Response {
+    let ready = state.status.lock().is_ready(Utc::now());
+
+    match ready {
+        Ok(()) => Json(json!({ "status": "ready" })).into_response(),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "not ready", "reason": reason })),
+        )
+            .into_response(),
+    }
+}
+
+async fn metrics(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    if let Some(token) = &state.token {
+        if !is_authorized(&headers, token) {
+            return error(StatusCode::UNAUTHORIZED, "Missing or wrong token");
+        }
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+        .into_response()
+}
+
+async fn status(State(state): State<SharedState>) -> Response {
+    let status = state.status.lock().clone();
+
+    Json(status).into_response()
+}
+
+async fn last_change(State(state): State<SharedState>) -> Response {
+    let last_change = state.status.lock().last_change;
+
+    match last_change {
+        Some(change) => Json(change).into_response(),
+        None => error(
+            StatusCode::NOT_FOUND,
+            "The A record hasn't been changed yet",
+        ),
+    }
+}
+
+async fn force_update(State(state): State<SharedState>) -> Response {
+    if state.sender.send(Event::ForceCheck).is_err() {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The daemon is shutting down",
+        );
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "message": "Checking right away" })),
+    )
+        .into_response()
+}
+
+/// Returns whether `headers` have `token` as a bearer token.
+pub fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get_all(AUTHORIZATION)
+        .iter()
+        .filter_map(|value| value.to_str().ok()?.strip_prefix("Bearer "))
+        .any(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compares without bailing out early, so the time it takes doesn't give away the token.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn error(code: StatusCode, message: &str) -> Response {
+    (code, Json(json!({ "error": message }))).into_response()
+}
+
+#[tokio::test]
+async fn test_api() {
+    use std::net::IpAddr;
+
+    use tokio::sync::mpsc;
+
+    use crate::config::Config;
+    use crate::updater::{Outcome, Updater};
+
+    let updater =
+        Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    let status = SharedStatus::new(&updater);
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    // Find a free port
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let options = Options {
+        listen,
+        token: Some(String::from("secret")),
+    };
+    spawn(&options, status.clone(), sender).await.unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |method: reqwest::Method, path: &str, token: &str| {
+        client
+            .request(method, format!("http://{listen}{path}"))
+            .bearer_auth(token)
+            .send()
+    };
+    let get = |path: &'static str, token: &'static str| request(reqwest::Method::GET, path, token);
+
+    assert_eq!(get("/", "").await.unwrap().status(), 200);
+    assert_eq!(get("/healthz", "").await.unwrap().status(), 200);
+    assert_eq!(get("/readyz", "").await.unwrap().status(), 503);
+    status
+        .lock()
+        .record(&Ok(Outcome::Unchanged(IpAddr::from([192, 0, 2, 1]))));
+    assert_eq!(get("/readyz", "").await.unwrap().status(), 200);
+
+    assert_eq!(get("/metrics", "").await.unwrap().status(), 401);
+    let response = get("/metrics", "secret").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("# TYPE cdu_checks_total counter"));
+
+    assert_eq!(get("/status", "wrong").await.unwrap().status(), 401);
+    let response = get("/status", "secret").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#""domains":["example.com"]"#));
+
+    assert_eq!(get("/last-change", "secret").await.unwrap().status(), 404);
+    assert_eq!(get("/force-update", "secret").await.unwrap().status(), 405);
+    assert_eq!(get("/nothing", "secret").await.unwrap().status(), 404);
+
+    let response = request(reqwest::Method::POST, "/force-update", "secret")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    assert_eq!(receiver.recv().await.unwrap(), Event::ForceCheck);
+}
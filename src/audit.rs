@@ -0,0 +1,214 @@
+//! Keeps an audit log of every change cdu makes at Cloudflare, apart from the usual logs, for those
+//! who have to be able to tell who changed a record of a business domain, when and to what.
+//!
+//! Every change, and every one that was tried and failed, is a line of JSON appended to
+//! `cdu-audit.jsonl` in the state directory, or the file in `--audit-log`. Nothing in it is ever
+//! changed or removed by cdu, and only its owner can read it.
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::cloudflare::ARecord;
+
+pub const AUDIT_FILE: &str = "cdu-audit.jsonl";
+
+/// An A record as it was before a change, or as it was changed to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Content {
+    pub ip: IpAddr,
+    pub proxied: Option<bool>,
+    pub ttl: Option<u32>,
+}
+
+impl From<&ARecord> for Content {
+    fn from(record: &ARecord) -> Self {
+        Self {
+            ip: record.ip,
+            proxied: record.proxied,
+            ttl: record.ttl,
+        }
+    }
+}
+
+/// Who made a change: this version of cdu, on this host, as this user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub program: String,
+    pub host: String,
+    pub user: Option<String>,
+    pub pid: u32,
+}
+
+impl Actor {
+    fn current() -> Self {
+        Self {
+            program: format!("cdu {}", clap::crate_version!()),
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            user: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok(),
+            pid: std::process::id(),
+        }
+    }
+}
+
+/// A change of a record, or an attempt at one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub at: DateTime<Utc>,
+    pub by: Actor,
+    /// What was done, like `update`.
+    pub action: String,
+    pub record_type: String,
+    pub domain: String,
+    pub zone_id: String,
+    pub record_id: String,
+    pub old: Option<Content>,
+    pub new: Option<Content>,
+    /// The Ray ID Cloudflare answered with, which its support can look the request up by.
+    pub ray_id: Option<String>,
+    /// Why the change failed, if it did.
+    pub error: Option<String>,
+}
+
+impl Entry {
+    /// Returns the entry for updating the A record of `domain` from `old` to `new`, which failed
+    /// with `error` if there is one.
+    #[must_use]
+    pub fn update(
+        domain: &str,
+        zone_id: &str,
+        old: &ARecord,
+        new: &ARecord,
+        ray_id: Option<String>,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            at: Utc::now(),
+            by: Actor::current(),
+            action: String::from("update"),
+            record_type: String::from("A"),
+            domain: domain.to_string(),
+            zone_id: zone_id.to_string(),
+            record_id: new.id.clone(),
+            old: Some(Content::from(old)),
+            new: Some(Content::from(new)),
+            ray_id,
+            error,
+        }
+    }
+}
+
+/// The file the changes are appended to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    #[must_use]
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Appends `entry`, as a line of its own, creating the file if it doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or written to.
+    pub fn record(&self, entry: &Entry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(entry).context("Failed to serialize audit entry")?;
+        line.push('\n');
+
+        let mut options = fs::OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&self.path)
+            .with_context(|| format!("Failed to open audit log: {}", self.path.display()))?;
+        // One write, so a line is never split by another instance appending at the same time
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+            .with_context(|| format!("Failed to write to audit log: {}", self.path.display()))?;
+
+        Ok(())
+    }
+
+    /// Reads every entry, in the order they were appended. There are none if the file doesn't
+    /// exist yet, and a line that isn't an entry, like one cut short, is skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn read(&self) -> anyhow::Result<Vec<Entry>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read audit log: {}", self.path.display()))
+            }
+        };
+
+        Ok(text
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    debug!("Skipping line {} of the audit log: {e}", index + 1);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn test_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let audit_log = AuditLog::new(&dir.path().join(AUDIT_FILE));
+    let old = ARecord {
+        id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
+        ip: IpAddr::from([192, 0, 2, 1]),
+        proxied: Some(false),
+        ttl: Some(300),
+    };
+    let new = ARecord {
+        ip: IpAddr::from([192, 0, 2, 2]),
+        ..old.clone()
+    };
+
+    let entry = Entry::update(
+        "example.com",
+        "023e105f4ecef8ad9ca31a8372d0c353",
+        &old,
+        &new,
+        Some(String::from("8a1f2b3c4d5e6f70-AMS")),
+        None,
+    );
+    audit_log.record(&entry).unwrap();
+    audit_log.record(&entry).unwrap();
+
+    let text = fs::read_to_string(dir.path().join(AUDIT_FILE)).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    let line = serde_json::from_str::<serde_json::Value>(lines[0]).unwrap();
+    assert_eq!(line["action"], "update");
+    assert_eq!(line["old"]["ip"], "192.0.2.1");
+    assert_eq!(line["new"]["ip"], "192.0.2.2");
+    assert_eq!(line["ray_id"], "8a1f2b3c4d5e6f70-AMS");
+
+    assert_eq!(audit_log.read().unwrap(), [entry.clone(), entry]);
+    let missing = AuditLog::new(&dir.path().join("missing.jsonl"));
+    assert!(missing.read().unwrap().is_empty());
+}
@@ -0,0 +1,222 @@
+//! Stops asking a server that keeps failing for a while, so one that's down doesn't add its timeout,
+//! and its retries, to every check of the daemon.
+//!
+//! After a number of failures in a row, the circuit of the server opens, and it's skipped until the
+//! cool-down is over. Then it's asked once more: if it answers, its circuit closes again, and if it
+//! doesn't, it's skipped for another cool-down.
+//!
+//! The circuits are kept in the state as [`Health`], so a server that keeps failing is still
+//! skipped after a restart.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// How many failures in a row open a circuit, unless told otherwise.
+pub const DEFAULT_FAILURES: u32 = 3;
+
+/// How long an open circuit stays open, unless told otherwise.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// How one server has been doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Circuit {
+    /// How many times in a row it failed.
+    failures: u32,
+    /// Until when it's skipped, if it failed too many times.
+    open_until: Option<Instant>,
+}
+
+/// How one server has been doing, as it's kept in the state.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub name: String,
+    /// How many times in a row it failed.
+    pub failures: u32,
+    /// Until when it's skipped, if it failed too many times.
+    pub open_until: Option<DateTime<Utc>>,
+}
+
+/// The circuits of the servers, by their names.
+#[derive(Debug, Clone)]
+pub struct Breaker {
+    /// How many failures in a row open a circuit, where 0 never opens one.
+    failures: u32,
+    cooldown: Duration,
+    circuits: HashMap<String, Circuit>,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURES, DEFAULT_COOLDOWN)
+    }
+}
+
+impl Breaker {
+    /// Returns a breaker that skips a server after `failures` in a row for `cooldown`. With zero
+    /// failures, no server is ever skipped.
+    #[must_use]
+    pub fn new(failures: u32, cooldown: Duration) -> Self {
+        Self {
+            failures,
+            cooldown,
+            circuits: HashMap::new(),
+        }
+    }
+
+    /// Returns whether `name` should be asked at `now`: its circuit is closed, or its cool-down is
+    /// over, to see whether it's back.
+    #[must_use]
+    pub fn allows(&self, name: &str, now: Instant) -> bool {
+        !matches!(
+            self.circuits.get(name),
+            Some(Circuit { open_until: Some(until), .. }) if *until > now
+        )
+    }
+
+    /// Closes the circuit of `name`, which answered.
+    pub fn succeeded(&mut self, name: &str) {
+        if let Some(circuit) = self.circuits.remove(name) {
+            if circuit.open_until.is_some() {
+                info!("{name} answered again, asking it again on every check");
+            }
+        }
+    }
+
+    /// Counts a failure of `name` at `now`, and opens its circuit if it failed too many times in a
+    /// row.
+    pub fn failed(&mut self, name: &str, now: Instant) {
+        if self.failures == 0 {
+            return;
+        }
+
+        let circuit = self.circuits.entry(name.to_string()).or_insert(Circuit {
+            failures: 0,
+            open_until: None,
+        });
+        circuit.failures += 1;
+        if circuit.failures >= self.failures {
+            if circuit.open_until.is_none() {
+                warn!(
+                    "{name} failed {} times in a row, skipping it for {}",
+                    circuit.failures,
+                    humantime::format_duration(self.cooldown)
+                );
+            }
+            circuit.open_until = Some(now + self.cooldown);
+        }
+    }
+
+    /// Takes the circuits of `other`, like those of the breaker before a reload, keeping its own
+    /// number of failures and cool-down.
+    pub fn adopt(&mut self, other: &Self) {
+        self.circuits.clone_from(&other.circuits);
+    }
+
+    /// Returns how every server that failed has been doing, to keep in the state, with `now` being
+    /// `at` on the clock.
+    #[must_use]
+    pub fn health(&self, now: Instant, at: DateTime<Utc>) -> Vec<Health> {
+        let mut health = self
+            .circuits
+            .iter()
+            .map(|(name, circuit)| Health {
+                name: name.clone(),
+                failures: circuit.failures,
+                open_until: circuit.open_until.map(|until| {
+                    at + chrono::Duration::from_std(until.saturating_duration_since(now))
+                        .unwrap_or_default()
+                }),
+            })
+            .collect::<Vec<_>>();
+        health.sort_by(|a, b| a.name.cmp(&b.name));
+
+        health
+    }
+
+    /// Takes the circuits of the servers from `health`, read from the state, with `now` being `at`
+    /// on the clock. A cool-down that's over leaves the server to be asked once more.
+    pub fn restore(&mut self, health: &[Health], now: Instant, at: DateTime<Utc>) {
+        for server in health {
+            let open_until = server
+                .open_until
+                .map(|until| now + (until - at).to_std().unwrap_or_default());
+            self.circuits.insert(
+                server.name.clone(),
+                Circuit {
+                    failures: server.failures,
+                    open_until,
+                },
+            );
+        }
+    }
+}
+
+#[test]
+fn test_breaker() {
+    let mut breaker = Breaker::new(2, Duration::from_secs(60));
+    let now = Instant::now();
+
+    breaker.failed("icanhazip.com", now);
+    assert!(breaker.allows("icanhazip.com", now));
+    breaker.failed("icanhazip.com", now);
+    assert!(!breaker.allows("icanhazip.com", now));
+    assert!(breaker.allows("seeip.org", now));
+
+    // Asked again after the cool-down, and skipped for another one if it fails again
+    let later = now + Duration::from_secs(60);
+    assert!(breaker.allows("icanhazip.com", later));
+    breaker.failed("icanhazip.com", later);
+    assert!(!breaker.allows("icanhazip.com", later + Duration::from_secs(30)));
+
+    breaker.succeeded("icanhazip.com");
+    assert!(breaker.allows("icanhazip.com", later));
+
+    let mut never = Breaker::new(0, Duration::from_secs(60));
+    for _ in 0..10 {
+        never.failed("icanhazip.com", now);
+    }
+    assert!(never.allows("icanhazip.com", now));
+}
+
+#[test]
+fn test_health() {
+    let mut breaker = Breaker::new(2, Duration::from_secs(60));
+    let now = Instant::now();
+    let at = Utc::now();
+    breaker.failed("seeip.org", now);
+    breaker.failed("icanhazip.com", now);
+    breaker.failed("icanhazip.com", now);
+
+    let health = breaker.health(now, at);
+    assert_eq!(
+        health,
+        [
+            Health {
+                name: String::from("icanhazip.com"),
+                failures: 2,
+                open_until: Some(at + chrono::Duration::seconds(60)),
+            },
+            Health {
+                name: String::from("seeip.org"),
+                failures: 1,
+                open_until: None,
+            },
+        ]
+    );
+
+    // Read back on a later run, the circuit is still open until the cool-down is over
+    let mut restored = Breaker::new(2, Duration::from_secs(60));
+    let later = now + Duration::from_secs(10);
+    restored.restore(&health, later, at + chrono::Duration::seconds(10));
+    assert!(!restored.allows("icanhazip.com", later));
+    assert!(restored.allows("icanhazip.com", later + Duration::from_secs(50)));
+    restored.failed("seeip.org", later);
+    assert!(!restored.allows("seeip.org", later));
+
+    let mut adopted = Breaker::new(5, Duration::from_secs(60));
+    adopted.adopt(&breaker);
+    assert!(!adopted.allows("icanhazip.com", now));
+}
@@ -0,0 +1,56 @@
+//! Compares the outside IP with the A record of every domain at Cloudflare, without updating
+//! anything or touching the state, so monitoring can run it with a token that may only read them.
+use crate::cloudflare::Handler;
+use crate::exit;
+use crate::network::{detect_outside_ip, Timeouts, Transport};
+use crate::updater::DomainSettings;
+
+/// Prints whether the A record of every domain points at the outside IP, and returns the ones that
+/// don't. A domain with a zone of its own is looked up there, instead of in `zone_id`. The requests
+/// take no longer than `timeouts`, reach the servers as `transport` says, and go to the Cloudflare
+/// API at `api_url`.
+///
+/// # Errors
+///
+/// Returns an error if the outside IP cannot be detected, or an A record cannot be looked up.
+pub async fn run(
+    api_key: &str,
+    zone_id: &str,
+    domains: &[String],
+    per_domain: &[DomainSettings],
+    timeouts: Timeouts,
+    transport: &Transport,
+    api_url: &str,
+) -> anyhow::Result<Vec<String>> {
+    let detection_client = transport.detection_client(timeouts.detection);
+    let (outside_ip, source) = detect_outside_ip(&detection_client, None, timeouts.detection)
+        .await
+        .map_err(|e| exit::wrap(exit::DETECTION_FAILED, e))?;
+    println!("Outside IP is {outside_ip}, from {source}");
+
+    let cloudflare = Handler::try_new(api_key)?
+        .with_api_url(api_url)
+        .with_client(transport.client(timeouts.api))
+        .with_timeout(timeouts.api);
+    let mut drifts = Vec::new();
+    for domain in domains {
+        let zone_id = per_domain
+            .iter()
+            .find(|settings| &settings.name == domain)
+            .and_then(|settings| settings.zone_id.as_deref())
+            .unwrap_or(zone_id);
+        let record = cloudflare
+            .get_a_record(zone_id, domain)
+            .await
+            .map_err(|e| exit::wrap(exit::CLOUDFLARE_FAILED, e))?;
+
+        if record.ip == outside_ip {
+            println!("in sync  {domain}");
+        } else {
+            println!("drift    {domain} points at {}", record.ip);
+            drifts.push(domain.clone());
+        }
+    }
+
+    Ok(drifts)
+}
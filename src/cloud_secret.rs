@@ -0,0 +1,429 @@
+//! Reads a secret, like the API token, from the secret manager of the cloud cdu runs in, with the
+//! credentials the cloud gives the machine, so no copy of the token has to be kept in the settings:
+//!
+//! - `aws:<name or ARN>` from AWS Secrets Manager, with the credentials in the `AWS_` variables, or
+//!   else of the ECS task or the EC2 instance, in the region of `AWS_REGION` or the ARN.
+//! - `gcp:projects/<project>/secrets/<name>` from Google Cloud Secret Manager, with the service
+//!   account of the instance, in its latest version unless one is given with `/versions/<version>`.
+//! - `azure:<vault>/<name>` from Azure Key Vault, with the managed identity of the VM or the App
+//!   Service.
+//!
+//! A secret that's JSON, like the key/value pairs AWS keeps, can be followed by `#<key>` for the
+//! value of one of its keys.
+use std::env;
+use std::fmt;
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::Utc;
+use reqwest::{Client as RqClient, RequestBuilder};
+use serde_json::{json, Value};
+
+use crate::crypto;
+
+/// Where the EC2 instance and the VM in Azure are told about themselves.
+const INSTANCE_METADATA: &str = "http://169.254.169.254";
+/// Where an ECS task is given its credentials.
+const ECS_METADATA: &str = "http://169.254.170.2";
+/// Where the instance in Google Cloud is told about itself.
+const GCP_METADATA: &str = "http://metadata.google.internal";
+/// What the token of a managed identity in Azure is for.
+const AZURE_RESOURCE: &str = "https://vault.azure.net";
+
+/// How long the metadata of the machine may take, which is there right away on the cloud, and not
+/// at all off it.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The secret manager a secret is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Aws,
+    Gcp,
+    Azure,
+}
+
+/// Where a secret is, as `<provider>:<name>[#<key>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub provider: Provider,
+    pub name: String,
+    /// The key of the value in a secret that's JSON.
+    pub key: Option<String>,
+}
+
+impl FromStr for Reference {
+    type Err = String;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("Invalid secret {reference}, expected aws:<name>, gcp:projects/<project>/secrets/<name> or azure:<vault>/<name>")
+        };
+        let (provider, name) = reference.split_once(':').ok_or_else(invalid)?;
+        let (name, key) = match name.split_once('#') {
+            Some((name, key)) => (name, Some(key.to_string())),
+            None => (name, None),
+        };
+        let provider = match provider {
+            "aws" => Provider::Aws,
+            "gcp" => Provider::Gcp,
+            "azure" => Provider::Azure,
+            _ => {
+                return Err(format!(
+                    "Unknown secret manager {provider}, use aws, gcp or azure"
+                ))
+            }
+        };
+        let valid = match provider {
+            Provider::Aws => !name.is_empty(),
+            Provider::Gcp => name.starts_with("projects/") && name.contains("/secrets/"),
+            Provider::Azure => name.split('/').filter(|part| !part.is_empty()).count() == 2,
+        };
+        if !valid || key.as_deref() == Some("") {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            provider,
+            name: name.to_string(),
+            key,
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let provider = match self.provider {
+            Provider::Aws => "aws",
+            Provider::Gcp => "gcp",
+            Provider::Azure => "azure",
+        };
+        write!(f, "{provider}:{}", self.name)?;
+        if let Some(key) = &self.key {
+            write!(f, "#{key}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the secret at `reference`.
+///
+/// # Errors
+///
+/// Returns an error if there are no credentials of the cloud, the secret manager refuses to read
+/// the secret, or it doesn't have the key.
+pub async fn read(client: &RqClient, reference: &Reference) -> anyhow::Result<String> {
+    let secret = match reference.provider {
+        Provider::Aws => aws(client, &reference.name).await,
+        Provider::Gcp => gcp(client, &reference.name).await,
+        Provider::Azure => azure(client, &reference.name).await,
+    }
+    .with_context(|| format!("Failed to read {reference}"))?;
+    let Some(key) = &reference.key else {
+        return Ok(secret);
+    };
+
+    let secret = serde_json::from_str::<Value>(&secret)
+        .with_context(|| format!("{reference} isn't JSON, so it has no keys"))?;
+    secret[key]
+        .as_str()
+        .map(ToString::to_string)
+        .with_context(|| format!("{reference} has no {key} that's a string"))
+}
+
+/// The credentials of AWS, which can be temporary ones with a session token.
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+async fn aws(client: &RqClient, secret_id: &str) -> anyhow::Result<String> {
+    let region = env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .ok()
+        .or_else(|| {
+            let region = secret_id.strip_prefix("arn:")?.split(':').nth(2)?;
+            Some(region.to_string())
+        })
+        .context("Missing the region, set AWS_REGION or give the ARN of the secret")?;
+    let credentials = aws_credentials(client).await?;
+
+    let host = format!("secretsmanager.{region}.amazonaws.com");
+    let body = json!({ "SecretId": secret_id }).to_string();
+    let date_time = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    // In the order they're signed in
+    let mut headers = vec![
+        ("content-type", String::from("application/x-amz-json-1.1")),
+        ("host", host.clone()),
+        ("x-amz-date", date_time.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    headers.push((
+        "x-amz-target",
+        String::from("secretsmanager.GetSecretValue"),
+    ));
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{}\n{signed_headers}\n{}",
+        headers
+            .iter()
+            .fold(String::new(), |mut lines, (name, value)| {
+                let _ = writeln!(lines, "{name}:{value}");
+                lines
+            }),
+        crypto::sha256_hex(body.as_bytes())
+    );
+    let signature = aws_signature(
+        &credentials.secret_access_key,
+        &date_time,
+        &region,
+        "secretsmanager",
+        &canonical_request,
+    );
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}/{region}/secretsmanager/aws4_request, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id,
+        &date_time[..8]
+    );
+
+    let mut request = client
+        .post(format!("https://{host}/"))
+        .header("Authorization", authorization)
+        .body(body);
+    for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+        request = request.header(name, value);
+    }
+    let secret = send(request).await?;
+
+    secret["SecretString"]
+        .as_str()
+        .map(ToString::to_string)
+        .context("The secret is binary, not a string")
+}
+
+/// Returns the credentials in the `AWS_` variables, or else of the ECS task, or else of the role
+/// of the EC2 instance.
+async fn aws_credentials(client: &RqClient) -> anyhow::Result<AwsCredentials> {
+    if let (Ok(access_key_id), Ok(secret_access_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        return Ok(AwsCredentials {
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+
+    let credentials = if let Ok(uri) = env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+        send(
+            client
+                .get(format!("{ECS_METADATA}{uri}"))
+                .timeout(METADATA_TIMEOUT),
+        )
+        .await
+    } else {
+        // IMDSv2, which needs a token for the metadata first
+        let token = client
+            .put(format!("{INSTANCE_METADATA}/latest/api/token"))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+            .timeout(METADATA_TIMEOUT)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("No AWS credentials, set AWS_ACCESS_KEY_ID or run on EC2 or ECS")?
+            .text()
+            .await?;
+        let url = format!("{INSTANCE_METADATA}/latest/meta-data/iam/security-credentials/");
+        let roles = client
+            .get(&url)
+            .header("X-aws-ec2-metadata-token", &token)
+            .timeout(METADATA_TIMEOUT)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)?
+            .text()
+            .await?;
+        let role = roles
+            .lines()
+            .next()
+            .context("The EC2 instance has no role")?;
+        send(
+            client
+                .get(format!("{url}{role}"))
+                .header("X-aws-ec2-metadata-token", &token)
+                .timeout(METADATA_TIMEOUT),
+        )
+        .await
+    }
+    .context("Failed to get the AWS credentials of the machine")?;
+
+    let field = |name| {
+        credentials[name]
+            .as_str()
+            .map(ToString::to_string)
+            .with_context(|| format!("The AWS credentials have no {name}"))
+    };
+    Ok(AwsCredentials {
+        access_key_id: field("AccessKeyId")?,
+        secret_access_key: field("SecretAccessKey")?,
+        session_token: field("Token").ok(),
+    })
+}
+
+/// Returns the signature of `canonical_request` with Signature Version 4 at `date_time`, written
+/// like `20150830T123600Z`.
+fn aws_signature(
+    secret_access_key: &str,
+    date_time: &str,
+    region: &str,
+    service: &str,
+    canonical_request: &str,
+) -> String {
+    let date = &date_time[..8];
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{date_time}\n{date}/{region}/{service}/aws4_request\n{}",
+        crypto::sha256_hex(canonical_request.as_bytes())
+    );
+    let key = [date, region, service, "aws4_request"].into_iter().fold(
+        format!("AWS4{secret_access_key}").into_bytes(),
+        |key, part| crypto::hmac_sha256(&key, &[part.as_bytes()]),
+    );
+
+    crypto::hmac_sha256_hex(&key, string_to_sign.as_bytes())
+}
+
+async fn gcp(client: &RqClient, name: &str) -> anyhow::Result<String> {
+    let token = send(
+        client
+            .get(format!(
+                "{GCP_METADATA}/computeMetadata/v1/instance/service-accounts/default/token"
+            ))
+            .header("Metadata-Flavor", "Google")
+            .timeout(METADATA_TIMEOUT),
+    )
+    .await
+    .context("Failed to get a token for the service account of the instance")?;
+    let token = token["access_token"]
+        .as_str()
+        .context("The metadata server didn't answer with a token")?;
+
+    let name = if name.contains("/versions/") {
+        name.to_string()
+    } else {
+        format!("{name}/versions/latest")
+    };
+    let secret = send(
+        client
+            .get(format!(
+                "https://secretmanager.googleapis.com/v1/{name}:access"
+            ))
+            .bearer_auth(token),
+    )
+    .await?;
+    let data = secret["payload"]["data"]
+        .as_str()
+        .context("The secret has no data")?;
+    let data = BASE64_STANDARD
+        .decode(data)
+        .context("The data of the secret isn't base64")?;
+
+    String::from_utf8(data).context("The secret isn't text")
+}
+
+async fn azure(client: &RqClient, name: &str) -> anyhow::Result<String> {
+    // App Service has an endpoint of its own, and VMs have the metadata service
+    let request = match (env::var("IDENTITY_ENDPOINT"), env::var("IDENTITY_HEADER")) {
+        (Ok(endpoint), Ok(header)) => client
+            .get(format!(
+                "{endpoint}?api-version=2019-08-01&resource={AZURE_RESOURCE}"
+            ))
+            .header("X-IDENTITY-HEADER", header),
+        _ => client
+            .get(format!(
+                "{INSTANCE_METADATA}/metadata/identity/oauth2/token?api-version=2018-02-01&resource={AZURE_RESOURCE}"
+            ))
+            .header("Metadata", "true"),
+    };
+    let token = send(request.timeout(METADATA_TIMEOUT))
+        .await
+        .context("Failed to get a token for the managed identity")?;
+    let token = token["access_token"]
+        .as_str()
+        .context("Azure didn't answer with a token")?;
+
+    let (vault, name) = name
+        .trim_matches('/')
+        .split_once('/')
+        .context("Expected <vault>/<name>")?;
+    let secret = send(
+        client
+            .get(format!(
+                "https://{vault}.vault.azure.net/secrets/{name}?api-version=7.4"
+            ))
+            .bearer_auth(token),
+    )
+    .await?;
+
+    secret["value"]
+        .as_str()
+        .map(ToString::to_string)
+        .context("The secret has no value")
+}
+
+async fn send(request: RequestBuilder) -> anyhow::Result<Value> {
+    let response = request.send().await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("Received response status {status}: {body}");
+    }
+
+    Ok(response.json().await?)
+}
+
+#[test]
+fn test_reference() {
+    let reference = "aws:cdu/cloudflare#api_key".parse::<Reference>().unwrap();
+    assert_eq!(
+        reference,
+        Reference {
+            provider: Provider::Aws,
+            name: String::from("cdu/cloudflare"),
+            key: Some(String::from("api_key")),
+        }
+    );
+    assert_eq!(reference.to_string(), "aws:cdu/cloudflare#api_key");
+    assert!("gcp:projects/home/secrets/cloudflare"
+        .parse::<Reference>()
+        .is_ok());
+    assert!("gcp:cloudflare".parse::<Reference>().is_err());
+    assert!("azure:home-vault/cloudflare".parse::<Reference>().is_ok());
+    assert!("azure:cloudflare".parse::<Reference>().is_err());
+    assert!("vault:secret/cdu".parse::<Reference>().is_err());
+    assert!("aws:cdu#".parse::<Reference>().is_err());
+
+    // The example of the documentation of AWS
+    let canonical_request = "GET\n/\nAction=ListUsers&Version=2010-05-08\ncontent-type:application/x-www-form-urlencoded; charset=utf-8\nhost:iam.amazonaws.com\nx-amz-date:20150830T123600Z\n\ncontent-type;host;x-amz-date\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    assert_eq!(
+        aws_signature(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830T123600Z",
+            "us-east-1",
+            "iam",
+            canonical_request
+        ),
+        "5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+    );
+}
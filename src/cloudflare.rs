@@ -1,121 +1,655 @@
-use std::net::Ipv4Addr;
+use std::net::{AddrParseError, IpAddr};
+use std::time::Duration;
 
-use anyhow::anyhow;
-use anyhow::Context;
-use reqwest::blocking::Client as RqClient;
 use reqwest::header::HeaderMap;
 use reqwest::header::HeaderValue;
+use reqwest::header::InvalidHeaderValue;
 use reqwest::header::AUTHORIZATION;
+use reqwest::Client as RqClient;
+use reqwest::Method;
+use reqwest::StatusCode;
 use serde_json::json;
 use serde_json::Value;
 use tracing::trace;
 
-const BASE_URL: &str = "https://api.cloudflare.com/client/v4/zones";
+use crate::hint::Category;
+use crate::network::{self, Family, DEFAULT_TIMEOUT};
+use crate::rate_limit::RateLimit;
+use crate::redact;
+use crate::retry;
+
+/// Where the Cloudflare API is, unless told otherwise.
+pub const API_URL: &str = "https://api.cloudflare.com/client/v4";
+
+/// The code of the error of the API for a record ID that isn't in the zone.
+const RECORD_NOT_FOUND: u64 = 81044;
+
+/// What can go wrong talking to the Cloudflare API.
+#[derive(Debug, thiserror::Error)]
+pub enum CloudflareError {
+    /// The API token cannot be sent in a header, as it has characters a header can't have.
+    #[error("The API token isn't valid")]
+    InvalidToken(#[from] InvalidHeaderValue),
+    /// The API couldn't be reached, or didn't answer in time.
+    #[error("Failed to send request to Cloudflare API")]
+    Request(#[source] reqwest::Error),
+    /// The API stopped answering halfway through.
+    #[error("Failed to read response text from Cloudflare API")]
+    Response(#[source] reqwest::Error),
+    /// The API answered with something that isn't JSON.
+    #[error("Failed to parse JSON response from Cloudflare API")]
+    Json {
+        status: StatusCode,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The API answered with an error.
+    #[error("Cloudflare API error: {message}")]
+    Api {
+        status: StatusCode,
+        /// The code of the first error, like 9109 for a token that isn't valid.
+        code: Option<u64>,
+        message: String,
+    },
+    /// The API didn't take the change of an A record.
+    #[error("Failed to update A record: {body}")]
+    Update {
+        status: StatusCode,
+        code: Option<u64>,
+        body: String,
+    },
+    /// The zone has no A record for the domain, or no AAAA record for one that wants it.
+    #[error("A record not found for domain: {0}")]
+    RecordNotFound(String),
+    /// The API answered without a field it always has.
+    #[error("No '{0}' field found in JSON response")]
+    MissingField(&'static str),
+    /// An A record points at something that isn't an IPv4 address, or an AAAA record at something
+    /// that isn't an IPv6 address.
+    #[error("Invalid IP address: {0}")]
+    InvalidIp(#[from] AddrParseError),
+    /// The API token has expired, or was turned off.
+    #[error("The API token is {0}")]
+    InactiveToken(String),
+}
+
+impl CloudflareError {
+    /// Returns what kind of problem it is, for the hint after it, if it's a known kind.
+    #[must_use]
+    pub fn category(&self) -> Option<Category> {
+        match self {
+            Self::Request(_) | Self::Response(_) => Some(Category::Network),
+            Self::Json { status, .. } => Category::of_response(*status, None),
+            Self::Api { status, code, .. } | Self::Update { status, code, .. } => {
+                Category::of_response(*status, *code)
+            }
+            Self::RecordNotFound(_) => Some(Category::NotFound),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the request may succeed if it's sent again, see [`retry::is_transient`].
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(e) | Self::Response(e) => retry::is_transient(e),
+            Self::Json { status, .. } | Self::Api { status, .. } | Self::Update { status, .. } => {
+                retry::is_transient_status(*status)
+            }
+            _ => false,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Handler {
     client: RqClient,
     headers: HeaderMap,
-    zone_id: String,
-    record_id: Option<String>,
+    /// How long a request to the API may take.
+    timeout: Duration,
+    /// How often a request that failed is sent again.
+    retry: retry::Policy,
+    /// Where the API is, without a `/` at the end.
+    api_url: String,
+    /// How many requests a minute may be sent, if there's a limit.
+    rate_limit: Option<RateLimit>,
+}
+
+/// A zone the API token has access to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    pub id: String,
+    pub name: String,
+    /// Whether it's `active`, or still `pending` until the nameservers point at Cloudflare.
+    pub status: String,
+}
+
+impl Zone {
+    /// Returns the zone in a response of the API, if it has an ID and a name.
+    fn from_json(zone: &Value) -> Option<Self> {
+        Some(Self {
+            id: zone["id"].as_str()?.to_string(),
+            name: zone["name"].as_str()?.to_string(),
+            status: zone["status"].as_str().unwrap_or("unknown").to_string(),
+        })
+    }
+}
+
+/// An answer of the API to a change that it took.
+struct Written {
+    status: StatusCode,
+    /// The Ray ID of the response, if there's one.
+    ray_id: Option<String>,
+    body: String,
+}
+
+/// An A record, as found at Cloudflare, or an AAAA record when it points at an IPv6 address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ARecord {
+    pub id: String,
+    pub ip: IpAddr,
+    /// Whether the traffic goes through Cloudflare.
+    pub proxied: Option<bool>,
+    /// The TTL in seconds, where 1 is automatic.
+    pub ttl: Option<u32>,
 }
 
 impl Handler {
-    pub fn try_new(api_key: &str, zone_id: &str) -> anyhow::Result<Self> {
-        let mut headers = HeaderMap::new();
-        headers.insert(
+    /// Returns the client of the API, which sends `api_key` with every request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API token has characters a header can't have.
+    pub fn try_new(api_key: &str) -> Result<Self, CloudflareError> {
+        let mut handler = Self {
+            client: network::client(DEFAULT_TIMEOUT),
+            headers: HeaderMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+            retry: retry::Policy::default(),
+            api_url: API_URL.to_string(),
+            rate_limit: None,
+        };
+        handler.set_api_key(api_key)?;
+
+        Ok(handler)
+    }
+
+    /// Sends `api_key` with the requests from now on, instead of the one it had.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API token has characters a header can't have.
+    pub fn set_api_key(&mut self, api_key: &str) -> Result<(), CloudflareError> {
+        self.headers.insert(
             AUTHORIZATION,
             HeaderValue::from_str(&format!("Bearer {api_key}"))?,
         );
 
-        Ok(Self {
-            client: RqClient::new(),
-            headers,
-            zone_id: zone_id.to_string(),
-            record_id: None,
-        })
+        Ok(())
+    }
+
+    /// Sends the requests to the API at `api_url` instead of Cloudflare's, like a server that
+    /// answers as Cloudflare would in the tests.
+    #[must_use]
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sends the requests with `client`, sharing its connections with everything else that uses
+    /// it, instead of a client of its own.
+    #[must_use]
+    pub fn with_client(mut self, client: RqClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Gives up on a request to the API that isn't answered within `timeout`.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends no more than `per_minute` requests a minute, counting every try, waiting for the next
+    /// one that may be sent otherwise.
+    #[must_use]
+    pub fn with_rate_limit(mut self, per_minute: Option<u32>) -> Self {
+        self.rate_limit = per_minute.map(RateLimit::new);
+        self
+    }
+
+    /// Sends a request that failed in a way the next try may not again, as `retry` says.
+    #[must_use]
+    pub fn with_retry(mut self, retry: retry::Policy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn get_a_record(
+        &self,
+        zone_id: &str,
+        domain: &str,
+    ) -> Result<ARecord, CloudflareError> {
+        self.get_record(zone_id, domain, Family::V4).await
     }
 
+    /// Returns the record of `domain` that points at an IP of `family`, its A or its AAAA record.
     #[tracing::instrument(skip_all)]
-    pub fn get_a_record(&mut self, domain: &str) -> anyhow::Result<Ipv4Addr> {
+    pub async fn get_record(
+        &self,
+        zone_id: &str,
+        domain: &str,
+        family: Family,
+    ) -> Result<ARecord, CloudflareError> {
         let url = format!(
-            "{BASE_URL}/{}/dns_records?type=A&name={domain}",
-            self.zone_id
+            "{}/zones/{zone_id}/dns_records?type={}&name={domain}",
+            self.api_url,
+            family.record_type()
         );
 
+        self.a_records(&url)
+            .await?
+            .into_iter()
+            .find_map(|(name, record)| {
+                (name == domain && Family::of(record.ip) == family).then_some(record)
+            })
+            .ok_or_else(|| CloudflareError::RecordNotFound(domain.to_string()))
+    }
+
+    /// Returns the record of `domain` of `family` with the ID `id`, which is known from before,
+    /// without looking it up by its name.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CloudflareError::RecordNotFound`] if the zone has no such record with the ID
+    /// anymore, so it can be looked up again.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_record_by_id(
+        &self,
+        zone_id: &str,
+        id: &str,
+        domain: &str,
+        family: Family,
+    ) -> Result<ARecord, CloudflareError> {
+        let url = format!("{}/zones/{zone_id}/dns_records/{id}", self.api_url);
+        let v = self.get(&url).await.map_err(|e| match e {
+            CloudflareError::Api { status, code, .. }
+                if status == StatusCode::NOT_FOUND || code == Some(RECORD_NOT_FOUND) =>
+            {
+                CloudflareError::RecordNotFound(domain.to_string())
+            }
+            e => e,
+        })?;
+
+        match a_record(&v["result"])? {
+            Some((name, record)) if name == domain && Family::of(record.ip) == family => Ok(record),
+            _ => Err(CloudflareError::RecordNotFound(domain.to_string())),
+        }
+    }
+
+    /// Returns the A records in the zone, with their names.
+    #[tracing::instrument(skip_all)]
+    pub async fn list_a_records(
+        &self,
+        zone_id: &str,
+    ) -> Result<Vec<(String, ARecord)>, CloudflareError> {
+        self.a_records(&format!(
+            "{}/zones/{zone_id}/dns_records?type=A&per_page=1000",
+            self.api_url
+        ))
+        .await
+    }
+
+    /// Checks that the API token is valid, and hasn't expired or been turned off.
+    #[tracing::instrument(skip_all)]
+    pub async fn verify_token(&self) -> Result<(), CloudflareError> {
+        let v = self
+            .get(&format!("{}/user/tokens/verify", self.api_url))
+            .await?;
+
+        match v["result"]["status"].as_str() {
+            Some("active") => Ok(()),
+            Some(status) => Err(CloudflareError::InactiveToken(status.to_string())),
+            None => Err(CloudflareError::MissingField("status")),
+        }
+    }
+
+    /// Returns the zone with the ID, if the API token has access to it.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_zone(&self, zone_id: &str) -> Result<Zone, CloudflareError> {
+        let v = self
+            .get(&format!("{}/zones/{zone_id}", self.api_url))
+            .await?;
+
+        Zone::from_json(&v["result"]).ok_or(CloudflareError::MissingField("result"))
+    }
+
+    /// Returns the zones the API token has access to, going through every page of them.
+    #[tracing::instrument(skip_all)]
+    pub async fn list_zones(&self) -> Result<Vec<Zone>, CloudflareError> {
+        let mut found = Vec::new();
+        let mut page = 1;
+        loop {
+            let v = self
+                .get(&format!("{}/zones?per_page=50&page={page}", self.api_url))
+                .await?;
+            let zones = v["result"]
+                .as_array()
+                .ok_or(CloudflareError::MissingField("result"))?;
+            found.extend(zones.iter().filter_map(Zone::from_json));
+
+            let total_pages = v["result_info"]["total_pages"].as_u64().unwrap_or(1);
+            if zones.is_empty() || page >= total_pages {
+                return Ok(found);
+            }
+            page += 1;
+        }
+    }
+
+    /// Sets the TXT record `name` to `content`, creating it if the zone doesn't have it yet. The
+    /// content is quoted, as Cloudflare asks of TXT records.
+    #[tracing::instrument(skip_all)]
+    pub async fn set_txt_record(
+        &self,
+        zone_id: &str,
+        name: &str,
+        content: &str,
+    ) -> Result<(), CloudflareError> {
+        let records_url = format!("{}/zones/{zone_id}/dns_records", self.api_url);
+        let v = self
+            .get(&format!("{records_url}?type=TXT&name={name}"))
+            .await?;
+        let id = v["result"]
+            .as_array()
+            .ok_or(CloudflareError::MissingField("result"))?
+            .iter()
+            .find(|record| record["name"] == name)
+            .and_then(|record| record["id"].as_str());
+
+        let (method, url) = match id {
+            Some(id) => (Method::PUT, format!("{records_url}/{id}")),
+            None => (Method::POST, records_url),
+        };
+        let body = json!({
+            "type": "TXT",
+            "name": name,
+            "content": format!("\"{content}\""),
+            "ttl": 1,
+        });
+        self.retry
+            .run(
+                "Update of the TXT record",
+                || self.write_once(method.clone(), &url, &body),
+                CloudflareError::is_transient,
+            )
+            .await
+            .map(|_| ())
+    }
+
+    async fn a_records(&self, url: &str) -> Result<Vec<(String, ARecord)>, CloudflareError> {
+        let v = self.get(url).await?;
+        let records = v["result"]
+            .as_array()
+            .ok_or(CloudflareError::MissingField("result"))?;
+
+        let mut found = Vec::new();
+        for record in records {
+            if let Some(named) = a_record(record)? {
+                found.push(named);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Sends a GET request to the API, returning the response if it has no errors, and sends it
+    /// again if it failed in a way the next try may not.
+    /// Waits until the rate limit allows another request, if there's one.
+    async fn wait_for_turn(&self) {
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.acquire().await;
+        }
+    }
+
+    async fn get(&self, url: &str) -> Result<Value, CloudflareError> {
+        self.retry
+            .run(
+                "Request to the Cloudflare API",
+                || self.get_once(url),
+                CloudflareError::is_transient,
+            )
+            .await
+    }
+
+    async fn get_once(&self, url: &str) -> Result<Value, CloudflareError> {
+        self.wait_for_turn().await;
         let response = self
             .client
             .get(url)
             .headers(self.headers.clone())
+            .timeout(self.timeout)
             .send()
-            .context("Failed to send request to Cloudflare API")?
-            .text()
-            .context("Failed to read response text from Cloudflare API")?;
-        trace!("Response: {response}");
+            .await
+            .map_err(CloudflareError::Request)?;
+        let status = response.status();
+        let response = response.text().await.map_err(CloudflareError::Response)?;
+        trace!("Response: {}", redact::text(&response));
 
         let v: Value = serde_json::from_str(&response)
-            .context("Failed to parse JSON response from Cloudflare API")?;
+            .map_err(|source| CloudflareError::Json { status, source })?;
 
         if let Some(errors) = v["errors"].as_array() {
-            if !errors.is_empty() {
-                let message = errors
-                    .first()
-                    .map(|e| e["message"].as_str().unwrap_or_default())
-                    .unwrap_or_default()
-                    .to_string();
-
-                anyhow::bail!("Cloudflare API error: {message}");
+            if let Some(error) = errors.first() {
+                return Err(CloudflareError::Api {
+                    status,
+                    code: error["code"].as_u64(),
+                    message: error["message"].as_str().unwrap_or_default().to_string(),
+                });
             }
         }
 
-        let records = v["result"]
-            .as_array()
-            .ok_or_else(|| anyhow!("No 'result' field found in JSON response"))?;
+        Ok(v)
+    }
 
-        for record in records {
-            if let (Some(record_type), Some(record_name), Some(record_id), Some(content)) = (
-                record["type"].as_str(),
-                record["name"].as_str(),
-                record["id"].as_str(),
-                record["content"].as_str(),
-            ) {
-                if record_type == "A" && record_name == domain {
-                    self.record_id = Some(record_id.into());
-                    return content
-                        .parse::<Ipv4Addr>()
-                        .map_err(|e| anyhow!("Invalid IP address: {}", e));
-                }
-            }
+    /// Replaces the A or AAAA record with the ID of `record` with `record`. The proxied flag and the TTL
+    /// are left to Cloudflare if they aren't known. Returns the Ray ID of the response, if there's
+    /// one.
+    #[tracing::instrument(skip_all)]
+    pub async fn set_a_record(
+        &self,
+        zone_id: &str,
+        record: &ARecord,
+        domain: &str,
+    ) -> Result<Option<String>, CloudflareError> {
+        let url = format!(
+            "{}/zones/{}/dns_records/{}",
+            self.api_url, zone_id, record.id
+        );
+
+        let mut body = json!({
+            "type": Family::of(record.ip).record_type(),
+            "name": domain,
+            "content": record.ip.to_string(),
+        });
+        if let Some(proxied) = record.proxied {
+            body["proxied"] = json!(proxied);
+        }
+        if let Some(ttl) = record.ttl {
+            body["ttl"] = json!(ttl);
         }
 
-        Err(anyhow!("A record not found for domain: {}", domain))
+        self.retry
+            .run(
+                "Update of the A record",
+                || self.write_once(Method::PUT, &url, &body),
+                CloudflareError::is_transient,
+            )
+            .await
+            .map(|written| written.ray_id)
     }
 
+    /// Points the A record with the ID of `record` at the IP of `record`, in one request, for an ID
+    /// that's known from before instead of looked up first. Unlike [`Handler::set_a_record`], the
+    /// rest of the record is left as it is. The proxied flag and the TTL are only changed if
+    /// they're known. Returns the A record as it is after the change, and the Ray ID of the
+    /// response, if there's one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CloudflareError::RecordNotFound`] if the zone has no record with the ID anymore,
+    /// so it can be looked up again.
     #[tracing::instrument(skip_all)]
-    pub fn set_a_record(&self, domain: &str, new_ip_v4_addr: Ipv4Addr) -> anyhow::Result<()> {
-        let Some(ref record_id) = self.record_id else {
-            anyhow::bail!("Missing record_id")
-        };
-        let url = format!("{}/{}/dns_records/{}", BASE_URL, self.zone_id, record_id);
+    pub async fn patch_a_record(
+        &self,
+        zone_id: &str,
+        record: &ARecord,
+        domain: &str,
+    ) -> Result<(ARecord, Option<String>), CloudflareError> {
+        let url = format!(
+            "{}/zones/{}/dns_records/{}",
+            self.api_url, zone_id, record.id
+        );
 
-        let body = json!({
-            "type": "A",
-            "name": domain,
-            "content": new_ip_v4_addr.to_string(),
-        });
+        let mut body = json!({ "content": record.ip.to_string() });
+        if let Some(proxied) = record.proxied {
+            body["proxied"] = json!(proxied);
+        }
+        if let Some(ttl) = record.ttl {
+            body["ttl"] = json!(ttl);
+        }
+
+        let written = self
+            .retry
+            .run(
+                "Update of the A record",
+                || self.write_once(Method::PATCH, &url, &body),
+                CloudflareError::is_transient,
+            )
+            .await
+            .map_err(|e| match e {
+                CloudflareError::Update { status, code, .. }
+                    if status == StatusCode::NOT_FOUND || code == Some(RECORD_NOT_FOUND) =>
+                {
+                    CloudflareError::RecordNotFound(domain.to_string())
+                }
+                e => e,
+            })?;
+        let v: Value =
+            serde_json::from_str(&written.body).map_err(|source| CloudflareError::Json {
+                status: written.status,
+                source,
+            })?;
+        let (_, updated) =
+            a_record(&v["result"])?.ok_or(CloudflareError::MissingField("result"))?;
 
+        Ok((updated, written.ray_id))
+    }
+
+    /// Sends the body of a changed A record to the API with `method`, returning its answer.
+    async fn write_once(
+        &self,
+        method: Method,
+        url: &str,
+        body: &Value,
+    ) -> Result<Written, CloudflareError> {
+        self.wait_for_turn().await;
         let response = self
             .client
-            .put(url)
+            .request(method, url)
             .headers(self.headers.clone())
-            .json(&body)
-            .send()?;
+            .timeout(self.timeout)
+            .json(body)
+            .send()
+            .await
+            .map_err(CloudflareError::Request)?;
 
-        if response.status().is_success() {
-            Ok(())
+        let status = response.status();
+        let ray_id = response
+            .headers()
+            .get("cf-ray")
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        let body = response.text().await.map_err(CloudflareError::Response)?;
+        if status.is_success() {
+            Ok(Written {
+                status,
+                ray_id,
+                body,
+            })
         } else {
-            let error_text = response.text()?;
-            anyhow::bail!("Failed to update A record: {error_text}");
+            let code = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|v| v["errors"][0]["code"].as_u64());
+            Err(CloudflareError::Update { status, code, body })
         }
     }
 }
+
+/// Returns the name of the record in a response of the API, and the record, if it's an A or an
+/// AAAA record with everything such a record has.
+fn a_record(record: &Value) -> Result<Option<(String, ARecord)>, CloudflareError> {
+    let (Some(record_type @ ("A" | "AAAA")), Some(name), Some(id), Some(content)) = (
+        record["type"].as_str(),
+        record["name"].as_str(),
+        record["id"].as_str(),
+        record["content"].as_str(),
+    ) else {
+        return Ok(None);
+    };
+    let ip = if record_type == "A" {
+        IpAddr::V4(content.parse()?)
+    } else {
+        IpAddr::V6(content.parse()?)
+    };
+
+    Ok(Some((
+        name.to_string(),
+        ARecord {
+            id: id.to_string(),
+            ip,
+            proxied: record["proxied"].as_bool(),
+            ttl: record["ttl"]
+                .as_u64()
+                .and_then(|ttl| u32::try_from(ttl).ok()),
+        },
+    )))
+}
+
+#[test]
+fn test_zone_from_json() {
+    let zone = json!({
+        "id": "023e105f4ecef8ad9ca31a8372d0c353",
+        "name": "example.com",
+        "status": "pending",
+    });
+    assert_eq!(
+        Zone::from_json(&zone),
+        Some(Zone {
+            id: String::from("023e105f4ecef8ad9ca31a8372d0c353"),
+            name: String::from("example.com"),
+            status: String::from("pending"),
+        })
+    );
+    assert_eq!(Zone::from_json(&json!({ "name": "example.com" })), None);
+}
+
+#[test]
+fn test_category() {
+    let error = CloudflareError::Api {
+        status: StatusCode::BAD_REQUEST,
+        code: Some(9109),
+        message: String::from("Invalid access token"),
+    };
+    assert_eq!(error.category(), Some(Category::Authentication));
+    assert_eq!(
+        error.to_string(),
+        "Cloudflare API error: Invalid access token"
+    );
+    assert_eq!(
+        CloudflareError::RecordNotFound(String::from("example.com")).category(),
+        Some(Category::NotFound)
+    );
+    assert_eq!(CloudflareError::MissingField("result").category(), None);
+}
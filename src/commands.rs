@@ -0,0 +1,748 @@
+//! What the subcommands do, from their arguments, apart from running the updater and the daemon,
+//! which [`crate::app`] does itself.
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::ArgMatches;
+use tracing::warn;
+
+use cdu::config::{self, Config};
+use cdu::network::Family;
+use cdu::plan::Plan;
+use cdu::updater::Updater;
+use cdu::{
+    api, audit, check, cloudflare, confirm, exit, history, import, install, network, notify, stats,
+    status, validate,
+};
+
+use crate::args::{cli, daemon_args};
+use crate::{
+    api_url, audit_log, config_with_dir, dirs, domains, every_target, family, init, required_arg,
+    runtime, settings, settings_path, timeouts, transport, validate_settings,
+};
+
+/// Prints the settings in effect or checks them, or sets or unsets one in the settings file, which
+/// is created in the settings directory if there's none yet.
+///
+/// # Errors
+///
+/// Returns an error if the setting or its value isn't valid, the settings file cannot be changed,
+/// or a check failed.
+pub fn config(arg_matches: &ArgMatches, config_matches: &ArgMatches) -> anyhow::Result<()> {
+    let profile = arg_matches.get_one::<String>("profile").map(String::as_str);
+    let path = settings_path(arg_matches);
+
+    match config_matches.subcommand() {
+        Some(("show", show_matches)) => {
+            match &path {
+                Some(path) => println!("# Settings file: {}", path.display()),
+                None => println!("# No settings file"),
+            }
+            if let Some(profile) = profile {
+                println!("# Profile: {profile}");
+            }
+            let cli = cli();
+            let daemon_args = daemon_args();
+            println!(
+                "{}",
+                settings::show(arg_matches, &cli.get_arguments().collect::<Vec<_>>())
+            );
+            println!(
+                "{}",
+                settings::show(show_matches, &daemon_args.iter().collect::<Vec<_>>())
+            );
+
+            let vars = env::vars_os().filter_map(|(key, value)| {
+                Some((key.into_string().ok()?, value.into_string().ok()?))
+            });
+            let named = notify::targets_from_env(vars)
+                .map_err(anyhow::Error::msg)?
+                .len();
+            if named > 0 {
+                println!("# And {named} named notification targets, in CDU_NOTIFY_<NAME>");
+            }
+        }
+        Some(("export-env", export_matches)) => {
+            let secrets = export_matches.get_flag("with_secrets");
+            let cli = cli();
+            let daemon_args = daemon_args();
+            let vars = env::vars_os().filter_map(|(key, value)| {
+                Some((key.into_string().ok()?, value.into_string().ok()?))
+            });
+            let args = cli.get_arguments().collect::<Vec<_>>();
+            let daemon_args = daemon_args.iter().collect::<Vec<_>>();
+
+            let mut lines = settings::export_env(arg_matches, &args, secrets);
+            lines.extend(settings::export_env(export_matches, &daemon_args, secrets));
+            lines.extend(settings::export_targets(vars, secrets));
+            for line in lines {
+                println!("{line}");
+            }
+        }
+        Some(("validate", _)) => {
+            let settings = validate_settings(arg_matches)?;
+            runtime()?.block_on(validate::run(&settings))?;
+            println!("The settings are fine");
+        }
+        Some((command, matches)) => {
+            let path =
+                path.unwrap_or_else(|| dirs(arg_matches).settings.join(config::SETTINGS_FILE));
+            let key = matches.get_one::<String>("key").unwrap();
+            if command == "set" {
+                let values = matches
+                    .get_many::<String>("value")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                settings::set(&path, profile, key, &values)?;
+                println!("Set {key} in {}", path.display());
+            } else if settings::unset(&path, profile, key)? {
+                println!("Unset {key} in {}", path.display());
+            } else {
+                println!("{key} isn't set in {}", path.display());
+            }
+        }
+        None => unreachable!("clap requires a subcommand"),
+    }
+
+    Ok(())
+}
+
+/// Forgets the cached outside IP and what's known about the A records, or only the parts asked
+/// for, so the next run checks every A record at Cloudflare. The rest of the state is kept.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be read or written.
+pub fn clear_state(arg_matches: &ArgMatches, state_matches: &ArgMatches) -> anyhow::Result<()> {
+    let Some(("clear", clear_matches)) = state_matches.subcommand() else {
+        unreachable!("clap requires a subcommand");
+    };
+    let mut ip = clear_matches.get_flag("ip");
+    let mut record_ids = clear_matches.get_flag("record_ids");
+    if !ip && !record_ids {
+        (ip, record_ids) = (true, true);
+    }
+
+    let mut config = config_with_dir(arg_matches)?;
+    config.load()?;
+    confirm::confirm(
+        &state_changes(&config, ip, record_ids),
+        arg_matches.get_flag("yes"),
+    )?;
+    if ip {
+        config.clear_ips();
+        println!("Cleared the cached outside IP and the IPs of the A records");
+    }
+    if record_ids {
+        config.clear_record_ids();
+        println!("Cleared the IDs of the A records");
+    }
+    config.save()?;
+
+    Ok(())
+}
+
+/// Returns what clearing the state would forget, the cached IPs with `ip` and the IDs of the A
+/// records with `record_ids`.
+fn state_changes(config: &Config, ip: bool, record_ids: bool) -> Vec<String> {
+    let mut changes = Vec::new();
+    if ip {
+        if let Some(outside_ip) = config.outside_ip {
+            changes.push(format!("Forget that the outside IP is {outside_ip}"));
+        }
+        for record in &config.records {
+            if let Some(record_ip) = record.ip {
+                changes.push(format!(
+                    "Forget that the A record of {} points at {record_ip}",
+                    record.domain
+                ));
+            }
+        }
+    }
+    if record_ids {
+        for record in &config.records {
+            if let Some(record_id) = &record.record_id {
+                changes.push(format!(
+                    "Forget that the ID of the A record of {} is {record_id}",
+                    record.domain
+                ));
+            }
+        }
+    }
+
+    changes
+}
+
+/// Prints the changes of the outside IP in the history, the most recent one last, or exports them
+/// with [`history::export`].
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be read.
+pub fn history(arg_matches: &ArgMatches, history_matches: &ArgMatches) -> anyhow::Result<()> {
+    let mut config = config_with_dir(arg_matches)?;
+    config.load()?;
+
+    if let Some(("export", export_matches)) = history_matches.subcommand() {
+        let since = export_matches
+            .get_one::<chrono::DateTime<chrono::Utc>>("since")
+            .copied()
+            .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+        let changes = config
+            .history
+            .into_iter()
+            .filter(|change| change.at >= since)
+            .collect::<Vec<_>>();
+        let format = *export_matches.get_one::<history::Format>("format").unwrap();
+        print!("{}", history::export(&changes, format)?);
+        return Ok(());
+    }
+
+    if config.history.is_empty() {
+        println!("The outside IP hasn't changed yet");
+        return Ok(());
+    }
+
+    let limit = history_matches
+        .get_one::<usize>("limit")
+        .copied()
+        .unwrap_or(config.history.len());
+    for change in config
+        .history
+        .iter()
+        .skip(config.history.len().saturating_sub(limit))
+    {
+        println!("{change}");
+    }
+
+    Ok(())
+}
+
+/// Prints the stats of the history, with the latency of the updates from the audit log.
+///
+/// # Errors
+///
+/// Returns an error if the state or the audit log cannot be read.
+pub fn stats(arg_matches: &ArgMatches, stats_matches: &ArgMatches) -> anyhow::Result<()> {
+    let mut config = config_with_dir(arg_matches)?;
+    config.load()?;
+    let audit = match audit_log(arg_matches, &config) {
+        Some(path) => audit::AuditLog::new(&path).read()?,
+        None => Vec::new(),
+    };
+
+    let stats = stats::Stats::new(&config.history, &audit, chrono::Utc::now());
+    if stats_matches.get_one::<String>("format").unwrap() == "json" {
+        println!("{}", serde_json::to_string_pretty(&stats.to_json())?);
+    } else {
+        print!("{stats}");
+    }
+
+    Ok(())
+}
+
+/// Sets cdu up with the wizard in [`init`], and runs it once as a dry run with what was set up.
+///
+/// # Errors
+///
+/// Returns an error if the wizard fails, or the dry run does.
+pub async fn init(arg_matches: &ArgMatches) -> anyhow::Result<()> {
+    let profile = arg_matches.get_one::<String>("profile").map(String::as_str);
+    let path = settings_path(arg_matches)
+        .unwrap_or_else(|| dirs(arg_matches).settings.join(config::SETTINGS_FILE));
+    let client = transport(arg_matches).client(timeouts(arg_matches).api);
+    let setup = init::run(&path, profile, api_url(arg_matches), &client).await?;
+
+    println!();
+    println!("Checking what cdu would do, as a dry run:");
+    let domains = setup.domains.iter().map(String::as_str).collect::<Vec<_>>();
+    let mut updater = Updater::try_new(
+        &setup.api_key,
+        &setup.zone_id,
+        &domains,
+        true,
+        config_with_dir(arg_matches)?,
+    )?;
+    let outcome = updater.run().await?;
+    println!("{outcome}");
+    println!("Run cdu to update the A records for real, or `cdu install` to keep doing it.");
+
+    Ok(())
+}
+
+/// Prints the changes to the A records that would point them at the outside IP, as a [`Plan`], and
+/// saves it to `--out` if it's given.
+///
+/// # Errors
+///
+/// Returns an error if a setting that's needed is missing, the outside IP cannot be detected, an A
+/// record cannot be looked up, or the plan cannot be saved.
+pub async fn plan(arg_matches: &ArgMatches, plan_matches: &ArgMatches) -> anyhow::Result<()> {
+    let api_key = required_arg(arg_matches, "api_key")?;
+    let zone_id = required_arg(arg_matches, "zone_id")?;
+    let (domains, domain_settings) = domains(arg_matches)?;
+    if domains.is_empty() {
+        required_arg(arg_matches, "domain")?;
+    }
+
+    let timeouts = timeouts(arg_matches);
+    let transport = transport(arg_matches);
+    let family = family(arg_matches);
+    let (outside_ip, source) = network::detect_family(
+        &transport.detection_client(timeouts.detection),
+        family.servers(),
+        family,
+        timeouts.detection,
+    )
+    .await
+    .map_err(|e| exit::wrap(exit::DETECTION_FAILED, e))?;
+    println!("Outside IP is {outside_ip}, from {source}");
+
+    let cloudflare = cloudflare::Handler::try_new(api_key)?
+        .with_api_url(api_url(arg_matches))
+        .with_client(transport.client(timeouts.api))
+        .with_timeout(timeouts.api);
+    let plan = Plan::create(&cloudflare, zone_id, &domains, &domain_settings, outside_ip)
+        .await
+        .map_err(|e| exit::wrap(exit::CLOUDFLARE_FAILED, e))?;
+    if plan.changes.is_empty() {
+        println!("Every A record already points at the outside IP");
+    }
+    for change in &plan.changes {
+        println!("{change}");
+    }
+
+    if let Some(path) = plan_matches.get_one::<PathBuf>("out") {
+        fs::write(path, serde_json::to_string_pretty(&plan)?)
+            .with_context(|| format!("Failed to save the plan to {}", path.display()))?;
+        println!(
+            "Saved the plan, apply it with `cdu apply {}`",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Makes the changes of a [`Plan`] saved by `cdu plan --out`, after a confirmation.
+///
+/// # Errors
+///
+/// Returns an error if the plan cannot be read, the A records changed since it was made, or a
+/// change fails.
+pub async fn apply(arg_matches: &ArgMatches, apply_matches: &ArgMatches) -> anyhow::Result<()> {
+    let path = apply_matches.get_one::<PathBuf>("path").unwrap();
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the plan from {}", path.display()))?;
+    let plan = serde_json::from_str::<Plan>(&text)
+        .with_context(|| format!("Invalid plan in {}", path.display()))?;
+    if plan.changes.is_empty() {
+        println!("The plan has nothing to change");
+        return Ok(());
+    }
+
+    let api_key = required_arg(arg_matches, "api_key")?;
+    confirm::confirm(
+        &plan
+            .changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "Change the {} record of {} from {} to {}",
+                    Family::of(change.new_ip).record_type(),
+                    change.domain,
+                    change.old_ip,
+                    change.new_ip
+                )
+            })
+            .collect::<Vec<_>>(),
+        arg_matches.get_flag("yes"),
+    )?;
+    let timeouts = timeouts(arg_matches);
+    let cloudflare = cloudflare::Handler::try_new(api_key)?
+        .with_api_url(api_url(arg_matches))
+        .with_client(transport(arg_matches).client(timeouts.api))
+        .with_timeout(timeouts.api);
+    plan.apply(&cloudflare).await?;
+    for change in &plan.changes {
+        println!("{change}");
+    }
+
+    Ok(())
+}
+
+/// Prints a settings file made from the configuration of another client with [`import`], with the
+/// IDs of its zones looked up with its API token, and tells what couldn't be translated.
+///
+/// # Errors
+///
+/// Returns an error if the configuration cannot be read, or has no domains on Cloudflare.
+pub async fn import_settings(
+    arg_matches: &ArgMatches,
+    import_matches: &ArgMatches,
+) -> anyhow::Result<()> {
+    let (client, client_matches) = import_matches.subcommand().unwrap();
+    let path = client_matches.get_one::<PathBuf>("path").unwrap();
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut imported = match client {
+        "ddclient" => Ok(import::ddclient(&text)),
+        "inadyn" => import::inadyn(&text),
+        "ddns-go" => import::ddns_go(&text),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+    .with_context(|| format!("Failed to import {}", path.display()))?;
+    anyhow::ensure!(
+        !imported.entries.is_empty(),
+        "Found no domains on Cloudflare to import in {}",
+        path.display()
+    );
+
+    let zones = match &imported.api_key {
+        Some(api_key) => cloudflare::Handler::try_new(api_key)?
+            .with_api_url(api_url(arg_matches))
+            .with_client(transport(arg_matches).client(timeouts(arg_matches).api))
+            .list_zones()
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to look up the zones: {e:#}");
+                Vec::new()
+            }),
+        None => Vec::new(),
+    };
+    imported.resolve(&zones);
+
+    print!("{}", imported.settings());
+    for untranslated in &imported.untranslated {
+        eprintln!("Not imported: {untranslated}");
+    }
+
+    Ok(())
+}
+
+/// Compares the outside IP with the A records with [`check::run`], and returns whether they're all
+/// in sync.
+///
+/// # Errors
+///
+/// Returns an error if a setting that's needed is missing, or the comparison fails.
+pub async fn check(arg_matches: &ArgMatches) -> anyhow::Result<bool> {
+    let api_key = required_arg(arg_matches, "api_key")?;
+    let zone_id = required_arg(arg_matches, "zone_id")?;
+    let (domains, domain_settings) = domains(arg_matches)?;
+    if domains.is_empty() {
+        required_arg(arg_matches, "domain")?;
+    }
+
+    let drifts = check::run(
+        api_key,
+        zone_id,
+        &domains,
+        &domain_settings,
+        timeouts(arg_matches),
+        &transport(arg_matches),
+        api_url(arg_matches),
+    )
+    .await?;
+    if !drifts.is_empty() {
+        eprintln!(
+            "{} of {} A records don't point at the outside IP",
+            drifts.len(),
+            domains.len()
+        );
+    }
+
+    Ok(drifts.is_empty())
+}
+
+/// Prints whether DDNS is healthy, see [`status::show`], and returns whether it is. The daemon is
+/// asked through its API, if `--api-listen` says where that is.
+///
+/// # Errors
+///
+/// Returns an error if a setting is missing, or the state cannot be loaded.
+pub async fn status(arg_matches: &ArgMatches, status_matches: &ArgMatches) -> anyhow::Result<bool> {
+    let api_key = required_arg(arg_matches, "api_key")?;
+    let zone_id = required_arg(arg_matches, "zone_id")?;
+    let (domains, domain_settings) = domains(arg_matches)?;
+    if domains.is_empty() {
+        required_arg(arg_matches, "domain")?;
+    }
+    let mut config = config_with_dir(arg_matches)?;
+    config.load()?;
+    let api = status_matches
+        .get_one::<SocketAddr>("api_listen")
+        .map(|listen| api::Options {
+            listen: *listen,
+            token: status_matches.get_one::<String>("api_token").cloned(),
+        });
+
+    status::show(
+        &config,
+        api_key,
+        zone_id,
+        &domains,
+        &domain_settings,
+        api.as_ref(),
+    )
+    .await
+}
+
+/// Tells whether the last check succeeded recently enough, asking the daemon if it serves its API,
+/// and the state otherwise.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be read.
+pub async fn healthcheck(
+    arg_matches: &ArgMatches,
+    healthcheck_matches: &ArgMatches,
+) -> anyhow::Result<bool> {
+    let mut config = config_with_dir(arg_matches)?;
+    config.load()?;
+    let api = healthcheck_matches
+        .get_one::<SocketAddr>("api_listen")
+        .map(|listen| api::Options {
+            listen: *listen,
+            token: healthcheck_matches.get_one::<String>("api_token").cloned(),
+        });
+    let within = healthcheck_matches.get_one::<Duration>("within").unwrap();
+    let within = chrono::Duration::from_std(*within)
+        .context("The window of the health check is too long")?;
+
+    Ok(status::healthcheck(&config, api.as_ref(), within).await)
+}
+
+/// Prints the name, ID and status of every zone the API token has access to, so the zone ID can be
+/// found without the dashboard.
+///
+/// # Errors
+///
+/// Returns an error if the API token isn't set, or the zones cannot be listed.
+pub async fn list_zones(arg_matches: &ArgMatches) -> anyhow::Result<()> {
+    let api_key = required_arg(arg_matches, "api_key")?;
+    let zones = cloudflare::Handler::try_new(api_key)?
+        .with_api_url(api_url(arg_matches))
+        .with_client(transport(arg_matches).client(timeouts(arg_matches).api))
+        .list_zones()
+        .await
+        .map_err(|e| exit::wrap(exit::CLOUDFLARE_FAILED, e))?;
+    if zones.is_empty() {
+        println!("The API token has no access to any zone");
+    }
+
+    let width = zones
+        .iter()
+        .map(|zone| zone.name.len())
+        .max()
+        .unwrap_or_default();
+    for zone in zones {
+        println!("{:width$}  {}  {}", zone.name, zone.id, zone.status);
+    }
+
+    Ok(())
+}
+
+/// Prints the outside IP, detected the same way as before updating the A records, and the server
+/// that answered with `--source`.
+///
+/// # Errors
+///
+/// Returns an error if none of the servers answered with an IP address.
+pub async fn print_ip(arg_matches: &ArgMatches, ip_matches: &ArgMatches) -> anyhow::Result<()> {
+    let server = ip_matches.get_one::<String>("server").map(String::as_str);
+    let timeout = timeouts(arg_matches).detection;
+    let client = transport(arg_matches).detection_client(timeout);
+    let family = family(arg_matches);
+    let servers = server
+        .into_iter()
+        .chain(family.servers().iter().copied())
+        .collect::<Vec<_>>();
+    let (ip, source) = network::detect_family(&client, &servers, family, timeout)
+        .await
+        .map_err(|e| exit::wrap(exit::DETECTION_FAILED, e))?;
+
+    if ip_matches.get_flag("source") {
+        println!("{ip} {source}");
+    } else {
+        println!("{ip}");
+    }
+
+    Ok(())
+}
+
+/// Asks every server that tells the outside IP at once, with the ones in `--server` first, and
+/// prints a line for each: the IP it answered with, or why it didn't, and how long it took. The
+/// ones that answered with another IP than most of them are marked. Returns whether they all
+/// answered, with the same IP.
+pub async fn probe(arg_matches: &ArgMatches, probe_matches: &ArgMatches) -> bool {
+    let ipv6_only = arg_matches.get_flag("ipv6_only");
+    let servers = probe_matches
+        .get_many::<String>("server")
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .chain(if ipv6_only {
+            network::SERVERS_V6.iter().copied()
+        } else {
+            network::SERVERS.iter().copied()
+        })
+        .collect::<Vec<_>>();
+    let timeout = timeouts(arg_matches).detection;
+    let client = transport(arg_matches).detection_client(timeout);
+
+    let probes = network::probe(&client, &servers, ipv6_only, timeout).await;
+    let consensus = network::consensus(&probes);
+    let width = servers
+        .iter()
+        .map(|server| server.len())
+        .max()
+        .unwrap_or_default();
+    for probe in &probes {
+        let took = format!("{} ms", probe.took.as_millis());
+        match &probe.result {
+            Ok(ip) if Some(*ip) != consensus => {
+                println!("{:width$}  {took:>8}  {ip}  (differs)", probe.server);
+            }
+            Ok(ip) => println!("{:width$}  {took:>8}  {ip}", probe.server),
+            Err(e) => println!("{:width$}  {took:>8}  failed: {e}", probe.server),
+        }
+    }
+
+    match consensus {
+        Some(ip) => println!("Most of them answered {ip}"),
+        None => println!("None of them answered"),
+    }
+
+    consensus.is_some()
+        && probes
+            .iter()
+            .all(|probe| probe.result.as_ref().ok() == consensus.as_ref())
+}
+
+/// Sends a test message to every target, whatever it's subscribed to, and prints whether it
+/// arrived.
+///
+/// # Errors
+///
+/// Returns an error if there are no targets, or sending to any of them failed.
+pub async fn test_notify(arg_matches: &ArgMatches) -> anyhow::Result<()> {
+    let targets = every_target(arg_matches)?;
+    anyhow::ensure!(
+        !targets.is_empty(),
+        "No notification targets, set them with --notify or CDU_NOTIFY_<NAME>"
+    );
+
+    let domain = arg_matches
+        .get_many::<String>("domain")
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let message = notify::Message::test(if domain.is_empty() {
+        "example.com"
+    } else {
+        &domain
+    });
+
+    let transport = transport(arg_matches);
+    let timeout = timeouts(arg_matches).notify;
+    let client = transport.client(timeout);
+    let mut failed = 0;
+    for (index, target) in targets.iter().enumerate() {
+        let number = index + 1;
+        let kind = target.kind();
+        let host = target.address().map(|(host, _)| host);
+        let target_client = transport.client_for(&client, host.as_deref(), timeout);
+        match target.notifier(&target_client).send(&message).await {
+            Ok(()) => println!("{number}. {kind}: sent"),
+            Err(e) => {
+                println!("{number}. {kind}: failed: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+
+    anyhow::ensure!(
+        failed == 0,
+        "Failed to send to {failed} of {} targets",
+        targets.len()
+    );
+
+    Ok(())
+}
+
+pub fn install(
+    arg_matches: &ArgMatches,
+    install_matches: &ArgMatches,
+    env_file: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let Some((scheduler, matches)) = install_matches.subcommand() else {
+        unreachable!("clap requires a subcommand");
+    };
+
+    let config_dir = config_with_dir(arg_matches)?.save_dir;
+    let config_dir = fs::canonicalize(&config_dir)
+        .with_context(|| format!("Problem with config directory: {}", config_dir.display()))?;
+    let env_file = match matches.get_one::<String>("env_file") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env_file,
+    };
+    let env_file = env_file
+        .map(|path| {
+            fs::canonicalize(&path)
+                .with_context(|| format!("Problem with environment file: {}", path.display()))
+        })
+        .transpose()?;
+
+    let options = install::Options {
+        executable: env::current_exe().context("Failed to find the cdu executable")?,
+        config_dir,
+        env_file,
+        user: matches.get_one::<String>("user").cloned(),
+        timer: matches.try_get_one::<bool>("timer").ok().flatten() == Some(&true),
+        interval: *matches.get_one::<Duration>("interval").unwrap(),
+        jitter: matches
+            .try_get_one::<Duration>("jitter")
+            .ok()
+            .flatten()
+            .copied(),
+    };
+    let print = matches.get_flag("print");
+    let force = matches.get_flag("force");
+
+    match scheduler {
+        "systemd" => install::systemd(
+            &options,
+            Path::new(matches.get_one::<String>("dir").unwrap()),
+            print,
+            force,
+        ),
+        "launchd" => {
+            let dir = match matches.get_one::<String>("dir") {
+                Some(dir) => PathBuf::from(dir),
+                // A daemon running as another user has to be installed system-wide
+                None if options.user.is_some() => PathBuf::from("/Library/LaunchDaemons"),
+                None => env::var_os("HOME")
+                    .map(PathBuf::from)
+                    .context("Failed to find the home directory, use --dir")?
+                    .join("Library/LaunchAgents"),
+            };
+            install::launchd(&options, &dir, print, force)
+        }
+        "task" => install::task(
+            &options,
+            matches.get_one::<String>("name").unwrap(),
+            print,
+            force,
+        ),
+        _ => unreachable!("clap only allows known subcommands"),
+    }
+}
@@ -1,27 +1,296 @@
 use std::env;
+use std::error::Error;
 use std::fmt;
 use std::fs;
-use std::io::Write;
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
-use anyhow::Context;
 use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use tracing::debug;
+use toml::{Table, Value};
+use tracing::{debug, info};
+
+use crate::breaker::Health;
+use crate::cloudflare;
+use crate::crypt;
+use crate::network::{Timeouts, Transport};
 
 const CONFIG_DIR_LOCAL: &str = ".";
 const CONFIG_DIR_DOCKER: &str = "/config";
-const CONFIG_FILE: &str = "cdu.toml";
+/// The file with the settings, which cdu only ever reads.
+pub const SETTINGS_FILE: &str = "cdu.toml";
+/// The names the settings file is looked for under, in order, as it can also be YAML or JSON, or
+/// encrypted.
+pub const SETTINGS_FILES: [&str; 5] = [
+    SETTINGS_FILE,
+    "cdu.yaml",
+    "cdu.yml",
+    "cdu.json",
+    "cdu.toml.age",
+];
+/// The file cdu keeps what it found out in, which it rewrites as it goes.
+const STATE_FILE: &str = "cdu.state.toml";
+/// The layout of the state file, which is written in it as its `version`. Files without one are
+/// from before it had a file of its own.
+pub const STATE_VERSION: u32 = 2;
+/// The keys of the state, which older files kept along with the settings.
+const STATE_KEYS: [&str; 5] = [
+    "outside_ip",
+    "cloudflare_ip",
+    "last_updated",
+    "last_reconciled",
+    "last_heartbeat",
+];
+
+/// How many changes of the outside IP the history keeps, dropping the oldest ones.
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Why the state couldn't be read or written.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    /// The directory of the state file is missing, or cannot be looked at.
+    #[error("Problem with config directory: {path:?}")]
+    Dir {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to read file: {path:?}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// The state is encrypted with another key, or isn't text once decrypted.
+    #[error("Failed to decrypt file: {path:?}")]
+    Decrypt {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+    #[error("Failed to parse JSON from file: {path:?}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("Failed to upgrade file: {path:?}")]
+    Upgrade {
+        path: PathBuf,
+        #[source]
+        source: Box<ConfigError>,
+    },
+    #[error("Invalid version of the state: {0}")]
+    InvalidVersion(String),
+    #[error("The state is in layout {0}, from a newer version of cdu, which only knows up to {STATE_VERSION}")]
+    NewerLayout(u32),
+    #[error("Failed to serialize the state")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("The state isn't a table")]
+    NotATable,
+    #[error("Failed to write to file: {path:?}, it's read-only")]
+    ReadOnly { path: PathBuf },
+    #[error("Failed to create file: {path:?}")]
+    Create {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to write to file: {path:?}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to replace file: {path:?}")]
+    Replace {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to remove file: {path:?}")]
+    Remove {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to rename file: {path:?}")]
+    Rename {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to encrypt the state")]
+    Encrypt(#[source] Box<dyn Error + Send + Sync>),
+    /// The database of [`crate::sqlite`] cannot be read or written.
+    #[error("Failed to use the database: {path:?}")]
+    Database {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+/// Where the settings and the state are kept, unless `--config-dir` says otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirs {
+    pub settings: PathBuf,
+    pub state: PathBuf,
+}
+
+impl Dirs {
+    /// Returns `/config` in Docker, and the current directory if it has the files older versions
+    /// kept there. Otherwise it's the directories of the platform: `$XDG_CONFIG_HOME/cdu` and
+    /// `$XDG_STATE_HOME/cdu` on Linux, `~/Library/Application Support/cdu` on macOS and
+    /// `%APPDATA%\cdu\config` and `%LOCALAPPDATA%\cdu\data` on Windows.
+    #[must_use]
+    pub fn find() -> Self {
+        if env::var("DOCKER_RUNTIME").is_ok() {
+            return Self::same(CONFIG_DIR_DOCKER);
+        }
+        let local = PathBuf::from(CONFIG_DIR_LOCAL);
+        if local.join(SETTINGS_FILE).is_file() || local.join(STATE_FILE).is_file() {
+            return Self::same(local);
+        }
+
+        match ProjectDirs::from("", "", "cdu") {
+            Some(dirs) => Self {
+                settings: dirs.config_dir().to_path_buf(),
+                // Only Linux has a directory for state
+                state: dirs
+                    .state_dir()
+                    .unwrap_or_else(|| dirs.data_local_dir())
+                    .to_path_buf(),
+            },
+            // Without a home directory
+            None => Self::same(local),
+        }
+    }
+
+    /// Returns the same directory for both.
+    pub fn same(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        Self {
+            settings: dir.clone(),
+            state: dir,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct Config {
-    pub outside_ip: Option<Ipv4Addr>,
-    pub cloudflare_ip: Option<Ipv4Addr>,
+    /// The layout the state is in, see [`STATE_VERSION`].
+    pub version: u32,
+    pub outside_ip: Option<IpAddr>,
+    pub cloudflare_ip: Option<IpAddr>,
     pub last_updated: DateTime<Utc>,
+    /// The last time the A record was checked, whether the outside IP had changed or not.
+    pub last_reconciled: Option<DateTime<Utc>>,
+    /// The last time a heartbeat was sent.
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    /// The last time a check succeeded, for `cdu healthcheck`.
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// The domains whose A record has the low TTL it got with a change, until it's raised again.
+    pub lowered_ttls: Vec<String>,
+    /// Whether the fallback API token is in use, since Cloudflare refused the primary one.
+    pub fallback_token: bool,
+    /// Where the state file is, which doesn't go in the file itself.
+    #[serde(skip)]
     pub save_dir: PathBuf,
+    #[serde(skip)]
     pub file_name: String,
+    /// Whether the state is kept in the database of [`crate::sqlite`] instead of the file.
+    #[cfg(feature = "sqlite")]
+    #[serde(skip)]
+    pub use_database: bool,
+    /// The key the state is encrypted with, in the file of [`Config::state_path`].
+    #[serde(skip)]
+    pub encrypt_with: Option<crypt::Key>,
+    /// Whether the state is neither read nor written, with `--no-state`.
+    #[serde(skip)]
+    pub stateless: bool,
+    /// How long the requests of the updater may take.
+    #[serde(skip)]
+    pub timeouts: Timeouts,
+    /// How the requests of the updater reach the servers, like through a proxy.
+    #[serde(skip)]
+    pub transport: Transport,
+    /// Where the Cloudflare API is, with `--cf-api-base`.
+    #[serde(skip)]
+    pub api_url: String,
+    /// The Discord webhook of `--webhook-url`, which is a setting, and a secret, so it isn't kept
+    /// in the state.
+    #[serde(skip)]
     pub webhook_url: Option<String>,
+    /// How the servers that tell the outside IP, and the notification targets, have been doing,
+    /// see [`crate::breaker`]. It's last, with the records and the history, as TOML has the tables
+    /// after the values.
+    pub health: Vec<Health>,
+    /// What's known about the A record of every domain.
+    pub records: Vec<RecordState>,
+    /// The changes of the outside IP, the oldest first.
+    pub history: Vec<IpChange>,
+}
+
+/// What's known about the A record of one domain, so one that failed is tried again without
+/// checking the others, and one that was added is updated even if the outside IP didn't change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordState {
+    pub domain: String,
+    /// The ID of the record at Cloudflare, which the record is looked up and changed with on the
+    /// next check, without looking for it in the whole zone.
+    pub record_id: Option<String>,
+    /// The IP it pointed at when it was last checked, which isn't known if that failed.
+    pub ip: Option<IpAddr>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub last_updated: Option<DateTime<Utc>>,
+}
+
+/// A change of the outside IP, as cdu saw it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IpChange {
+    pub at: DateTime<Utc>,
+    pub old_ip: IpAddr,
+    pub new_ip: IpAddr,
+    /// The server that detected the new IP.
+    pub source: String,
+    /// The domains whose A record was updated to the new IP.
+    pub updated: Vec<String>,
+}
+
+impl fmt::Display for IpChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {} -> {}, detected by {}, ",
+            self.at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            self.old_ip,
+            self.new_ip,
+            self.source
+        )?;
+        if self.updated.is_empty() {
+            write!(f, "no A records updated")
+        } else {
+            write!(f, "updated {}", self.updated.join(", "))
+        }
+    }
+}
+
+impl RecordState {
+    fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            record_id: None,
+            ip: None,
+            checked_at: None,
+            last_updated: None,
+        }
+    }
 }
 
 impl Default for Config {
@@ -33,12 +302,28 @@ impl Default for Config {
         };
 
         Self {
+            version: STATE_VERSION,
             outside_ip: None,
             cloudflare_ip: None,
             last_updated: Utc::now(),
+            last_reconciled: None,
+            last_heartbeat: None,
+            last_success_at: None,
+            lowered_ttls: Vec::new(),
+            fallback_token: false,
             save_dir: PathBuf::from(config_dir),
-            file_name: String::from(CONFIG_FILE),
+            file_name: String::from(STATE_FILE),
+            #[cfg(feature = "sqlite")]
+            use_database: false,
+            encrypt_with: None,
+            stateless: false,
+            timeouts: Timeouts::default(),
+            transport: Transport::default(),
+            api_url: cloudflare::API_URL.to_string(),
             webhook_url: None,
+            health: Vec::new(),
+            records: Vec::new(),
+            history: Vec::new(),
         }
     }
 }
@@ -60,36 +345,160 @@ impl fmt::Display for Config {
 }
 
 impl Config {
-    /// Loads the configuration file if it exists.
+    /// Returns what's known about the A record of `domain`.
+    pub fn record(&self, domain: &str) -> Option<&RecordState> {
+        self.records.iter().find(|record| record.domain == domain)
+    }
+
+    /// Returns what's known about the A record of `domain`, to change it, starting with nothing.
+    pub fn record_mut(&mut self, domain: &str) -> &mut RecordState {
+        let index = self
+            .records
+            .iter()
+            .position(|record| record.domain == domain)
+            .unwrap_or_else(|| {
+                self.records.push(RecordState::new(domain));
+                self.records.len() - 1
+            });
+
+        &mut self.records[index]
+    }
+
+    /// Adds a change of the outside IP to the history, dropping the oldest one once there are
+    /// more than [`HISTORY_LIMIT`].
+    pub fn record_change(&mut self, change: IpChange) {
+        self.history.push(change);
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+    }
+
+    /// Forgets the outside IP and the IPs the A records pointed at, so the next run checks every
+    /// A record at Cloudflare.
+    pub fn clear_ips(&mut self) {
+        self.outside_ip = None;
+        self.cloudflare_ip = None;
+        self.last_reconciled = None;
+        for record in &mut self.records {
+            record.ip = None;
+        }
+    }
+
+    /// Forgets the IDs of the A records, so they're looked up again.
+    pub fn clear_record_ids(&mut self) {
+        for record in &mut self.records {
+            record.record_id = None;
+        }
+    }
+
+    /// Loads the state file if it exists.
     /// The file won't exist on the first run, and we log a message in that case, as it could be
-    /// an error if it's not the first run.
+    /// an error if it's not the first run. Older versions kept the state in the settings file, so
+    /// without a state file, the state is taken from there. A file in an older layout is upgraded
+    /// with [`migrate`], which logs what changed, and is saved in the new one.
+    ///
+    /// With the database, the state is read from there instead. Until it has one, the state is
+    /// taken from the file, and moved to the database. Nothing is read when it's stateless.
     ///
     /// # Errors
     ///
-    /// Returns an error if the file exists but cannot be read or parsed.
+    /// Returns an error if the file exists but cannot be read or parsed, or it's from a newer
+    /// version of cdu.
     #[tracing::instrument(skip(self))]
-    pub fn load(&mut self) -> anyhow::Result<()> {
-        let config_path = self.save_dir.join(&self.file_name);
-        let config_dir = config_path
-            .parent()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get config directory: {config_path:?}"))?;
+    pub fn load(&mut self) -> Result<(), ConfigError> {
+        if self.stateless {
+            debug!("Not reading the state, with --no-state");
+            return Ok(());
+        }
+
+        let plain_path = self.save_dir.join(&self.file_name);
+        let mut config_path = self.state_path();
+        // The state that isn't encrypted yet is taken, and encrypted below
+        if !config_path.exists() && plain_path.is_file() {
+            debug!("Taking the state from: {plain_path:?}");
+            config_path.clone_from(&plain_path);
+        }
+        let legacy_path = self.save_dir.join(SETTINGS_FILE);
+        if !config_path.exists() && legacy_path.is_file() {
+            debug!("Taking the state from: {legacy_path:?}");
+            config_path = legacy_path;
+        }
+        let config_dir = config_path.parent().unwrap_or_else(|| Path::new("."));
 
         // Check if there are any issues with the config directory
-        config_dir.metadata().map_err(|e| {
-            anyhow::anyhow!("Problem with config directory: {config_dir:?}. Error: {e:?}")
+        config_dir.metadata().map_err(|source| ConfigError::Dir {
+            path: config_dir.to_path_buf(),
+            source,
         })?;
 
+        #[cfg(feature = "sqlite")]
+        if self.use_database {
+            let database_path = self.database_path();
+            let database_error = |source: anyhow::Error| ConfigError::Database {
+                path: database_path.clone(),
+                source: source.into(),
+            };
+            if crate::sqlite::has_state(&database_path).map_err(database_error)? {
+                let table = crate::sqlite::read(&database_path).map_err(database_error)?;
+                return self.load_table(table, &database_path);
+            }
+        }
+
         if config_path.exists() {
             // If the file exists, proceed with loading
-            let file_content = fs::read_to_string(&config_path)
-                .with_context(|| format!("Failed to read file: {config_path:?}"))?;
-            let config: Self = toml::from_str(&file_content)
-                .with_context(|| format!("Failed to parse JSON from file: {config_path:?}"))?;
-            debug!("Loaded config from: {} ({})", config_path.display(), config);
-
-            self.outside_ip = config.outside_ip;
-            self.cloudflare_ip = config.cloudflare_ip;
-            self.last_updated = config.last_updated;
+            let read_error = |source| ConfigError::Read {
+                path: config_path.clone(),
+                source,
+            };
+            let file_content = match &self.encrypt_with {
+                Some(key) if crypt::is_encrypted(&config_path) => {
+                    let decrypt_error =
+                        |source: Box<dyn Error + Send + Sync>| ConfigError::Decrypt {
+                            path: config_path.clone(),
+                            source,
+                        };
+                    let ciphertext = fs::read(&config_path).map_err(read_error)?;
+                    let plaintext = key
+                        .decrypt(&ciphertext)
+                        .map_err(|e| decrypt_error(e.into()))?;
+                    String::from_utf8(plaintext).map_err(|e| decrypt_error(e.into()))?
+                }
+                _ => fs::read_to_string(&config_path).map_err(read_error)?,
+            };
+            let table = file_content
+                .parse::<Table>()
+                .map_err(|source| ConfigError::Parse {
+                    path: config_path.clone(),
+                    source,
+                })?;
+            self.load_table(table, &config_path)?;
+
+            if self.encrypt_with.is_some() && config_path == plain_path {
+                info!("Encrypting the state in {}", self.state_path().display());
+                self.save()?;
+                fs::remove_file(&plain_path).map_err(|source| ConfigError::Remove {
+                    path: plain_path.clone(),
+                    source,
+                })?;
+            }
+
+            #[cfg(feature = "sqlite")]
+            if self.use_database {
+                info!(
+                    "Moving the state from {} to {}",
+                    config_path.display(),
+                    self.database_path().display()
+                );
+                self.save()?;
+                // The settings file of older versions is left alone, as it has the settings too
+                if config_path == self.save_dir.join(&self.file_name) {
+                    let backup = config_path.with_extension("toml.bak");
+                    fs::rename(&config_path, &backup).map_err(|source| ConfigError::Rename {
+                        path: config_path.clone(),
+                        source,
+                    })?;
+                }
+            }
         } else {
             // If the file does not exist, do nothing and keep the current Config
             debug!("Config file does not exist: {config_path:?}");
@@ -98,29 +507,100 @@ impl Config {
         Ok(())
     }
 
-    /// Saves the configuration to a file.
+    /// Takes the state from `table`, read from `path`, upgrading it first.
+    fn load_table(&mut self, mut table: Table, path: &Path) -> Result<(), ConfigError> {
+        let changes = migrate(&mut table).map_err(|source| ConfigError::Upgrade {
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        })?;
+        for change in changes {
+            info!("Upgrading the state in {}: {change}", path.display());
+        }
+        let config: Self = Value::Table(table)
+            .try_into()
+            .map_err(|source| ConfigError::Parse {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        debug!("Loaded config from: {} ({})", path.display(), config);
+
+        self.outside_ip = config.outside_ip;
+        self.cloudflare_ip = config.cloudflare_ip;
+        self.last_updated = config.last_updated;
+        self.last_reconciled = config.last_reconciled;
+        self.last_heartbeat = config.last_heartbeat;
+        self.last_success_at = config.last_success_at;
+        self.lowered_ttls = config.lowered_ttls;
+        self.fallback_token = config.fallback_token;
+        self.health = config.health;
+        self.records = config.records;
+        self.history = config.history;
+
+        Ok(())
+    }
+
+    /// Returns the path of the state file, which has `.age` after it when the state is encrypted.
+    pub fn state_path(&self) -> PathBuf {
+        let path = self.save_dir.join(&self.file_name);
+        if self.encrypt_with.is_some() {
+            path.with_extension(format!("toml.{}", crypt::EXTENSION))
+        } else {
+            path
+        }
+    }
+
+    /// Returns the path of the database, which is the state file with `.db` instead of `.toml`.
+    #[cfg(feature = "sqlite")]
+    fn database_path(&self) -> PathBuf {
+        self.save_dir.join(&self.file_name).with_extension("db")
+    }
+
+    /// Saves the configuration to a file, with [`write_atomically`], or to the database. A file
+    /// that's read-only is left alone, and nothing is written when it's stateless.
     ///
     /// # Errors
     ///
-    /// Returns an error if the file cannot be created or written to.
+    /// Returns an error if the file is read-only, or cannot be created or written to.
     #[tracing::instrument(skip(self))]
-    pub fn save(&self) -> anyhow::Result<()> {
-        let config_path = self.save_dir.join(&self.file_name);
-        let config_toml = toml::to_string_pretty(self)
-            .with_context(|| format!("Failed to serialize Config to TOML: {:?}", &config_path))?;
-        let mut file = fs::File::create(&config_path).map_err(|e| {
-            anyhow::anyhow!(
-                "Failed to create file: {:?}. Error: {:?}, Error kind: {:?}",
-                config_path,
-                e,
-                e.kind()
-            )
-        })?;
+    pub fn save(&self) -> Result<(), ConfigError> {
+        if self.stateless {
+            return Ok(());
+        }
+
+        #[cfg(feature = "sqlite")]
+        if self.use_database {
+            let database_path = self.database_path();
+            let state = Value::try_from(self)?;
+            let table = state.as_table().ok_or(ConfigError::NotATable)?;
+            crate::sqlite::write(&database_path, table).map_err(|source| {
+                ConfigError::Database {
+                    path: database_path.clone(),
+                    source: source.into(),
+                }
+            })?;
+            debug!("Config saved to: {database_path:?}");
+
+            return Ok(());
+        }
+
+        let config_path = self.state_path();
+        let config_toml = toml::to_string_pretty(self)?;
+        // Renaming would replace it anyway
+        if fs::metadata(&config_path).is_ok_and(|metadata| metadata.permissions().readonly()) {
+            return Err(ConfigError::ReadOnly { path: config_path });
+        }
 
         debug!("config: {}", self);
 
-        file.write_all(config_toml.as_bytes())
-            .with_context(|| format!("Failed to write to file: {config_path:?}"))?;
+        match &self.encrypt_with {
+            Some(key) => {
+                let ciphertext = key
+                    .encrypt(config_toml.as_bytes())
+                    .map_err(|e| ConfigError::Encrypt(e.into()))?;
+                write_atomically(&config_path, &ciphertext)?;
+            }
+            None => write_atomically(&config_path, config_toml.as_bytes())?,
+        }
 
         debug!("Config saved to: {config_path:?}");
 
@@ -128,13 +608,123 @@ impl Config {
     }
 }
 
+/// Writes `contents` to `path` all at once: to a file next to it first, which is synced to disk
+/// and then renamed over it, so a crash halfway through leaves the old file as it was. The file is
+/// created so only its owner can read it, within the umask, as it can have webhook URLs in it.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created, written to or renamed.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), ConfigError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path.file_name().map_or_else(
+        || String::from("cdu"),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let temp_path = dir.join(format!(".{name}.{:08x}.tmp", fastrand::u32(..)));
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&temp_path)
+        .map_err(|source| ConfigError::Create {
+            path: temp_path.clone(),
+            source,
+        })?;
+
+    let written = file
+        .write_all(contents)
+        .and_then(|()| file.sync_all())
+        .map_err(|source| ConfigError::Write {
+            path: temp_path.clone(),
+            source,
+        })
+        .and_then(|()| {
+            fs::rename(&temp_path, path).map_err(|source| ConfigError::Replace {
+                path: path.to_path_buf(),
+                source,
+            })
+        });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    // The rename is only on disk once the directory is
+    #[cfg(unix)]
+    if written.is_ok() {
+        let _ = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+
+    written
+}
+
+/// Upgrades the state read from a file to the layout of [`STATE_VERSION`], one version at a time,
+/// returning what changed.
+///
+/// # Errors
+///
+/// Returns an error if the version isn't a number, or is newer than this version of cdu knows.
+fn migrate(table: &mut Table) -> Result<Vec<String>, ConfigError> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| ConfigError::InvalidVersion(version.to_string()))?
+        }
+        Some(version) => return Err(ConfigError::InvalidVersion(version.to_string())),
+    };
+    if version > STATE_VERSION {
+        return Err(ConfigError::NewerLayout(version));
+    }
+
+    let mut changes = Vec::new();
+    if version < 1 {
+        // The state was kept along with the settings, and where the file was
+        let others = table
+            .keys()
+            .filter(|key| !STATE_KEYS.contains(&key.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in &others {
+            table.remove(key);
+        }
+        if !others.is_empty() {
+            changes.push(format!(
+                "left out {}, which aren't part of the state",
+                others.join(", ")
+            ));
+        }
+    }
+    // Older versions wrote the webhook of --webhook-url in the state too
+    if table.remove("webhook_url").is_some() {
+        changes.push(String::from(
+            "left out webhook_url, which is a setting, not part of the state",
+        ));
+    }
+    if version < 2 && table.contains_key("outside_ip") {
+        // There's no telling which domains the outside IP was for
+        changes.push(String::from(
+            "the A records are checked once, as their state is kept per domain now",
+        ));
+    }
+    if version < STATE_VERSION {
+        table.insert(
+            String::from("version"),
+            Value::Integer(STATE_VERSION.into()),
+        );
+        changes.push(format!("from layout {version} to {STATE_VERSION}"));
+    }
+
+    Ok(changes)
+}
+
 #[test]
 fn test_load() {
     let dir = tempfile::tempdir().unwrap();
-    let file_path = dir.path().join(CONFIG_FILE);
+    let file_path = dir.path().join(STATE_FILE);
     let mut config = Config {
         save_dir: dir.path().to_path_buf(),
-        file_name: String::from(CONFIG_FILE),
+        file_name: String::from(STATE_FILE),
         ..Default::default()
     };
 
@@ -173,15 +763,65 @@ fn test_load() {
         result.is_err(),
         "Expected error when loading file with invalid IP, got {result:?}"
     );
+
+    // Test with the state in the settings file, as older versions kept it
+    fs::remove_file(&file_path).unwrap();
+    let file_content = r#"
+        domain = ["example.com"]
+        outside_ip = "5.6.7.8"
+        last_updated = "2024-03-10T13:54:04.032435Z"
+    "#;
+    fs::write(dir.path().join(SETTINGS_FILE), file_content).unwrap();
+    config.load().unwrap();
+    assert_eq!(config.outside_ip, Some(IpAddr::from([5, 6, 7, 8])));
+
+    // Test with the state from a newer version
+    fs::write(&file_path, "version = 99\noutside_ip = \"5.6.7.8\"\n").unwrap();
+    let result = config.load();
+    assert!(
+        matches!(
+            &result,
+            Err(ConfigError::Upgrade { source, .. }) if matches!(**source, ConfigError::NewerLayout(99))
+        ),
+        "Expected the state to be too new, got {result:?}"
+    );
+}
+
+#[test]
+fn test_migrate() {
+    let mut table = r#"
+        domain = ["example.com"]
+        outside_ip = "5.6.7.8"
+        save_dir = "/config"
+        file_name = "cdu.toml"
+    "#
+    .parse::<Table>()
+    .unwrap();
+
+    let changes = migrate(&mut table).unwrap();
+    assert_eq!(changes.len(), 3);
+    assert_eq!(
+        table,
+        "version = 2\noutside_ip = \"5.6.7.8\""
+            .parse::<Table>()
+            .unwrap()
+    );
+    assert!(migrate(&mut table).unwrap().is_empty());
+
+    let mut table = "version = 2\nwebhook_url = \"https://discord.com/api/webhooks/secret\""
+        .parse::<Table>()
+        .unwrap();
+    assert_eq!(migrate(&mut table).unwrap().len(), 1);
+    assert_eq!(table, "version = 2".parse::<Table>().unwrap());
 }
 
 #[test]
 fn test_save() {
     let dir = tempfile::tempdir().unwrap();
-    let file_path = dir.path().join(CONFIG_FILE);
+    let file_path = dir.path().join(STATE_FILE);
     let config = Config {
         save_dir: dir.path().to_path_buf(),
-        file_name: String::from(CONFIG_FILE),
+        file_name: String::from(STATE_FILE),
         ..Default::default()
     };
 
@@ -189,6 +829,49 @@ fn test_save() {
     let result = config.save();
     assert!(result.is_ok(), "Expected successful save, got {result:?}");
 
+    // Test that what's known about the records is loaded again
+    let mut config = config;
+    config.record_mut("example.com").ip = Some(IpAddr::from([192, 0, 2, 1]));
+    config.record_mut("www.example.com").record_id = Some(String::from("abc"));
+    config.lowered_ttls = vec![String::from("example.com")];
+    config.fallback_token = true;
+    config.health = vec![Health {
+        name: String::from("icanhazip.com"),
+        failures: 3,
+        open_until: Some(Utc::now()),
+    }];
+    config.webhook_url = Some(String::from("https://discord.com/api/webhooks/secret"));
+    config.save().unwrap();
+    let mut loaded = Config {
+        save_dir: dir.path().to_path_buf(),
+        ..Default::default()
+    };
+    loaded.load().unwrap();
+    assert_eq!(loaded.records, config.records);
+    assert_eq!(loaded.lowered_ttls, config.lowered_ttls);
+    assert!(loaded.fallback_token);
+    assert_eq!(loaded.health, config.health);
+    // The webhook is a setting, and is left out of the state
+    assert_eq!(loaded.webhook_url, None);
+    assert!(!fs::read_to_string(&file_path).unwrap().contains("webhook"));
+
+    // Test that the settings file is left alone, and the state doesn't say where it is
+    let settings = "# Mine\ndomain = [\"example.com\"]\n";
+    fs::write(dir.path().join(SETTINGS_FILE), settings).unwrap();
+    config.save().unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join(SETTINGS_FILE)).unwrap(),
+        settings
+    );
+    assert!(!fs::read_to_string(&file_path).unwrap().contains("save_dir"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&file_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o077, 0, "Expected only the owner to read it");
+    }
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
     // Test with a read-only file
     let file = fs::File::create(&file_path).unwrap();
     let metadata = file.metadata().unwrap();
@@ -201,3 +884,99 @@ fn test_save() {
         "Expected error when saving to read-only file, got {result:?}"
     );
 }
+
+#[test]
+fn test_clear() {
+    let ip = IpAddr::from([192, 0, 2, 1]);
+    let mut config = Config {
+        outside_ip: Some(ip),
+        webhook_url: Some(String::from("https://webhook.url")),
+        ..Default::default()
+    };
+    config.record_mut("example.com").ip = Some(ip);
+    config.record_mut("example.com").record_id = Some(String::from("abc"));
+
+    config.clear_ips();
+    assert_eq!(config.outside_ip, None);
+    assert_eq!(config.record("example.com").unwrap().ip, None);
+    assert!(config.record("example.com").unwrap().record_id.is_some());
+
+    config.clear_record_ids();
+    assert_eq!(config.record("example.com").unwrap().record_id, None);
+    assert!(config.webhook_url.is_some());
+}
+
+#[test]
+fn test_history() {
+    let mut config = Config::default();
+    for n in 0..=HISTORY_LIMIT {
+        config.record_change(IpChange {
+            at: Utc::now(),
+            old_ip: IpAddr::from([192, 0, 2, 1]),
+            new_ip: IpAddr::from([192, 0, 2, 2]),
+            source: format!("server {n}"),
+            updated: vec![String::from("example.com")],
+        });
+    }
+    assert_eq!(config.history.len(), HISTORY_LIMIT);
+    assert_eq!(config.history[0].source, "server 1");
+    assert!(config.history[0]
+        .to_string()
+        .ends_with("192.0.2.1 -> 192.0.2.2, detected by server 1, updated example.com"));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join(STATE_FILE);
+    fs::write(&file_path, "version = 2\noutside_ip = \"5.6.7.8\"\n").unwrap();
+    let mut config = Config {
+        save_dir: dir.path().to_path_buf(),
+        use_database: true,
+        ..Default::default()
+    };
+
+    // The state is moved from the file to the database on the first run
+    config.load().unwrap();
+    assert!(!file_path.exists());
+    assert!(dir.path().join("cdu.state.db").exists());
+
+    config.record_mut("example.com").ip = config.outside_ip;
+    config.save().unwrap();
+    let mut loaded = Config {
+        save_dir: dir.path().to_path_buf(),
+        use_database: true,
+        ..Default::default()
+    };
+    loaded.load().unwrap();
+    assert_eq!(loaded.outside_ip, Some(IpAddr::from([5, 6, 7, 8])));
+    assert_eq!(loaded.records, config.records);
+}
+
+#[test]
+fn test_encrypted_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join(STATE_FILE);
+    fs::write(&file_path, "version = 2\noutside_ip = \"5.6.7.8\"\n").unwrap();
+    let passphrase = || Some(crypt::Key::Passphrase("correct horse".to_string().into()));
+    let mut config = Config {
+        save_dir: dir.path().to_path_buf(),
+        encrypt_with: passphrase(),
+        ..Default::default()
+    };
+
+    // The state that isn't encrypted yet is encrypted on the first run
+    config.load().unwrap();
+    assert!(!file_path.exists());
+    let encrypted = fs::read(dir.path().join("cdu.state.toml.age")).unwrap();
+    assert!(!String::from_utf8_lossy(&encrypted).contains("5.6.7.8"));
+
+    let mut loaded = Config {
+        save_dir: dir.path().to_path_buf(),
+        encrypt_with: passphrase(),
+        ..Default::default()
+    };
+    loaded.load().unwrap();
+    assert_eq!(loaded.outside_ip, Some(IpAddr::from([5, 6, 7, 8])));
+}
@@ -0,0 +1,43 @@
+//! Asks before doing something that cannot be undone, like forgetting the state or replacing the
+//! binary, showing exactly what would change. Only someone at a terminal is asked: with `--yes`, or
+//! when stdin isn't a terminal, as in scripts and timers, it goes ahead without asking.
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Prints `changes` and asks whether to go ahead with them, unless `yes` is set, there's nobody to
+/// ask, or there's nothing to change.
+///
+/// # Errors
+///
+/// Returns an error if the answer isn't yes, or cannot be read.
+pub fn confirm(changes: &[String], yes: bool) -> anyhow::Result<()> {
+    if yes || changes.is_empty() || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    println!("This will:");
+    for change in changes {
+        println!("  {change}");
+    }
+    print!("Go ahead? [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    anyhow::ensure!(is_yes(&answer), "Cancelled, nothing was changed");
+
+    Ok(())
+}
+
+/// Returns whether `answer` is a yes. Anything else, including no answer, is a no.
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[test]
+fn test_is_yes() {
+    assert!(is_yes("y\n"));
+    assert!(is_yes(" Yes "));
+    assert!(!is_yes("\n"));
+    assert!(!is_yes("n"));
+    assert!(!is_yes("yep"));
+}
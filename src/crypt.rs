@@ -0,0 +1,152 @@
+//! Decrypts settings files encrypted with [age](https://age-encryption.org), like `cdu.toml.age`,
+//! and encrypts the state with `--encrypt-state`, so the API token isn't on a shared volume in
+//! plain text. The key is an identity file from `CDU_AGE_IDENTITY`, as made by `age-keygen`, or a
+//! passphrase in `CDU_AGE_PASSPHRASE`.
+//!
+//! They're read from the environment rather than the arguments, as the settings file is
+//! decrypted before the arguments are parsed.
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use age::secrecy::SecretString;
+use age::{scrypt, x25519};
+use anyhow::Context;
+
+/// The extension of encrypted files.
+pub const EXTENSION: &str = "age";
+
+/// The key to decrypt and encrypt with.
+pub enum Key {
+    Identity(x25519::Identity),
+    Passphrase(SecretString),
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identity(_) => write!(f, "Identity(********)"),
+            Self::Passphrase(_) => write!(f, "Passphrase(********)"),
+        }
+    }
+}
+
+impl Key {
+    /// Returns the key from `CDU_AGE_IDENTITY` or `CDU_AGE_PASSPHRASE`, in that order, or `None`
+    /// if neither is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the identity file cannot be read, or has no identity in it.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        if let Some(path) = env::var_os("CDU_AGE_IDENTITY") {
+            return read_identity(Path::new(&path)).map(|identity| Some(Self::Identity(identity)));
+        }
+
+        Ok(env::var("CDU_AGE_PASSPHRASE")
+            .ok()
+            .filter(|passphrase| !passphrase.is_empty())
+            .map(|passphrase| Self::Passphrase(SecretString::from(passphrase))))
+    }
+
+    /// Decrypts `ciphertext`, which can be armored as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it isn't encrypted to this key, or isn't an age file.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let plaintext = match self {
+            Self::Identity(identity) => age::decrypt(identity, ciphertext),
+            Self::Passphrase(passphrase) => {
+                age::decrypt(&scrypt::Identity::new(passphrase.clone()), ciphertext)
+            }
+        };
+
+        plaintext.context("Failed to decrypt, is it encrypted to this key?")
+    }
+
+    /// Encrypts `plaintext` to this key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encrypting fails.
+    pub fn encrypt(&self, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let ciphertext = match self {
+            Self::Identity(identity) => age::encrypt(&identity.to_public(), plaintext),
+            Self::Passphrase(passphrase) => {
+                age::encrypt(&scrypt::Recipient::new(passphrase.clone()), plaintext)
+            }
+        };
+
+        ciphertext.context("Failed to encrypt")
+    }
+}
+
+/// Returns whether the file at `path` is encrypted, going by its extension.
+#[must_use]
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == EXTENSION)
+}
+
+/// Reads the file at `path`, decrypting it with the key from the environment.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, there's no key, or it cannot be decrypted with it.
+pub fn read_to_string(path: &Path) -> anyhow::Result<String> {
+    let key = Key::from_env()?.with_context(|| {
+        format!(
+            "{} is encrypted, set CDU_AGE_IDENTITY or CDU_AGE_PASSPHRASE to decrypt it",
+            path.display()
+        )
+    })?;
+    let ciphertext =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let plaintext = key
+        .decrypt(&ciphertext)
+        .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+
+    String::from_utf8(plaintext).with_context(|| format!("{} isn't text", path.display()))
+}
+
+/// Reads the first identity in an identity file, skipping the comments.
+fn read_identity(path: &Path) -> anyhow::Result<x25519::Identity> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read identity file: {}", path.display()))?;
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .with_context(|| format!("There's no identity in {}", path.display()))?;
+
+    line.parse::<x25519::Identity>()
+        .map_err(|e| anyhow::anyhow!("Invalid identity in {}: {e}", path.display()))
+}
+
+#[test]
+fn test_encrypt() {
+    use age::secrecy::ExposeSecret;
+
+    let identity = x25519::Identity::generate();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("key.txt");
+    fs::write(
+        &path,
+        format!(
+            "# created: 2024-03-10T13:54:04Z\n{}\n",
+            identity.to_string().expose_secret()
+        ),
+    )
+    .unwrap();
+    let key = Key::Identity(read_identity(&path).unwrap());
+
+    let ciphertext = key.encrypt(b"api_key = \"secret\"").unwrap();
+    assert_eq!(key.decrypt(&ciphertext).unwrap(), b"api_key = \"secret\"");
+    assert!(Key::Identity(x25519::Identity::generate())
+        .decrypt(&ciphertext)
+        .is_err());
+    assert!(is_encrypted(Path::new("cdu.toml.age")));
+    assert!(!is_encrypted(Path::new("cdu.toml")));
+}
@@ -0,0 +1,58 @@
+//! The hashes and MACs that sign and check what cdu sends and downloads: the signatures of
+//! webhooks, AWS requests and TSIG records, and the checksums of releases.
+use std::fmt::Write as _;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Returns the HMAC-SHA256 of `parts`, one after the other, with `key`.
+///
+/// # Panics
+///
+/// Doesn't, as HMAC takes keys of any length.
+#[must_use]
+pub fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns the HMAC-SHA256 of `data` with `key`, in lowercase hex.
+#[must_use]
+pub fn hmac_sha256_hex(key: &[u8], data: &[u8]) -> String {
+    hex(&hmac_sha256(key, &[data]))
+}
+
+/// Returns the SHA-256 of `data`, in lowercase hex.
+#[must_use]
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Returns `bytes` in lowercase hex.
+#[must_use]
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[test]
+fn test_hmac_sha256_hex() {
+    // From RFC 4231
+    assert_eq!(
+        hmac_sha256_hex(b"Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    assert_eq!(
+        hmac_sha256(b"Jefe", &[b"what do ya ", b"want for nothing?"]),
+        hmac_sha256(b"Jefe", &[b"what do ya want for nothing?"])
+    );
+    assert_eq!(
+        sha256_hex(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
@@ -0,0 +1,751 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, Utc};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, error, info, warn};
+
+use crate::api;
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::hint;
+use crate::metrics;
+use crate::mqtt;
+use crate::status::SharedStatus;
+use crate::systemd;
+use crate::updater::Updater;
+
+/// How long to wait for the network or the settings file to settle after a change, before acting
+/// on it.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// How many times longer than the interval of an adaptive schedule the outside IP has to have
+/// stayed the same. A day after a change, that's every 14 minutes or so.
+const STABILITY_FACTOR: u32 = 100;
+
+/// Something that happened, which the daemon has to act upon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// An address or route changed, so the outside IP may have changed too.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    NetworkChange,
+    /// Somebody asked for a check right away.
+    ForceCheck,
+    /// The configuration has to be read again.
+    Reload,
+    /// The settings file changed, so the configuration has to be read again.
+    ConfigChange,
+    /// The daemon has to stop, after flushing its state.
+    Shutdown,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NetworkChange => write!(f, "the network changed"),
+            Self::ForceCheck => write!(f, "a check was requested"),
+            Self::Reload => write!(f, "a reload was requested"),
+            Self::ConfigChange => write!(f, "the settings file changed"),
+            Self::Shutdown => write!(f, "a shutdown was requested"),
+        }
+    }
+}
+
+/// How the daemon should run.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// When to run the check/update cycle.
+    pub schedule: Schedule,
+    /// The maximum random delay added to each scheduled cycle.
+    pub jitter: Duration,
+    /// Whether to check right away when an address or route changes.
+    pub watch_network: bool,
+    /// The settings file to reload the configuration from whenever it changes.
+    pub watch_config: Option<PathBuf>,
+    /// Where to serve the HTTP API, if at all.
+    pub api: Option<api::Options>,
+    /// Where to serve gRPC, if at all, with the token of the API.
+    #[cfg(feature = "grpc")]
+    pub grpc: Option<api::Options>,
+    /// How many scheduled checks in a row may fail, before the daemon stops being ready.
+    pub ready_checks: u32,
+    /// Where to publish the state to over MQTT, if at all.
+    pub mqtt: Option<mqtt::Options>,
+}
+
+impl Options {
+    /// Returns an error if the daemon cannot run with these options.
+    fn validate(&self) -> anyhow::Result<()> {
+        match &self.schedule {
+            Schedule::Interval(interval) | Schedule::Adaptive { min: interval, .. }
+                if interval.is_zero() =>
+            {
+                anyhow::bail!("The check interval must be greater than zero");
+            }
+            Schedule::Adaptive { min, max } if min > max => {
+                anyhow::bail!("The shortest check interval must not be longer than the longest");
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Describes how `other` differs from these options.
+    fn changes(&self, other: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        let (schedule, other_schedule) = (self.schedule.to_string(), other.schedule.to_string());
+        if schedule != other_schedule {
+            changes.push(format!("schedule: {schedule} -> {other_schedule}"));
+        }
+        if self.jitter != other.jitter {
+            changes.push(format!(
+                "jitter: {} -> {}",
+                humantime::format_duration(self.jitter),
+                humantime::format_duration(other.jitter)
+            ));
+        }
+        if self.watch_network != other.watch_network {
+            changes.push(format!(
+                "watch network: {} -> {}",
+                self.watch_network, other.watch_network
+            ));
+        }
+        if self.watch_config != other.watch_config {
+            let describe = |path: &Option<PathBuf>| {
+                path.as_ref()
+                    .map_or_else(|| String::from("off"), |path| path.display().to_string())
+            };
+            changes.push(format!(
+                "watch config: {} -> {}",
+                describe(&self.watch_config),
+                describe(&other.watch_config)
+            ));
+        }
+
+        match (&self.api, &other.api) {
+            (Some(api), Some(other_api)) => {
+                if api.listen != other_api.listen {
+                    changes.push(format!(
+                        "API listen: {} -> {}",
+                        api.listen, other_api.listen
+                    ));
+                }
+                if api.token != other_api.token {
+                    changes.push(String::from("API token"));
+                }
+            }
+            (None, Some(other_api)) => {
+                changes.push(format!("API listen: off -> {}", other_api.listen));
+            }
+            (Some(api), None) => changes.push(format!("API listen: {} -> off", api.listen)),
+            (None, None) => {}
+        }
+        #[cfg(feature = "grpc")]
+        {
+            let describe = |grpc: &Option<api::Options>| {
+                grpc.as_ref()
+                    .map_or_else(|| String::from("off"), |grpc| grpc.listen.to_string())
+            };
+            let (grpc, other_grpc) = (describe(&self.grpc), describe(&other.grpc));
+            if grpc != other_grpc {
+                changes.push(format!("gRPC listen: {grpc} -> {other_grpc}"));
+            }
+        }
+        if self.ready_checks != other.ready_checks {
+            changes.push(format!(
+                "ready checks: {} -> {}",
+                self.ready_checks, other.ready_checks
+            ));
+        }
+        match (&self.mqtt, &other.mqtt) {
+            (Some(mqtt), Some(other_mqtt)) => {
+                if mqtt.broker() != other_mqtt.broker() {
+                    changes.push(format!(
+                        "MQTT broker: {} -> {}",
+                        mqtt.broker(),
+                        other_mqtt.broker()
+                    ));
+                } else if mqtt.url != other_mqtt.url {
+                    changes.push(String::from("MQTT credentials"));
+                }
+                if mqtt.topic != other_mqtt.topic {
+                    changes.push(format!(
+                        "MQTT topic: {} -> {}",
+                        mqtt.topic, other_mqtt.topic
+                    ));
+                }
+                if mqtt.discovery_prefix != other_mqtt.discovery_prefix {
+                    changes.push(format!(
+                        "MQTT discovery prefix: {} -> {}",
+                        mqtt.discovery_prefix, other_mqtt.discovery_prefix
+                    ));
+                }
+            }
+            (None, Some(other_mqtt)) => {
+                changes.push(format!("MQTT broker: off -> {}", other_mqtt.broker()));
+            }
+            (Some(mqtt), None) => changes.push(format!("MQTT broker: {} -> off", mqtt.broker())),
+            (None, None) => {}
+        }
+
+        changes
+    }
+}
+
+/// When the daemon runs its check/update cycle.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// Run once every interval.
+    Interval(Duration),
+    /// Run whenever the cron expression fires, in local time.
+    Cron(Box<cron::Schedule>),
+    /// Run more often after the outside IP changed, as it tends to change again soon after, and
+    /// less often the longer it stays the same, once every [`STABILITY_FACTOR`]th of the time
+    /// since the last change, but never more often than every `min` or less than every `max`.
+    Adaptive { min: Duration, max: Duration },
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interval(interval) => {
+                write!(f, "every {}", humantime::format_duration(*interval))
+            }
+            Self::Cron(schedule) => write!(f, "on schedule \"{schedule}\""),
+            Self::Adaptive { min, max } => write!(
+                f,
+                "every {} to {}, depending on how long the outside IP stays the same",
+                humantime::format_duration(*min),
+                humantime::format_duration(*max)
+            ),
+        }
+    }
+}
+
+impl Schedule {
+    /// Returns the time between two scheduled cycles. For a cron expression, that's the time
+    /// between its next two runs, and for an adaptive schedule the longest it waits.
+    fn period(&self) -> Option<Duration> {
+        match self {
+            Self::Interval(interval) | Self::Adaptive { max: interval, .. } => Some(*interval),
+            Self::Cron(schedule) => {
+                let mut upcoming = schedule.upcoming(Local);
+                let first = upcoming.next()?;
+                let second = upcoming.next()?;
+                (second - first).to_std().ok()
+            }
+        }
+    }
+
+    /// Returns when the cycle after the one scheduled at `previous` should run, with the outside
+    /// IP last changed at `last_change`.
+    fn next_run(&self, previous: Instant, last_change: Option<DateTime<Utc>>) -> Option<Instant> {
+        let now = Instant::now();
+
+        match self {
+            // Keep the schedule steady, regardless of how long the cycle took, but don't try to
+            // catch up on cycles that were missed
+            Self::Interval(interval) => Some((previous + *interval).max(now)),
+            &Self::Adaptive { min, max } => {
+                Some((previous + adaptive_interval(min, max, last_change, Utc::now())).max(now))
+            }
+            Self::Cron(schedule) => {
+                let local_now = Local::now();
+                let next = schedule.after(&local_now).next()?;
+                Some(now + (next - local_now).to_std().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Returns the time until the next check of an adaptive schedule, at `now`. Without a change to
+/// go by, nothing suggests another one is coming, so that's `max`.
+fn adaptive_interval(
+    min: Duration,
+    max: Duration,
+    last_change: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Duration {
+    let Some(stable_for) = last_change.and_then(|at| (now - at).to_std().ok()) else {
+        return max;
+    };
+
+    (stable_for / STABILITY_FACTOR).clamp(min, max.max(min))
+}
+
+/// Parses the bounds of an adaptive schedule, like `1m..30m`.
+///
+/// # Errors
+///
+/// Returns an error if it isn't two durations with `..` between them.
+pub fn parse_bounds(bounds: &str) -> Result<(Duration, Duration), String> {
+    let Some((min, max)) = bounds.split_once("..") else {
+        return Err(format!(
+            "Expected <shortest>..<longest>, e.g. 1m..30m, got: {bounds}"
+        ));
+    };
+    let parse = |duration: &str| {
+        humantime::parse_duration(duration.trim()).map_err(|e| format!("{duration}: {e}"))
+    };
+
+    Ok((parse(min)?, parse(max)?))
+}
+
+/// Returns a random delay between zero and `max`, inclusive.
+fn random_jitter(max: Duration) -> Duration {
+    let max_millis = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    Duration::from_millis(fastrand::u64(..=max_millis))
+}
+
+/// Parses a cron expression.
+///
+/// Besides the six and seven field expressions with seconds (and years), the common five field
+/// form `minute hour day month weekday` is accepted, which runs at the start of the minute.
+///
+/// # Errors
+///
+/// Returns an error if the expression is invalid.
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+    let expression = expression.trim();
+    if expression.split_whitespace().count() == 5 {
+        cron::Schedule::from_str(&format!("0 {expression}"))
+    } else {
+        cron::Schedule::from_str(expression)
+    }
+}
+
+/// Runs the check/update cycle according to the schedule, until a shutdown is requested.
+///
+/// Every scheduled cycle, including the first one, is delayed by a random amount of time up to the
+/// jitter, so that daemons which were started at the same moment don't all hit the same services
+/// at once.
+///
+/// When watching the network, which is only supported on Linux, a cycle also runs as soon as an
+/// address or route changes. These cycles aren't delayed, and don't move the schedule.
+///
+/// On Unix, the daemon handles these signals:
+///
+/// - `SIGTERM` and `SIGINT` stop it, after the cycle in progress has finished and the
+///   configuration has been saved.
+/// - `SIGHUP` reloads the configuration, by building a new [`Updater`] and [`Options`] with
+///   `reload`. If that fails, the current ones are kept.
+/// - `SIGUSR1` runs a cycle right away.
+///
+/// When watching the settings file, the configuration is reloaded whenever it changes, just like
+/// with `SIGHUP`. Every setting that changed is logged, and a new schedule starts right away.
+///
+/// With the HTTP API enabled, the state of the daemon can be queried, and a cycle can be run right
+/// away. See [`api`] for the details. With the `grpc` feature, the same can be done over gRPC,
+/// which also streams every cycle as it finishes.
+///
+/// With an MQTT broker, the state is published after every cycle. See [`mqtt`] for the details.
+///
+/// When running as a systemd service with `Type=notify`, systemd is told when the daemon is ready,
+/// reloading or stopping, the status shows the outcome of the last cycle, and the watchdog is
+/// pinged if `WatchdogSec=` is set.
+///
+/// A failing cycle is logged and doesn't stop the daemon; the cycle is simply tried again at the
+/// next scheduled time.
+///
+/// # Errors
+///
+/// Returns an error if the interval is zero, if the cron expression never fires, if signals, the
+/// network or the settings file cannot be watched, if the API or gRPC cannot be served, or if the
+/// MQTT URL is invalid.
+pub async fn run(
+    updater: Updater,
+    options: Options,
+    reload: impl FnMut() -> anyhow::Result<(Updater, Options)>,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    run_with_events(updater, options, reload, sender, receiver).await
+}
+
+/// Like [`run`], but with events coming from elsewhere too, like the Windows service control
+/// manager. `sender` has to be the sending half of `receiver`'s channel.
+///
+/// # Errors
+///
+/// See [`run`].
+#[allow(clippy::too_many_lines)]
+pub async fn run_with_events(
+    mut updater: Updater,
+    mut options: Options,
+    mut reload: impl FnMut() -> anyhow::Result<(Updater, Options)>,
+    sender: UnboundedSender<Event>,
+    mut receiver: UnboundedReceiver<Event>,
+) -> anyhow::Result<()> {
+    options.validate()?;
+
+    #[cfg(unix)]
+    crate::signals::spawn(sender.clone())
+        .map_err(|e| anyhow::anyhow!("Failed to handle signals: {e}"))?;
+
+    if options.watch_network {
+        watch_network(&sender)?;
+    }
+    // The settings file is watched for as long as the watcher lives
+    let mut _config_watcher = options
+        .watch_config
+        .as_deref()
+        .map(|path| crate::watch::spawn(path, sender.clone()))
+        .transpose()?;
+
+    let status = SharedStatus::new(&updater);
+    if let Some((_, at)) = updater.last_change() {
+        metrics::record_last_change(at);
+    }
+    status.lock().ready_within = ready_within(&options);
+    if let Some(api) = &options.api {
+        api::spawn(api, status.clone(), sender.clone()).await?;
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = &options.grpc {
+        grpc::spawn(grpc, status.clone(), sender.clone()).await?;
+    }
+    let mqtt = options
+        .mqtt
+        .as_ref()
+        .map(|mqtt| mqtt::spawn(mqtt, status.clone()))
+        .transpose()?;
+
+    if options.jitter.is_zero() {
+        info!("Starting daemon, checking {}", options.schedule);
+    } else {
+        info!(
+            "Starting daemon, checking {}, with up to {} of jitter",
+            options.schedule,
+            humantime::format_duration(options.jitter)
+        );
+    }
+
+    let watchdog = systemd::watchdog_interval();
+    if let Some(interval) = watchdog {
+        debug!(
+            "Pinging the systemd watchdog every {}",
+            humantime::format_duration(interval)
+        );
+    }
+    systemd::ready();
+
+    let mut scheduled = Instant::now();
+    let mut deadline = scheduled + random_jitter(options.jitter);
+    let mut next_ping = Instant::now();
+    let mut pending = None;
+
+    record_next_check(deadline, &status);
+
+    loop {
+        // The pings come from this loop, so systemd notices when a cycle hangs
+        if let Some(interval) = watchdog {
+            if Instant::now() >= next_ping {
+                systemd::watchdog();
+                next_ping = Instant::now() + interval;
+            }
+        }
+
+        // No event means it's time to wake up
+        let event = if let Some(event) = pending.take() {
+            Some(event)
+        } else {
+            let wake_up = watchdog.map_or(deadline, |_| deadline.min(next_ping));
+
+            match tokio::time::timeout_at(wake_up.into(), receiver.recv()).await {
+                Ok(Some(event)) => Some(event),
+                Ok(None) => unreachable!("the daemon holds a sender itself"),
+                Err(_) => None,
+            }
+        };
+
+        match event {
+            Some(Event::Shutdown) => {
+                info!("Shutting down, because {}", Event::Shutdown);
+                systemd::stopping();
+                updater.save_config();
+
+                return Ok(());
+            }
+            Some(event @ (Event::Reload | Event::ConfigChange)) => {
+                if event == Event::ConfigChange {
+                    pending = settle(&mut receiver, event).await;
+                }
+                info!("Reloading configuration, because {event}");
+                systemd::reloading();
+
+                let reloaded = reload().and_then(|(reloaded, reloaded_options)| {
+                    reloaded_options.validate()?;
+                    Ok((reloaded, reloaded_options))
+                });
+                match reloaded {
+                    Ok((mut reloaded, reloaded_options)) => {
+                        let changes = updater
+                            .changes(&reloaded)
+                            .into_iter()
+                            .chain(options.changes(&reloaded_options))
+                            .collect::<Vec<_>>();
+                        if changes.is_empty() {
+                            info!("Configuration reloaded, nothing changed");
+                        }
+                        for change in &changes {
+                            info!("Configuration reloaded, changed {change}");
+                        }
+
+                        if reloaded_options.watch_network && !options.watch_network {
+                            if let Err(e) = watch_network(&sender) {
+                                error!("{e}");
+                            }
+                        } else if !reloaded_options.watch_network && options.watch_network {
+                            warn!("Restart the daemon to stop watching the network");
+                        }
+                        if reloaded_options.api != options.api {
+                            warn!("Restart the daemon to apply the changes to the API");
+                        }
+                        if reloaded_options.mqtt != options.mqtt {
+                            warn!("Restart the daemon to apply the changes to MQTT");
+                        }
+                        #[cfg(feature = "grpc")]
+                        if reloaded_options.grpc != options.grpc {
+                            warn!("Restart the daemon to apply the changes to gRPC");
+                        }
+                        if reloaded_options.watch_config != options.watch_config {
+                            _config_watcher = None;
+                            if let Some(path) = &reloaded_options.watch_config {
+                                match crate::watch::spawn(path, sender.clone()) {
+                                    Ok(watcher) => _config_watcher = Some(watcher),
+                                    Err(e) => error!("{e:#}"),
+                                }
+                            }
+                        }
+
+                        let reschedule = reloaded_options.schedule.to_string()
+                            != options.schedule.to_string()
+                            || reloaded_options.jitter != options.jitter;
+
+                        status.lock().configure(&reloaded);
+                        status.lock().ready_within = ready_within(&reloaded_options);
+                        // What the daemon found out while it ran, like the servers that keep
+                        // failing, isn't part of the configuration
+                        reloaded.adopt_runtime(&updater);
+                        updater = reloaded;
+                        // The API, gRPC and MQTT keep running as they were
+                        options = Options {
+                            api: options.api.take(),
+                            #[cfg(feature = "grpc")]
+                            grpc: options.grpc.take(),
+                            mqtt: options.mqtt.take(),
+                            ..reloaded_options
+                        };
+
+                        if reschedule {
+                            let Some(next) = options
+                                .schedule
+                                .next_run(Instant::now(), last_change_at(&updater))
+                            else {
+                                anyhow::bail!("The schedule has no upcoming runs");
+                            };
+                            scheduled = next;
+                            deadline = scheduled + random_jitter(options.jitter);
+                            record_next_check(deadline, &status);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to reload configuration, keeping the current one: {e}");
+                    }
+                }
+                systemd::ready();
+            }
+            Some(event @ (Event::ForceCheck | Event::NetworkChange)) => {
+                if event == Event::NetworkChange {
+                    pending = settle(&mut receiver, event).await;
+                }
+                info!("Checking right away, because {event}");
+                run_cycle(&mut updater, &status, mqtt.as_ref()).await;
+
+                // After a change, an adaptive schedule checks sooner than it was going to
+                if let Schedule::Adaptive { min, max } = options.schedule {
+                    let interval =
+                        adaptive_interval(min, max, last_change_at(&updater), Utc::now());
+                    if Instant::now() + interval < scheduled {
+                        scheduled = Instant::now() + interval;
+                        deadline = scheduled + random_jitter(options.jitter);
+                        record_next_check(deadline, &status);
+                    }
+                }
+            }
+            None => {
+                // Woken up to ping the watchdog
+                if Instant::now() < deadline {
+                    continue;
+                }
+
+                run_cycle(&mut updater, &status, mqtt.as_ref()).await;
+
+                let Some(next) = options
+                    .schedule
+                    .next_run(scheduled, last_change_at(&updater))
+                else {
+                    anyhow::bail!("The schedule has no upcoming runs");
+                };
+                scheduled = next;
+                deadline = scheduled + random_jitter(options.jitter);
+                record_next_check(deadline, &status);
+            }
+        }
+    }
+}
+
+/// Starts watching for network changes, which is only supported on Linux.
+fn watch_network(sender: &UnboundedSender<Event>) -> anyhow::Result<()> {
+    #[cfg(target_os = "linux")]
+    crate::netlink::spawn(sender.clone())
+        .map_err(|e| anyhow::anyhow!("Failed to watch for network changes: {e}"))?;
+    #[cfg(all(target_os = "linux", feature = "networkmanager"))]
+    {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            // Not every system runs NetworkManager, and the kernel still tells about the rest
+            if let Err(e) = crate::networkmanager::spawn(sender).await {
+                debug!("Not watching NetworkManager: {e}");
+            }
+        });
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = sender;
+        warn!("Watching for network changes is only supported on Linux");
+    }
+
+    Ok(())
+}
+
+async fn run_cycle(updater: &mut Updater, status: &SharedStatus, mqtt: Option<&mqtt::Publisher>) {
+    let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+
+    let result = updater.run().await;
+    match &result {
+        Ok(outcome) => {
+            info!("Cycle finished: {outcome}");
+            systemd::status(&format!("Last check at {now}: {outcome}"));
+        }
+        Err(e) => {
+            error!("Cycle failed: {e}");
+            if let Some(hint) = hint::of(e) {
+                info!("Hint: {hint}");
+            }
+            systemd::status(&format!("Last check at {now} failed: {e}"));
+        }
+    }
+    status.record(&result);
+    status.lock().update_records(updater.records());
+    metrics::record_check(&result);
+    if let Some(mqtt) = mqtt {
+        mqtt.publish(&status.lock());
+    }
+}
+
+/// Returns when the A records were last changed to a new outside IP, if ever.
+fn last_change_at(updater: &Updater) -> Option<DateTime<Utc>> {
+    updater.last_change().map(|(_, at)| at)
+}
+
+/// Returns how long ago the last successful cycle may be, for the daemon to be ready. Jitter and
+/// the cycle itself take time too, so that's allowed for on top of the scheduled checks.
+fn ready_within(options: &Options) -> Option<chrono::Duration> {
+    let period = options.schedule.period()?;
+    let within = period * options.ready_checks.max(1) + options.jitter + Duration::from_secs(60);
+
+    chrono::Duration::from_std(within).ok()
+}
+
+fn record_next_check(deadline: Instant, status: &SharedStatus) {
+    let delay = deadline.saturating_duration_since(Instant::now());
+    debug!(
+        "Next check in {}",
+        humantime::format_duration(Duration::from_secs(delay.as_secs()))
+    );
+    status.lock().next_check_at = chrono::Duration::from_std(delay)
+        .ok()
+        .map(|delay| Utc::now() + delay);
+}
+
+/// Waits until no more events of the same kind arrived for a little while. A reconnect usually
+/// causes a burst of network changes, and the address isn't routable right away. Saving a file
+/// often takes a few steps too.
+///
+/// Returns any other event that arrived in the meantime, so it isn't lost.
+async fn settle(receiver: &mut UnboundedReceiver<Event>, kind: Event) -> Option<Event> {
+    loop {
+        match tokio::time::timeout(SETTLE_TIME, receiver.recv()).await {
+            Ok(Some(event)) if event == kind => {}
+            Ok(Some(event)) => return Some(event),
+            Ok(None) | Err(_) => return None,
+        }
+    }
+}
+
+#[test]
+fn test_parse_cron() {
+    // Five fields, as used by crontab
+    let schedule = parse_cron("*/5 * * * *").unwrap();
+    let now = Local::now();
+    let mut upcoming = schedule.after(&now);
+    let first = upcoming.next().unwrap();
+    let second = upcoming.next().unwrap();
+    assert_eq!((second - first).num_minutes(), 5);
+
+    // Six fields, with seconds
+    assert!(parse_cron("30 0 * * * *").is_ok());
+
+    // Garbage
+    assert!(parse_cron("every now and then").is_err());
+}
+
+#[test]
+fn test_random_jitter() {
+    assert_eq!(random_jitter(Duration::ZERO), Duration::ZERO);
+
+    let max = Duration::from_secs(30);
+    for _ in 0..100 {
+        assert!(random_jitter(max) <= max);
+    }
+}
+
+#[test]
+fn test_adaptive_interval() {
+    let (min, max) = parse_bounds("1m..30m").unwrap();
+    assert_eq!(min, Duration::from_secs(60));
+    assert_eq!(max, Duration::from_secs(30 * 60));
+    assert!(parse_bounds("1m").is_err());
+    assert!(parse_bounds("1m..soon").is_err());
+
+    let now = Utc::now();
+    let ago = |hours| Some(now - chrono::Duration::hours(hours));
+    // Right after a change, and when nothing is known
+    assert_eq!(adaptive_interval(min, max, ago(1), now), min);
+    assert_eq!(adaptive_interval(min, max, None, now), max);
+    // A day later, a hundredth of it
+    assert_eq!(
+        adaptive_interval(min, max, ago(24), now),
+        Duration::from_secs(864)
+    );
+    // A week later
+    assert_eq!(adaptive_interval(min, max, ago(7 * 24), now), max);
+
+    let options = Options {
+        schedule: Schedule::Adaptive { min: max, max: min },
+        jitter: Duration::ZERO,
+        watch_network: false,
+        watch_config: None,
+        api: None,
+        #[cfg(feature = "grpc")]
+        grpc: None,
+        ready_checks: 3,
+        mqtt: None,
+    };
+    assert!(options.validate().is_err());
+}
@@ -0,0 +1,110 @@
+//! Passes the outside IP on to [DNS-O-Matic](https://dnsomatic.com), which updates every service
+//! linked to the account with it, for the dynamic DNS hostnames that are kept elsewhere, next to
+//! the A records at Cloudflare.
+//!
+//! It's told with the update protocol of DynDNS, and answers a line per service, starting with
+//! `good` or `nochg` for the ones that took it.
+use std::fmt;
+use std::net::IpAddr;
+
+use anyhow::Context;
+use reqwest::Client as RqClient;
+
+/// Where the updates go.
+pub const URL: &str = "https://updates.dnsomatic.com/nic/update";
+
+/// The hostname that stands for every service linked to the account.
+pub const ALL: &str = "all.dnsomatic.com";
+
+/// An account of DNS-O-Matic, and what to update in it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Account {
+    pub username: String,
+    pub password: String,
+    /// A hostname of one of the services, or [`ALL`] for every one of them.
+    pub hostname: String,
+    pub url: String,
+}
+
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("username", &self.username)
+            .field("hostname", &self.hostname)
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Account {
+    /// Parses the credentials, as `<username>:<password>`, for every service of the account.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no `:` between them.
+    pub fn parse(credentials: &str) -> Result<Self, String> {
+        let (username, password) = credentials
+            .split_once(':')
+            .filter(|(username, password)| !username.is_empty() && !password.is_empty())
+            .ok_or_else(|| String::from("Expected <username>:<password>"))?;
+
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            hostname: ALL.to_string(),
+            url: URL.to_string(),
+        })
+    }
+
+    /// Tells DNS-O-Matic the outside IP is `ip`, through `client`. Wildcards and MX records are
+    /// left as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if DNS-O-Matic cannot be reached, or any of its answers isn't `good` or
+    /// `nochg`, like `badauth` for a wrong password.
+    pub async fn update(&self, client: &RqClient, ip: IpAddr) -> anyhow::Result<()> {
+        let response = client
+            .get(&self.url)
+            .basic_auth(&self.username, Some(&self.password))
+            .query(&[
+                ("hostname", self.hostname.as_str()),
+                ("myip", &ip.to_string()),
+                ("wildcard", "NOCHG"),
+                ("mx", "NOCHG"),
+                ("backmx", "NOCHG"),
+            ])
+            .send()
+            .await
+            .context("Failed to reach DNS-O-Matic")?;
+
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        anyhow::ensure!(
+            status.is_success() && !body.trim().is_empty(),
+            "DNS-O-Matic answered with status {status}: {}",
+            body.trim()
+        );
+        for answer in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let code = answer.split_whitespace().next().unwrap_or_default();
+            anyhow::ensure!(
+                matches!(code, "good" | "nochg"),
+                "DNS-O-Matic answered {answer}"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse() {
+    let account = Account::parse("me@example.com:pass:word").unwrap();
+    assert_eq!(account.username, "me@example.com");
+    assert_eq!(account.password, "pass:word");
+    assert_eq!(account.hostname, ALL);
+    assert!(!format!("{account:?}").contains("pass:word"));
+
+    assert!(Account::parse("me@example.com").is_err());
+    assert!(Account::parse("me@example.com:").is_err());
+}
@@ -0,0 +1,132 @@
+//! Finds out why cdu doesn't work on a machine, going through what a run needs in the order it
+//! needs it: DNS, the servers that tell the outside IP, the token, the zones and A records, the
+//! state directory and the notification targets. Every step is run, with a hint for each one that
+//! failed.
+//!
+//! Unlike `cdu config validate`, a test message is sent to every target, to see that it arrives.
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::network::{self, SERVERS};
+use crate::notify::Message;
+use crate::validate::{self, Report, Settings};
+
+/// How long to wait for a server or a notification target to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The host that's looked up to see that DNS works.
+const CLOUDFLARE_API_HOST: &str = "api.cloudflare.com";
+
+/// Runs every step, printing how each went. `state` is the configuration the state is kept with,
+/// or why it couldn't be made.
+///
+/// # Errors
+///
+/// Returns an error if any step failed.
+pub async fn run(settings: &Settings, state: anyhow::Result<Config>) -> anyhow::Result<()> {
+    let mut report = Report::default();
+
+    match tokio::net::lookup_host((CLOUDFLARE_API_HOST, 443)).await {
+        Ok(mut addresses) => match addresses.next() {
+            Some(address) => report.ok(&format!("DNS, {CLOUDFLARE_API_HOST} is {}", address.ip())),
+            None => report.failed(
+                "DNS",
+                format!("{CLOUDFLARE_API_HOST} has no addresses, check the DNS server in use"),
+            ),
+        },
+        Err(e) => report.failed(
+            "DNS",
+            format!(
+                "cannot look up {CLOUDFLARE_API_HOST}: {e}, check the DNS server in \
+                 /etc/resolv.conf or of the network"
+            ),
+        ),
+    }
+
+    let client = settings.transport.client(TIMEOUT);
+    let detection_client = settings.transport.detection_client(TIMEOUT);
+    let mut answered = 0;
+    for server in SERVERS {
+        match network::ask(&detection_client, server, TIMEOUT).await {
+            Ok(ip) => {
+                report.ok(&format!("Outside IP is {ip}, from {server}"));
+                answered += 1;
+            }
+            Err(e) => report.warning(
+                "Outside IP",
+                format!("{e:#}, cdu asks the next server when this one doesn't answer"),
+            ),
+        }
+    }
+    if answered == 0 {
+        report.failed(
+            "Outside IP",
+            "none of the servers answered, check that this machine can reach the internet",
+        );
+    }
+
+    if let Some(api_key) = validate::check_required(&mut report, settings) {
+        validate::check_cloudflare(&mut report, settings, api_key).await?;
+    }
+
+    match state {
+        Ok(config) if config.stateless => report.ok("State isn't kept, with --no-state"),
+        Ok(config) => check_writable(&mut report, &config.save_dir),
+        Err(e) => report.failed("State directory", format!("{e:#}")),
+    }
+
+    let domain = settings.domains.join(", ");
+    let message = Message::test(if domain.is_empty() {
+        "example.com"
+    } else {
+        &domain
+    });
+    for (index, target) in settings.targets.iter().enumerate() {
+        let check = format!("Notification target {}, {}", index + 1, target.kind());
+        let host = target.address().map(|(host, _)| host);
+        let target_client = settings
+            .transport
+            .client_for(&client, host.as_deref(), TIMEOUT);
+        match target.notifier(&target_client).send(&message).await {
+            Ok(()) => report.ok(&format!("{check}, sent a test message")),
+            Err(e) => report.failed(
+                &check,
+                format!("{e:#}, check its URL and credentials, and try again with cdu notify"),
+            ),
+        }
+    }
+
+    report.finish("this setup")
+}
+
+/// Checks that a file can be written in the state directory, and removes it again.
+fn check_writable(report: &mut Report, state_dir: &Path) {
+    let probe = state_dir.join(".cdu-doctor");
+    match fs::write(&probe, b"").and_then(|()| fs::remove_file(&probe)) {
+        Ok(()) => report.ok(&format!(
+            "State directory {} is writable",
+            state_dir.display()
+        )),
+        Err(e) => report.failed(
+            &format!("State directory {}", state_dir.display()),
+            format!(
+                "cannot write to it: {e}, check that it exists and the user cdu runs as owns it"
+            ),
+        ),
+    }
+}
+
+#[test]
+fn test_check_writable() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut report = Report::default();
+    check_writable(&mut report, dir.path());
+    assert!(report.finish("the state").is_ok());
+    assert!(!dir.path().join(".cdu-doctor").exists());
+
+    check_writable(&mut report, &dir.path().join("missing"));
+    assert!(report.finish("the state").is_err());
+}
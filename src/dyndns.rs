@@ -0,0 +1,216 @@
+//! Serves the update protocol of DynDNS2 with `cdu serve-dyndns`, so a router that only knows how
+//! to update a provider like dyn.com can point the A records at Cloudflare through cdu.
+//!
+//! The router is set up with `http://<host>:8245/nic/update` as the URL of a custom provider, and
+//! sends the zone, by its ID or its name, as the username and the API token as the password, in
+//! an `Authorization: Basic` header. `hostname` has the domains to update, separated by commas,
+//! and `myip` the IP, or the address the request came from if it's left out. Each domain gets a
+//! line of the answer, with the same words as for DSM, see [`synology`]: `good` or `nochg` with the
+//! IP, `badauth`, `nohost`, `badparam` or `911`.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use reqwest::Client as RqClient;
+use tokio::net::TcpListener;
+use tracing::info;
+
+use crate::{redact, synology};
+
+/// The port of DynDNS2 that routers know.
+pub const DEFAULT_LISTEN: &str = "0.0.0.0:8245";
+
+struct ServerState {
+    api_url: String,
+    client: RqClient,
+}
+
+/// Answers the updates sent to `listen` until cdu is interrupted, sending the requests to the
+/// Cloudflare API at `api_url` through `client`.
+///
+/// # Errors
+///
+/// Returns an error if cdu cannot listen on `listen`.
+pub async fn serve(listen: SocketAddr, api_url: &str, client: RqClient) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {listen}"))?;
+    info!("Answering DynDNS2 updates at http://{listen}/nic/update");
+
+    axum::serve(
+        listener,
+        router(api_url, client).into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await
+    .context("Stopped answering DynDNS2 updates")
+}
+
+fn router(api_url: &str, client: RqClient) -> Router {
+    let state = Arc::new(ServerState {
+        api_url: api_url.to_string(),
+        client,
+    });
+
+    Router::new()
+        .route("/nic/update", get(update))
+        .with_state(state)
+}
+
+async fn update(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let Some((username, password)) = credentials(&headers) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, "Basic realm=\"cdu\"")],
+            "badauth",
+        )
+            .into_response();
+    };
+    let Some(hostnames) = params
+        .get("hostname")
+        .filter(|hostnames| !hostnames.is_empty())
+    else {
+        return "notfqdn".into_response();
+    };
+    let ip = params
+        .get("myip")
+        .cloned()
+        .unwrap_or_else(|| peer.ip().to_canonical().to_string());
+
+    let mut answers = Vec::new();
+    for hostname in hostnames.split(',').map(str::trim) {
+        let response = synology::run(
+            &username,
+            &password,
+            hostname,
+            &ip,
+            &state.api_url,
+            state.client.clone(),
+        )
+        .await;
+        // Only a token Cloudflare accepted is masked in the logs, or anyone who can reach cdu could
+        // have words of their choosing masked, and the secrets to mask pile up
+        if matches!(
+            response,
+            synology::Response::Good(_) | synology::Response::NoChange(_)
+        ) {
+            redact::add(&password);
+        }
+        info!(
+            "{} asked to point {hostname} at {ip}: {response}",
+            peer.ip()
+        );
+        answers.push(response.to_string());
+    }
+
+    answers.join("\n").into_response()
+}
+
+/// Returns the username and the password of an `Authorization: Basic` header, if there's one.
+fn credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64_STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
+
+#[tokio::test]
+async fn test_update() {
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+    async fn answer(client: &RqClient, url: &str, query: &[(&str, &str)]) -> String {
+        let response = client
+            .get(url)
+            .basic_auth(ZONE_ID, Some("token"))
+            .query(query)
+            .send()
+            .await
+            .unwrap();
+        response.text().await.unwrap()
+    }
+
+    let cloudflare = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+        .and(query_param("name", "home.example.com"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": [{
+                "id": "record-1",
+                "type": "A",
+                "name": "home.example.com",
+                "content": "192.0.2.1",
+            }],
+        })))
+        .mount(&cloudflare)
+        .await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listen = listener.local_addr().unwrap();
+    let app = router(&cloudflare.uri(), RqClient::new());
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    let client = RqClient::new();
+    let url = format!("http://{listen}/nic/update");
+    let response = client
+        .get(&url)
+        .query(&[("hostname", "home.example.com")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    assert_eq!(
+        answer(
+            &client,
+            &url,
+            &[("hostname", "home.example.com"), ("myip", "192.0.2.1")]
+        )
+        .await,
+        "nochg 192.0.2.1"
+    );
+    assert_eq!(
+        answer(&client, &url, &[("myip", "192.0.2.1")]).await,
+        "notfqdn"
+    );
+    assert_eq!(
+        answer(
+            &client,
+            &url,
+            &[("hostname", "home.example.com"), ("myip", "2001:db8::1")]
+        )
+        .await,
+        "badparam"
+    );
+}
@@ -0,0 +1,200 @@
+//! Loads the settings from an environment file, a settings file, or a directory of secrets, and
+//! reloads them when asked.
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::settings;
+
+/// Reads the variables from a file.
+type Read = fn(&Path) -> anyhow::Result<Vec<(String, String)>>;
+
+/// An environment file whose variables have been loaded into the environment of the process.
+///
+/// Variables that were already set before the file was loaded win over the file, both when loading
+/// and when reloading it, as they can't have changed in the meantime.
+#[derive(Debug)]
+pub struct EnvFile {
+    path: PathBuf,
+    /// The variables that come from the file, and can be changed or removed by reloading it.
+    loaded: HashSet<String>,
+    /// Reads the variables from the file.
+    read: Read,
+}
+
+impl EnvFile {
+    /// Finds the `.env` file in the current directory or one of its parents, and loads it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists, but cannot be read or parsed.
+    pub fn find() -> anyhow::Result<Option<Self>> {
+        let current_dir = env::current_dir().context("Failed to get the current directory")?;
+
+        current_dir
+            .ancestors()
+            .map(|dir| dir.join(".env"))
+            .find(|path| path.is_file())
+            .map(|path| Self::load(&path))
+            .transpose()
+    }
+
+    /// Loads the environment file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Self::load_with(path, read_env_file)
+    }
+
+    /// Loads the settings file at `path`, as read by [`settings::read`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed, or has a setting cdu doesn't know.
+    pub fn load_settings(path: &Path) -> anyhow::Result<Self> {
+        Self::load_with(path, settings::read)
+    }
+
+    /// Loads the directory of secrets at `path`, like a mounted Kubernetes secret, which has a file
+    /// per setting named like its variable without `CDU_`, like `api_key` for `CDU_API_KEY`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory, or a file of a setting in it, cannot be read.
+    pub fn load_secrets(path: &Path) -> anyhow::Result<Self> {
+        Self::load_with(path, read_secrets_dir)
+    }
+
+    fn load_with(path: &Path, read: Read) -> anyhow::Result<Self> {
+        let mut env_file = Self {
+            path: path.to_path_buf(),
+            loaded: HashSet::new(),
+            read,
+        };
+        env_file.reload()?;
+
+        Ok(env_file)
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the file again, so changed variables are updated and removed ones are unset.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed, in which case the environment is left
+    /// alone.
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        let variables = (self.read)(&self.path)?;
+
+        let mut loaded = HashSet::new();
+        for (key, value) in variables {
+            if self.loaded.contains(&key) || env::var_os(&key).is_none() {
+                env::set_var(&key, value);
+                loaded.insert(key);
+            }
+        }
+        for key in self.loaded.difference(&loaded) {
+            env::remove_var(key);
+        }
+        self.loaded = loaded;
+
+        Ok(())
+    }
+}
+
+fn read_env_file(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    dotenvy::from_path_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Failed to load settings from: {}", path.display()))
+}
+
+/// Reads the file of every setting that has one in the directory at `path`. Files of anything
+/// else, like the ones Kubernetes keeps its own links in, are left alone.
+fn read_secrets_dir(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let cli = crate::args::cli();
+    let daemon_args = crate::args::daemon_args();
+    let mut variables = Vec::new();
+    for name in cli
+        .get_arguments()
+        .chain(&daemon_args)
+        .filter_map(|arg| arg.get_env()?.to_str())
+    {
+        let Some(file_name) = name.strip_prefix("CDU_") else {
+            continue;
+        };
+        let file = path.join(file_name.to_ascii_lowercase());
+        match fs::read_to_string(&file) {
+            // The newline a file usually ends with isn't part of the secret
+            Ok(value) => variables.push((name.to_string(), value.trim_end().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read secret: {}", file.display()))
+            }
+        }
+    }
+    if variables.is_empty() && !path.is_dir() {
+        anyhow::bail!("The secrets directory doesn't exist: {}", path.display());
+    }
+
+    Ok(variables)
+}
+
+#[test]
+fn test_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+    env::set_var("CDU_TEST_ENV_FILE_INHERITED", "inherited");
+    fs::write(
+        &path,
+        "CDU_TEST_ENV_FILE_KEPT=one\nCDU_TEST_ENV_FILE_REMOVED=two\nCDU_TEST_ENV_FILE_INHERITED=file\n",
+    )
+    .unwrap();
+
+    let mut env_file = EnvFile::load(&path).unwrap();
+    assert_eq!(env::var("CDU_TEST_ENV_FILE_KEPT").unwrap(), "one");
+    assert_eq!(env::var("CDU_TEST_ENV_FILE_REMOVED").unwrap(), "two");
+    assert_eq!(
+        env::var("CDU_TEST_ENV_FILE_INHERITED").unwrap(),
+        "inherited"
+    );
+
+    fs::write(
+        &path,
+        "CDU_TEST_ENV_FILE_KEPT=three\nCDU_TEST_ENV_FILE_INHERITED=file\n",
+    )
+    .unwrap();
+    env_file.reload().unwrap();
+    assert_eq!(env::var("CDU_TEST_ENV_FILE_KEPT").unwrap(), "three");
+    assert!(env::var_os("CDU_TEST_ENV_FILE_REMOVED").is_none());
+    assert_eq!(
+        env::var("CDU_TEST_ENV_FILE_INHERITED").unwrap(),
+        "inherited"
+    );
+
+    // A broken file leaves everything as it was
+    fs::write(&path, "CDU_TEST_ENV_FILE_KEPT='unterminated\n").unwrap();
+    assert!(env_file.reload().is_err());
+    assert_eq!(env::var("CDU_TEST_ENV_FILE_KEPT").unwrap(), "three");
+}
+
+#[test]
+fn test_load_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("webhook_secret"), "s3cret\n").unwrap();
+    fs::write(dir.path().join("..data"), "").unwrap();
+
+    let _secrets = EnvFile::load_secrets(dir.path()).unwrap();
+    assert_eq!(env::var("CDU_WEBHOOK_SECRET").unwrap(), "s3cret");
+
+    assert!(EnvFile::load_secrets(&dir.path().join("missing")).is_err());
+}
@@ -0,0 +1,200 @@
+//! Writes the important events to the Windows Event Log, under the `cdu` source of the Application
+//! log, which is where Windows admins look first.
+//!
+//! Only the warnings, the errors and the events with an `event` field, like an A record that was
+//! updated or an outside IP that changed, are written there. Everything else stays in the usual
+//! logs. The source is registered by `cdu service install`, with the messages of `EventCreate.exe`,
+//! which shows the text as it is.
+use std::ffi::OsStr;
+use std::fmt::{self, Write as _};
+use std::os::windows::ffi::OsStrExt;
+use std::process::Command;
+
+use anyhow::Context;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context as LayerContext, Layer};
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+    EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+};
+
+use crate::redact;
+
+/// The source the events are written under.
+const SOURCE: &str = "cdu";
+
+/// The key the source is registered under.
+const SOURCE_KEY: &str = r"HKLM\SYSTEM\CurrentControlSet\Services\EventLog\Application\cdu";
+
+/// The IDs of the events, which `EventCreate.exe` has a message for that's only the text.
+const INFORMATION_ID: u32 = 1;
+const WARNING_ID: u32 = 2;
+const ERROR_ID: u32 = 3;
+
+/// The layer that writes the important events to the Event Log.
+pub struct EventLog {
+    handle: HANDLE,
+}
+
+impl EventLog {
+    /// Opens the `cdu` source of the Event Log.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Event Log cannot be opened.
+    pub fn open() -> anyhow::Result<Self> {
+        let source = wide(OsStr::new(SOURCE));
+        // SAFETY: the name is a string that ends in a zero, which lives until the call returns
+        let handle = unsafe { RegisterEventSourceW(std::ptr::null(), source.as_ptr()) };
+        if handle == 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to open the Event Log");
+        }
+
+        Ok(Self { handle })
+    }
+
+    fn report(&self, level: Level, message: &str) {
+        let (kind, id) = match level {
+            Level::ERROR => (EVENTLOG_ERROR_TYPE, ERROR_ID),
+            Level::WARN => (EVENTLOG_WARNING_TYPE, WARNING_ID),
+            _ => (EVENTLOG_INFORMATION_TYPE, INFORMATION_ID),
+        };
+        let message = wide(OsStr::new(redact::text(message).as_ref()));
+        let strings = [message.as_ptr()];
+        // SAFETY: the handle is open until this is dropped, and the one string ends in a zero and
+        // lives until the call returns. Failing to write to the Event Log isn't worth a word.
+        unsafe {
+            ReportEventW(
+                self.handle,
+                kind,
+                0,
+                id,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        // SAFETY: the handle was opened by `open`, and isn't used after this
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let level = *event.metadata().level();
+        let mut visitor = Fields::default();
+        event.record(&mut visitor);
+        if level > Level::WARN && !visitor.has_event {
+            return;
+        }
+
+        self.report(level, &visitor.text);
+    }
+}
+
+/// Puts the message of an event first, followed by its other fields, a line each.
+#[derive(Default)]
+struct Fields {
+    text: String,
+    has_event: bool,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.text.insert_str(0, &format!("{value:?}"));
+            return;
+        }
+        if field.name() == "event" {
+            self.has_event = true;
+        }
+        let _ = write!(self.text, "\n{}: {value:?}", field.name());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.text.insert_str(0, value);
+            return;
+        }
+        if field.name() == "event" {
+            self.has_event = true;
+        }
+        let _ = write!(self.text, "\n{}: {value}", field.name());
+    }
+}
+
+/// Registers the `cdu` source, so Event Viewer shows the text of its events.
+///
+/// # Errors
+///
+/// Returns an error if the source cannot be registered, which needs an administrator.
+pub fn register() -> anyhow::Result<()> {
+    reg(&[
+        "add",
+        SOURCE_KEY,
+        "/v",
+        "EventMessageFile",
+        "/t",
+        "REG_EXPAND_SZ",
+        "/d",
+        r"%SystemRoot%\System32\EventCreate.exe",
+        "/f",
+    ])?;
+    reg(&[
+        "add",
+        SOURCE_KEY,
+        "/v",
+        "TypesSupported",
+        "/t",
+        "REG_DWORD",
+        "/d",
+        "7",
+        "/f",
+    ])
+}
+
+/// Removes the `cdu` source. The events that were written are kept.
+///
+/// # Errors
+///
+/// Returns an error if the source cannot be removed.
+pub fn unregister() -> anyhow::Result<()> {
+    reg(&["delete", SOURCE_KEY, "/f"])
+}
+
+fn reg(args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new("reg")
+        .args(args)
+        .output()
+        .context("Failed to run reg")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to change the Event Log source: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns `text` as a string for Windows, which ends in a zero.
+fn wide(text: &OsStr) -> Vec<u16> {
+    text.encode_wide().chain([0]).collect()
+}
+
+#[test]
+fn test_wide() {
+    assert_eq!(wide(OsStr::new("cdu")), [99, 100, 117, 0]);
+    assert_eq!(wide(OsStr::new("")), [0]);
+}
@@ -0,0 +1,102 @@
+//! The codes cdu exits with, so a script that runs it can tell whether there was nothing to do, an
+//! A record was updated or is out of step, or what went wrong.
+use std::error::Error;
+use std::fmt;
+
+/// Nothing needed to change.
+pub const UNCHANGED: i32 = 0;
+/// Anything that went wrong that doesn't have a code of its own.
+pub const FAILED: i32 = 1;
+/// An A record was updated.
+pub const UPDATED: i32 = 2;
+/// None of the servers told the outside IP.
+pub const DETECTION_FAILED: i32 = 3;
+/// Cloudflare couldn't be asked, or didn't take the update.
+pub const CLOUDFLARE_FAILED: i32 = 4;
+/// A setting is missing or isn't valid.
+pub const CONFIG_INVALID: i32 = 5;
+/// `cdu check` found an A record that doesn't point at the outside IP.
+pub const DRIFT: i32 = 6;
+
+/// Every code, with what it means.
+pub const ALL: [(i32, &str); 7] = [
+    (UNCHANGED, "Nothing needed to change"),
+    (FAILED, "Something else went wrong"),
+    (UPDATED, "An A record was updated"),
+    (DETECTION_FAILED, "None of the servers told the outside IP"),
+    (
+        CLOUDFLARE_FAILED,
+        "Cloudflare couldn't be asked, or didn't take the update",
+    ),
+    (
+        CONFIG_INVALID,
+        "A setting is missing or isn't valid, including the arguments themselves",
+    ),
+    (DRIFT, "cdu check found an A record that's out of step"),
+];
+
+/// An error with an exit code of its own, which shows as the error it wraps.
+#[derive(Debug)]
+pub struct Failure {
+    code: i32,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for Failure {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl Failure {
+    /// Returns the error it wraps.
+    #[must_use]
+    pub fn error(&self) -> &anyhow::Error {
+        &self.error
+    }
+}
+
+/// Gives `error` the exit code `code`.
+pub fn wrap(code: i32, error: impl Into<anyhow::Error>) -> anyhow::Error {
+    Failure {
+        code,
+        error: error.into(),
+    }
+    .into()
+}
+
+/// Returns the code to exit with for `error`, which is the one of the first [`Failure`] in its
+/// chain, or [`FAILED`].
+#[must_use]
+pub fn code(error: &anyhow::Error) -> i32 {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<Failure>())
+        .map_or(FAILED, |failure| failure.code)
+}
+
+#[test]
+fn test_code() {
+    use anyhow::Context;
+
+    let error = wrap(
+        DETECTION_FAILED,
+        anyhow::anyhow!("connection refused").context("Failed to get outside IP"),
+    );
+    assert_eq!(code(&error), DETECTION_FAILED);
+    assert_eq!(
+        format!("{error:#}"),
+        "Failed to get outside IP: connection refused"
+    );
+
+    let error = Err::<(), _>(error).context("Check failed").unwrap_err();
+    assert_eq!(code(&error), DETECTION_FAILED);
+    assert_eq!(error.to_string(), "Check failed");
+    assert_eq!(code(&anyhow::anyhow!("Something else")), FAILED);
+}
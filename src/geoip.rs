@@ -0,0 +1,282 @@
+//! Looks up who an IP belongs to, and roughly where it is, to tell in the notifications about a
+//! change. A new IP from an unexpected network, like a VPN's, stands out that way.
+//!
+//! A source is either a MaxMind database, like GeoLite2-ASN or GeoLite2-City, or the URL of a
+//! service that returns JSON, with `{ip}` where the IP goes. These services are understood:
+//!
+//! - `http://ip-api.com/json/{ip}`
+//! - `https://ipinfo.io/{ip}/json`
+//! - `https://ipapi.co/{ip}/json/`
+//!
+//! With more than one source, the first one that knows something wins, so an ASN and a city
+//! database complement each other.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::Client as RqClient;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// How long to wait for a service, as the notification waits for it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to look an IP up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The URL of a service, with `{ip}` where the IP goes.
+    Service(String),
+    /// A MaxMind database.
+    Database(PathBuf),
+}
+
+impl Source {
+    /// Parses a source, which is a service if it starts with `http://` or `https://`, and a
+    /// database otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL of a service has no `{ip}` in it.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            if !source.contains("{ip}") {
+                return Err(format!(
+                    "The GeoIP service URL has no {{ip}} in it: {source}"
+                ));
+            }
+            Ok(Self::Service(source.to_string()))
+        } else {
+            Ok(Self::Database(PathBuf::from(source)))
+        }
+    }
+}
+
+/// Who an IP belongs to, and where it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Network {
+    pub asn: Option<u32>,
+    /// The organization the ASN is registered to, or the ISP.
+    pub organization: Option<String>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+}
+
+impl Network {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fills in what's missing from `other`.
+    fn merge(&mut self, other: Self) {
+        self.asn = self.asn.or(other.asn);
+        self.organization = self.organization.take().or(other.organization);
+        self.city = self.city.take().or(other.city);
+        self.country = self.country.take().or(other.country);
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let owner = match (self.asn, &self.organization) {
+            (Some(asn), Some(organization)) => Some(format!("AS{asn} {organization}")),
+            (Some(asn), None) => Some(format!("AS{asn}")),
+            (None, organization) => organization.clone(),
+        };
+        let parts = [owner, self.city.clone(), self.country.clone()];
+
+        write!(
+            f,
+            "{}",
+            parts.into_iter().flatten().collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+/// Looks IPs up in the sources, in order.
+#[derive(Debug)]
+pub struct GeoIp {
+    client: RqClient,
+    sources: Vec<Source>,
+}
+
+impl GeoIp {
+    #[must_use]
+    pub fn new(client: RqClient, sources: Vec<Source>) -> Self {
+        Self { client, sources }
+    }
+
+    /// Looks `ip` up in every source. Databases are read again every time, so they can be
+    /// replaced with a newer one while the daemon runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a source cannot be read or reached, or none of them know the IP.
+    pub async fn lookup(&self, ip: IpAddr) -> anyhow::Result<Network> {
+        let mut network = Network::default();
+        for source in &self.sources {
+            let found = match source {
+                Source::Service(url) => self.ask_service(url, ip).await?,
+                Source::Database(path) => read_database(path, ip)?,
+            };
+            network.merge(found);
+        }
+        anyhow::ensure!(!network.is_empty(), "Nothing is known about {ip}");
+
+        Ok(network)
+    }
+
+    async fn ask_service(&self, url: &str, ip: IpAddr) -> anyhow::Result<Network> {
+        let url = url.replace("{ip}", &ip.to_string());
+        let response = self
+            .client
+            .get(&url)
+            .timeout(TIMEOUT)
+            .send()
+            .await
+            .with_context(|| format!("Failed to look up {ip}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Received response status {status}: {body}");
+        }
+        let body = response
+            .json::<Value>()
+            .await
+            .context("Failed to parse the response")?;
+
+        Ok(from_json(&body))
+    }
+}
+
+/// What's read from a database, which can be an ASN, a city or a country database.
+#[derive(Debug, Default, Deserialize)]
+struct Record<'a> {
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<&'a str>,
+    #[serde(borrow, default)]
+    city: Place<'a>,
+    #[serde(borrow, default)]
+    country: Place<'a>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Place<'a> {
+    #[serde(borrow, default)]
+    names: BTreeMap<&'a str, &'a str>,
+}
+
+impl Place<'_> {
+    fn name(&self) -> Option<String> {
+        self.names.get("en").map(ToString::to_string)
+    }
+}
+
+fn read_database(path: &Path, ip: IpAddr) -> anyhow::Result<Network> {
+    let reader = maxminddb::Reader::open_readfile(path)
+        .with_context(|| format!("Failed to read GeoIP database: {}", path.display()))?;
+    let record = reader
+        .lookup(ip)
+        .and_then(|result| result.decode::<Record>())
+        .with_context(|| format!("Failed to look up {ip} in {}", path.display()))?
+        .unwrap_or_default();
+
+    Ok(Network {
+        asn: record.autonomous_system_number,
+        organization: record.autonomous_system_organization.map(String::from),
+        city: record.city.name(),
+        country: record.country.name(),
+    })
+}
+
+/// Reads the response of a service, which all name the same things differently.
+fn from_json(body: &Value) -> Network {
+    let text = |key: &str| {
+        body.get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(String::from)
+    };
+    // "AS3320 Deutsche Telekom AG" is the ASN and the organization in one
+    let split = |text: String| match text.split_once(' ') {
+        Some((asn, organization)) if parse_asn(asn).is_some() => {
+            (parse_asn(asn), Some(organization.to_string()))
+        }
+        _ => (parse_asn(&text), None),
+    };
+
+    let (mut asn, mut organization) = text("as")
+        .or_else(|| text("org"))
+        .map_or((None, None), split);
+    if let Some(given) = body.get("asn") {
+        asn = asn.or_else(|| {
+            given
+                .as_u64()
+                .and_then(|asn| u32::try_from(asn).ok())
+                .or_else(|| given.as_str().and_then(parse_asn))
+        });
+    }
+    organization = organization.or_else(|| text("isp")).or_else(|| text("org"));
+
+    Network {
+        asn,
+        organization,
+        city: text("city"),
+        country: text("country_name").or_else(|| text("country")),
+    }
+}
+
+fn parse_asn(text: &str) -> Option<u32> {
+    text.strip_prefix("AS").unwrap_or(text).parse().ok()
+}
+
+#[test]
+fn test_from_json() {
+    use serde_json::json;
+
+    let ip_api = json!({
+        "status": "success",
+        "country": "Germany",
+        "city": "Bonn",
+        "isp": "Deutsche Telekom AG",
+        "org": "Deutsche Telekom AG",
+        "as": "AS3320 Deutsche Telekom AG",
+    });
+    let network = from_json(&ip_api);
+    assert_eq!(network.asn, Some(3320));
+    assert_eq!(
+        network.to_string(),
+        "AS3320 Deutsche Telekom AG, Bonn, Germany"
+    );
+
+    let ipinfo = json!({ "city": "Bonn", "country": "DE", "org": "AS3320 Deutsche Telekom AG" });
+    assert_eq!(
+        from_json(&ipinfo).to_string(),
+        "AS3320 Deutsche Telekom AG, Bonn, DE"
+    );
+
+    let ipapi_co = json!({
+        "city": "Bonn",
+        "country": "DE",
+        "country_name": "Germany",
+        "asn": "AS3320",
+        "org": "Deutsche Telekom AG",
+    });
+    assert_eq!(
+        from_json(&ipapi_co).to_string(),
+        "AS3320 Deutsche Telekom AG, Bonn, Germany"
+    );
+
+    assert!(from_json(&json!({ "error": true })).is_empty());
+    assert!(Source::parse("https://ipinfo.io/json").is_err());
+    assert_eq!(
+        Source::parse("/var/lib/GeoIP/GeoLite2-ASN.mmdb"),
+        Ok(Source::Database(PathBuf::from(
+            "/var/lib/GeoIP/GeoLite2-ASN.mmdb"
+        )))
+    );
+}
@@ -0,0 +1,132 @@
+//! Posts messages to a Gotify server, as an application.
+//!
+//! The target is the URL of the server, like `https://gotify.example.com?token=<app token>`, with
+//! the token of the application in the query, and optionally the `priority`, from `0` to `10`.
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{Client as RqClient, Url};
+use serde_json::{json, Value};
+
+use crate::notify::{Message, Notifier};
+use crate::retry::StatusError;
+
+/// A Gotify server, and the application to post as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The URL of the server, without the options.
+    pub url: Url,
+    pub token: String,
+    pub priority: Option<u8>,
+}
+
+impl Options {
+    /// Parses the URL of a server, taking the options out of its query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid, the token is missing, or an option is unknown or
+    /// has an invalid value.
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let mut url = Url::parse(target).map_err(|e| format!("Invalid Gotify URL: {e}"))?;
+
+        let mut token = None;
+        let mut priority = None;
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "token" => token = Some(value.into_owned()),
+                "priority" => match value.parse() {
+                    Ok(value @ 0..=10) => priority = Some(value),
+                    _ => {
+                        return Err(format!(
+                            "Invalid Gotify priority: {value}, expected 0 to 10"
+                        ))
+                    }
+                },
+                _ => return Err(format!("Unknown Gotify option: {name}")),
+            }
+        }
+        let token = token
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| String::from("Missing the Gotify app token, add ?token=<app token>"))?;
+        url.set_query(None);
+
+        Ok(Self {
+            url,
+            token,
+            priority,
+        })
+    }
+}
+
+/// An application on a Gotify server.
+#[derive(Debug)]
+pub struct Gotify {
+    client: RqClient,
+    options: Options,
+}
+
+impl Gotify {
+    #[must_use]
+    pub fn new(client: RqClient, options: &Options) -> Self {
+        Self {
+            client,
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Gotify {
+    fn name(&self) -> &'static str {
+        "Gotify"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let mut url = self.options.url.clone();
+        url.path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("Invalid Gotify URL"))?
+            .pop_if_empty()
+            .push("message");
+
+        let response = self
+            .client
+            .post(url)
+            .header("X-Gotify-Key", &self.options.token)
+            .json(&payload(message, self.options.priority))
+            .send()
+            .await
+            .context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
+    }
+}
+
+fn payload(message: &Message, priority: Option<u8>) -> Value {
+    let mut payload = json!({
+        "title": message.title(),
+        "message": message.to_string(),
+    });
+    if let Some(priority) = priority {
+        payload["priority"] = json!(priority);
+    }
+
+    payload
+}
+
+#[test]
+fn test_parse_options() {
+    let options = Options::parse("https://gotify.example.com/?token=AbC.123&priority=8").unwrap();
+    assert_eq!(options.url.as_str(), "https://gotify.example.com/");
+    assert_eq!(options.token, "AbC.123");
+    assert_eq!(options.priority, Some(8));
+
+    assert!(Options::parse("https://gotify.example.com").is_err());
+    assert!(Options::parse("https://gotify.example.com?token=AbC&priority=11").is_err());
+}
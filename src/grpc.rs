@@ -0,0 +1,244 @@
+//! A gRPC service to query the daemon and control it, with the `grpc` feature, for tooling that
+//! would rather make typed calls than requests to the HTTP API, and be told about every check as it
+//! finishes.
+//!
+//! The service is `cdu.v1.Control`, defined in `proto/cdu.proto`:
+//!
+//! - `GetStatus` returns the state of the daemon, like `GET /status`.
+//! - `ForceUpdate` makes the daemon check right away, like `POST /force-update`.
+//! - `WatchChecks` streams every check as it finishes, with what it did.
+//!
+//! Every call needs the token of the API in an `authorization: Bearer <token>` entry of the
+//! metadata, and none is answered without a token.
+use std::net::IpAddr;
+use std::pin::Pin;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{debug, error};
+
+use crate::api;
+use crate::daemon::Event;
+use crate::status::{self, SharedStatus};
+
+pub mod proto {
+    tonic::include_proto!("cdu.v1");
+}
+
+use proto::control_server::{Control, ControlServer};
+
+type CheckStream = Pin<Box<dyn Stream<Item = Result<proto::CheckEvent, Status>> + Send>>;
+
+struct Service {
+    token: Option<String>,
+    status: SharedStatus,
+    sender: UnboundedSender<Event>,
+}
+
+/// Starts serving the gRPC service on the address of `options`, in the background.
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be listened on.
+pub async fn spawn(
+    options: &api::Options,
+    status: SharedStatus,
+    sender: UnboundedSender<Event>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(options.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", options.listen))?;
+    let service = ControlServer::new(Service {
+        token: options.token.clone(),
+        status,
+        sender,
+    });
+
+    tokio::spawn(async move {
+        if let Err(e) = Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+        {
+            error!("Stopped serving gRPC: {e}");
+        }
+    });
+
+    debug!("gRPC listening on {}", options.listen);
+
+    Ok(())
+}
+
+impl Service {
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(token) = &self.token else {
+            return Err(Status::permission_denied("Set a token to use gRPC"));
+        };
+        if !api::is_authorized(&request.metadata().clone().into_headers(), token) {
+            return Err(Status::unauthenticated("Missing or wrong token"));
+        }
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Control for Service {
+    type WatchChecksStream = CheckStream;
+
+    async fn get_status(
+        &self,
+        request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, Status> {
+        self.authorize(&request)?;
+        let status = self.status.lock().clone();
+
+        Ok(Response::new(proto::Status {
+            version: status.version.to_string(),
+            started_at: time(Some(status.started_at)),
+            records: status
+                .records
+                .iter()
+                .map(|record| proto::Record {
+                    domain: record.domain.clone(),
+                    ip: ip(record.ip),
+                    checked_at: time(record.checked_at),
+                    error: record.error.clone().unwrap_or_default(),
+                })
+                .collect(),
+            dry_run: status.dry_run,
+            outside_ip: ip(status.outside_ip),
+            last_check: status.last_check.as_ref().map(check),
+            last_change: status.last_change.map(|change| proto::Change {
+                at: time(Some(change.at)),
+                ip: change.ip.to_string(),
+            }),
+            next_check_at: time(status.next_check_at),
+            last_success_at: time(status.last_success_at),
+            recent_errors: status.recent_errors.iter().map(check).collect(),
+        }))
+    }
+
+    async fn force_update(
+        &self,
+        request: Request<proto::ForceUpdateRequest>,
+    ) -> Result<Response<proto::ForceUpdateResponse>, Status> {
+        self.authorize(&request)?;
+        self.sender
+            .send(Event::ForceCheck)
+            .map_err(|_| Status::unavailable("The daemon is shutting down"))?;
+
+        Ok(Response::new(proto::ForceUpdateResponse {}))
+    }
+
+    async fn watch_checks(
+        &self,
+        request: Request<proto::WatchChecksRequest>,
+    ) -> Result<Response<Self::WatchChecksStream>, Status> {
+        self.authorize(&request)?;
+        // A client that falls too far behind misses the checks it didn't keep up with
+        let checks = BroadcastStream::new(self.status.subscribe()).filter_map(|event| async move {
+            event.ok().map(|event| {
+                Ok(proto::CheckEvent {
+                    check: Some(check(&event.check)),
+                    outcome: event.outcome.to_string(),
+                    outside_ip: ip(event.outside_ip),
+                    changed: event.changed,
+                })
+            })
+        });
+
+        Ok(Response::new(Box::pin(checks)))
+    }
+}
+
+fn check(check: &status::Check) -> proto::Check {
+    proto::Check {
+        at: time(Some(check.at)),
+        success: check.success,
+        message: check.message.clone(),
+    }
+}
+
+fn time(at: Option<DateTime<Utc>>) -> String {
+    at.map(|at| at.to_rfc3339()).unwrap_or_default()
+}
+
+fn ip(ip: Option<IpAddr>) -> String {
+    ip.map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_grpc() {
+    use tokio::sync::mpsc;
+    use tonic::Code;
+
+    use crate::config::Config;
+    use crate::updater::{Outcome, Updater};
+
+    use proto::control_client::ControlClient;
+
+    let updater =
+        Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    let status = SharedStatus::new(&updater);
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    // Find a free port
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let options = api::Options {
+        listen,
+        token: Some(String::from("secret")),
+    };
+    spawn(&options, status.clone(), sender).await.unwrap();
+
+    let mut client = ControlClient::connect(format!("http://{listen}"))
+        .await
+        .unwrap();
+    let authorized = |message| {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        request
+    };
+
+    let error = client
+        .get_status(proto::GetStatusRequest {})
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Code::Unauthenticated);
+
+    let reply = client
+        .get_status(authorized(proto::GetStatusRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(reply.records[0].domain, "example.com");
+    assert_eq!(reply.last_check, None);
+
+    let mut checks = client
+        .watch_checks(authorized(proto::WatchChecksRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    status.record(&Ok(Outcome::Updated(IpAddr::from([192, 0, 2, 1]))));
+    let event = checks.message().await.unwrap().unwrap();
+    assert_eq!(event.outcome, "updated");
+    assert_eq!(event.outside_ip, "192.0.2.1");
+    assert!(event.changed);
+
+    client
+        .force_update(authorized(proto::ForceUpdateRequest {}))
+        .await
+        .unwrap();
+    assert_eq!(receiver.recv().await, Some(Event::ForceCheck));
+}
@@ -0,0 +1,108 @@
+//! Pings a [Healthchecks.io](https://healthchecks.io) check, or a server of your own, on every run.
+//!
+//! The check is pinged at `<url>/start` when a run starts, and at `<url>` or `<url>/fail` when it
+//! ends, with what happened in the body. Healthchecks.io alerts once a ping is late, so a cron job
+//! that stopped running is noticed too.
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{Client as RqClient, Url};
+
+use crate::monitor::Monitor;
+use crate::updater::Outcome;
+
+/// How long to wait for Healthchecks.io, which shouldn't hold up the run for long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A check, by its ping URL.
+#[derive(Debug)]
+pub struct Healthchecks {
+    client: RqClient,
+    url: Url,
+}
+
+impl Healthchecks {
+    #[must_use]
+    pub fn new(client: RqClient, url: &Url) -> Self {
+        Self {
+            client,
+            url: url.clone(),
+        }
+    }
+
+    /// Returns the URL to ping, with `suffix` added to the path of the check.
+    fn ping_url(&self, suffix: &str) -> Url {
+        let mut url = self.url.clone();
+        if !suffix.is_empty() {
+            let path = format!("{}/{suffix}", url.path().trim_end_matches('/'));
+            url.set_path(&path);
+        }
+
+        url
+    }
+
+    async fn ping(&self, suffix: &str, body: String) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(self.ping_url(suffix))
+            .timeout(TIMEOUT)
+            .body(body)
+            .send()
+            .await
+            .context("Failed to ping the check")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Received response status {status}: {body}");
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Monitor for Healthchecks {
+    fn name(&self) -> &'static str {
+        "Healthchecks.io"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn start(&self) -> anyhow::Result<()> {
+        self.ping("start", String::new()).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn finish(
+        &self,
+        result: &anyhow::Result<Outcome>,
+        _took: Duration,
+    ) -> anyhow::Result<()> {
+        match result {
+            Ok(outcome) => self.ping("", outcome.to_string()).await,
+            Err(e) => self.ping("fail", format!("{e:#}")).await,
+        }
+    }
+}
+
+#[test]
+fn test_ping_url() {
+    let check = |url| Healthchecks::new(RqClient::new(), &Url::parse(url).unwrap());
+
+    let healthchecks = check("https://hc-ping.com/5bf6e1f5-6d2c-4b3e-9c1c-0f0e5a1d7c3e");
+    assert_eq!(
+        healthchecks.ping_url("start").as_str(),
+        "https://hc-ping.com/5bf6e1f5-6d2c-4b3e-9c1c-0f0e5a1d7c3e/start"
+    );
+    assert_eq!(
+        healthchecks.ping_url("").as_str(),
+        "https://hc-ping.com/5bf6e1f5-6d2c-4b3e-9c1c-0f0e5a1d7c3e"
+    );
+
+    let healthchecks = check("https://hc.example.com/ping/key/cdu/?create=1");
+    assert_eq!(
+        healthchecks.ping_url("fail").as_str(),
+        "https://hc.example.com/ping/key/cdu/fail?create=1"
+    );
+}
@@ -0,0 +1,164 @@
+//! Sorts the errors that are run into the most, so each kind comes with a short hint on how to fix
+//! it, after the error itself.
+use std::error::Error;
+use std::fmt;
+
+use reqwest::StatusCode;
+
+use crate::cloudflare::CloudflareError;
+use crate::exit;
+use crate::network::DetectionError;
+
+/// What kind of problem an error is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// Cloudflare doesn't know the API token.
+    Authentication,
+    /// The API token may not do what was asked.
+    Authorization,
+    /// The zone or the A record isn't there.
+    NotFound,
+    /// A server couldn't be reached, or didn't answer in time.
+    Network,
+    /// Cloudflare got too many requests.
+    RateLimit,
+    /// A setting is missing or isn't valid.
+    Config,
+}
+
+impl Category {
+    /// Returns the category of an error response of the Cloudflare API, going by its status and
+    /// the code of its first error, if it has one.
+    #[must_use]
+    pub fn of_response(status: StatusCode, code: Option<u64>) -> Option<Self> {
+        match (status, code) {
+            (StatusCode::UNAUTHORIZED, _) | (_, Some(6003 | 6111 | 9106 | 9109)) => {
+                Some(Self::Authentication)
+            }
+            (StatusCode::FORBIDDEN, _) => Some(Self::Authorization),
+            (StatusCode::NOT_FOUND, _) | (_, Some(7000 | 7003 | 81044)) => Some(Self::NotFound),
+            (StatusCode::TOO_MANY_REQUESTS, _) | (_, Some(10013)) => Some(Self::RateLimit),
+            _ => None,
+        }
+    }
+
+    /// Returns what to do about a problem of this kind.
+    #[must_use]
+    pub fn hint(self) -> &'static str {
+        match self {
+            Self::Authentication => {
+                "Cloudflare doesn't know the API token. Check that it was copied whole and is still \
+                 active, or create a new one at https://dash.cloudflare.com/profile/api-tokens"
+            }
+            Self::Authorization => {
+                "The API token lacks a permission on this zone. Create a token from the \"Edit zone \
+                 DNS\" template, which has Zone:Read and DNS:Edit, for the zone"
+            }
+            Self::NotFound => {
+                "cdu only updates A records that exist. Check the domain, and that the zone ID is \
+                 the one it's in, which `cdu zones list` shows, and create the A record if it isn't \
+                 there yet"
+            }
+            Self::Network => {
+                "Check that this machine can reach the internet and look up names, which `cdu \
+                 doctor` goes through, or give it longer with --timeout"
+            }
+            Self::RateLimit => {
+                "Cloudflare allows 1200 requests in 5 minutes per user. Check less often, with a \
+                 longer --interval, or run fewer copies of cdu with the same token"
+            }
+            Self::Config => {
+                "`cdu config validate` checks the settings, and `cdu init` sets them up from scratch"
+            }
+        }
+    }
+}
+
+/// An error of a known kind, which shows as the error it wraps.
+#[derive(Debug)]
+pub struct Categorized {
+    category: Category,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Categorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for Categorized {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Puts `error` in `category`.
+#[must_use]
+pub fn wrap(category: Category, error: anyhow::Error) -> anyhow::Error {
+    Categorized { category, error }.into()
+}
+
+/// Returns the hint for `error`, which is the one of the first category in its chain, including the
+/// ones of [`CloudflareError`] and [`DetectionError`]. An error without a category that exits with
+/// [`exit::CONFIG_INVALID`] is a [`Category::Config`] one.
+pub fn of(error: &anyhow::Error) -> Option<&'static str> {
+    category_of(error)
+        .or_else(|| (exit::code(error) == exit::CONFIG_INVALID).then_some(Category::Config))
+        .map(Category::hint)
+}
+
+/// Returns the category of the first error in the chain of `error` that has one.
+#[must_use]
+pub fn category_of(error: &anyhow::Error) -> Option<Category> {
+    error.chain().find_map(|cause| {
+        if let Some(categorized) = cause.downcast_ref::<Categorized>() {
+            return Some(categorized.category);
+        }
+        if let Some(error) = cause.downcast_ref::<CloudflareError>() {
+            return error.category();
+        }
+        if let Some(error) = cause.downcast_ref::<DetectionError>() {
+            return error.category();
+        }
+        // It only shows the error it wraps, which isn't part of the chain then
+        cause
+            .downcast_ref::<exit::Failure>()
+            .and_then(|failure| category_of(failure.error()))
+    })
+}
+
+#[test]
+fn test_of() {
+    use anyhow::Context;
+
+    let error = wrap(
+        Category::Authorization,
+        anyhow::anyhow!("Cloudflare API error: Authentication error"),
+    );
+    let error = exit::wrap(exit::CLOUDFLARE_FAILED, error);
+    let error = Err::<(), _>(error).context("Check failed").unwrap_err();
+    assert_eq!(of(&error), Some(Category::Authorization.hint()));
+    assert_eq!(exit::code(&error), exit::CLOUDFLARE_FAILED);
+    assert_eq!(
+        format!("{error:#}"),
+        "Check failed: Cloudflare API error: Authentication error"
+    );
+
+    let invalid = exit::wrap(exit::CONFIG_INVALID, anyhow::anyhow!("Missing --zone-id"));
+    assert_eq!(of(&invalid), Some(Category::Config.hint()));
+    assert_eq!(of(&anyhow::anyhow!("Something else")), None);
+
+    assert_eq!(
+        Category::of_response(StatusCode::FORBIDDEN, Some(10000)),
+        Some(Category::Authorization)
+    );
+    assert_eq!(
+        Category::of_response(StatusCode::BAD_REQUEST, Some(9109)),
+        Some(Category::Authentication)
+    );
+    assert_eq!(
+        Category::of_response(StatusCode::BAD_REQUEST, Some(1004)),
+        None
+    );
+}
@@ -0,0 +1,112 @@
+//! Exports the history of the changes of the outside IP, as CSV for spreadsheets or as JSON for
+//! anything else.
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+
+use crate::config::IpChange;
+
+/// What the history is exported as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    /// Parses a format, which is `csv` or `json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it's neither.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown format: {name}, expected csv or json")),
+        }
+    }
+}
+
+/// Parses the start of the changes to export: a date, which starts at midnight where cdu runs, or
+/// a time like `2024-01-01T12:00:00Z`.
+///
+/// # Errors
+///
+/// Returns an error if it's neither.
+pub fn parse_since(since: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(since) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+        .map(|at| at.with_timezone(&Utc))
+        .ok_or_else(|| {
+            format!("Expected a date like 2024-01-01, or a time like 2024-01-01T12:00:00Z, got: {since}")
+        })
+}
+
+/// Returns the changes in `format`, with a header if it's CSV. The domains that were updated are
+/// separated by spaces in CSV.
+///
+/// # Errors
+///
+/// Returns an error if the changes cannot be serialized.
+pub fn export(changes: &[IpChange], format: Format) -> anyhow::Result<String> {
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(changes)? + "\n"),
+        Format::Csv => {
+            let mut csv = String::from("at,old_ip,new_ip,source,updated\n");
+            for change in changes {
+                let fields = [
+                    change.at.to_rfc3339(),
+                    change.old_ip.to_string(),
+                    change.new_ip.to_string(),
+                    change.source.clone(),
+                    change.updated.join(" "),
+                ];
+                let fields = fields.iter().map(|field| quote(field)).collect::<Vec<_>>();
+                csv.push_str(&fields.join(","));
+                csv.push('\n');
+            }
+
+            Ok(csv)
+        }
+    }
+}
+
+/// Quotes a CSV field if it needs it, doubling the quotes in it.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[test]
+fn test_export() {
+    use std::net::IpAddr;
+
+    let change = IpChange {
+        at: parse_since("2024-03-10T13:54:04Z").unwrap(),
+        old_ip: IpAddr::from([192, 0, 2, 1]),
+        new_ip: IpAddr::from([192, 0, 2, 2]),
+        source: String::from("api.ipify.org"),
+        updated: vec![String::from("example.com"), String::from("www.example.com")],
+    };
+
+    assert_eq!(
+        export(std::slice::from_ref(&change), Format::Csv).unwrap(),
+        "at,old_ip,new_ip,source,updated\n\
+         2024-03-10T13:54:04+00:00,192.0.2.1,192.0.2.2,api.ipify.org,example.com www.example.com\n"
+    );
+    let json = export(&[change], Format::Json).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&json).unwrap()[0]["new_ip"],
+        "192.0.2.2"
+    );
+    assert_eq!(quote("a \"b\", c"), "\"a \"\"b\"\", c\"");
+    assert!(parse_since("2024-01-01").is_ok());
+    assert!(parse_since("yesterday").is_err());
+}
@@ -0,0 +1,139 @@
+//! Runs commands of your own at points of a run, to restart a VPN, change firewall rules or tell
+//! other services about the new address.
+//!
+//! The commands run in a shell, `sh -c` or `cmd /C` on Windows, with these variables set:
+//!
+//! - `CDU_EVENT`, like `updated` or `update-failed`, as in `--notify-on`, or `before`.
+//! - `CDU_EVENT_DOMAIN`, the domain it's about.
+//! - `CDU_OLD_IP` and `CDU_NEW_IP`, the IP the A record pointed at before and should point at now.
+//! - `CDU_ERROR`, what went wrong, for a failure.
+//!
+//! A variable is left out when there's nothing to put in it.
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::notify::{EventKind, Message, Severity};
+
+/// How long a command may take, after which it's stopped, so it cannot hold up the checks.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The commands to run, at which points.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hooks {
+    /// Before the outside IP is detected.
+    pub before: Option<String>,
+    /// After the A record of a domain was updated.
+    pub after_update: Option<String>,
+    /// After the outside IP couldn't be detected, or a domain couldn't be updated.
+    pub on_failure: Option<String>,
+}
+
+impl Hooks {
+    /// Runs the command for before the outside IP is detected, if there is one.
+    pub async fn before(&self, domains: &str) {
+        if let Some(command) = &self.before {
+            run(
+                command,
+                &[("CDU_EVENT", "before"), ("CDU_EVENT_DOMAIN", domains)],
+            )
+            .await;
+        }
+    }
+
+    /// Runs the command for what the message is about, if there is one.
+    pub async fn after(&self, message: &Message) {
+        let command = match message.kind {
+            EventKind::Updated => &self.after_update,
+            _ if message.severity == Severity::Error => &self.on_failure,
+            _ => &None,
+        };
+        if let Some(command) = command {
+            run(command, &variables(message)).await;
+        }
+    }
+}
+
+/// Returns the variables the command gets for the message.
+fn variables(message: &Message) -> Vec<(&'static str, String)> {
+    let event = EventKind::ALL
+        .iter()
+        .find(|(_, kind)| *kind == message.kind)
+        .map_or("", |(name, _)| name);
+
+    [
+        ("CDU_EVENT", Some(event.to_string())),
+        ("CDU_EVENT_DOMAIN", Some(message.domain.clone())),
+        ("CDU_OLD_IP", message.old_ip.map(|ip| ip.to_string())),
+        ("CDU_NEW_IP", message.new_ip.map(|ip| ip.to_string())),
+        ("CDU_ERROR", message.error.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name, value?)))
+    .collect()
+}
+
+/// Runs the command, logging how it went. A command that fails doesn't stop the run.
+async fn run<V: AsRef<str>>(command: &str, variables: &[(&str, V)]) {
+    match try_run(command, variables).await {
+        Ok(()) => debug!("Ran hook: {command}"),
+        Err(e) => warn!("Hook failed: {command}: {e:#}"),
+    }
+}
+
+async fn try_run<V: AsRef<str>>(command: &str, variables: &[(&str, V)]) -> anyhow::Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command).kill_on_drop(true);
+    for (name, value) in variables {
+        shell.env(name, value.as_ref());
+    }
+
+    let status = tokio::time::timeout(TIMEOUT, shell.status())
+        .await
+        .context("Took too long, so it was stopped")?
+        .context("Failed to start it")?;
+    anyhow::ensure!(status.success(), "Exited with {status}");
+
+    Ok(())
+}
+
+#[test]
+fn test_variables() {
+    use std::net::IpAddr;
+
+    let message = Message::updated(
+        "home.example.com",
+        Some(IpAddr::from([192, 0, 2, 1])),
+        IpAddr::from([192, 0, 2, 2]),
+    );
+    let variables = variables(&message);
+    assert_eq!(
+        variables,
+        [
+            ("CDU_EVENT", String::from("updated")),
+            ("CDU_EVENT_DOMAIN", String::from("home.example.com")),
+            ("CDU_OLD_IP", String::from("192.0.2.1")),
+            ("CDU_NEW_IP", String::from("192.0.2.2")),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_try_run() {
+    try_run(r#"test "$CDU_EVENT" = before"#, &[("CDU_EVENT", "before")])
+        .await
+        .unwrap();
+    let e = try_run("exit 3", &[] as &[(&str, &str)]).await.unwrap_err();
+    assert!(e.to_string().contains("exit status: 3"), "{e}");
+}
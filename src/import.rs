@@ -0,0 +1,910 @@
+//! Turns the configuration of another DDNS client into a settings file of cdu, for `cdu import`, to
+//! move over from it without writing the settings by hand. Only what's on Cloudflare is imported:
+//!
+//! - From ddclient, the entries with `protocol=cloudflare`: their hostnames, zone, API token and
+//!   TTL, and `daemon=` as the interval.
+//! - From inadyn, the `provider cloudflare.com` sections: their hostnames, the zone in `username`,
+//!   the API token in `password`, the TTL and whether they're proxied, and `period` as the interval.
+//! - From ddns-go, the `dnsconf` entries on `cloudflare`: the domains of their `ipv4`, the API token
+//!   in `secret` and the TTL, and the URL of the webhook.
+//!
+//! The clients name the zone, where cdu needs its ID, so the zones are looked up with the API token.
+//! Whatever has no counterpart in cdu, like another provider, IPv6 or telling the IP from an
+//! interface, is reported rather than dropped.
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde_yaml::Value;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::cloudflare::Zone;
+
+/// The keys of ddclient that cdu does without, as it does the same on its own.
+const DDCLIENT_IGNORED: [&str; 11] = [
+    "cache",
+    "debug",
+    "foreground",
+    "pid",
+    "quiet",
+    "server",
+    "ssl",
+    "syslog",
+    "verbose",
+    "web",
+    "webv4",
+];
+
+/// The settings of inadyn that cdu does without, as it does the same on its own.
+const INADYN_IGNORED: [&str; 7] = [
+    "broken-rtc",
+    "ca-trust-file",
+    "forced-update",
+    "secure-ssl",
+    "ssl",
+    "user-agent",
+    "verify-address",
+];
+
+/// The outside IP of the hosts is told about by the servers cdu asks, not by an interface or a
+/// command.
+const OWN_DETECTION: &str = "cdu asks servers on the internet for the outside IP";
+
+/// Domains in a zone that share their settings.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub zone: String,
+    /// The ID of the zone, once it's looked up.
+    pub zone_id: Option<String>,
+    pub domains: Vec<String>,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+}
+
+/// What was read from the configuration of another client.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Import {
+    pub api_key: Option<String>,
+    pub entries: Vec<Entry>,
+    pub interval: Option<String>,
+    pub webhook_url: Option<String>,
+    /// What couldn't be translated, to tell about.
+    pub untranslated: Vec<String>,
+}
+
+impl Import {
+    /// Looks up the IDs of the zones in `zones`, the ones the API token has access to.
+    pub fn resolve(&mut self, zones: &[Zone]) {
+        for entry in &mut self.entries {
+            entry.zone_id = zones
+                .iter()
+                .find(|zone| zone.name.eq_ignore_ascii_case(&entry.zone))
+                .map(|zone| zone.id.clone());
+        }
+
+        let missing = self
+            .entries
+            .iter()
+            .filter(|entry| entry.zone_id.is_none())
+            .map(|entry| entry.zone.clone())
+            .collect::<Vec<_>>();
+        for zone in missing {
+            self.note(format!(
+                "The ID of the zone {zone} wasn't found with the API token, replace {} with it",
+                placeholder(&zone)
+            ));
+        }
+    }
+
+    /// Returns the settings file: the domains of the first zone go in `domain`, and the others,
+    /// and the ones with a TTL or proxied, in `[[domains]]` tables of their own.
+    #[must_use]
+    pub fn settings(&self) -> DocumentMut {
+        let mut document = DocumentMut::new();
+        if let Some(api_key) = &self.api_key {
+            document["api_key"] = value(api_key);
+        }
+        let Some(first) = self.entries.first() else {
+            return document;
+        };
+        document["zone_id"] = value(zone_id(first));
+
+        let (plain, own): (Vec<_>, Vec<_>) = self.entries.iter().partition(|entry| {
+            entry.zone == first.zone && entry.ttl.is_none() && entry.proxied.is_none()
+        });
+        let domains = plain
+            .iter()
+            .flat_map(|entry| &entry.domains)
+            .collect::<Array>();
+        if !domains.is_empty() {
+            document["domain"] = value(domains);
+        }
+        if let Some(interval) = &self.interval {
+            document["interval"] = value(interval);
+        }
+        if let Some(webhook_url) = &self.webhook_url {
+            document["webhook_url"] = value(webhook_url);
+        }
+
+        let mut tables = ArrayOfTables::new();
+        for entry in own {
+            for domain in &entry.domains {
+                let mut table = Table::new();
+                table["name"] = value(domain);
+                if entry.zone != first.zone {
+                    table["zone_id"] = value(zone_id(entry));
+                }
+                if let Some(ttl) = entry.ttl {
+                    table["ttl"] = value(i64::from(ttl));
+                }
+                if let Some(proxied) = entry.proxied {
+                    table["proxied"] = value(proxied);
+                }
+                tables.push(table);
+            }
+        }
+        if !tables.is_empty() {
+            document["domains"] = Item::ArrayOfTables(tables);
+        }
+
+        document
+    }
+
+    fn note(&mut self, note: String) {
+        if !self.untranslated.contains(&note) {
+            self.untranslated.push(note);
+        }
+    }
+
+    fn add(&mut self, zone: &str, domains: Vec<String>, ttl: Option<u32>, proxied: Option<bool>) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.zone == zone && entry.ttl == ttl && entry.proxied == proxied)
+        {
+            Some(entry) => entry.domains.extend(domains),
+            None => self.entries.push(Entry {
+                zone: zone.to_string(),
+                zone_id: None,
+                domains,
+                ttl,
+                proxied,
+            }),
+        }
+    }
+
+    /// Takes the API token of `names`, if it's the first one. cdu has one for every domain.
+    fn use_api_key(&mut self, names: &str, api_key: &str) {
+        if self.api_key.is_none() {
+            self.api_key = Some(api_key.to_string());
+        } else if self.api_key.as_deref() != Some(api_key) {
+            self.note(format!(
+                "{names}: have an API token of their own, where cdu updates every domain with the first one"
+            ));
+        }
+    }
+
+    fn use_interval(&mut self, setting: &str, seconds: &str) {
+        match interval(seconds) {
+            Some(interval) => self.interval = Some(interval),
+            None => self.note(format!("{setting} isn't an interval cdu knows")),
+        }
+    }
+
+    fn ttl(&mut self, names: &str, ttl: Option<&str>) -> Option<u32> {
+        let ttl = ttl.filter(|ttl| !ttl.is_empty())?;
+        if let Ok(ttl) = ttl.parse() {
+            Some(ttl)
+        } else {
+            self.note(format!("{names}: the TTL {ttl} isn't a number"));
+            None
+        }
+    }
+
+    /// Adds the hosts of a line of ddclient, with the `options` that apply to them.
+    fn add_ddclient(&mut self, options: &BTreeMap<String, String>, hosts: Vec<String>) {
+        for (key, option) in options {
+            match key.as_str() {
+                "protocol" | "zone" | "login" | "password" | "ttl" => {}
+                "daemon" => self.use_interval(&format!("daemon={option}"), option),
+                "use" | "usev4" if matches!(option.as_str(), "web" | "webv4") => {}
+                "use" | "usev4" => self.note(format!(
+                    "{key}={option} has no counterpart in cdu, {OWN_DETECTION}"
+                )),
+                key if DDCLIENT_IGNORED.contains(&key) => {}
+                _ => self.note(format!("{key}={option} has no counterpart in cdu")),
+            }
+        }
+
+        let names = hosts.join(", ");
+        let protocol = options.get("protocol").map_or("dyndns2", String::as_str);
+        if protocol != "cloudflare" {
+            self.note(format!("{names}: protocol={protocol} isn't Cloudflare"));
+            return;
+        }
+        let Some(zone) = options.get("zone") else {
+            self.note(format!("{names}: no zone= to find the A records in"));
+            return;
+        };
+
+        match (options.get("login").map(String::as_str), options.get("password")) {
+            (Some(login), _) if login != "token" => self.note(format!(
+                "{names}: login={login} is for the Global API Key, create an API token for api_key instead"
+            )),
+            (_, Some(password)) => self.use_api_key(&names, password),
+            (_, None) => {}
+        }
+
+        let ttl = self.ttl(&names, options.get("ttl").map(String::as_str));
+        self.add(zone, hosts, ttl, None);
+    }
+
+    /// Adds a section of inadyn, a provider and its settings.
+    fn add_inadyn(&mut self, section: &Section) {
+        let names = section
+            .options
+            .get("hostname")
+            .map(|hosts| hosts.join(", "))
+            .unwrap_or_default();
+        let provider = section.name.split(':').next().unwrap_or_default();
+        if section.kind != "provider" || provider.trim_start_matches("default@") != "cloudflare.com"
+        {
+            self.note(format!(
+                "{names}: {} {} isn't Cloudflare",
+                section.kind, section.name
+            ));
+            return;
+        }
+
+        for (key, values) in &section.options {
+            match key.as_str() {
+                "username" | "password" | "hostname" | "ttl" | "proxied" => {}
+                "checkip-command" => self.note(format!(
+                    "{names}: {key} has no counterpart in cdu, {OWN_DETECTION}"
+                )),
+                key if INADYN_IGNORED.contains(&key) => {}
+                _ => self.note(format!(
+                    "{names}: {key} = {} has no counterpart in cdu",
+                    values.join(", ")
+                )),
+            }
+        }
+
+        let option = |key: &str| match section.options.get(key).map(Vec::as_slice) {
+            Some([option]) => Some(option.as_str()),
+            _ => None,
+        };
+        let Some(zone) = option("username") else {
+            self.note(format!(
+                "{names}: no username with the zone to find the A records in"
+            ));
+            return;
+        };
+        if let Some(password) = option("password") {
+            self.use_api_key(&names, password);
+        }
+        let ttl = self.ttl(&names, option("ttl"));
+        let proxied = option("proxied").and_then(|proxied| {
+            if let Ok(proxied) = proxied.parse() {
+                Some(proxied)
+            } else {
+                self.note(format!("{names}: proxied = {proxied} isn't true or false"));
+                None
+            }
+        });
+
+        let hosts = section.options.get("hostname").cloned().unwrap_or_default();
+        self.add(zone, hosts, ttl, proxied);
+    }
+
+    /// Adds an entry of `dnsconf` of ddns-go.
+    fn add_ddns_go(&mut self, entry: &Value) {
+        let ipv4 = &entry["ipv4"];
+        let ipv6_domains = ddns_go_domains(&entry["ipv6"]);
+        if entry["ipv6"]["enable"].as_bool() == Some(true) && !ipv6_domains.is_empty() {
+            self.note(format!(
+                "{}: IPv6 isn't updated by cdu",
+                ipv6_domains.join(", ")
+            ));
+        }
+        let domains = ddns_go_domains(ipv4);
+        if ipv4["enable"].as_bool() != Some(true) || domains.is_empty() {
+            return;
+        }
+
+        let names = domains.join(", ");
+        let dns = entry["dns"]["name"].as_str().unwrap_or_default();
+        if dns != "cloudflare" {
+            self.note(format!("{names}: {dns} isn't Cloudflare"));
+            return;
+        }
+        match ipv4["gettype"].as_str() {
+            None | Some("url") => {}
+            Some(kind) => self.note(format!(
+                "{names}: gettype {kind} has no counterpart in cdu, {OWN_DETECTION}"
+            )),
+        }
+        if let Some(secret) = entry["dns"]["secret"]
+            .as_str()
+            .filter(|secret| !secret.is_empty())
+        {
+            self.use_api_key(&names, secret);
+        }
+        let ttl = match &entry["ttl"] {
+            Value::Number(ttl) => self.ttl(&names, Some(&ttl.to_string())),
+            ttl => self.ttl(&names, ttl.as_str()),
+        };
+
+        for domain in domains {
+            let (name, zone, proxied) = ddns_go_domain(domain);
+            self.add(&zone, vec![name], ttl, proxied);
+        }
+    }
+}
+
+/// Reads a ddclient.conf: each line sets options, and updates the hosts at the end of it, if there
+/// are any, with them. The options of a line without hosts apply to the lines after it.
+#[must_use]
+pub fn ddclient(text: &str) -> Import {
+    let mut import = Import::default();
+    let mut globals = BTreeMap::new();
+
+    for line in logical_lines(text) {
+        let mut options = BTreeMap::new();
+        let mut hosts = Vec::new();
+        for token in tokens(&line) {
+            match token.split_once('=') {
+                Some((key, option)) => {
+                    options.insert(key.trim().to_ascii_lowercase(), option.to_string());
+                }
+                None => hosts.push(token),
+            }
+        }
+
+        if hosts.is_empty() {
+            globals.extend(options);
+        } else {
+            let mut merged = globals.clone();
+            merged.extend(options);
+            import.add_ddclient(&merged, hosts);
+        }
+    }
+
+    import
+}
+
+/// Reads an inadyn.conf: `period` as the interval, and the sections of the providers.
+///
+/// # Errors
+///
+/// Returns an error if a section or a list isn't closed, or a setting has no value.
+pub fn inadyn(text: &str) -> anyhow::Result<Import> {
+    let (globals, sections) = inadyn_settings(&mut inadyn_tokens(text).into_iter(), false)?;
+    let mut import = Import::default();
+
+    for (key, values) in &globals {
+        let option = values.join(", ");
+        match key.as_str() {
+            "period" => import.use_interval(&format!("period = {option}"), &option),
+            "allow-ipv6" if option == "false" => {}
+            "iface" | "checkip-command" => import.note(format!(
+                "{key} = {option} has no counterpart in cdu, {OWN_DETECTION}"
+            )),
+            key if INADYN_IGNORED.contains(&key) => {}
+            _ => import.note(format!("{key} = {option} has no counterpart in cdu")),
+        }
+    }
+    for section in &sections {
+        import.add_inadyn(section);
+    }
+
+    Ok(import)
+}
+
+/// Reads the YAML configuration of ddns-go, of version 5 or later, with its entries in `dnsconf`.
+///
+/// # Errors
+///
+/// Returns an error if it isn't YAML, or has no `dnsconf`.
+pub fn ddns_go(text: &str) -> anyhow::Result<Import> {
+    let config = serde_yaml::from_str::<Value>(text).context("Invalid YAML")?;
+    let entries = config["dnsconf"]
+        .as_sequence()
+        .context("No dnsconf in it, which ddns-go has since version 5")?;
+    let mut import = Import::default();
+
+    for entry in entries {
+        import.add_ddns_go(entry);
+    }
+
+    let webhook = &config["webhook"];
+    if let Some(url) = webhook["webhookurl"].as_str().filter(|url| !url.is_empty()) {
+        import.webhook_url = Some(url.to_string());
+        for key in ["webhookrequestbody", "webhookheaders"] {
+            if webhook[key]
+                .as_str()
+                .is_some_and(|option| !option.is_empty())
+            {
+                import.note(format!(
+                    "{key} has no counterpart in cdu, see --webhook-template and --webhook-header"
+                ));
+            }
+        }
+    }
+
+    Ok(import)
+}
+
+fn ddns_go_domains(ip: &Value) -> Vec<&str> {
+    ip["domains"]
+        .as_sequence()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+/// Returns the name, zone and whether it's proxied of a domain of ddns-go, which is like
+/// `www.example.com`, `www:example.com` to tell where the zone starts, or
+/// `www.example.com?proxied=true`. Without a `:`, the zone is the last two labels.
+fn ddns_go_domain(domain: &str) -> (String, String, Option<bool>) {
+    let (domain, query) = domain.split_once('?').unwrap_or((domain, ""));
+    let proxied = query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("proxied="))
+        .and_then(|proxied| proxied.parse().ok());
+
+    match domain.split_once(':') {
+        Some(("" | "@", zone)) => (zone.to_string(), zone.to_string(), proxied),
+        Some((name, zone)) => (format!("{name}.{zone}"), zone.to_string(), proxied),
+        None => {
+            let zone = match domain.rsplitn(3, '.').collect::<Vec<_>>().as_slice() {
+                [tld, name, ..] => format!("{name}.{tld}"),
+                _ => domain.to_string(),
+            };
+            (domain.to_string(), zone, proxied)
+        }
+    }
+}
+
+/// A token of inadyn.conf, where a quoted string is a word too.
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Symbol(char),
+}
+
+/// The options of inadyn.conf, or of a section of it, with their values.
+type InadynOptions = BTreeMap<String, Vec<String>>;
+
+/// A section of inadyn.conf, like `provider cloudflare.com { ... }`.
+#[derive(Debug)]
+struct Section {
+    kind: String,
+    name: String,
+    options: InadynOptions,
+}
+
+fn inadyn_tokens(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for line in text.lines() {
+        let mut chars = without_comment(line).chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' | '}' | '=' | ',' => tokens.push(Token::Symbol(c)),
+                '"' | '\'' => tokens.push(Token::Word(
+                    chars.by_ref().take_while(|&next| next != c).collect(),
+                )),
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut word = String::from(c);
+                    while let Some(&next) = chars.peek() {
+                        if next.is_whitespace() || "{}=,\"'".contains(next) {
+                            break;
+                        }
+                        word.push(next);
+                        chars.next();
+                    }
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Reads the settings up to the end, or up to the `}` that closes the section they're in, and the
+/// sections in them.
+fn inadyn_settings(
+    tokens: &mut impl Iterator<Item = Token>,
+    in_section: bool,
+) -> anyhow::Result<(InadynOptions, Vec<Section>)> {
+    let mut options = BTreeMap::new();
+    let mut sections = Vec::new();
+    loop {
+        match tokens.next() {
+            None if in_section => anyhow::bail!("A section isn't closed with }}"),
+            None => break,
+            Some(Token::Symbol('}')) if in_section => break,
+            Some(Token::Word(key)) => match tokens.next() {
+                Some(Token::Symbol('=')) => {
+                    let values = inadyn_value(tokens).with_context(|| format!("Invalid {key}"))?;
+                    options.insert(key.to_ascii_lowercase(), values);
+                }
+                Some(Token::Word(name)) => {
+                    anyhow::ensure!(
+                        tokens.next() == Some(Token::Symbol('{')),
+                        "{key} {name} isn't followed by {{"
+                    );
+                    let (section, _) = inadyn_settings(tokens, true)
+                        .with_context(|| format!("Invalid {key} {name}"))?;
+                    sections.push(Section {
+                        kind: key,
+                        name,
+                        options: section,
+                    });
+                }
+                _ => anyhow::bail!("{key} has no value"),
+            },
+            Some(Token::Symbol(symbol)) => anyhow::bail!("Unexpected {symbol}"),
+        }
+    }
+
+    Ok((options, sections))
+}
+
+/// Reads a value, or a list of them in braces.
+fn inadyn_value(tokens: &mut impl Iterator<Item = Token>) -> anyhow::Result<Vec<String>> {
+    match tokens.next() {
+        Some(Token::Word(value)) => Ok(vec![value]),
+        Some(Token::Symbol('{')) => {
+            let mut values = Vec::new();
+            loop {
+                match tokens.next() {
+                    Some(Token::Word(value)) => values.push(value),
+                    Some(Token::Symbol(',')) => {}
+                    Some(Token::Symbol('}')) => return Ok(values),
+                    _ => anyhow::bail!("The list isn't closed with }}"),
+                }
+            }
+        }
+        _ => anyhow::bail!("Missing the value"),
+    }
+}
+
+/// Returns the lines without comments, with the ones that end with `\` joined to the next.
+fn logical_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        let line = without_comment(line);
+        if let Some(continued) = line.trim_end().strip_suffix('\\') {
+            current.push_str(continued);
+            current.push(' ');
+        } else {
+            current.push_str(line);
+            lines.push(std::mem::take(&mut current));
+        }
+    }
+    lines.push(current);
+
+    lines.retain(|line| !line.trim().is_empty());
+    lines
+}
+
+fn without_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('#', None) => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// Splits a line at commas and spaces outside of quotes, and takes the quotes off.
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    for c in line.chars() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (c, None) if c == ',' || c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+/// Returns the interval of `daemon=`, in seconds or with a unit like `5m`, as cdu takes it.
+fn interval(daemon: &str) -> Option<String> {
+    let interval = if daemon.chars().all(|c| c.is_ascii_digit()) {
+        format!("{daemon}s")
+    } else {
+        daemon.to_string()
+    };
+
+    humantime::parse_duration(&interval).ok().map(|_| interval)
+}
+
+fn zone_id(entry: &Entry) -> String {
+    entry
+        .zone_id
+        .clone()
+        .unwrap_or_else(|| placeholder(&entry.zone))
+}
+
+fn placeholder(zone: &str) -> String {
+    format!("<ID of {zone}>")
+}
+
+#[test]
+fn test_ddclient() {
+    let mut import = ddclient(
+        "# Every 5 minutes
+daemon=300
+syslog=yes
+mail=root
+use=web, web=checkip.dyndns.org
+
+protocol=cloudflare, \\
+zone=example.com, \\
+login=token, \\
+password='a-token#1' \\
+example.com,www.example.com
+
+protocol=cloudflare, zone=example.com, ttl=60, login=token, password='a-token#1' vpn.example.com
+protocol=cloudflare, zone=example.net, login=token, password=another-token home.example.net
+protocol=dyndns2, server=members.dyndns.org, login=me, password=secret me.dyndns.org
+",
+    );
+
+    assert_eq!(import.api_key.as_deref(), Some("a-token#1"));
+    assert_eq!(import.interval.as_deref(), Some("300s"));
+    assert_eq!(
+        import.entries,
+        [
+            Entry {
+                zone: String::from("example.com"),
+                zone_id: None,
+                domains: vec![String::from("example.com"), String::from("www.example.com")],
+                ttl: None,
+                proxied: None,
+            },
+            Entry {
+                zone: String::from("example.com"),
+                zone_id: None,
+                domains: vec![String::from("vpn.example.com")],
+                ttl: Some(60),
+                proxied: None,
+            },
+            Entry {
+                zone: String::from("example.net"),
+                zone_id: None,
+                domains: vec![String::from("home.example.net")],
+                ttl: None,
+                proxied: None,
+            },
+        ]
+    );
+    assert_eq!(
+        import.untranslated,
+        [
+            "mail=root has no counterpart in cdu",
+            "home.example.net: have an API token of their own, where cdu updates every domain with the first one",
+            "me.dyndns.org: protocol=dyndns2 isn't Cloudflare",
+        ]
+    );
+
+    import.resolve(&[Zone {
+        id: String::from("zone-1"),
+        name: String::from("example.com"),
+        status: String::from("active"),
+    }]);
+    assert_eq!(
+        import.untranslated[3],
+        "The ID of the zone example.net wasn't found with the API token, replace <ID of example.net> with it"
+    );
+    assert_eq!(
+        import.settings().to_string(),
+        r#"api_key = "a-token#1"
+zone_id = "zone-1"
+domain = ["example.com", "www.example.com"]
+interval = "300s"
+
+[[domains]]
+name = "vpn.example.com"
+ttl = 60
+
+[[domains]]
+name = "home.example.net"
+zone_id = "<ID of example.net>"
+"#
+    );
+}
+
+#[test]
+fn test_ddclient_login() {
+    let import = ddclient(
+        "use=if, if=eth0
+protocol=cloudflare, zone=example.com, login=me@example.com, password=global-key example.com",
+    );
+
+    assert_eq!(import.api_key, None);
+    assert_eq!(
+        import.untranslated,
+        [
+            "if=eth0 has no counterpart in cdu",
+            "use=if has no counterpart in cdu, cdu asks servers on the internet for the outside IP",
+            "example.com: login=me@example.com is for the Global API Key, create an API token for api_key instead",
+        ]
+    );
+}
+
+#[test]
+fn test_inadyn() {
+    let mut import = inadyn(
+        r#"# Check every 10 minutes
+period = 600
+iface = eth0
+
+provider cloudflare.com {
+    username = example.com
+    password = "a-token"
+    hostname = { "example.com", "www.example.com" }
+    ttl = 1
+}
+
+provider cloudflare.com:2 {
+    username = example.com
+    password = a-token
+    hostname = vpn.example.com
+    ttl = 1
+    proxied = true
+}
+
+provider default@dyndns.org {
+    username = me
+    password = secret
+    hostname = me.dyndns.org
+}
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(import.api_key.as_deref(), Some("a-token"));
+    assert_eq!(import.interval.as_deref(), Some("600s"));
+    assert_eq!(
+        import.untranslated,
+        [
+            "iface = eth0 has no counterpart in cdu, cdu asks servers on the internet for the outside IP",
+            "me.dyndns.org: provider default@dyndns.org isn't Cloudflare",
+        ]
+    );
+
+    import.resolve(&[Zone {
+        id: String::from("zone-1"),
+        name: String::from("example.com"),
+        status: String::from("active"),
+    }]);
+    assert_eq!(
+        import.settings().to_string(),
+        r#"api_key = "a-token"
+zone_id = "zone-1"
+interval = "600s"
+
+[[domains]]
+name = "example.com"
+ttl = 1
+
+[[domains]]
+name = "www.example.com"
+ttl = 1
+
+[[domains]]
+name = "vpn.example.com"
+ttl = 1
+proxied = true
+"#
+    );
+
+    assert!(inadyn("provider cloudflare.com { username = example.com").is_err());
+    assert!(inadyn("hostname = { \"example.com\"").is_err());
+}
+
+#[test]
+fn test_ddns_go() {
+    let import = ddns_go(
+        "dnsconf:
+    - name: home
+      ipv4:
+        enable: true
+        gettype: url
+        url: https://api.ipify.org
+        domains:
+            - home.example.com
+            - www:example.co.uk?proxied=true
+      ipv6:
+        enable: true
+        gettype: netInterface
+        domains:
+            - v6.example.com
+      dns:
+        name: cloudflare
+        id: \"\"
+        secret: a-token
+      ttl: \"120\"
+    - ipv4:
+        enable: true
+        gettype: netInterface
+        domains:
+            - home.example.org
+      dns:
+        name: alidns
+        id: an-id
+        secret: a-secret
+      ttl: \"\"
+webhook:
+    webhookurl: https://hooks.example.com/ddns
+    webhookrequestbody: '{\"ip\": \"#{ipv4Addr}\"}'
+",
+    )
+    .unwrap();
+
+    assert_eq!(import.api_key.as_deref(), Some("a-token"));
+    assert_eq!(
+        import.webhook_url.as_deref(),
+        Some("https://hooks.example.com/ddns")
+    );
+    assert_eq!(
+        import.entries,
+        [
+            Entry {
+                zone: String::from("example.com"),
+                zone_id: None,
+                domains: vec![String::from("home.example.com")],
+                ttl: Some(120),
+                proxied: None,
+            },
+            Entry {
+                zone: String::from("example.co.uk"),
+                zone_id: None,
+                domains: vec![String::from("www.example.co.uk")],
+                ttl: Some(120),
+                proxied: Some(true),
+            },
+        ]
+    );
+    assert_eq!(
+        import.untranslated,
+        [
+            "v6.example.com: IPv6 isn't updated by cdu",
+            "home.example.org: alidns isn't Cloudflare",
+            "webhookrequestbody has no counterpart in cdu, see --webhook-template and --webhook-header",
+        ]
+    );
+
+    assert_eq!(
+        ddns_go_domain("example.com"),
+        (
+            String::from("example.com"),
+            String::from("example.com"),
+            None
+        )
+    );
+    assert!(ddns_go("lang: en").is_err());
+}
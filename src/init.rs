@@ -0,0 +1,218 @@
+//! Sets cdu up by asking for what it needs, for a first run that doesn't start with reading up on
+//! every setting: the API token, which is checked right away, the zone, picked from the ones the
+//! token has access to, and the domains, picked from the A records in it.
+//!
+//! The answers are written to the settings file, and cdu is then run once as a dry run, to show
+//! what it would do.
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+use cdu::cloudflare;
+
+use crate::settings;
+
+/// What was set up.
+#[derive(Debug)]
+pub struct Setup {
+    pub api_key: String,
+    pub zone_id: String,
+    pub domains: Vec<String>,
+}
+
+/// Asks for the settings, checking the token with the Cloudflare API at `api_url` through `client`,
+/// and writes them to the settings file at `path`, or the table of `profile` in it.
+///
+/// # Errors
+///
+/// Returns an error if the questions cannot be asked, the token has no zones or the zone no A
+/// records, or the settings file cannot be written.
+pub async fn run(
+    path: &Path,
+    profile: Option<&str>,
+    api_url: &str,
+    client: &reqwest::Client,
+) -> anyhow::Result<Setup> {
+    println!("Create an API token with the Zone:Read and DNS:Edit permissions at");
+    println!("https://dash.cloudflare.com/profile/api-tokens, for the zone of your domains.");
+    println!();
+
+    let (api_key, cloudflare) = loop {
+        let api_key = read_secret("API token: ")?;
+        anyhow::ensure!(!api_key.is_empty(), "No API token given");
+        let cloudflare = cloudflare::Handler::try_new(&api_key)?
+            .with_api_url(api_url)
+            .with_client(client.clone());
+        match cloudflare.verify_token().await.map_err(anyhow::Error::from) {
+            Ok(()) => break (api_key, cloudflare),
+            Err(e) => println!("That didn't work: {e:#}"),
+        }
+    };
+    println!("The API token works.");
+
+    let zones = cloudflare.list_zones().await?;
+    anyhow::ensure!(!zones.is_empty(), "The API token has no access to any zone");
+    let zone = if let [zone] = zones.as_slice() {
+        zone
+    } else {
+        let names = zones
+            .iter()
+            .map(|zone| zone.name.clone())
+            .collect::<Vec<_>>();
+        let index = loop {
+            list(&names);
+            match parse_choice(&ask("Zone, by number: ")?, names.len()).as_deref() {
+                Some(&[index]) => break index,
+                _ => println!("Pick one of the zones."),
+            }
+        };
+        &zones[index]
+    };
+    println!("Zone: {}", zone.name);
+
+    let records = cloudflare.list_a_records(&zone.id).await?;
+    anyhow::ensure!(
+        !records.is_empty(),
+        "There are no A records in {}, add the ones to update at Cloudflare first",
+        zone.name
+    );
+    let names = records
+        .iter()
+        .map(|(name, record)| format!("{name} ({})", record.ip))
+        .collect::<Vec<_>>();
+    let domains = loop {
+        list(&names);
+        match parse_choice(
+            &ask("Domains to update, by number and separated by commas [all]: ")?,
+            names.len(),
+        ) {
+            Some(indices) => {
+                break indices
+                    .into_iter()
+                    .map(|index| records[index].0.clone())
+                    .collect::<Vec<_>>()
+            }
+            None => println!("Pick the domains by their numbers, like 1,3."),
+        }
+    };
+
+    let setup = Setup {
+        api_key,
+        zone_id: zone.id.clone(),
+        domains,
+    };
+    write(path, profile, &setup)?;
+    println!("Saved the settings in {}", path.display());
+
+    Ok(setup)
+}
+
+/// Writes the settings, so only the owner can read the file, as the API token is in it.
+fn write(path: &Path, profile: Option<&str>, setup: &Setup) -> anyhow::Result<()> {
+    settings::set(
+        path,
+        profile,
+        "api_key",
+        std::slice::from_ref(&setup.api_key),
+    )?;
+    settings::set(
+        path,
+        profile,
+        "zone_id",
+        std::slice::from_ref(&setup.zone_id),
+    )?;
+    settings::set(path, profile, "domain", &setup.domains)?;
+
+    #[cfg(unix)]
+    {
+        use anyhow::Context;
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to change the permissions of: {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn list(names: &[String]) {
+    for (index, name) in names.iter().enumerate() {
+        println!("{:>3}. {name}", index + 1);
+    }
+}
+
+/// Parses the numbers of the picked items, out of `count`, as indices. Nothing picks them all.
+fn parse_choice(answer: &str, count: usize) -> Option<Vec<usize>> {
+    if answer.trim().is_empty() {
+        return Some((0..count).collect());
+    }
+
+    let mut indices = Vec::new();
+    for number in answer.split(',').map(str::trim) {
+        let index = number.parse::<usize>().ok()?.checked_sub(1)?;
+        if index >= count {
+            return None;
+        }
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+    }
+
+    Some(indices)
+}
+
+fn ask(prompt: &str) -> anyhow::Result<String> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    let read = io::stdin().lock().read_line(&mut answer)?;
+    anyhow::ensure!(read > 0, "No answer given");
+
+    Ok(answer.trim().to_string())
+}
+
+/// Asks for a secret, without showing it as it's typed when it's typed in a terminal.
+fn read_secret(prompt: &str) -> anyhow::Result<String> {
+    if !io::stdin().is_terminal() {
+        return ask(prompt);
+    }
+    print!("{prompt}");
+    io::stdout().flush()?;
+
+    terminal::enable_raw_mode()?;
+    let mut secret = String::new();
+    let read = loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.into()),
+        };
+        match key.code {
+            KeyCode::Enter => break Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                break Err(anyhow::anyhow!("Cancelled"));
+            }
+            KeyCode::Char(c) => secret.push(c),
+            KeyCode::Backspace => {
+                secret.pop();
+            }
+            _ => {}
+        }
+    };
+    let _ = terminal::disable_raw_mode();
+    println!();
+
+    read.map(|()| secret.trim().to_string())
+}
+
+#[test]
+fn test_parse_choice() {
+    assert_eq!(parse_choice("", 3), Some(vec![0, 1, 2]));
+    assert_eq!(parse_choice("3, 1,3", 3), Some(vec![2, 0]));
+    assert_eq!(parse_choice("0", 3), None);
+    assert_eq!(parse_choice("4", 3), None);
+    assert_eq!(parse_choice("one", 3), None);
+}
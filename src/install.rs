@@ -0,0 +1,529 @@
+//! Generates the files needed to run cdu as a service, or periodically.
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use tracing::{info, warn};
+
+const SERVICE_FILE: &str = "cdu.service";
+const TIMER_FILE: &str = "cdu.timer";
+const LAUNCHD_LABEL: &str = "com.github.agingorange.cdu";
+
+/// What goes into the generated files.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The cdu executable to run.
+    pub executable: PathBuf,
+    /// The directory the configuration file is saved in. The service is only allowed to write
+    /// here.
+    pub config_dir: PathBuf,
+    /// The environment file with the settings, if any.
+    pub env_file: Option<PathBuf>,
+    /// The user to run cdu as.
+    pub user: Option<String>,
+    /// For systemd, generate a one-shot service with a timer, instead of a service running the
+    /// daemon. The other schedulers always run cdu periodically.
+    pub timer: bool,
+    /// How often cdu is run.
+    pub interval: Duration,
+    /// The maximum random delay the scheduler adds to each run, if supported.
+    pub jitter: Option<Duration>,
+}
+
+impl Options {
+    /// Returns the contents of `cdu.service`.
+    #[must_use]
+    pub fn service_unit(&self) -> String {
+        let mut unit = String::new();
+        let executable = self.executable.display();
+
+        unit.push_str(
+            "[Unit]\n\
+             Description=Cloudflare DNS Updater\n\
+             Documentation=https://github.com/agingorange/cdu\n\
+             Wants=network-online.target\n\
+             After=network-online.target\n\
+             \n\
+             [Service]\n",
+        );
+
+        if self.timer {
+            unit.push_str("Type=oneshot\n");
+            let _ = writeln!(unit, "ExecStart={executable}");
+        } else {
+            unit.push_str("Type=notify\n");
+            let _ = writeln!(unit, "ExecStart={executable} daemon");
+            unit.push_str(
+                "ExecReload=/bin/kill -HUP $MAINPID\n\
+                 Restart=on-failure\n\
+                 RestartSec=30s\n\
+                 WatchdogSec=5min\n",
+            );
+        }
+
+        if let Some(env_file) = &self.env_file {
+            let _ = writeln!(unit, "EnvironmentFile={}", env_file.display());
+        }
+        let _ = writeln!(
+            unit,
+            "Environment=CDU_CONFIG_DIR={}",
+            self.config_dir.display()
+        );
+        if let Some(user) = &self.user {
+            let _ = writeln!(unit, "User={user}");
+        }
+
+        // Hardening. The service only needs to write its configuration file, and talk to the
+        // outside world.
+        let _ = writeln!(unit, "ReadWritePaths={}", self.config_dir.display());
+        let protect_home = if self.uses_home() { "read-only" } else { "yes" };
+        let _ = writeln!(unit, "ProtectHome={protect_home}");
+        unit.push_str(
+            "ProtectSystem=strict\n\
+             PrivateTmp=yes\n\
+             PrivateDevices=yes\n\
+             NoNewPrivileges=yes\n\
+             CapabilityBoundingSet=\n\
+             AmbientCapabilities=\n\
+             ProtectKernelTunables=yes\n\
+             ProtectKernelModules=yes\n\
+             ProtectKernelLogs=yes\n\
+             ProtectControlGroups=yes\n\
+             ProtectClock=yes\n\
+             ProtectHostname=yes\n\
+             RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX AF_NETLINK\n\
+             RestrictNamespaces=yes\n\
+             RestrictRealtime=yes\n\
+             RestrictSUIDSGID=yes\n\
+             LockPersonality=yes\n\
+             MemoryDenyWriteExecute=yes\n\
+             SystemCallArchitectures=native\n\
+             SystemCallFilter=@system-service\n\
+             SystemCallFilter=~@privileged @resources\n\
+             UMask=0077\n",
+        );
+
+        if !self.timer {
+            unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+        }
+
+        unit
+    }
+
+    /// Returns the contents of `cdu.timer`.
+    #[must_use]
+    pub fn timer_unit(&self) -> String {
+        let mut unit = String::from(
+            "[Unit]\n\
+             Description=Run the Cloudflare DNS Updater periodically\n\
+             \n\
+             [Timer]\n\
+             OnBootSec=1min\n",
+        );
+
+        let _ = writeln!(unit, "OnUnitActiveSec={}s", self.interval.as_secs().max(1));
+        if let Some(jitter) = self.jitter {
+            let _ = writeln!(unit, "RandomizedDelaySec={}s", jitter.as_secs());
+        }
+        let _ = writeln!(unit, "Unit={SERVICE_FILE}");
+
+        unit.push_str("\n[Install]\nWantedBy=timers.target\n");
+
+        unit
+    }
+
+    /// Returns the contents of the launchd property list, which runs cdu every interval.
+    ///
+    /// launchd can't load an environment file, so cdu runs in the directory of the environment
+    /// file, and picks it up from there. This only works if it's called `.env`.
+    #[must_use]
+    pub fn launchd_plist(&self) -> String {
+        let mut plist = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n",
+        );
+        let log_file = self.config_dir.join("cdu.log");
+
+        let _ = writeln!(
+            plist,
+            "    <key>Label</key>\n    <string>{LAUNCHD_LABEL}</string>"
+        );
+        plist.push_str("    <key>ProgramArguments</key>\n    <array>\n");
+        for argument in self.arguments() {
+            let _ = writeln!(plist, "        <string>{}</string>", escape_xml(&argument));
+        }
+        plist.push_str("    </array>\n");
+        let _ = writeln!(
+            plist,
+            "    <key>WorkingDirectory</key>\n    <string>{}</string>",
+            escape_xml(&self.working_dir().to_string_lossy())
+        );
+        let _ = writeln!(
+            plist,
+            "    <key>StartInterval</key>\n    <integer>{}</integer>",
+            self.interval.as_secs().max(1)
+        );
+        plist.push_str("    <key>RunAtLoad</key>\n    <true/>\n");
+        for key in ["StandardOutPath", "StandardErrorPath"] {
+            let _ = writeln!(
+                plist,
+                "    <key>{key}</key>\n    <string>{}</string>",
+                escape_xml(&log_file.to_string_lossy())
+            );
+        }
+        if let Some(user) = &self.user {
+            let _ = writeln!(
+                plist,
+                "    <key>UserName</key>\n    <string>{}</string>",
+                escape_xml(user)
+            );
+        }
+        plist.push_str("</dict>\n</plist>\n");
+
+        plist
+    }
+
+    /// Returns the Task Scheduler definition, which runs cdu every interval from `start` on.
+    ///
+    /// Just like launchd, Task Scheduler can't load an environment file, so cdu runs in the
+    /// directory of the environment file.
+    #[must_use]
+    pub fn task_xml(&self, start: DateTime<Local>) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\n\
+             <Task version=\"1.2\" xmlns=\"http://schemas.microsoft.com/windows/2004/02/mit/task\">\n\
+             \x20 <RegistrationInfo>\n",
+        );
+        let _ = writeln!(
+            xml,
+            "    <Description>{}</Description>",
+            escape_xml(clap::crate_description!())
+        );
+        xml.push_str(
+            "  </RegistrationInfo>\n  <Triggers>\n    <TimeTrigger>\n      <Repetition>\n",
+        );
+        // Task Scheduler doesn't repeat more often than once a minute
+        let _ = writeln!(
+            xml,
+            "        <Interval>PT{}M</Interval>",
+            (self.interval.as_secs() / 60).max(1)
+        );
+        xml.push_str("        <StopAtDurationEnd>false</StopAtDurationEnd>\n      </Repetition>\n");
+        let _ = writeln!(
+            xml,
+            "      <StartBoundary>{}</StartBoundary>",
+            start.format("%Y-%m-%dT%H:%M:%S")
+        );
+        if let Some(jitter) = self.jitter {
+            let _ = writeln!(
+                xml,
+                "      <RandomDelay>PT{}S</RandomDelay>",
+                jitter.as_secs()
+            );
+        }
+        xml.push_str(
+            "      <Enabled>true</Enabled>\n    </TimeTrigger>\n  </Triggers>\n  <Settings>\n\
+             \x20   <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>\n\
+             \x20   <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>\n\
+             \x20   <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>\n\
+             \x20   <StartWhenAvailable>true</StartWhenAvailable>\n\
+             \x20   <ExecutionTimeLimit>PT10M</ExecutionTimeLimit>\n\
+             \x20   <Enabled>true</Enabled>\n  </Settings>\n  <Actions>\n    <Exec>\n",
+        );
+
+        let mut arguments = self.arguments().into_iter();
+        let command = arguments.next().unwrap_or_default();
+        let arguments = arguments
+            .map(|argument| format!("\"{argument}\""))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(xml, "      <Command>{}</Command>", escape_xml(&command));
+        let _ = writeln!(
+            xml,
+            "      <Arguments>{}</Arguments>",
+            escape_xml(&arguments)
+        );
+        let _ = writeln!(
+            xml,
+            "      <WorkingDirectory>{}</WorkingDirectory>",
+            escape_xml(&self.working_dir().to_string_lossy())
+        );
+        xml.push_str("    </Exec>\n  </Actions>\n</Task>\n");
+
+        xml
+    }
+
+    /// Returns the command line that runs cdu once, for the schedulers that can't set environment
+    /// variables.
+    fn arguments(&self) -> Vec<String> {
+        vec![
+            self.executable.to_string_lossy().into_owned(),
+            String::from("--config-dir"),
+            self.config_dir.to_string_lossy().into_owned(),
+        ]
+    }
+
+    /// Returns the directory to run cdu in, so it finds its environment file.
+    fn working_dir(&self) -> &Path {
+        self.env_file
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(&self.config_dir)
+    }
+
+    /// Warns if the environment file won't be picked up from the working directory.
+    fn check_env_file_name(&self) {
+        if let Some(env_file) = &self.env_file {
+            if env_file.file_name() != Some(OsStr::new(".env")) {
+                warn!(
+                    "The environment file has to be called .env to be picked up: {}",
+                    env_file.display()
+                );
+            }
+        }
+    }
+
+    /// Returns whether any of the paths the service needs is in a home directory, which would be
+    /// hidden with `ProtectHome=yes`.
+    fn uses_home(&self) -> bool {
+        let in_home = |path: &Path| {
+            ["/home", "/root", "/run/user"]
+                .iter()
+                .any(|home| path.starts_with(home))
+        };
+
+        in_home(&self.executable)
+            || in_home(&self.config_dir)
+            || self.env_file.as_deref().is_some_and(in_home)
+    }
+}
+
+/// Writes the systemd units to `dir`, or prints them if `print` is set.
+///
+/// # Errors
+///
+/// Returns an error if a unit already exists and `force` isn't set, or if a unit cannot be
+/// written.
+pub fn systemd(options: &Options, dir: &Path, print: bool, force: bool) -> anyhow::Result<()> {
+    let mut units = vec![(SERVICE_FILE, options.service_unit())];
+    if options.timer {
+        units.push((TIMER_FILE, options.timer_unit()));
+    }
+
+    if print {
+        for (name, contents) in &units {
+            println!("# {name}\n{contents}");
+        }
+
+        return Ok(());
+    }
+
+    write_files(dir, &units, force)?;
+
+    let enable = if options.timer {
+        TIMER_FILE
+    } else {
+        SERVICE_FILE
+    };
+    println!("Done. To start cdu, run:\n\n    systemctl daemon-reload\n    systemctl enable --now {enable}");
+
+    Ok(())
+}
+
+/// Writes the launchd property list to `dir`, or prints it if `print` is set.
+///
+/// # Errors
+///
+/// Returns an error if the file already exists and `force` isn't set, or if it cannot be written.
+pub fn launchd(options: &Options, dir: &Path, print: bool, force: bool) -> anyhow::Result<()> {
+    options.check_env_file_name();
+    let plist = options.launchd_plist();
+
+    if print {
+        print!("{plist}");
+
+        return Ok(());
+    }
+
+    let name = format!("{LAUNCHD_LABEL}.plist");
+    write_files(dir, &[(&name, plist)], force)?;
+
+    println!(
+        "Done. To start cdu, run:\n\n    launchctl load -w {}",
+        dir.join(&name).display()
+    );
+
+    Ok(())
+}
+
+/// Registers the scheduled task called `name` with `schtasks`, or prints its definition if `print`
+/// is set. Without a user, the task runs as the current user, whenever they're logged on.
+///
+/// # Errors
+///
+/// Returns an error if the task already exists and `force` isn't set, or if `schtasks` fails.
+pub fn task(options: &Options, name: &str, print: bool, force: bool) -> anyhow::Result<()> {
+    options.check_env_file_name();
+    let xml = options.task_xml(Local::now());
+
+    if print {
+        print!("{xml}");
+
+        return Ok(());
+    }
+
+    // schtasks wants the definition in UTF-16, with a byte order mark
+    let mut file = tempfile::Builder::new()
+        .suffix(".xml")
+        .tempfile()
+        .context("Failed to create a temporary file for the task definition")?;
+    let utf16 = std::iter::once(0xfeff)
+        .chain(xml.encode_utf16())
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    file.write_all(&utf16)
+        .context("Failed to write the task definition")?;
+
+    let mut command = Command::new("schtasks");
+    command
+        .arg("/Create")
+        .arg("/TN")
+        .arg(name)
+        .arg("/XML")
+        .arg(file.path());
+    if let Some(user) = &options.user {
+        command.arg("/RU").arg(user);
+    }
+    if force {
+        command.arg("/F");
+    }
+
+    let status = command
+        .status()
+        .context("Failed to run schtasks, this only works on Windows")?;
+    if !status.success() {
+        anyhow::bail!("schtasks failed: {status}");
+    }
+
+    println!(
+        "Done. The task {name} runs cdu every {}",
+        humantime::format_duration(options.interval)
+    );
+
+    Ok(())
+}
+
+/// Writes each `(name, contents)` pair to a file in `dir`. Nothing is written if one of them
+/// already exists, unless `force` is set.
+fn write_files(dir: &Path, files: &[(&str, String)], force: bool) -> anyhow::Result<()> {
+    for (name, _) in files {
+        let path = dir.join(name);
+        if path.exists() && !force {
+            anyhow::bail!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            );
+        }
+    }
+
+    for (name, contents) in files {
+        let path = dir.join(name);
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write file: {}", path.display()))?;
+        info!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn test_systemd_units() {
+    let mut options = Options {
+        executable: PathBuf::from("/usr/local/bin/cdu"),
+        config_dir: PathBuf::from("/var/lib/cdu"),
+        env_file: Some(PathBuf::from("/etc/cdu/cdu.env")),
+        user: None,
+        timer: false,
+        interval: Duration::from_secs(300),
+        jitter: None,
+    };
+
+    // The daemon
+    let service = options.service_unit();
+    assert!(service.contains("Type=notify\n"));
+    assert!(service.contains("ExecStart=/usr/local/bin/cdu daemon\n"));
+    assert!(service.contains("EnvironmentFile=/etc/cdu/cdu.env\n"));
+    assert!(service.contains("Environment=CDU_CONFIG_DIR=/var/lib/cdu\n"));
+    assert!(service.contains("ReadWritePaths=/var/lib/cdu\n"));
+    assert!(service.contains("ProtectHome=yes\n"));
+    assert!(service.contains("WantedBy=multi-user.target\n"));
+    assert!(!service.contains("User="));
+
+    // A one-shot service with a timer, for a user with everything in their home directory
+    options.timer = true;
+    options.config_dir = PathBuf::from("/home/me/.cdu");
+    options.user = Some(String::from("me"));
+    options.jitter = Some(Duration::from_secs(30));
+    let service = options.service_unit();
+    assert!(service.contains("Type=oneshot\n"));
+    assert!(service.contains("ExecStart=/usr/local/bin/cdu\n"));
+    assert!(service.contains("User=me\n"));
+    assert!(service.contains("ProtectHome=read-only\n"));
+    assert!(!service.contains("[Install]"));
+    assert!(!service.contains("WatchdogSec"));
+
+    let timer = options.timer_unit();
+    assert!(timer.contains("OnUnitActiveSec=300s\n"));
+    assert!(timer.contains("RandomizedDelaySec=30s\n"));
+    assert!(timer.contains("WantedBy=timers.target\n"));
+}
+
+#[test]
+fn test_launchd_plist_and_task_xml() {
+    let options = Options {
+        executable: PathBuf::from("/usr/local/bin/cdu"),
+        config_dir: PathBuf::from("/Users/me/cdu & co"),
+        env_file: Some(PathBuf::from("/Users/me/cdu & co/.env")),
+        user: None,
+        timer: false,
+        interval: Duration::from_secs(600),
+        jitter: Some(Duration::from_secs(30)),
+    };
+
+    let plist = options.launchd_plist();
+    assert!(plist.contains("<string>com.github.agingorange.cdu</string>"));
+    assert!(plist.contains("<string>/usr/local/bin/cdu</string>\n        <string>--config-dir</string>\n        <string>/Users/me/cdu &amp; co</string>"));
+    assert!(
+        plist.contains("<key>WorkingDirectory</key>\n    <string>/Users/me/cdu &amp; co</string>")
+    );
+    assert!(plist.contains("<key>StartInterval</key>\n    <integer>600</integer>"));
+    assert!(!plist.contains("UserName"));
+
+    let start = Local::now();
+    let xml = options.task_xml(start);
+    assert!(xml.contains("<Interval>PT10M</Interval>"));
+    assert!(xml.contains("<RandomDelay>PT30S</RandomDelay>"));
+    assert!(xml.contains(&format!(
+        "<StartBoundary>{}</StartBoundary>",
+        start.format("%Y-%m-%dT%H:%M:%S")
+    )));
+    assert!(xml.contains("<Command>/usr/local/bin/cdu</Command>"));
+    assert!(xml.contains(
+        "<Arguments>&quot;--config-dir&quot; &quot;/Users/me/cdu &amp; co&quot;</Arguments>"
+    ));
+}
@@ -0,0 +1,111 @@
+//! Updates the A record of a domain at Cloudflare with the current outside IP address, for Rust
+//! programs that would rather do that themselves than run the `cdu` binary, like the firmware of a
+//! router or a home automation daemon.
+//!
+//! What's here is everything the binary updates with: [`network`] detects the outside IP,
+//! [`cloudflare`] looks up and changes the A records, [`config`] keeps the state between runs,
+//! [`notify`] tells about the changes, and [`updater`] puts these together into a run, which
+//! [`daemon`] repeats on a schedule, with [`status`] and [`api`] to tell how it's going. Only the
+//! command line, and the settings file and the logging it sets up, stay with the binary.
+//!
+//! ```no_run
+//! use cdu::config::Config;
+//! use cdu::updater::Updater;
+//!
+//! # async fn update() -> anyhow::Result<()> {
+//! let config = Config {
+//!     stateless: true,
+//!     ..Config::default()
+//! };
+//! let mut updater = Updater::try_new("<token>", "<zone ID>", &["home.example.com"], false, config)?;
+//! println!("{}", updater.run().await?);
+//! # Ok(())
+//! # }
+//! ```
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("cdu needs TLS, build it with the rustls or the native-tls feature");
+
+pub mod api;
+pub mod apprise;
+pub mod audit;
+pub mod breaker;
+pub mod check;
+pub mod cloud_secret;
+pub mod cloudflare;
+pub mod config;
+pub mod confirm;
+pub mod crypt;
+pub mod crypto;
+pub mod daemon;
+pub mod dnsomatic;
+pub mod doctor;
+pub mod dyndns;
+pub mod email;
+#[cfg(windows)]
+pub mod event_log;
+pub mod exit;
+pub mod geoip;
+pub mod gotify;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod healthchecks;
+pub mod hint;
+pub mod history;
+pub mod hooks;
+pub mod import;
+pub mod install;
+pub mod lock;
+pub mod log_file;
+pub mod matrix;
+pub mod metrics;
+pub mod monitor;
+pub mod mqtt;
+#[cfg(target_os = "linux")]
+pub mod netlink;
+pub mod network;
+#[cfg(all(target_os = "linux", feature = "networkmanager"))]
+pub mod networkmanager;
+pub mod notify;
+pub mod ntfy;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod output;
+pub mod password_manager;
+pub mod plan;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod ptr;
+pub mod push_metrics;
+pub mod pushover;
+pub mod queue;
+pub mod rate_limit;
+pub mod redact;
+pub mod retry;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "keyring")]
+pub mod secret;
+#[cfg(feature = "self-update")]
+pub mod self_update;
+#[cfg(windows)]
+pub mod service;
+#[cfg(unix)]
+pub mod signals;
+pub mod simulate;
+pub mod slack;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod status;
+pub mod synology;
+pub mod syslog;
+pub mod systemd;
+pub mod telegram;
+pub mod throttle;
+pub mod tui;
+pub mod updater;
+pub mod uptime_kuma;
+pub mod validate;
+pub mod vault;
+pub mod watch;
+pub mod webhook;
@@ -0,0 +1,74 @@
+//! Keeps two runs of cdu from racing over the state and the A records, like a timer that fires
+//! while the last run is still going, or a run while the daemon is.
+//!
+//! The lock is an advisory one on `cdu.lock` in the state directory, which the system releases
+//! when cdu exits, however it exits, so a crash never leaves it behind.
+use std::fs::{self, File};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use fs4::TryLockError;
+use tracing::debug;
+
+const LOCK_FILE: &str = "cdu.lock";
+
+/// How often to try again, while waiting for the lock.
+const RETRY_EVERY: Duration = Duration::from_millis(200);
+
+/// The lock on the state directory, which is held until it's dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Locks the state in `dir`, waiting up to `wait` for another instance to let go of it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lock file cannot be opened, or another instance still holds the lock
+    /// after `wait`.
+    pub fn acquire(dir: &Path, wait: Duration) -> anyhow::Result<Self> {
+        let path = dir.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file: {}", path.display()))?;
+
+        let deadline = Instant::now() + wait;
+        loop {
+            // The trait's, as newer versions of std have a method of the same name
+            match fs4::FileExt::try_lock(&file) {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(RETRY_EVERY);
+                }
+                Err(TryLockError::WouldBlock) => anyhow::bail!(
+                    "Another cdu instance is running, with the state in {}",
+                    dir.display()
+                ),
+                Err(TryLockError::Error(e)) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to lock file: {}", path.display()));
+                }
+            }
+        }
+        debug!("Locked the state: {path:?}");
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[test]
+fn test_acquire() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let lock = Lock::acquire(dir.path(), Duration::ZERO).unwrap();
+    assert!(Lock::acquire(dir.path(), Duration::from_millis(300)).is_err());
+    drop(lock);
+    assert!(Lock::acquire(dir.path(), Duration::ZERO).is_ok());
+}
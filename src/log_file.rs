@@ -0,0 +1,198 @@
+//! Logs to a file instead of stderr, for appliances where nothing captures stderr, rotating it so it
+//! doesn't fill the disk.
+//!
+//! The file is rotated every hour or day, which `tracing-appender` does by adding the date to its
+//! name, or once it grows past a size, in which case the old ones are numbered like logrotate does:
+//! `cdu.log.1` is the most recent one. Only the newest `keep` old files are kept either way.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+/// When to start a new log file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    Hourly,
+    Daily,
+    /// Once the file would grow past this many bytes.
+    Size(u64),
+}
+
+impl Rotation {
+    /// Parses `never`, `hourly`, `daily`, or a size like `10MB`, `512KB` or `1GB`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it's neither of these, or the size is zero.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "never" => return Ok(Self::Never),
+            "hourly" => return Ok(Self::Hourly),
+            "daily" => return Ok(Self::Daily),
+            _ => {}
+        }
+
+        let unknown =
+            || format!("Unknown rotation: {value}, use never, hourly, daily or a size like 10MB");
+        let upper = value.trim().to_ascii_uppercase();
+        let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let unit = match upper[digits.len()..].trim_end_matches('B') {
+            "" => 1,
+            "K" => 1 << 10,
+            "M" => 1 << 20,
+            "G" => 1 << 30,
+            _ => return Err(unknown()),
+        };
+        match digits.trim().parse::<u64>() {
+            Ok(0) => Err(String::from("The size to rotate at cannot be zero")),
+            Ok(size) => Ok(Self::Size(size.saturating_mul(unit))),
+            Err(_) => Err(unknown()),
+        }
+    }
+}
+
+/// Returns a writer that appends to the file at `path`, and starts a new one on `rotation`, keeping
+/// `keep` of the old ones. The directory is created if it doesn't exist yet.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be created, or the file cannot be opened.
+pub fn writer(path: &Path, rotation: Rotation, keep: usize) -> anyhow::Result<BoxMakeWriter> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = path
+        .file_name()
+        .with_context(|| format!("The log file has no name: {}", path.display()))?;
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    let rotation = match rotation {
+        Rotation::Size(max_size) => {
+            let file = SizeRotating::open(dir.join(name), max_size, keep)?;
+            return Ok(BoxMakeWriter::new(Mutex::new(file)));
+        }
+        Rotation::Never => rolling::Rotation::NEVER,
+        Rotation::Hourly => rolling::Rotation::HOURLY,
+        Rotation::Daily => rolling::Rotation::DAILY,
+    };
+    let dated = rotation != rolling::Rotation::NEVER;
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name.to_string_lossy());
+    // Every file has the date in its name, the one that's being written to as well
+    if dated {
+        builder = builder.max_log_files(keep.saturating_add(1));
+    }
+    let appender = builder
+        .build(&dir)
+        .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+
+    Ok(BoxMakeWriter::new(appender))
+}
+
+/// A log file that's moved aside once it would grow past a size.
+struct SizeRotating {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl SizeRotating {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> anyhow::Result<Self> {
+        let file = append(&path)?;
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    /// Moves every old file one number up, dropping the oldest, and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(numbered(&self.path, self.keep));
+            for number in (1..self.keep).rev() {
+                let from = numbered(&self.path, number);
+                if from.exists() {
+                    fs::rename(from, numbered(&self.path, number + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+        }
+
+        self.file = append(&self.path).map_err(io::Error::other)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotating {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A line is never split over two files, even if it's bigger than a whole one
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Opens the file at `path` to append to it, creating it if it doesn't exist yet.
+fn append(path: &Path) -> anyhow::Result<File> {
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file: {}", path.display()))
+}
+
+/// Returns the path of the old file with `number`, like `cdu.log.1`.
+fn numbered(path: &Path, number: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{number}"));
+    PathBuf::from(name)
+}
+
+#[test]
+fn test_size_rotating() {
+    assert_eq!(Rotation::parse("daily"), Ok(Rotation::Daily));
+    assert_eq!(Rotation::parse("10MB"), Ok(Rotation::Size(10 << 20)));
+    assert_eq!(Rotation::parse("512k"), Ok(Rotation::Size(512 << 10)));
+    assert!(Rotation::parse("0MB").is_err());
+    assert!(Rotation::parse("weekly").is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cdu.log");
+    let mut file = SizeRotating::open(path.clone(), 10, 2).unwrap();
+    for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+    assert_eq!(fs::read_to_string(numbered(&path, 1)).unwrap(), "third\n");
+    assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "second\n");
+    assert!(!numbered(&path, 3).exists());
+}
@@ -0,0 +1,217 @@
+//! Sets up the logging from the arguments: what's logged, what it looks like, and where it goes,
+//! whether that's stderr, a file, syslog, journald, the Event Log or an OpenTelemetry collector.
+use std::path::PathBuf;
+#[cfg(windows)]
+use std::{env, fs};
+
+use anyhow::Context;
+use clap::ArgMatches;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, EnvFilter, FmtSubscriber};
+
+#[cfg(feature = "otel")]
+use cdu::otel;
+#[cfg(windows)]
+use cdu::{event_log, service};
+use cdu::{log_file, redact, syslog, tui};
+
+/// Sets up where the logs go and what they look like, from the arguments.
+///
+/// # Errors
+///
+/// Returns an error if where the logs should go cannot be written to.
+pub fn start_logging(arg_matches: &ArgMatches) -> anyhow::Result<()> {
+    if arg_matches.get_flag("journald") {
+        return journald(arg_matches, log_filter(arg_matches));
+    }
+
+    let (writer, ansi) = log_writer(arg_matches)?;
+    let builder = FmtSubscriber::builder()
+        .with_env_filter(log_filter(arg_matches))
+        .with_writer(redact::Redacting::new(writer));
+    let event_log = event_log(arg_matches);
+    let result = match arg_matches
+        .get_one::<String>("log_format")
+        .map(String::as_str)
+    {
+        Some("json") => tracing::subscriber::set_global_default(
+            builder
+                .json()
+                .flatten_event(true)
+                .with_ansi(false)
+                .finish()
+                .with(event_log)
+                .with(otel(arg_matches)?),
+        ),
+        _ => tracing::subscriber::set_global_default(
+            builder
+                .fmt_fields(fmt::format::PrettyFields::new())
+                .event_format(fmt::format())
+                .without_time()
+                .with_ansi(ansi)
+                .finish()
+                .with(event_log)
+                .with(otel(arg_matches)?),
+        ),
+    };
+
+    result.context("Failed to set up the logging")
+}
+
+/// Logs to journald, with the fields of every event, like `DOMAIN`, `OLD_IP`, `NEW_IP` and `EVENT`,
+/// as fields of its entry, so they can be queried with `journalctl -o json`.
+///
+/// # Errors
+///
+/// Returns an error if journald cannot be reached.
+#[cfg(target_os = "linux")]
+fn journald(arg_matches: &ArgMatches, filter: EnvFilter) -> anyhow::Result<()> {
+    let layer = tracing_journald::layer()
+        .context("Failed to connect to journald")?
+        .with_field_prefix(None)
+        .with_syslog_identifier(String::from("cdu"));
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(layer)
+            .with(otel(arg_matches)?),
+    )
+    .context("Failed to set up the logging")
+}
+
+/// Logging to journald needs systemd, which only runs on Linux.
+#[cfg(not(target_os = "linux"))]
+fn journald(_arg_matches: &ArgMatches, _filter: EnvFilter) -> anyhow::Result<()> {
+    anyhow::bail!("Logging to journald is only supported on Linux")
+}
+
+/// Returns the layer that writes the important events to the Windows Event Log, which the service
+/// always does, and anything else with `--event-log`. Failing to open it isn't a reason not to run.
+#[cfg(windows)]
+fn event_log(arg_matches: &ArgMatches) -> Option<event_log::EventLog> {
+    let service = service::is_service_run(&env::args_os().collect::<Vec<_>>());
+    if !service && !arg_matches.get_flag("event_log") {
+        return None;
+    }
+
+    match event_log::EventLog::open() {
+        Ok(event_log) => Some(event_log),
+        Err(e) => {
+            eprintln!("Warning: {e:#}");
+            None
+        }
+    }
+}
+
+/// There's only an Event Log on Windows.
+#[cfg(not(windows))]
+fn event_log(_arg_matches: &ArgMatches) -> Option<tracing_subscriber::layer::Identity> {
+    None
+}
+
+/// Returns the layer that exports the spans to the OpenTelemetry collector in `--otlp-endpoint`, if
+/// there is one.
+///
+/// # Errors
+///
+/// Returns an error if the exporter cannot be set up.
+#[cfg(feature = "otel")]
+fn otel<S>(
+    arg_matches: &ArgMatches,
+) -> anyhow::Result<
+    Option<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>,
+>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let headers = arg_matches
+        .get_many::<(String, String)>("otlp_header")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    arg_matches
+        .get_one::<reqwest::Url>("otlp_endpoint")
+        .map(|endpoint| otel::layer(endpoint, &headers))
+        .transpose()
+}
+
+/// Exporting the spans needs the `otel` feature.
+#[cfg(not(feature = "otel"))]
+#[allow(clippy::unnecessary_wraps)]
+fn otel(_arg_matches: &ArgMatches) -> anyhow::Result<Option<tracing_subscriber::layer::Identity>> {
+    Ok(None)
+}
+
+/// Returns what to log: the level from `-v` or `-q` if either is given, or `RUST_LOG`, which is only
+/// the errors if it isn't set.
+fn log_filter(arg_matches: &ArgMatches) -> EnvFilter {
+    let level = match (
+        arg_matches.get_flag("quiet"),
+        arg_matches.get_count("verbose"),
+    ) {
+        (true, _) => Some(LevelFilter::ERROR),
+        (false, 0) => None,
+        (false, 1) => Some(LevelFilter::INFO),
+        (false, 2) => Some(LevelFilter::DEBUG),
+        (false, _) => Some(LevelFilter::TRACE),
+    };
+
+    level.map_or_else(EnvFilter::from_default_env, |level| {
+        EnvFilter::new(level.to_string())
+    })
+}
+
+/// Returns where to log to, and whether colors can be used. That's the file from `--log-file` or
+/// syslog from `--syslog` if either is given, or stderr, or the screen of `cdu tui` while it's
+/// showing, except for the Windows service, which doesn't have one, and logs to a file in its data
+/// directory instead.
+///
+/// # Errors
+///
+/// Returns an error if the file from `--log-file` cannot be opened, or syslog cannot be reached.
+fn log_writer(arg_matches: &ArgMatches) -> anyhow::Result<(BoxMakeWriter, bool)> {
+    if let Some(path) = arg_matches.get_one::<PathBuf>("log_file") {
+        // A value that isn't valid is reported once the arguments are parsed for real
+        let rotation = arg_matches
+            .get_one::<log_file::Rotation>("log_rotate")
+            .copied()
+            .unwrap_or(log_file::Rotation::Daily);
+        let keep = arg_matches
+            .get_one::<usize>("log_keep")
+            .copied()
+            .unwrap_or(7);
+        return Ok((log_file::writer(path, rotation, keep)?, false));
+    }
+    if let Some(target) = arg_matches.get_one::<syslog::Target>("syslog") {
+        let facility = arg_matches
+            .get_one::<u8>("syslog_facility")
+            .copied()
+            .unwrap_or(3);
+        let tag = arg_matches
+            .get_one::<String>("syslog_tag")
+            .map_or("cdu", String::as_str);
+        let syslog = syslog::Syslog::connect(target, facility, tag)?;
+        return Ok((BoxMakeWriter::new(syslog), false));
+    }
+
+    #[cfg(windows)]
+    if service::is_service_run(&env::args_os().collect::<Vec<_>>()) {
+        let data_dir = service::data_dir();
+        let file = fs::create_dir_all(&data_dir).and_then(|()| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(data_dir.join("cdu.log"))
+        });
+        if let Ok(file) = file {
+            return Ok((BoxMakeWriter::new(std::sync::Mutex::new(file)), false));
+        }
+    }
+
+    Ok((BoxMakeWriter::new(|| tui::LogWriter), true))
+}
@@ -1,182 +1,1223 @@
 //! This Rust program is a command-line utility for updating the A record of a domain on Cloudflare
-//! to match the current outside IP address.
-use std::io;
+//! to match the current outside IP address. The updating itself is done by the library, see
+//! [`cdu`], daemon and all, and what's here is the command line: the arguments, the settings file
+//! and the logging, which the commands hand over to the library.
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use anyhow::bail;
-use clap::{command, crate_description, crate_version, Arg, ArgAction, ArgMatches};
-use reqwest::blocking::Client as RqClient;
-use tracing::{debug, error, info};
-use tracing_subscriber::{fmt, EnvFilter, FmtSubscriber};
+use anyhow::Context;
+use clap::{Arg, ArgMatches};
+use clap_complete::Shell;
+use tracing::{debug, info, warn};
 
-use crate::config::Config;
-use crate::network::get_outside_ip;
+use cdu::config::{self, Config};
+use cdu::daemon::{self, Schedule};
+use cdu::lock::Lock;
+#[cfg(feature = "otel")]
+use cdu::otel;
+use cdu::output::{self, Output};
+#[cfg(feature = "keyring")]
+use cdu::secret;
+#[cfg(feature = "self-update")]
+use cdu::self_update;
+use cdu::simulate::Simulation;
+use cdu::updater::{self, Outcome, Updater};
+use cdu::{
+    api, audit, cloud_secret, crypt, dnsomatic, doctor, dyndns, exit, geoip, hint, hooks, metrics,
+    mqtt, network, notify, password_manager, ptr, push_metrics, redact, retry, synology, tui,
+    validate, vault, webhook,
+};
+#[cfg(windows)]
+use cdu::{confirm, service};
 
-mod cloudflare;
-mod config;
-mod network;
-mod webhook;
+use crate::args::{cli, daemon_args};
+use crate::env_file::EnvFile;
+
+mod args;
+mod commands;
+mod env_file;
+mod init;
+mod logging;
+mod man;
+mod settings;
 
 fn main() {
-    let subscriber = FmtSubscriber::builder()
-        .fmt_fields(fmt::format::PrettyFields::new())
-        .event_format(fmt::format())
-        .without_time()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_writer(io::stderr)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-
-    match app() {
-        Ok(()) => {}
+    // The arguments are looked at before they're parsed for real, as the logging starts first
+    let early_matches = cli().ignore_errors(true).get_matches();
+    if let Err(e) = logging::start_logging(&early_matches) {
+        eprintln!("Error: {e:#}");
+        std::process::exit(exit::CONFIG_INVALID);
+    }
+
+    let code = match app() {
+        Ok(code) => code,
         Err(e) => {
             eprintln!("Error: {e}");
-            std::process::exit(1);
+            if let Some(hint) = hint::of(&e) {
+                eprintln!("Hint: {hint}");
+            }
+            exit::code(&e)
         }
-    }
+    };
+
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+    std::process::exit(code);
 }
 
+/// Runs the command, and returns the code to exit with, see [`exit`].
 #[tracing::instrument]
-fn app() -> anyhow::Result<()> {
-    dotenvy::dotenv()?;
+fn app() -> anyhow::Result<i32> {
+    let env_file = EnvFile::find().map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
+    let secrets = secrets_dir().map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
+    secret_files().map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
+    remote_secret().map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
+    let settings = settings_file().map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
+    // Whatever set the token already wins over the credential store
+    #[cfg(feature = "keyring")]
+    secret::load(profile().as_deref()).map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
 
-    let arg_matches = parse_args();
-    let api_key = arg_matches.get_one::<String>("api_key").unwrap();
-    let zone_id = arg_matches.get_one::<String>("zone_id").unwrap();
-    let domain = arg_matches.get_one::<String>("domain").unwrap();
-    let dry_run = arg_matches.get_flag("dry_run");
+    let arg_matches = cli().try_get_matches().unwrap_or_else(|e| {
+        // Help and the version are printed the usual way
+        if !e.use_stderr() {
+            e.exit();
+        }
+        let _ = e.print();
+        std::process::exit(exit::CONFIG_INVALID);
+    });
+    hide_secrets(&arg_matches);
 
-    if dry_run {
-        debug!("Performing dry run");
-    }
+    let result = match arg_matches.subcommand() {
+        Some(("install", install_matches)) => commands::install(
+            &arg_matches,
+            install_matches,
+            env_file
+                .as_ref()
+                .map(|env_file| env_file.path().to_path_buf()),
+        ),
+        Some(("daemon", daemon_matches)) => {
+            let options = daemon_options(daemon_matches, env_file.as_ref())?;
+            let (mut env_file, mut secrets, mut settings) = (env_file, secrets, settings);
 
-    let mut config = Config::default();
-    config.load()?;
+            let _lock = lock(&arg_matches)?;
+            let updater =
+                build_updater(&arg_matches).map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?;
 
-    if let Some(config_dir) = arg_matches.get_one::<String>("config_dir") {
-        debug!("Setting config directory to: {config_dir}");
-        config.save_dir = config_dir.into();
-    }
+            runtime()?.block_on(wait_for_network(&arg_matches));
+            runtime()?.block_on(daemon::run(updater, options, move || {
+                reload(env_file.as_mut(), secrets.as_mut(), settings.as_mut())
+            }))
+        }
+        Some(("notify", _)) => runtime()?.block_on(commands::test_notify(&arg_matches)),
+        Some(("check", _)) => {
+            if !runtime()?.block_on(commands::check(&arg_matches))? {
+                return Ok(exit::DRIFT);
+            }
+            Ok(())
+        }
+        Some(("plan", plan_matches)) => {
+            runtime()?.block_on(commands::plan(&arg_matches, plan_matches))
+        }
+        Some(("apply", apply_matches)) => {
+            let _lock = lock(&arg_matches)?;
+            runtime()?.block_on(commands::apply(&arg_matches, apply_matches))
+        }
+        Some(("status", status_matches)) => {
+            if !runtime()?.block_on(commands::status(&arg_matches, status_matches))? {
+                return Ok(exit::FAILED);
+            }
+            Ok(())
+        }
+        Some(("healthcheck", healthcheck_matches)) => {
+            if !runtime()?.block_on(commands::healthcheck(&arg_matches, healthcheck_matches))? {
+                return Ok(exit::FAILED);
+            }
+            Ok(())
+        }
+        Some(("config", config_matches)) => commands::config(&arg_matches, config_matches),
+        Some(("state", state_matches)) => {
+            let _lock = lock(&arg_matches)?;
+            commands::clear_state(&arg_matches, state_matches)
+        }
+        Some(("history", history_matches)) => commands::history(&arg_matches, history_matches),
+        Some(("stats", stats_matches)) => commands::stats(&arg_matches, stats_matches),
+        Some(("doctor", _)) => {
+            let settings = validate_settings(&arg_matches)?;
+            runtime()?.block_on(doctor::run(&settings, config_with_dir(&arg_matches)))?;
+            println!("Everything cdu needs works");
+            Ok(())
+        }
+        #[cfg(feature = "keyring")]
+        Some(("secret", secret_matches)) => {
+            let profile = arg_matches.get_one::<String>("profile").map(String::as_str);
+            match secret_matches.subcommand_name() {
+                Some("set") => {
+                    secret::set(profile)?;
+                    println!("Stored the API token in the credential store");
+                }
+                Some("delete") => {
+                    if secret::delete(profile)? {
+                        println!("Removed the API token from the credential store");
+                    } else {
+                        println!("No API token was stored");
+                    }
+                }
+                _ => unreachable!("clap requires a subcommand"),
+            }
+            Ok(())
+        }
+        #[cfg(feature = "self-update")]
+        Some(("self-update", update_matches)) => runtime()?.block_on(self_update::run(
+            update_matches.get_flag("check"),
+            arg_matches.get_flag("yes"),
+        )),
+        Some(("zones", _)) => runtime()?.block_on(commands::list_zones(&arg_matches)),
+        Some(("ip", ip_matches)) => {
+            runtime()?.block_on(commands::print_ip(&arg_matches, ip_matches))
+        }
+        Some(("probe", probe_matches)) => {
+            if !runtime()?.block_on(commands::probe(&arg_matches, probe_matches)) {
+                return Ok(exit::FAILED);
+            }
+            Ok(())
+        }
+        Some(("synology", synology_matches)) => {
+            let arg = |id: &str| synology_matches.get_one::<String>(id).unwrap();
+            redact::add(arg("password"));
+            let response = runtime()?.block_on(synology::run(
+                arg("username"),
+                arg("password"),
+                arg("hostname"),
+                arg("ip"),
+                api_url(&arg_matches),
+                transport(&arg_matches).client(timeouts(&arg_matches).api),
+            ));
+            println!("{response}");
+            Ok(())
+        }
+        Some(("serve-dyndns", serve_matches)) => runtime()?.block_on(dyndns::serve(
+            *serve_matches.get_one::<SocketAddr>("listen").unwrap(),
+            api_url(&arg_matches),
+            transport(&arg_matches).client(timeouts(&arg_matches).api),
+        )),
+        Some(("completions", completions_matches)) => {
+            let shell = *completions_matches.get_one::<Shell>("shell").unwrap();
+            clap_complete::generate(shell, &mut cli(), "cdu", &mut std::io::stdout());
+            Ok(())
+        }
+        Some(("man", _)) => {
+            let cli = cli();
+            let daemon_args = daemon_args();
+            let args = cli.get_arguments().chain(&daemon_args).collect::<Vec<_>>();
+            man::write(cli.clone(), &args, &mut std::io::stdout().lock())
+                .context("Failed to write the man page")
+        }
+        Some(("init", _)) => {
+            let _lock = lock(&arg_matches)?;
+            runtime()?.block_on(commands::init(&arg_matches))
+        }
+        Some(("import", import_matches)) => {
+            runtime()?.block_on(commands::import_settings(&arg_matches, import_matches))
+        }
+        Some(("tui", tui_matches)) => {
+            let _lock = lock(&arg_matches)?;
+            let updater = build_updater(&arg_matches)?;
+            let interval = *tui_matches.get_one::<Duration>("interval").unwrap();
 
-    if let Some(webhook_url) = arg_matches.get_one::<String>("webhook_url") {
-        debug!("Setting webhook URL to: {webhook_url}");
-        config.webhook_url = Some(webhook_url.into());
-    }
+            runtime()?.block_on(tui::run(updater, interval))
+        }
+        #[cfg(windows)]
+        Some(("service", service_matches)) => match service_matches.subcommand() {
+            Some(("install", _)) => service::install(),
+            Some(("uninstall", _)) => {
+                confirm::confirm(
+                    &[String::from(
+                        "Stop and remove the cdu service, and its source of the Event Log",
+                    )],
+                    arg_matches.get_flag("yes"),
+                )?;
+                service::uninstall()
+            }
+            Some(("run", _)) => service::run(service_daemon),
+            _ => unreachable!("clap requires a subcommand"),
+        },
+        _ => {
+            let _lock = lock(&arg_matches)?;
+            let mut updater = build_updater(&arg_matches)
+                .map_err(|e| exit::wrap(exit::CONFIG_INVALID, e))?
+                .with_confirm(!arg_matches.get_flag("yes"));
+            runtime()?.block_on(wait_for_network(&arg_matches));
+            let result = runtime()?.block_on(updater.run());
 
-    let client = RqClient::new();
-    let outside_ip = match get_outside_ip(&client, None) {
-        Ok(ip) => ip,
-        Err(e) => {
-            bail!("Error: {e}");
+            if let Some(path) = arg_matches.get_one::<PathBuf>("metrics_file") {
+                if let Some((_, at)) = updater.last_change() {
+                    metrics::record_last_change(at);
+                }
+                metrics::record_check(&result);
+                if let Err(e) = metrics::write_file(path) {
+                    warn!("{e:#}");
+                }
+            }
+            match arg_matches.get_one::<Output>("output") {
+                Some(Output::Json) => println!("{}", output::json(&result, updater.report())?),
+                Some(Output::Human) => println!(
+                    "{}",
+                    output::human(&result, updater.report(), output::use_color())
+                ),
+                _ => {}
+            }
+
+            if let Outcome::Updated(_) = result? {
+                if !arg_matches.get_flag("zero_exit_on_update") {
+                    return Ok(exit::UPDATED);
+                }
+            }
+            Ok(())
         }
     };
 
-    if let Some(config_outside_ip) = config.outside_ip {
-        if outside_ip == config_outside_ip {
-            info!("Outside IP has not changed. Nothing to do.");
+    result.map(|()| exit::UNCHANGED)
+}
+
+/// Masks the values of the arguments the help doesn't show, like the API key and the webhooks, in
+/// the logs from now on, see [`redact`].
+fn hide_secrets(arg_matches: &ArgMatches) {
+    let cli = cli();
+    let daemon_args = daemon_args();
+    let secrets = cli
+        .get_arguments()
+        .chain(&daemon_args)
+        .filter(|arg| arg.is_hide_env_values_set())
+        .map(|arg| arg.get_id().as_str())
+        .collect::<Vec<_>>();
+
+    let mut matches = Some(arg_matches);
+    while let Some(arg_matches) = matches {
+        for id in &secrets {
+            if let Ok(Some(values)) = arg_matches.try_get_raw(id) {
+                values.for_each(|value| redact::add(&value.to_string_lossy()));
+            }
+        }
+        matches = arg_matches.subcommand().map(|(_, matches)| matches);
+    }
+}
+
+/// Builds the runtime everything that talks to the network runs on. One thread is plenty, as cdu
+/// spends nearly all its time waiting.
+fn runtime() -> anyhow::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the runtime")
+}
+
+/// Runs `future` on a runtime of its own, in a thread of its own, for what's needed before the
+/// daemon starts and again when it reloads, from inside its runtime, which cannot be blocked on.
+fn block_on_aside<T: Send>(
+    future: impl std::future::Future<Output = anyhow::Result<T>> + Send,
+) -> anyhow::Result<T> {
+    std::thread::scope(|scope| scope.spawn(|| runtime()?.block_on(future)).join())
+        .map_err(|_| anyhow::anyhow!("The thread it ran on panicked"))?
+}
+
+/// Loads the settings file from `--settings`, or the configuration file if there is one. It's
+/// loaded after the environment file, and before the arguments are parsed for real, so both win
+/// over it.
+///
+/// # Errors
+///
+/// Returns an error if the file from `--settings` doesn't exist, or a file cannot be read or parsed.
+fn settings_file() -> anyhow::Result<Option<EnvFile>> {
+    // Whatever else is wrong with the arguments is reported when they're parsed for real
+    let arg_matches = cli().ignore_errors(true).get_matches();
+    // Changing the settings file works without reading it, so a broken one can be fixed that way
+    match arg_matches.subcommand() {
+        Some(("config", config_matches))
+            if !matches!(config_matches.subcommand_name(), Some("show" | "validate")) =>
+        {
+            return Ok(None);
+        }
+        Some(("init" | "import" | "completions" | "man" | "self-update", _)) => return Ok(None),
+        _ => {}
+    }
+
+    settings_path(&arg_matches)
+        .map(|path| EnvFile::load_settings(&path))
+        .transpose()
+}
 
-            return Ok(());
+/// Loads the secrets directory from `--secrets-dir`, or the credentials systemd passes in
+/// `$CREDENTIALS_DIRECTORY` with `LoadCredential=`, if there are any. It's looked up before the
+/// arguments are parsed for real, as the secrets in it are parsed with them.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read.
+fn secrets_dir() -> anyhow::Result<Option<EnvFile>> {
+    cli()
+        .ignore_errors(true)
+        .get_matches()
+        .get_one::<PathBuf>("secrets_dir")
+        .cloned()
+        .or_else(|| env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from))
+        .map(|dir| EnvFile::load_secrets(&dir))
+        .transpose()
+}
+
+/// Reads the API key and the webhook URL from the files of `--api-key-file` and
+/// `--webhook-url-file` into their variables, which win over the ones set before. Like the secrets
+/// directory, they're read before the arguments are parsed for real, and again when the daemon
+/// reloads.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read.
+fn secret_files() -> anyhow::Result<()> {
+    let arg_matches = cli().ignore_errors(true).get_matches();
+    for (id, variable) in [
+        ("api_key_file", "CDU_API_KEY"),
+        ("webhook_url_file", "CDU_WEBHOOK_URL"),
+    ] {
+        if let Some(path) = arg_matches.get_one::<PathBuf>(id) {
+            let value = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read secret: {}", path.display()))?;
+            // The newline a file usually ends with isn't part of the secret
+            env::set_var(variable, value.trim_end());
         }
     }
 
-    // Save the outside IP to the configuration, so we can exit early next time if it hasn't changed
-    config.outside_ip = Some(outside_ip);
-    if let Err(e) = config.save() {
-        error!("Error: {e}");
+    Ok(())
+}
+
+/// Reads the API key from Vault with `--api-key-vault`, or from the secret manager of the cloud
+/// with `--api-key-secret`, into `CDU_API_KEY`, which wins over the one set before. Like the files
+/// of secrets, it's read before the arguments are parsed for real, and again when the daemon
+/// reloads, so a rotated token is picked up.
+///
+/// # Errors
+///
+/// Returns an error if there's no address or login for Vault, or the secret cannot be read.
+fn remote_secret() -> anyhow::Result<()> {
+    let arg_matches = cli().ignore_errors(true).get_matches();
+    let client = network::client(network::DEFAULT_TIMEOUT);
+    let token = if let Some(reference) = arg_matches.get_one::<vault::Reference>("api_key_vault") {
+        block_on_aside(vault(&arg_matches, client)?.read(reference))?
+    } else if let Some(reference) = arg_matches.get_one::<cloud_secret::Reference>("api_key_secret")
+    {
+        block_on_aside(cloud_secret::read(&client, reference))?
     } else {
-        info!("Config saved");
+        return Ok(());
+    };
+    redact::add(&token);
+    env::set_var("CDU_API_KEY", token);
+
+    Ok(())
+}
+
+/// Returns the Vault of `--vault-addr`, logged in to with the AppRole if there is one, or the
+/// token.
+fn vault(arg_matches: &ArgMatches, client: reqwest::Client) -> anyhow::Result<vault::Vault> {
+    // The variables of the Vault CLI do too
+    let address = arg_matches
+        .get_one::<reqwest::Url>("vault_addr")
+        .cloned()
+        .or_else(|| env::var("VAULT_ADDR").ok()?.parse().ok())
+        .context("Missing --vault-addr, or CDU_VAULT_ADDR, for --api-key-vault")?;
+    let auth = match (
+        arg_matches.get_one::<String>("vault_role_id"),
+        arg_matches.get_one::<String>("vault_secret_id"),
+    ) {
+        (Some(role_id), Some(secret_id)) => vault::Auth::AppRole {
+            role_id: role_id.clone(),
+            secret_id: secret_id.clone(),
+        },
+        _ => vault::Auth::Token(
+            arg_matches
+                .get_one::<String>("vault_token")
+                .cloned()
+                .or_else(|| env::var("VAULT_TOKEN").ok())
+                .context("Missing --vault-token, or --vault-role-id and --vault-secret-id, for --api-key-vault")?,
+        ),
+    };
+
+    Ok(vault::Vault::new(
+        client,
+        address,
+        auth,
+        network::DEFAULT_TIMEOUT,
+    ))
+}
+
+/// Returns the profile from `--profile`. It's looked up before the arguments are parsed for real,
+/// as it picks the settings they're parsed with.
+fn profile() -> Option<String> {
+    cli()
+        .ignore_errors(true)
+        .get_matches()
+        .get_one::<String>("profile")
+        .cloned()
+}
+
+/// Returns the settings file from `--settings`, or the first one in the settings directory that
+/// exists.
+fn settings_path(arg_matches: &ArgMatches) -> Option<PathBuf> {
+    if let Some(path) = arg_matches.get_one::<PathBuf>("settings") {
+        return Some(path.clone());
+    }
+
+    let dir = dirs(arg_matches).settings;
+    config::SETTINGS_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Reads the environment file, the secrets directory, the settings file and the arguments again,
+/// and builds a new [`Updater`] and daemon options from them. The daemon does this when it's asked
+/// to reload its configuration.
+fn reload(
+    mut env_file: Option<&mut EnvFile>,
+    secrets: Option<&mut EnvFile>,
+    settings: Option<&mut EnvFile>,
+) -> anyhow::Result<(Updater, daemon::Options)> {
+    // A secret that was rotated in the password manager is picked up too
+    password_manager::forget();
+    if let Some(env_file) = env_file.as_deref_mut() {
+        env_file.reload()?;
+    }
+    if let Some(secrets) = secrets {
+        secrets.reload()?;
+    }
+    if let Some(settings) = settings {
+        settings.reload()?;
     }
+    secret_files()?;
+    remote_secret()?;
 
-    debug!("Processing domain: {}", domain);
-    debug!("Outside IP: {}", outside_ip);
+    rebuild(env_file.as_deref())
+}
 
-    let mut cloudflare_client = cloudflare::Handler::try_new(api_key, zone_id)?;
+/// Builds a new [`Updater`] and daemon options from the arguments, for a daemon that's reloading
+/// its configuration.
+fn rebuild(env_file: Option<&EnvFile>) -> anyhow::Result<(Updater, daemon::Options)> {
+    let arg_matches = cli().try_get_matches()?;
+    hide_secrets(&arg_matches);
+    let daemon_matches = match arg_matches.subcommand() {
+        Some(("daemon", daemon_matches)) => Some(daemon_matches),
+        #[cfg(windows)]
+        Some(("service", service_matches)) => service_matches.subcommand_matches("run"),
+        _ => None,
+    }
+    .context("Not running the daemon")?;
 
-    // Get the A record
-    let cloudflare_ip = cloudflare_client.get_a_record(domain)?;
+    Ok((
+        build_updater(&arg_matches)?,
+        daemon_options(daemon_matches, env_file)?,
+    ))
+}
 
-    debug!("Cloudflare IP: {cloudflare_ip}");
+/// Runs the daemon for the Windows service, with the settings from its environment file, and the
+/// configuration file next to it.
+#[cfg(windows)]
+fn service_daemon(
+    sender: tokio::sync::mpsc::UnboundedSender<daemon::Event>,
+    receiver: tokio::sync::mpsc::UnboundedReceiver<daemon::Event>,
+) -> anyhow::Result<()> {
+    let mut env_file = EnvFile::load(&service::env_file())?;
+    let default_config_dir = || {
+        if env::var_os("CDU_CONFIG_DIR").is_none() {
+            env::set_var("CDU_CONFIG_DIR", service::data_dir());
+        }
+    };
+    default_config_dir();
 
-    if outside_ip == cloudflare_ip {
-        info!("Cloudflare IP is already up to date");
+    let arg_matches = cli().try_get_matches()?;
+    hide_secrets(&arg_matches);
+    let Some(("service", service_matches)) = arg_matches.subcommand() else {
+        anyhow::bail!("Not started as a service");
+    };
+    let Some(("run", run_matches)) = service_matches.subcommand() else {
+        anyhow::bail!("Not started as a service");
+    };
+
+    let _lock = lock(&arg_matches)?;
+    let updater = build_updater(&arg_matches)?;
+    let options = daemon_options(run_matches, Some(&env_file))?;
+
+    runtime()?.block_on(daemon::run_with_events(
+        updater,
+        options,
+        move || {
+            env_file.reload()?;
+            default_config_dir();
+
+            rebuild(Some(&env_file))
+        },
+        sender,
+        receiver,
+    ))
+}
+
+/// Returns the targets from `--notify` and the `CDU_NOTIFY_<NAME>` variables, with the webhook
+/// settings applied.
+fn notify_targets(
+    arg_matches: &ArgMatches,
+) -> anyhow::Result<Vec<(notify::Target, notify::Subscription)>> {
+    let webhook_settings = webhook::Options {
+        template: arg_matches
+            .get_one::<PathBuf>("webhook_template")
+            .map(|path| webhook::load_template(path))
+            .transpose()?,
+        secret: arg_matches.get_one::<String>("webhook_secret").cloned(),
+        method: arg_matches
+            .get_one::<webhook::Method>("webhook_method")
+            .copied()
+            .unwrap_or_default(),
+        headers: arg_matches
+            .get_many::<(String, String)>("webhook_header")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+        ..webhook::Options::default()
+    };
+    // Variables that aren't Unicode can't be targets anyway
+    let vars = env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)));
+    let from_env = notify::targets_from_env(vars).map_err(anyhow::Error::msg)?;
+
+    Ok(arg_matches
+        .get_many::<notify::Target>("notify")
+        .into_iter()
+        .flatten()
+        .map(|target| (target.clone(), notify::Subscription::default()))
+        .chain(from_env)
+        .map(|(target, subscription)| {
+            (
+                target.with_webhook_settings(&webhook_settings),
+                subscription,
+            )
+        })
+        .collect())
+}
+
+/// Returns where the audit log is: next to the state, unless there's none or it's turned off.
+fn audit_log(arg_matches: &ArgMatches, config: &Config) -> Option<PathBuf> {
+    if arg_matches.get_flag("no_audit_log") {
+        None
+    } else if let Some(path) = arg_matches.get_one::<PathBuf>("audit_log") {
+        Some(path.clone())
     } else {
-        info!("Need to update Cloudflare IP");
-        if dry_run {
-            debug!("Dry run: Would update A record for {domain}: {outside_ip}");
-        } else {
-            cloudflare_client.set_a_record(domain, outside_ip)?;
-            info!("A record for {domain} updated with {outside_ip} at Cloudflare");
-            config.cloudflare_ip = Some(outside_ip);
-
-            if let Err(e) = config.save() {
-                error!("Error: {e}");
-            } else {
-                info!("Config saved");
+        (!config.stateless).then(|| config.save_dir.join(audit::AUDIT_FILE))
+    }
+}
+
+/// Waits for the network to come up, with `--wait-for-network`, before the first check, so a check
+/// at boot doesn't fail on a link that isn't up yet. When it doesn't come up in time, the check
+/// goes ahead anyway, and fails the usual way.
+async fn wait_for_network(arg_matches: &ArgMatches) {
+    let Some(&wait) = arg_matches.get_one::<Duration>("wait_for_network") else {
+        return;
+    };
+    let timeouts = timeouts(arg_matches);
+    let client = &transport(arg_matches).detection_client(timeouts.detection);
+    let family = family(arg_matches);
+
+    let is_up = move || async move {
+        network::detect_family(client, family.servers(), family, timeouts.detection)
+            .await
+            .is_ok()
+    };
+    if !network::wait_for_network(wait, is_up).await {
+        warn!(
+            "The network isn't up after {}, checking anyway",
+            humantime::format_duration(wait)
+        );
+    }
+}
+
+/// Returns the settings that `cdu config validate` and `cdu doctor` check.
+fn validate_settings(arg_matches: &ArgMatches) -> anyhow::Result<validate::Settings> {
+    let (domains, domain_settings) = domains(arg_matches)?;
+
+    Ok(validate::Settings {
+        api_key: arg_matches.get_one::<String>("api_key").cloned(),
+        zone_id: arg_matches.get_one::<String>("zone_id").cloned(),
+        domains,
+        per_domain: domain_settings,
+        targets: every_target(arg_matches)?,
+        api_url: api_url(arg_matches).to_string(),
+        transport: transport(arg_matches),
+    })
+}
+
+/// Returns every notification target, with the Discord webhook from `--webhook-url` first.
+fn every_target(arg_matches: &ArgMatches) -> anyhow::Result<Vec<notify::Target>> {
+    Ok(arg_matches
+        .get_one::<String>("webhook_url")
+        .map(|url| notify::Target::Discord(url.clone()))
+        .into_iter()
+        .chain(
+            notify_targets(arg_matches)?
+                .into_iter()
+                .map(|(target, _)| target),
+        )
+        .collect())
+}
+
+/// Returns the domains from `--domain`, followed by the ones in `--domains-file` and then the
+/// settings file that aren't given before, along with the settings of those in the settings file.
+fn domains(
+    arg_matches: &ArgMatches,
+) -> anyhow::Result<(Vec<String>, Vec<updater::DomainSettings>)> {
+    let domain_settings = settings_path(arg_matches)
+        .map(|path| {
+            settings::domains(
+                &path,
+                arg_matches.get_one::<String>("profile").map(String::as_str),
+            )
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let mut domains = arg_matches
+        .get_many::<String>("domain")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    let from_file = arg_matches
+        .get_one::<PathBuf>("domains_file")
+        .map(|path| settings::domains_file(path))
+        .transpose()?
+        .unwrap_or_default();
+    let from_settings = domain_settings.iter().map(|settings| &settings.name);
+    for domain in from_file.iter().chain(from_settings) {
+        if !domains.contains(domain) {
+            domains.push(domain.clone());
+        }
+    }
+
+    Ok((domains, domain_settings))
+}
+
+/// Builds the [`Updater`] from the arguments, loading the configuration file along the way.
+#[allow(clippy::too_many_lines)]
+fn build_updater(arg_matches: &ArgMatches) -> anyhow::Result<Updater> {
+    let simulation = simulation(arg_matches)?;
+    // Any token and zone do for the simulation
+    let (api_key, zone_id) = match simulation {
+        Some(_) => (
+            arg_matches
+                .get_one::<String>("api_key")
+                .map_or("simulated", String::as_str),
+            arg_matches
+                .get_one::<String>("zone_id")
+                .map_or("simulated", String::as_str),
+        ),
+        None => (
+            required_arg(arg_matches, "api_key")?,
+            required_arg(arg_matches, "zone_id")?,
+        ),
+    };
+    let (domains, domain_settings) = domains(arg_matches)?;
+    let domains = domains.iter().map(String::as_str).collect::<Vec<_>>();
+    if domains.is_empty() {
+        required_arg(arg_matches, "domain")?;
+    }
+    if let Some(settings) = domain_settings.iter().find(|settings| settings.ptr) {
+        if !arg_matches.contains_id("ptr_server") {
+            anyhow::bail!(
+                "{} wants its PTR record, which needs --ptr-server",
+                settings.name
+            );
+        }
+    }
+    let wans = arg_matches
+        .get_many::<network::Wan>("wan")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    for settings in &domain_settings {
+        if let Some(wan) = &settings.wan {
+            if !wans.iter().any(|defined| defined.name == *wan) {
+                anyhow::bail!(
+                    "{} is pointed at the connection {wan}, which isn't in --wan",
+                    settings.name
+                );
             }
+        }
+    }
+    let monitor_only = arg_matches.get_flag("monitor_only");
+    let dry_run = arg_matches.get_flag("dry_run") || monitor_only;
 
-            if let Some(url) = &config.webhook_url {
-                if let Err(e) = webhook::send(
-                    url,
-                    &format!("Updated A record of {domain} to {outside_ip}"),
-                ) {
-                    error!("Error sending message to Discord webhook: {e}");
-                }
+    if monitor_only {
+        debug!("Only monitoring, the A records are never changed");
+    } else if dry_run {
+        debug!("Performing dry run");
+    }
+
+    let mut config = config_with_dir(arg_matches)?;
+    if let Some(simulation) = simulation {
+        info!(
+            "Simulating, with {} as the outside IP, nothing is sent anywhere",
+            simulation.outside_ip()
+        );
+        // Next to the real state, which is left alone
+        config.save_dir = config.save_dir.join("simulated");
+        if !config.stateless {
+            fs::create_dir_all(&config.save_dir).with_context(|| {
+                format!("Failed to create directory: {}", config.save_dir.display())
+            })?;
+        }
+        config.api_url = simulation.api_url();
+    }
+    config.load()?;
+
+    if let Some(webhook_url) = arg_matches.get_one::<String>("webhook_url") {
+        debug!("Setting webhook URL to: {}", redact::text(webhook_url));
+        config.webhook_url = Some(webhook_url.into());
+    }
+
+    let mut notify = notify_targets(arg_matches)?;
+    for settings in &domain_settings {
+        for name in settings.notify.iter().flatten() {
+            let named = notify.iter().any(|(_, subscription)| {
+                subscription
+                    .name
+                    .as_ref()
+                    .is_some_and(|named| named.eq_ignore_ascii_case(name))
+            });
+            if !named {
+                anyhow::bail!(
+                    "{} sends its messages to {name}, but there's no CDU_NOTIFY_{} or [notify.{name}]",
+                    settings.name,
+                    name.to_ascii_uppercase()
+                );
             }
         }
     }
+    if simulation.is_some() {
+        // Every target only logs what it would have been sent, the webhook of --webhook-url too
+        for (target, _) in &mut notify {
+            *target = notify::Target::Log(target.kind().to_string());
+        }
+        if config.webhook_url.take().is_some() {
+            notify.insert(
+                0,
+                (
+                    notify::Target::Log(String::from("discord")),
+                    notify::Subscription::default(),
+                ),
+            );
+        }
+    }
+    let mut notify_on = arg_matches
+        .get_many::<notify::EventKind>("notify_on")
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+    // Asking for heartbeats is enough to get them, without also listing them in --notify-on
+    if arg_matches.get_one::<Duration>("heartbeat_every").is_some()
+        && !notify_on.contains(&notify::EventKind::Heartbeat)
+    {
+        notify_on.push(notify::EventKind::Heartbeat);
+    }
+    // Having a fallback token is enough to hear about switching to it
+    let fallback_api_key = arg_matches.get_one::<String>("fallback_api_key");
+    if fallback_api_key.is_some() && !notify_on.contains(&notify::EventKind::TokenSwitched) {
+        notify_on.push(notify::EventKind::TokenSwitched);
+    }
+    // Nothing is updated, so a change of the outside IP is what there is to tell
+    if monitor_only && !notify_on.contains(&notify::EventKind::IpChanged) {
+        notify_on.push(notify::EventKind::IpChanged);
+    }
 
-    Ok(())
-}
+    let audit_log = audit_log(arg_matches, &config);
+    let ptr = arg_matches
+        .get_one::<String>("ptr_server")
+        .map(|address| ptr::Server {
+            address: address.clone(),
+            zone: arg_matches.get_one::<String>("ptr_zone").cloned(),
+            key: arg_matches.get_one::<ptr::Key>("ptr_key").cloned(),
+            timeout: config.timeouts.api,
+        });
+    let dnsomatic = arg_matches
+        .get_one::<dnsomatic::Account>("dnsomatic")
+        .map(|account| dnsomatic::Account {
+            hostname: arg_matches
+                .get_one::<String>("dnsomatic_hostname")
+                .unwrap()
+                .clone(),
+            ..account.clone()
+        });
+    let wans = wans
+        .into_iter()
+        .map(|wan| {
+            let client = config.transport.wan_client(wan, config.timeouts.detection);
+            (wan.name.clone(), client)
+        })
+        .collect();
 
-fn parse_args() -> ArgMatches {
-    command!()
-        .about(crate_description!())
-        .version(crate_version!())
-        .arg(
-            Arg::new("api_key")
-                .short('k')
-                .long("api-key")
-                .required(true)
-                .env("CDU_API_KEY")
-                .help("Cloudflare API key"),
-        )
-        .arg(
-            Arg::new("zone_id")
-                .short('z')
-                .long("zone-id")
-                .required(true)
-                .env("CDU_ZONE_ID")
-                .help("Cloudflare zone ID"),
-        )
-        .arg(
-            Arg::new("domain")
-                .short('d')
-                .long("domain")
-                .required(true)
-                .env("CDU_DOMAIN")
-                .help("Domain name to update the A record of"),
-        )
-        .arg(
-            Arg::new("dry_run")
-                .short('n')
-                .long("dry-run")
-                .action(ArgAction::SetTrue)
-                .env("CDU_DRY_RUN")
-                .help("Do not update the A record"),
+    let updater = Updater::try_new(api_key, zone_id, &domains, dry_run, config)?;
+
+    let updater = updater
+        .with_family(family(arg_matches))
+        .with_domain_settings(domain_settings)
+        .with_parallelism(usize::from(
+            *arg_matches.get_one::<u16>("parallelism").unwrap(),
+        ))
+        .with_cooldown(arg_matches.get_one::<Duration>("cooldown").copied())
+        .with_reconcile_every(arg_matches.get_one::<Duration>("reconcile_every").copied())
+        .with_max_state_age(arg_matches.get_one::<Duration>("max_state_age").copied())
+        .with_low_ttl(
+            arg_matches
+                .get_one::<u32>("low_ttl")
+                .map(|&ttl| updater::LowTtl {
+                    ttl,
+                    until_stable_for: *arg_matches.get_one::<Duration>("raise_ttl_after").unwrap(),
+                }),
         )
-        .arg(
-            Arg::new("config_dir")
-                .short('c')
-                .long("config-dir")
-                .env("CDU_CONFIG_DIR")
-                .help("Directory to save the configuration file in"),
+        .with_leave_drift(arg_matches.get_one::<String>("on_drift").unwrap() == "leave")
+        .with_force(arg_matches.get_flag("force"))
+        .with_output(*arg_matches.get_one::<Output>("output").unwrap())
+        .with_notify(notify)
+        .with_retry(retry_policy(arg_matches))
+        .with_rate_limit(arg_matches.get_one::<u32>("api_rate_limit").copied())
+        .with_circuit_breaker(
+            *arg_matches.get_one::<u32>("circuit_failures").unwrap(),
+            *arg_matches.get_one::<Duration>("circuit_cooldown").unwrap(),
         )
-        .arg(
-            Arg::new("webhook_url")
-                .short('w')
-                .long("webhook")
-                .env("CDU_WEBHOOK_URL")
-                .help("Webhook URL to use when the outside IP changes"),
+        .with_notify_retry_for(*arg_matches.get_one::<Duration>("notify_retry_for").unwrap())
+        .with_notify_cooldown(*arg_matches.get_one::<Duration>("notify_cooldown").unwrap())
+        .with_alert_after(*arg_matches.get_one::<u32>("alert_after").unwrap())
+        .with_notify_per_domain(arg_matches.get_flag("notify_per_domain"))
+        .with_heartbeat_every(arg_matches.get_one::<Duration>("heartbeat_every").copied())
+        .with_audit_log(audit_log.as_deref())
+        .with_notify_on(notify_on)
+        .with_fallback_api_key(fallback_api_key.map(String::as_str))?;
+    // Nothing that would reach out of the simulation, or act on the world, is set up
+    let updater = match simulation {
+        Some(simulation) => updater.with_servers(vec![simulation.detection_url()]),
+        None => with_outside_world(updater, arg_matches)
+            .with_ptr(ptr)
+            .with_wans(wans)
+            .with_txt_record(arg_matches.get_flag("txt_record"))
+            .with_dnsomatic(dnsomatic),
+    };
+    #[cfg(feature = "scripting")]
+    let updater = updater.with_policy(
+        arg_matches
+            .get_one::<cdu::script::Script>("policy_script")
+            .cloned(),
+    );
+
+    Ok(updater)
+}
+
+/// Returns the updater with everything it tells about a run, or runs along with it, besides the
+/// notifications: the monitors, the metrics that are pushed, the hooks, the GeoIP lookups and the
+/// plugins.
+fn with_outside_world(updater: Updater, arg_matches: &ArgMatches) -> Updater {
+    let updater = updater
+        .with_healthchecks(arg_matches.get_one::<reqwest::Url>("healthchecks_url"))
+        .with_uptime_kuma(arg_matches.get_one::<reqwest::Url>("uptime_kuma_url"))
+        .with_push_metrics(
+            arg_matches.get_one::<push_metrics::Endpoint>("push_metrics"),
+            arg_matches.get_one::<String>("push_metrics_token").cloned(),
         )
-        .get_matches()
+        .with_hooks(hooks::Hooks {
+            before: arg_matches.get_one::<String>("hook_before").cloned(),
+            after_update: arg_matches.get_one::<String>("hook_after_update").cloned(),
+            on_failure: arg_matches.get_one::<String>("hook_on_failure").cloned(),
+        })
+        .with_geoip(
+            arg_matches
+                .get_many::<geoip::Source>("geoip")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        );
+    #[cfg(feature = "plugins")]
+    let updater = updater.with_plugins(cdu::plugin::Plugins(
+        arg_matches
+            .get_many::<cdu::plugin::Plugin>("plugin")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+    ));
+
+    updater
+}
+
+/// Returns the stand-in for Cloudflare of `--simulate`, which is started the first time it's asked
+/// for, and kept for the reloads of the daemon.
+fn simulation(arg_matches: &ArgMatches) -> anyhow::Result<Option<&'static Simulation>> {
+    static SIMULATION: OnceLock<Simulation> = OnceLock::new();
+
+    let Some(&outside_ip) = arg_matches.get_one::<Ipv4Addr>("simulate") else {
+        return Ok(None);
+    };
+    if let Some(simulation) = SIMULATION.get() {
+        return Ok(Some(simulation));
+    }
+    let simulation = Simulation::start(outside_ip)?;
+
+    Ok(Some(SIMULATION.get_or_init(|| simulation)))
+}
+
+/// Returns the directories of the settings and the state, which are both the one from the
+/// arguments if one was given, except for the state with a directory of its own.
+fn dirs(arg_matches: &ArgMatches) -> config::Dirs {
+    let mut dirs = match arg_matches.get_one::<String>("config_dir") {
+        Some(config_dir) => config::Dirs::same(config_dir),
+        None => config::Dirs::find(),
+    };
+    if let Some(state_dir) = arg_matches.get_one::<PathBuf>("state_dir") {
+        dirs.state.clone_from(state_dir);
+    }
+
+    dirs
+}
+
+/// Returns the configuration, with the state directory from [`dirs`], or the one of the profile in
+/// it. A directory from the arguments has to exist, while the one of the platform is created on the
+/// first run. Nothing is created with `--no-state`.
+///
+/// # Errors
+///
+/// Returns an error if the directory of the platform cannot be created.
+fn config_with_dir(arg_matches: &ArgMatches) -> anyhow::Result<Config> {
+    let mut state_dir = dirs(arg_matches).state;
+    let stateless = arg_matches.get_flag("no_state");
+    let from_args = arg_matches.get_one::<String>("config_dir").is_some()
+        || arg_matches.get_one::<PathBuf>("state_dir").is_some();
+    if !from_args && !stateless && !state_dir.exists() {
+        fs::create_dir_all(&state_dir)
+            .with_context(|| format!("Failed to create directory: {}", state_dir.display()))?;
+    }
+    // Every profile keeps its own state, in a directory of its own
+    if let Some(profile) = arg_matches.get_one::<String>("profile") {
+        state_dir = state_dir.join("profiles").join(profile);
+        if !stateless {
+            fs::create_dir_all(&state_dir)
+                .with_context(|| format!("Failed to create directory: {}", state_dir.display()))?;
+        }
+    }
+    debug!("Setting config directory to: {}", state_dir.display());
+
+    let encrypt_with = if arg_matches.get_flag("encrypt_state") {
+        let key = crypt::Key::from_env()?
+            .context("--encrypt-state needs a key, set CDU_AGE_IDENTITY or CDU_AGE_PASSPHRASE")?;
+        #[cfg(feature = "sqlite")]
+        anyhow::ensure!(
+            arg_matches
+                .get_one::<String>("state_store")
+                .is_some_and(|store| store == "file"),
+            "--encrypt-state only works with the state in a file, not with --state-store sqlite"
+        );
+        Some(key)
+    } else {
+        None
+    };
+
+    Ok(Config {
+        save_dir: state_dir,
+        encrypt_with,
+        stateless,
+        timeouts: timeouts(arg_matches),
+        transport: transport(arg_matches),
+        api_url: api_url(arg_matches).to_string(),
+        #[cfg(feature = "sqlite")]
+        use_database: arg_matches
+            .get_one::<String>("state_store")
+            .is_some_and(|store| store == "sqlite"),
+        ..Config::default()
+    })
+}
+
+/// Returns how long each kind of request may take, which is `--timeout` unless it's given for that
+/// kind on its own.
+fn timeouts(arg_matches: &ArgMatches) -> network::Timeouts {
+    let timeout = *arg_matches.get_one::<Duration>("timeout").unwrap();
+    let timeout_of = |id| {
+        arg_matches
+            .get_one::<Duration>(id)
+            .copied()
+            .unwrap_or(timeout)
+    };
+
+    network::Timeouts {
+        detection: timeout_of("detection_timeout"),
+        api: timeout_of("api_timeout"),
+        notify: timeout_of("notify_timeout"),
+    }
+}
+
+/// Returns the family of the outside IP the domains are pointed at, the IPv6 address with their
+/// AAAA records with `--ipv6-only`.
+fn family(arg_matches: &ArgMatches) -> network::Family {
+    if arg_matches.get_flag("ipv6_only") {
+        network::Family::V6
+    } else {
+        network::Family::V4
+    }
+}
+
+/// Returns how the requests reach the servers, from `--proxy`, `--no-proxy-detection`,
+/// `--ca-cert` and `--insecure-host`.
+fn transport(arg_matches: &ArgMatches) -> network::Transport {
+    network::Transport {
+        proxy: arg_matches.get_one::<reqwest::Proxy>("proxy").cloned(),
+        direct_detection: arg_matches.get_flag("no_proxy_detection"),
+        roots: arg_matches
+            .get_many::<Vec<reqwest::Certificate>>("ca_cert")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .cloned()
+            .collect(),
+        insecure_hosts: arg_matches
+            .get_many::<String>("insecure_host")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Returns where the Cloudflare API is, from `--cf-api-base`.
+fn api_url(arg_matches: &ArgMatches) -> &str {
+    arg_matches
+        .get_one::<reqwest::Url>("cf_api_base")
+        .unwrap()
+        .as_str()
+}
+
+/// Returns how often, and how long apart, a request that failed is sent again.
+fn retry_policy(arg_matches: &ArgMatches) -> retry::Policy {
+    retry::Policy {
+        attempts: arg_matches
+            .get_one::<u32>("retries")
+            .unwrap()
+            .saturating_add(1),
+        delay: *arg_matches.get_one::<Duration>("retry_delay").unwrap(),
+        jitter: *arg_matches.get_one::<Duration>("retry_jitter").unwrap(),
+    }
+}
+
+/// Locks the state directory for as long as the lock lives, so two runs don't race over the state
+/// and the A records. There's nothing to lock with `--no-state`, which leaves the directory alone.
+///
+/// # Errors
+///
+/// Returns an error if another instance holds the lock for longer than `--lock-wait`.
+fn lock(arg_matches: &ArgMatches) -> anyhow::Result<Option<Lock>> {
+    if arg_matches.get_flag("no_state") {
+        return Ok(None);
+    }
+    let wait = arg_matches
+        .get_one::<Duration>("lock_wait")
+        .copied()
+        .unwrap_or_default();
+
+    Lock::acquire(&config_with_dir(arg_matches)?.save_dir, wait).map(Some)
+}
+
+/// Returns an argument that's needed to talk to Cloudflare.
+///
+/// Clap only enforces these when no subcommand is given, as not every subcommand needs them.
+fn required_arg<'a>(arg_matches: &'a ArgMatches, id: &str) -> anyhow::Result<&'a str> {
+    if let Some(value) = arg_matches.get_one::<String>(id) {
+        return Ok(value);
+    }
+
+    let cli = cli();
+    let daemon_args = daemon_args();
+    let arg = cli
+        .get_arguments()
+        .chain(&daemon_args)
+        .find(|arg| arg.get_id() == id);
+    let long = arg.and_then(Arg::get_long).unwrap_or(id);
+    let error = match arg.and_then(Arg::get_env) {
+        Some(env) => anyhow::anyhow!("Missing --{long}, or {}", env.to_string_lossy()),
+        None => anyhow::anyhow!("Missing --{long}"),
+    };
+    Err(exit::wrap(exit::CONFIG_INVALID, error))
+}
+
+/// Returns the options of the daemon, from the arguments returned by [`daemon_args`]. The settings
+/// file can only be watched if there is one.
+fn daemon_options(
+    daemon_matches: &ArgMatches,
+    env_file: Option<&EnvFile>,
+) -> anyhow::Result<daemon::Options> {
+    let schedule = match (
+        daemon_matches.get_one::<cron::Schedule>("schedule"),
+        daemon_matches.get_one::<(Duration, Duration)>("adaptive_interval"),
+    ) {
+        (Some(schedule), _) => Schedule::Cron(Box::new(schedule.clone())),
+        (None, Some(&(min, max))) => Schedule::Adaptive { min, max },
+        (None, None) => {
+            Schedule::Interval(*daemon_matches.get_one::<Duration>("interval").unwrap())
+        }
+    };
+
+    let token = daemon_matches.get_one::<String>("api_token").cloned();
+    if token.as_ref().is_some_and(String::is_empty) {
+        anyhow::bail!("The API token cannot be empty");
+    }
+    let api = daemon_matches
+        .get_one::<SocketAddr>("api_listen")
+        .map(|listen| api::Options {
+            listen: *listen,
+            token: token.clone(),
+        });
+
+    Ok(daemon::Options {
+        schedule,
+        jitter: daemon_matches
+            .get_one::<Duration>("jitter")
+            .copied()
+            .unwrap_or_default(),
+        watch_network: daemon_matches.get_flag("watch_network"),
+        watch_config: if daemon_matches.get_flag("watch_config") {
+            if env_file.is_none() {
+                warn!("There is no .env file to watch");
+            }
+            env_file.map(|env_file| env_file.path().to_path_buf())
+        } else {
+            None
+        },
+        api,
+        #[cfg(feature = "grpc")]
+        grpc: daemon_matches
+            .get_one::<SocketAddr>("grpc_listen")
+            .map(|listen| api::Options {
+                listen: *listen,
+                token,
+            }),
+        mqtt: daemon_matches
+            .get_one::<reqwest::Url>("mqtt_url")
+            .map(|url| mqtt::Options {
+                url: url.clone(),
+                topic: daemon_matches
+                    .get_one::<String>("mqtt_topic")
+                    .unwrap()
+                    .trim_end_matches('/')
+                    .to_string(),
+                discovery_prefix: daemon_matches
+                    .get_one::<String>("mqtt_discovery_prefix")
+                    .unwrap()
+                    .trim_end_matches('/')
+                    .to_string(),
+            }),
+        ready_checks: *daemon_matches.get_one::<u32>("ready_checks").unwrap(),
+    })
 }
@@ -0,0 +1,92 @@
+//! Writes the man page of cdu, for packages to install. On top of what clap knows about the
+//! arguments, it has the keys of the settings file, the environment variables that aren't
+//! arguments, and the exit codes.
+use std::io::{self, Write};
+
+use clap::{Arg, Command};
+use clap_mangen::Man;
+
+use cdu::exit;
+
+/// The environment variables that aren't the ones of arguments.
+const ENVIRONMENT: [(&str, &str); 4] = [
+    (
+        "RUST_LOG",
+        "What to log, like info or debug, unless -v or -q is given",
+    ),
+    (
+        "CDU_AGE_IDENTITY",
+        "The age identity file to decrypt the settings and the state with",
+    ),
+    (
+        "CDU_AGE_PASSPHRASE",
+        "The passphrase to decrypt them with, instead of an identity file",
+    ),
+    ("NO_COLOR", "Leaves the colors out of --output human"),
+];
+
+/// Writes the man page of `command` to `out`, with the keys of the settings file for `settings`.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write(command: Command, settings: &[&Arg], out: &mut impl Write) -> io::Result<()> {
+    Man::new(command).render(out)?;
+
+    writeln!(out, ".SH \"SETTINGS FILE\"")?;
+    writeln!(
+        out,
+        "{}",
+        escape(
+            "The settings can also be written in cdu.toml, in the configuration directory, or in \
+             the file of --settings. Every key is the name of an argument, with underscores, which \
+             the argument and its environment variable win over:"
+        )
+    )?;
+    for arg in settings {
+        let (Some(env), Some(long)) = (arg.get_env(), arg.get_long()) else {
+            continue;
+        };
+        writeln!(out, ".TP\n\\fB{}\\fR", escape(arg.get_id().as_str()))?;
+        let names = format!("--{long}, {}", env.to_string_lossy());
+        writeln!(out, "{}", escape(&names))?;
+    }
+
+    writeln!(out, ".SH ENVIRONMENT")?;
+    for (name, description) in ENVIRONMENT {
+        writeln!(
+            out,
+            ".TP\n\\fB{}\\fR\n{}",
+            escape(name),
+            escape(description)
+        )?;
+    }
+
+    writeln!(out, ".SH \"EXIT STATUS\"")?;
+    for (code, description) in exit::ALL {
+        writeln!(out, ".TP\n\\fB{code}\\fR\n{}", escape(description))?;
+    }
+
+    Ok(())
+}
+
+/// Escapes text for roff, where a backslash starts an escape, and a dash has to be one so it can
+/// be searched for.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+#[test]
+fn test_write() {
+    let cli = crate::args::cli();
+    let daemon_args = crate::args::daemon_args();
+    let args = cli.get_arguments().chain(&daemon_args).collect::<Vec<_>>();
+    let mut page = Vec::new();
+    write(crate::args::cli(), &args, &mut page).unwrap();
+    let page = String::from_utf8(page).unwrap();
+
+    assert!(page.starts_with(".ie"), "{}", &page[..100]);
+    assert!(page.contains(".TP\n\\fBzone_id\\fR\n\\-\\-zone\\-id, CDU_ZONE_ID\n"));
+    assert!(page.contains(".SH \"EXIT STATUS\"\n.TP\n\\fB0\\fR\nNothing needed to change\n"));
+    assert!(page.contains("\\fBNO_COLOR\\fR"));
+}
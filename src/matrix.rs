@@ -0,0 +1,181 @@
+//! Sends messages to a Matrix room, through the client-server API of a homeserver.
+//!
+//! The target is the URL of the homeserver, with the access token of the user to send as and the
+//! ID of the room in the query, like
+//! `https://matrix.example.com?token=<access token>&room=!abc123:example.com`. The user has to have
+//! joined the room already.
+use std::fmt::Write as _;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{Client as RqClient, Url};
+use serde_json::{json, Value};
+
+use crate::notify::{Message, Notifier};
+use crate::retry::StatusError;
+
+/// A homeserver, and the room to send to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The URL of the homeserver, without the options.
+    pub url: Url,
+    pub token: String,
+    pub room_id: String,
+}
+
+impl Options {
+    /// Parses the URL of a homeserver, taking the options out of its query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid, the token or room is missing, or an option is
+    /// unknown.
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let mut url = Url::parse(target).map_err(|e| format!("Invalid Matrix URL: {e}"))?;
+
+        let mut token = String::new();
+        let mut room_id = String::new();
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "token" => token = value.into_owned(),
+                "room" => room_id = value.into_owned(),
+                _ => return Err(format!("Unknown Matrix option: {name}")),
+            }
+        }
+        if token.is_empty() {
+            return Err(String::from(
+                "Missing the Matrix access token, add ?token=<access token>",
+            ));
+        }
+        if !room_id.starts_with('!') {
+            return Err(String::from(
+                "Missing the Matrix room ID, add &room=!<room>:<server>",
+            ));
+        }
+        url.set_query(None);
+
+        Ok(Self {
+            url,
+            token,
+            room_id,
+        })
+    }
+}
+
+/// A Matrix room.
+#[derive(Debug)]
+pub struct Matrix {
+    client: RqClient,
+    options: Options,
+}
+
+impl Matrix {
+    #[must_use]
+    pub fn new(client: RqClient, options: &Options) -> Self {
+        Self {
+            client,
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Matrix {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        // The transaction ID only has to be unique, so a retried request isn't sent twice
+        let transaction_id = format!(
+            "cdu-{}-{}",
+            message.at.timestamp_millis(),
+            fastrand::u32(..)
+        );
+        let mut url = self.options.url.clone();
+        url.path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("Invalid Matrix URL"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3", "rooms"])
+            .push(&self.options.room_id)
+            .extend(["send", "m.room.message"])
+            .push(&transaction_id);
+
+        let response = self
+            .client
+            .put(url)
+            .bearer_auth(&self.options.token)
+            .json(&payload(message))
+            .send()
+            .await
+            .context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the event, with the message as plain text and as HTML.
+fn payload(message: &Message) -> Value {
+    let mut html = format!(
+        "<b>{}</b><br>Domain: <code>{}</code>",
+        message.title(),
+        escape(&message.domain)
+    );
+    match (message.old_ip, message.new_ip) {
+        (Some(old_ip), Some(new_ip)) => {
+            let _ = write!(
+                html,
+                "<br>IP: <code>{old_ip}</code> → <code>{new_ip}</code>"
+            );
+        }
+        (None, Some(ip)) | (Some(ip), None) => {
+            let _ = write!(html, "<br>IP: <code>{ip}</code>");
+        }
+        (None, None) => {}
+    }
+    if let Some(error) = &message.error {
+        let _ = write!(html, "<br>Error: {}", escape(error));
+    }
+
+    json!({
+        "msgtype": "m.notice",
+        "body": message.to_string(),
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn test_payload() {
+    use std::net::IpAddr;
+
+    let options =
+        Options::parse("https://matrix.example.com?token=syt_abc&room=!room:example.com").unwrap();
+    assert_eq!(options.url.as_str(), "https://matrix.example.com/");
+    assert_eq!(options.room_id, "!room:example.com");
+    assert!(Options::parse("https://matrix.example.com?token=syt_abc").is_err());
+
+    let message = Message::updated(
+        "example.com",
+        Some(IpAddr::from([192, 0, 2, 1])),
+        IpAddr::from([192, 0, 2, 2]),
+    );
+    assert_eq!(
+        payload(&message)["formatted_body"],
+        "<b>A record updated</b><br>Domain: <code>example.com</code><br>IP: <code>192.0.2.1</code> → <code>192.0.2.2</code>"
+    );
+}
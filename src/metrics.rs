@@ -0,0 +1,289 @@
+//! Counts what cdu does, and renders it in the Prometheus text format.
+//!
+//! The metrics are kept for the whole process, so they survive the daemon reloading its
+//! configuration. One-shot runs can write them to a file for the textfile collector of the node
+//! exporter instead.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write as _;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+
+use crate::updater::Outcome;
+
+static METRICS: Metrics = Metrics::new();
+
+struct Metrics {
+    checks_succeeded: AtomicU64,
+    checks_failed: AtomicU64,
+    updates: AtomicU64,
+    cloudflare_errors: AtomicU64,
+    detection_failures: Mutex<BTreeMap<String, u64>>,
+    gauges: Mutex<Gauges>,
+}
+
+struct Gauges {
+    outside_ip: Option<IpAddr>,
+    last_check: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    last_change: Option<DateTime<Utc>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            checks_succeeded: AtomicU64::new(0),
+            checks_failed: AtomicU64::new(0),
+            updates: AtomicU64::new(0),
+            cloudflare_errors: AtomicU64::new(0),
+            detection_failures: Mutex::new(BTreeMap::new()),
+            gauges: Mutex::new(Gauges {
+                outside_ip: None,
+                last_check: None,
+                last_success: None,
+                last_change: None,
+            }),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Records the result of a check/update cycle.
+pub fn record_check(result: &anyhow::Result<Outcome>) {
+    let now = Utc::now();
+    let mut gauges = lock(&METRICS.gauges);
+    gauges.last_check = Some(now);
+
+    match result {
+        Ok(outcome) => {
+            METRICS.checks_succeeded.fetch_add(1, Ordering::Relaxed);
+            gauges.outside_ip = Some(outcome.ip());
+            gauges.last_success = Some(now);
+            if let Outcome::Updated(_) = outcome {
+                METRICS.updates.fetch_add(1, Ordering::Relaxed);
+                gauges.last_change = Some(now);
+            }
+        }
+        Err(_) => {
+            METRICS.checks_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records when the A record was last changed, as far as the configuration file knows, unless a
+/// change was already seen.
+pub fn record_last_change(at: DateTime<Utc>) {
+    lock(&METRICS.gauges).last_change.get_or_insert(at);
+}
+
+/// Records when the last cycle succeeded, as far as an earlier run knows, unless this run has
+/// already succeeded.
+fn record_last_success(at: DateTime<Utc>) {
+    lock(&METRICS.gauges).last_success.get_or_insert(at);
+}
+
+/// Records that a service failed to tell the outside IP.
+pub fn record_detection_failure(service: &str) {
+    *lock(&METRICS.detection_failures)
+        .entry(service.to_string())
+        .or_default() += 1;
+}
+
+/// Records that a request to the Cloudflare API failed.
+pub fn record_cloudflare_error() {
+    METRICS.cloudflare_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Renders the metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut text = String::new();
+
+    metric(
+        &mut text,
+        "cdu_build_info",
+        "gauge",
+        "The version of cdu",
+        &[(format!("{{version=\"{}\"}}", clap::crate_version!()), 1)],
+    );
+    metric(
+        &mut text,
+        "cdu_checks_total",
+        "counter",
+        "Check/update cycles performed, by result",
+        &[
+            (
+                String::from("{result=\"success\"}"),
+                METRICS.checks_succeeded.load(Ordering::Relaxed),
+            ),
+            (
+                String::from("{result=\"failure\"}"),
+                METRICS.checks_failed.load(Ordering::Relaxed),
+            ),
+        ],
+    );
+    metric(
+        &mut text,
+        "cdu_updates_total",
+        "counter",
+        "Updates of the A record",
+        &[(String::new(), METRICS.updates.load(Ordering::Relaxed))],
+    );
+    let detection_failures = lock(&METRICS.detection_failures)
+        .iter()
+        .map(|(service, count)| (format!("{{service=\"{}\"}}", escape(service)), *count))
+        .collect::<Vec<_>>();
+    metric(
+        &mut text,
+        "cdu_detection_failures_total",
+        "counter",
+        "Failures to get the outside IP, by service",
+        &detection_failures,
+    );
+    metric(
+        &mut text,
+        "cdu_cloudflare_errors_total",
+        "counter",
+        "Failed requests to the Cloudflare API",
+        &[(
+            String::new(),
+            METRICS.cloudflare_errors.load(Ordering::Relaxed),
+        )],
+    );
+
+    let gauges = lock(&METRICS.gauges);
+    for (name, help, at) in [
+        (
+            "cdu_last_check_timestamp_seconds",
+            "When the last check/update cycle finished",
+            gauges.last_check,
+        ),
+        (
+            "cdu_last_success_timestamp_seconds",
+            "When the last successful check/update cycle finished",
+            gauges.last_success,
+        ),
+        (
+            "cdu_last_change_timestamp_seconds",
+            "When the A record was last changed",
+            gauges.last_change,
+        ),
+    ] {
+        let samples = at
+            .map(|at| {
+                (
+                    String::new(),
+                    u64::try_from(at.timestamp()).unwrap_or_default(),
+                )
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        metric(&mut text, name, "gauge", help, &samples);
+    }
+    let outside_ip = gauges
+        .outside_ip
+        .map(|ip| (format!("{{ip=\"{ip}\"}}"), 1))
+        .into_iter()
+        .collect::<Vec<_>>();
+    metric(
+        &mut text,
+        "cdu_outside_ip_info",
+        "gauge",
+        "The current outside IP",
+        &outside_ip,
+    );
+
+    text
+}
+
+/// Writes the metrics to `path`, in the format of the textfile collector.
+///
+/// Every run starts counting from scratch, but the time of the last success is taken over from the
+/// file written by the previous run, so it's still there when this run failed. The file is written
+/// next to the old one and then renamed, so the collector never reads half of it.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn write_file(path: &Path) -> anyhow::Result<()> {
+    if let Some(at) = fs::read_to_string(path)
+        .ok()
+        .and_then(|text| previous_timestamp(&text, "cdu_last_success_timestamp_seconds"))
+    {
+        record_last_success(at);
+    }
+
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a file in: {}", dir.display()))?;
+    file.write_all(render().as_bytes())
+        .context("Failed to write the metrics")?;
+    // The collector runs as another user, and has to be able to read it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(file.path(), fs::Permissions::from_mode(0o644))
+            .context("Failed to make the metrics file readable")?;
+    }
+    file.persist(path)
+        .with_context(|| format!("Failed to write metrics file: {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Returns the timestamp of the metric called `name`, in the text written by [`render`].
+fn previous_timestamp(text: &str, name: &str) -> Option<DateTime<Utc>> {
+    text.lines()
+        .filter_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .find_map(|value| DateTime::from_timestamp(value.trim().parse().ok()?, 0))
+}
+
+/// Writes a metric with its samples, each with its labels, if any.
+fn metric(text: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    let _ = writeln!(text, "# HELP {name} {help}");
+    let _ = writeln!(text, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(text, "{name}{labels} {value}");
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_render() {
+    record_check(&Ok(Outcome::Updated(IpAddr::from([192, 0, 2, 1]))));
+    record_detection_failure("icanhazip.com");
+    record_cloudflare_error();
+
+    let text = render();
+    assert!(text.contains("# TYPE cdu_checks_total counter\n"));
+    assert!(text.contains("cdu_updates_total "));
+    assert!(text.contains("cdu_detection_failures_total{service=\"icanhazip.com\"} "));
+    assert!(text.contains("cdu_cloudflare_errors_total "));
+    assert!(text.contains("cdu_last_change_timestamp_seconds "));
+    assert!(text.contains("cdu_outside_ip_info{ip=\"192.0.2.1\"} 1\n"));
+
+    assert_eq!(
+        previous_timestamp(&text, "cdu_last_change_timestamp_seconds").map(|at| at.date_naive()),
+        Some(Utc::now().date_naive())
+    );
+    assert_eq!(previous_timestamp(&text, "cdu_nonexistent"), None);
+}
@@ -0,0 +1,55 @@
+//! Tells monitoring services about every run, when it starts and how it ended, so they can alert
+//! when runs fail or stop happening altogether, which notifications can't tell.
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::{debug, warn};
+
+use crate::updater::Outcome;
+
+/// A service that's told about every run.
+#[async_trait]
+pub trait Monitor: fmt::Debug + Send + Sync {
+    /// Returns the name of the service, for the logs.
+    fn name(&self) -> &str;
+
+    /// Tells the service a run started.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service cannot be reached.
+    async fn start(&self) -> anyhow::Result<()>;
+
+    /// Tells the service how the run ended, after `took`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service cannot be reached.
+    async fn finish(&self, result: &anyhow::Result<Outcome>, took: Duration) -> anyhow::Result<()>;
+}
+
+/// Tells every service a run started, logging the ones that couldn't be told, as a monitor that's
+/// down is no reason to skip the run.
+pub async fn start_all(monitors: &[Box<dyn Monitor>]) {
+    for monitor in monitors {
+        match monitor.start().await {
+            Ok(()) => debug!("Told {} the run started", monitor.name()),
+            Err(e) => warn!("Failed to tell {} the run started: {e:#}", monitor.name()),
+        }
+    }
+}
+
+/// Tells every service how the run ended, logging the ones that couldn't be told.
+pub async fn finish_all(
+    monitors: &[Box<dyn Monitor>],
+    result: &anyhow::Result<Outcome>,
+    took: Duration,
+) {
+    for monitor in monitors {
+        match monitor.finish(result, took).await {
+            Ok(()) => debug!("Told {} how the run ended", monitor.name()),
+            Err(e) => warn!("Failed to tell {} how the run ended: {e:#}", monitor.name()),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Watches for IPv4 address and default route changes on Linux, using rtnetlink.
+//!
+//! When an ISP reconnects, the WAN interface usually gets a new address or default route. Hearing
+//! about that from the kernel lets the daemon check right away, instead of at the next interval.
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, trace};
+
+use crate::daemon::Event;
+
+/// Size of the `nlmsghdr` that precedes every netlink message.
+const HEADER_LEN: usize = 16;
+
+/// Subscribes to rtnetlink address and route events, and sends an event to the daemon whenever
+/// one of them could mean that the outside IP changed.
+///
+/// # Errors
+///
+/// Returns an error if the netlink socket cannot be created.
+pub fn spawn(sender: UnboundedSender<Event>) -> io::Result<()> {
+    let socket = AsyncFd::new(bind()?)?;
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 16 * 1024];
+
+        loop {
+            let len = match socket.readable().await {
+                Ok(mut guard) => match guard.try_io(|socket| recv(socket.get_ref(), &mut buf)) {
+                    Ok(Ok(len)) => len,
+                    Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Ok(Err(e)) => {
+                        error!("Stopped watching for network changes: {e}");
+                        break;
+                    }
+                    // Not readable after all
+                    Err(_) => continue,
+                },
+                Err(e) => {
+                    error!("Stopped watching for network changes: {e}");
+                    break;
+                }
+            };
+
+            if is_relevant(&buf[..len]) {
+                debug!("Network change detected");
+                if sender.send(Event::NetworkChange).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn recv(socket: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: the buffer is valid for writes of its whole length.
+    let len = unsafe { libc::recv(socket.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+
+    usize::try_from(len).map_err(|_| io::Error::last_os_error())
+}
+
+fn bind() -> io::Result<OwnedFd> {
+    // SAFETY: plain socket creation, the result is checked before use.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just created and is owned by nobody else.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: `sockaddr_nl` is plain data, for which all zeroes is a valid value.
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = u16::try_from(libc::AF_NETLINK).unwrap_or_default();
+    #[allow(clippy::cast_sign_loss)]
+    {
+        addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV4_ROUTE) as u32;
+    }
+
+    // SAFETY: `addr` is a valid `sockaddr_nl`, and the length matches.
+    let result = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            std::ptr::addr_of!(addr).cast(),
+            u32::try_from(mem::size_of::<libc::sockaddr_nl>()).unwrap_or_default(),
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+/// Returns whether any of the netlink messages in `buf` is about a global address, or about the
+/// default route in the main table. Everything else, like link-local addresses or routes added for
+/// containers, can't change the outside IP.
+fn is_relevant(mut buf: &[u8]) -> bool {
+    let mut relevant = false;
+
+    while buf.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        if len < HEADER_LEN || len > buf.len() {
+            break;
+        }
+        let payload = &buf[HEADER_LEN..len];
+        trace!("Netlink message type {kind}, {len} bytes");
+
+        relevant |= match kind {
+            // struct ifaddrmsg { family, prefixlen, flags, scope, index }
+            libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+                payload.get(3) == Some(&libc::RT_SCOPE_UNIVERSE)
+            }
+            // struct rtmsg { family, dst_len, src_len, tos, table, ... }
+            libc::RTM_NEWROUTE | libc::RTM_DELROUTE => {
+                payload.get(1) == Some(&0) && payload.get(4) == Some(&libc::RT_TABLE_MAIN)
+            }
+            _ => false,
+        };
+
+        // Messages are aligned to four bytes
+        let aligned = (len + 3) & !3;
+        buf = buf.get(aligned..).unwrap_or_default();
+    }
+
+    relevant
+}
+
+#[test]
+fn test_is_relevant() {
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(HEADER_LEN + payload.len()).unwrap();
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&len.to_ne_bytes());
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(&[0; 10]);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    // A global address
+    let global = message(
+        libc::RTM_NEWADDR,
+        &[2, 24, 0, libc::RT_SCOPE_UNIVERSE, 1, 0, 0, 0],
+    );
+    assert!(is_relevant(&global));
+
+    // A link-local address
+    let link = message(
+        libc::RTM_NEWADDR,
+        &[2, 16, 0, libc::RT_SCOPE_LINK, 1, 0, 0, 0],
+    );
+    assert!(!is_relevant(&link));
+
+    // The default route, and a route to a container network
+    let default = message(
+        libc::RTM_DELROUTE,
+        &[2, 0, 0, 0, libc::RT_TABLE_MAIN, 0, 0, 0],
+    );
+    let container = message(
+        libc::RTM_NEWROUTE,
+        &[2, 16, 0, 0, libc::RT_TABLE_MAIN, 0, 0, 0],
+    );
+    assert!(is_relevant(&default));
+    assert!(!is_relevant(&container));
+
+    // Several messages in one datagram
+    let mut batch = link.clone();
+    batch.extend_from_slice(&global);
+    assert!(is_relevant(&batch));
+
+    // Truncated garbage
+    assert!(!is_relevant(&[1, 2, 3]));
+}
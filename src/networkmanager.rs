@@ -0,0 +1,101 @@
+//! Watches NetworkManager over D-Bus for a change of the primary connection, on Linux.
+//!
+//! A laptop that roams from one network to another may keep its addresses and default route the
+//! same shape, or join a network that only lets it out after a captive portal. NetworkManager
+//! tells when the primary connection changes, and when it reaches the internet, which is when the
+//! outside IP is worth checking again.
+use std::collections::HashMap;
+
+use futures_util::StreamExt;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error};
+use zbus::zvariant::OwnedValue;
+use zbus::{Connection, MatchRule, MessageStream};
+
+use crate::daemon::Event;
+
+const NETWORK_MANAGER: &str = "org.freedesktop.NetworkManager";
+
+/// `NM_CONNECTIVITY_FULL`, when the host can reach the internet.
+const CONNECTIVITY_FULL: u32 = 4;
+
+/// Subscribes to the changes of the properties of NetworkManager, and sends an event to the daemon
+/// whenever the primary connection changes, or the connectivity becomes full.
+///
+/// # Errors
+///
+/// Returns an error if the system bus cannot be reached.
+pub async fn spawn(sender: UnboundedSender<Event>) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let rule = MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender(NETWORK_MANAGER)?
+        .path("/org/freedesktop/NetworkManager")?
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .build();
+    let mut stream = MessageStream::for_match_rule(rule, &connection, None).await?;
+
+    tokio::spawn(async move {
+        while let Some(message) = stream.next().await {
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Stopped watching NetworkManager: {e}");
+                    break;
+                }
+            };
+            let Ok((interface, changed, _)) =
+                message
+                    .body()
+                    .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+            else {
+                continue;
+            };
+
+            if interface == NETWORK_MANAGER && is_relevant(&changed) {
+                debug!("NetworkManager changed the primary connection or its connectivity");
+                if sender.send(Event::NetworkChange).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Returns whether the `changed` properties of NetworkManager could mean the outside IP changed:
+/// another primary connection, or one that just reached the internet. The rest, like the
+/// connectivity dropping, or the devices, can't.
+fn is_relevant(changed: &HashMap<String, OwnedValue>) -> bool {
+    changed.contains_key("PrimaryConnection")
+        || changed
+            .get("Connectivity")
+            .is_some_and(|value| matches!(u32::try_from(&**value), Ok(CONNECTIVITY_FULL)))
+}
+
+#[test]
+fn test_is_relevant() {
+    use zbus::zvariant::{ObjectPath, Value};
+
+    fn changed(name: &str, value: &Value<'_>) -> HashMap<String, OwnedValue> {
+        HashMap::from([(name.to_string(), value.try_to_owned().unwrap())])
+    }
+
+    let path = ObjectPath::try_from("/org/freedesktop/NetworkManager/ActiveConnection/3").unwrap();
+    assert!(is_relevant(&changed(
+        "PrimaryConnection",
+        &Value::from(path)
+    )));
+    assert!(is_relevant(&changed(
+        "Connectivity",
+        &Value::from(CONNECTIVITY_FULL)
+    )));
+    // Behind a captive portal
+    assert!(!is_relevant(&changed("Connectivity", &Value::from(2_u32))));
+    assert!(!is_relevant(&changed(
+        "WirelessEnabled",
+        &Value::from(true)
+    )));
+}
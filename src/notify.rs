@@ -0,0 +1,1049 @@
+//! Tells people what cdu did, through whichever services they use.
+//!
+//! Every service is a [`Notifier`], which gets a [`Message`] that says what happened, to which
+//! domain, and how bad it is, and formats it however suits the service. Which services to use is
+//! given as a [`Target`] each, with a [`Subscription`] that says which messages it gets.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Client as RqClient, Url};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use crate::{apprise, email, gotify, matrix, ntfy, pushover, retry, slack, telegram, webhook};
+
+/// What a message is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// The outside IP is the same as during the previous check.
+    Unchanged,
+    /// The A record was changed to the outside IP.
+    Updated,
+    /// The outside IP is another one than during the previous check, whether or not the A record
+    /// was changed, as with `--monitor-only`.
+    IpChanged,
+    /// None of the servers told the outside IP.
+    DetectionFailed,
+    /// Cloudflare couldn't be asked for the A record, or refused to change it.
+    UpdateFailed,
+    /// The A record didn't point at the outside IP after changing it.
+    Mismatch,
+    /// The A record was changed elsewhere, to another IP than the one cdu pointed it at.
+    Drift,
+    /// Now and then, to tell cdu is still checking.
+    Heartbeat,
+    /// Sent by `cdu notify --test`, to check the targets work.
+    Test,
+    /// A failure that was alerted about is over, see [`Recovery`].
+    Recovered,
+    /// Cloudflare refused the API token, so cdu switched to the other one.
+    TokenSwitched,
+}
+
+impl EventKind {
+    /// Every kind, by the name it's given in `--notify-on`.
+    pub const ALL: [(&'static str, Self); 9] = [
+        ("unchanged", Self::Unchanged),
+        ("updated", Self::Updated),
+        ("ip-changed", Self::IpChanged),
+        ("detection-failed", Self::DetectionFailed),
+        ("update-failed", Self::UpdateFailed),
+        ("mismatch", Self::Mismatch),
+        ("drift", Self::Drift),
+        ("heartbeat", Self::Heartbeat),
+        ("token-switched", Self::TokenSwitched),
+    ];
+
+    /// Parses the name of a kind, as given in `--notify-on`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no such kind.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Self::ALL
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| {
+                let names = Self::ALL.map(|(name, _)| name);
+                format!(
+                    "Unknown kind of event: {name}, expected one of: {}",
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// Returns a short summary of a message of this kind.
+    #[must_use]
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Unchanged => "Outside IP unchanged",
+            Self::Updated => "A record updated",
+            Self::IpChanged => "Outside IP changed",
+            Self::DetectionFailed => "Outside IP detection failed",
+            Self::UpdateFailed => "A record update failed",
+            Self::Mismatch => "A record mismatch",
+            Self::Drift => "A record changed elsewhere",
+            Self::Heartbeat => "cdu is alive",
+            Self::Test => "Test notification",
+            Self::Recovered => "Recovered",
+            Self::TokenSwitched => "API token switched",
+        }
+    }
+
+    /// Returns how much a message of this kind needs attention.
+    #[must_use]
+    pub fn severity(self) -> Severity {
+        match self {
+            Self::Unchanged | Self::Heartbeat | Self::Test => Severity::Info,
+            Self::Updated | Self::IpChanged | Self::Recovered | Self::TokenSwitched => {
+                Severity::Notice
+            }
+            Self::DetectionFailed | Self::UpdateFailed | Self::Mismatch | Self::Drift => {
+                Severity::Error
+            }
+        }
+    }
+}
+
+/// How much a message needs attention, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Nothing happened, which is good to know now and then.
+    Info,
+    /// Something changed, as it should.
+    Notice,
+    /// Something went wrong, and may need fixing.
+    Error,
+}
+
+impl Severity {
+    /// Parses a severity, by its name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no such severity.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "info" => Ok(Self::Info),
+            "notice" => Ok(Self::Notice),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "Unknown severity: {name}, expected info, notice or error"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Notice => write!(f, "notice"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Something worth telling people about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub kind: EventKind,
+    pub severity: Severity,
+    pub domain: String,
+    /// The IP the A record pointed at before, if it's known.
+    pub old_ip: Option<IpAddr>,
+    /// The IP the A record points at now.
+    pub new_ip: Option<IpAddr>,
+    /// The server that told the outside IP.
+    pub source: Option<String>,
+    /// When the A record was changed before this, if it's known.
+    pub previous_change_at: Option<DateTime<Utc>>,
+    /// What went wrong, for a failure.
+    pub error: Option<String>,
+    /// Which failure is over, for a recovery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<Recovery>,
+    /// Every A record that was changed, when they're told about together.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<RecordChange>,
+    /// Who the new IP belongs to, and where it is, see [`crate::geoip`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// An A record that was changed, as part of a digest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordChange {
+    pub domain: String,
+    /// The IP it pointed at before.
+    pub old_ip: Option<IpAddr>,
+}
+
+/// A failure that's over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recovery {
+    /// The kind of event the alerts were about.
+    pub kind: EventKind,
+    /// When it first failed.
+    pub since: DateTime<Utc>,
+    /// How many alerts were held back since the last one was sent.
+    pub held_back: u32,
+}
+
+impl Message {
+    /// Returns a message about the outside IP still being `ip`, so the A record of `domain` was
+    /// left alone.
+    #[must_use]
+    pub fn unchanged(domain: &str, ip: IpAddr) -> Self {
+        Self {
+            kind: EventKind::Unchanged,
+            severity: EventKind::Unchanged.severity(),
+            domain: domain.to_string(),
+            old_ip: None,
+            new_ip: Some(ip),
+            source: None,
+            previous_change_at: None,
+            error: None,
+            recovery: None,
+            records: Vec::new(),
+            network: None,
+            at: Utc::now(),
+        }
+    }
+
+    /// Returns a message about the A record of `domain` having been changed.
+    #[must_use]
+    pub fn updated(domain: &str, old_ip: Option<IpAddr>, new_ip: IpAddr) -> Self {
+        Self {
+            kind: EventKind::Updated,
+            severity: EventKind::Updated.severity(),
+            domain: domain.to_string(),
+            old_ip,
+            new_ip: Some(new_ip),
+            source: None,
+            previous_change_at: None,
+            error: None,
+            recovery: None,
+            records: Vec::new(),
+            network: None,
+            at: Utc::now(),
+        }
+    }
+
+    /// Returns a message about the outside IP having changed from `old_ip` to `new_ip`, for the
+    /// domains in `domain`.
+    #[must_use]
+    pub fn ip_changed(domain: &str, old_ip: IpAddr, new_ip: IpAddr) -> Self {
+        Self {
+            kind: EventKind::IpChanged,
+            severity: EventKind::IpChanged.severity(),
+            old_ip: Some(old_ip),
+            ..Self::updated(domain, None, new_ip)
+        }
+    }
+
+    /// Returns a single message about the A records of several domains having been changed to
+    /// `new_ip`, with the IP each pointed at before.
+    #[must_use]
+    pub fn digest(records: Vec<RecordChange>, new_ip: IpAddr) -> Self {
+        let domain = records
+            .iter()
+            .map(|record| record.domain.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        // The old IP is only worth telling on its own if they all had it
+        let old_ip = records
+            .first()
+            .and_then(|first| first.old_ip)
+            .filter(|old_ip| records.iter().all(|record| record.old_ip == Some(*old_ip)));
+
+        Self {
+            records,
+            ..Self::updated(&domain, old_ip, new_ip)
+        }
+    }
+
+    /// Returns a message about something having gone wrong for `domain`, while making the A
+    /// record point at `new_ip` if it got that far.
+    #[must_use]
+    pub fn failed(kind: EventKind, domain: &str, new_ip: Option<IpAddr>, error: &str) -> Self {
+        Self {
+            kind,
+            severity: kind.severity(),
+            domain: domain.to_string(),
+            old_ip: None,
+            new_ip,
+            source: None,
+            previous_change_at: None,
+            error: Some(error.to_string()),
+            recovery: None,
+            records: Vec::new(),
+            network: None,
+            at: Utc::now(),
+        }
+    }
+
+    /// Returns a message telling cdu is still checking `domain`, which points at `ip`, since the
+    /// A record was last changed at `changed_at`, if it's known.
+    #[must_use]
+    pub fn heartbeat(domain: &str, ip: IpAddr, changed_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            kind: EventKind::Heartbeat,
+            severity: EventKind::Heartbeat.severity(),
+            domain: domain.to_string(),
+            old_ip: None,
+            new_ip: Some(ip),
+            source: None,
+            previous_change_at: changed_at,
+            error: None,
+            recovery: None,
+            records: Vec::new(),
+            network: None,
+            at: Utc::now(),
+        }
+    }
+
+    /// Returns a message that only checks the target works, naming `domain` so it's clear which
+    /// setup sent it.
+    #[must_use]
+    pub fn test(domain: &str) -> Self {
+        Self {
+            kind: EventKind::Test,
+            severity: EventKind::Test.severity(),
+            domain: domain.to_string(),
+            old_ip: None,
+            new_ip: None,
+            source: None,
+            previous_change_at: None,
+            error: None,
+            recovery: None,
+            records: Vec::new(),
+            network: None,
+            at: Utc::now(),
+        }
+    }
+
+    /// Returns a message about the failure of `domain` being over.
+    #[must_use]
+    pub fn recovered(domain: &str, recovery: Recovery) -> Self {
+        Self {
+            kind: EventKind::Recovered,
+            severity: EventKind::Recovered.severity(),
+            domain: domain.to_string(),
+            old_ip: None,
+            new_ip: None,
+            source: None,
+            previous_change_at: None,
+            error: None,
+            recovery: Some(recovery),
+            records: Vec::new(),
+            network: None,
+            at: Utc::now(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_network(mut self, network: Option<String>) -> Self {
+        self.network = network;
+        self
+    }
+
+    #[must_use]
+    pub fn with_previous_change_at(mut self, at: Option<DateTime<Utc>>) -> Self {
+        self.previous_change_at = at;
+        self
+    }
+
+    /// Returns how long the A record pointed at the old IP, if it's known.
+    #[must_use]
+    pub fn since_previous_change(&self) -> Option<std::time::Duration> {
+        (self.at - self.previous_change_at?).to_std().ok()
+    }
+}
+
+/// Formats a duration for people, leaving out the seconds once it's more than a minute, as they're
+/// noise by then.
+#[must_use]
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = match duration.as_secs() {
+        secs @ 0..=59 => secs,
+        secs => secs / 60 * 60,
+    };
+
+    humantime::format_duration(std::time::Duration::from_secs(secs)).to_string()
+}
+
+impl Message {
+    /// Returns a short summary, for services that show a title above the text.
+    #[must_use]
+    pub fn title(&self) -> &'static str {
+        self.kind.title()
+    }
+}
+
+impl fmt::Display for Message {
+    #[allow(clippy::too_many_lines)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            EventKind::Unchanged => {
+                write!(f, "The outside IP")?;
+                if let Some(ip) = self.new_ip {
+                    write!(f, " {ip}")?;
+                }
+                write!(
+                    f,
+                    " hasn't changed, so the A record of {} was left alone",
+                    self.domain
+                )
+            }
+            EventKind::Updated if self.records.len() > 1 => {
+                write!(f, "Updated A records of {} domains", self.records.len())?;
+                if let Some(new_ip) = self.new_ip {
+                    write!(f, " to {new_ip}")?;
+                }
+                if let Some(network) = &self.network {
+                    write!(f, " ({network})")?;
+                }
+                for (index, record) in self.records.iter().enumerate() {
+                    write!(
+                        f,
+                        "{}{}",
+                        if index == 0 { ": " } else { ", " },
+                        record.domain
+                    )?;
+                    if let Some(old_ip) = record.old_ip {
+                        write!(f, " from {old_ip}")?;
+                    }
+                }
+
+                Ok(())
+            }
+            EventKind::Updated => {
+                write!(f, "Updated A record of {}", self.domain)?;
+                if let Some(old_ip) = self.old_ip {
+                    write!(f, " from {old_ip}")?;
+                }
+                if let Some(new_ip) = self.new_ip {
+                    write!(f, " to {new_ip}")?;
+                }
+                if let Some(network) = &self.network {
+                    write!(f, " ({network})")?;
+                }
+
+                Ok(())
+            }
+            EventKind::IpChanged => {
+                write!(f, "The outside IP of {} changed", self.domain)?;
+                if let Some(old_ip) = self.old_ip {
+                    write!(f, " from {old_ip}")?;
+                }
+                if let Some(new_ip) = self.new_ip {
+                    write!(f, " to {new_ip}")?;
+                }
+
+                Ok(())
+            }
+            EventKind::DetectionFailed => write!(
+                f,
+                "Failed to detect the outside IP for {}: {}",
+                self.domain,
+                self.error.as_deref().unwrap_or_default()
+            ),
+            EventKind::UpdateFailed => {
+                write!(f, "Failed to update A record of {}", self.domain)?;
+                if let Some(new_ip) = self.new_ip {
+                    write!(f, " to {new_ip}")?;
+                }
+                write!(f, ": {}", self.error.as_deref().unwrap_or_default())
+            }
+            EventKind::Mismatch | EventKind::Drift | EventKind::TokenSwitched => {
+                write!(f, "{}", self.error.as_deref().unwrap_or_default())
+            }
+            EventKind::Heartbeat => {
+                write!(f, "cdu is still checking {}", self.domain)?;
+                if let Some(ip) = self.new_ip {
+                    write!(f, ", the outside IP is {ip}")?;
+                }
+                if let Some(since) = self.since_previous_change() {
+                    write!(f, " and hasn't changed for {}", format_duration(since))?;
+                }
+
+                Ok(())
+            }
+            EventKind::Test => write!(
+                f,
+                "This is a test notification from cdu for {}, so this target works",
+                self.domain
+            ),
+            EventKind::Recovered => {
+                let Some(recovery) = self.recovery else {
+                    return write!(f, "{} has recovered", self.domain);
+                };
+                write!(f, "{} has recovered", self.domain)?;
+                if let Ok(lasted) = (self.at - recovery.since).to_std() {
+                    write!(f, " after {}", format_duration(lasted))?;
+                }
+                write!(f, " ({})", recovery.kind.title())?;
+                if recovery.held_back > 0 {
+                    write!(f, ", {} repeated alerts were held back", recovery.held_back)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A service that messages can be sent to.
+#[async_trait]
+pub trait Notifier: fmt::Debug + Send + Sync {
+    /// Returns the name of the service, for the logs.
+    fn name(&self) -> &str;
+
+    /// Sends the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service cannot be reached, or doesn't accept the message.
+    async fn send(&self, message: &Message) -> anyhow::Result<()>;
+}
+
+/// Which messages a target gets.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Subscription {
+    /// The kinds of events, if not the ones from `--notify-on`.
+    pub on: Option<Vec<EventKind>>,
+    /// Messages that need less attention than this aren't sent.
+    pub min_severity: Option<Severity>,
+    /// The name of a named target, lowercase, like `phone` for `CDU_NOTIFY_PHONE`, which the
+    /// settings of a domain can send its messages to.
+    pub name: Option<String>,
+}
+
+impl Subscription {
+    /// Returns whether the message should be sent, where `default_on` are the kinds of events
+    /// from `--notify-on`.
+    ///
+    /// A recovery goes wherever the alerts about the failure went.
+    #[must_use]
+    pub fn wants(&self, message: &Message, default_on: &[EventKind]) -> bool {
+        let kind = message
+            .recovery
+            .map_or(message.kind, |recovery| recovery.kind);
+        let severity = if message.recovery.is_some() {
+            kind.severity()
+        } else {
+            message.severity
+        };
+
+        self.on.as_deref().unwrap_or(default_on).contains(&kind)
+            && self
+                .min_severity
+                .filter(|min_severity| severity < *min_severity)
+                .is_none()
+    }
+}
+
+/// A notifier, with the messages it's subscribed to.
+#[derive(Debug)]
+pub struct Destination {
+    /// The ID of the target, see [`Target::id`].
+    pub id: String,
+    pub notifier: Box<dyn Notifier>,
+    pub subscription: Subscription,
+}
+
+impl Destination {
+    #[must_use]
+    pub fn new(target: &Target, subscription: &Subscription, client: &RqClient) -> Self {
+        Self {
+            id: target.id(),
+            notifier: target.notifier(client),
+            subscription: subscription.clone(),
+        }
+    }
+}
+
+/// The kinds of services, as given before the target.
+const KINDS: [&str; 10] = [
+    "discord", "slack", "telegram", "ntfy", "gotify", "pushover", "matrix", "email", "webhook",
+    "log",
+];
+
+/// A service to send messages to, given as `<kind>:<target>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// `discord:<webhook URL>`
+    Discord(String),
+    /// `slack:<incoming webhook URL>`
+    Slack(String),
+    /// `telegram:<bot token>@<chat ID>`
+    Telegram { token: String, chat_id: String },
+    /// `ntfy:<topic URL>`, with the options in the query
+    Ntfy(ntfy::Options),
+    /// `gotify:<server URL>?token=<app token>`
+    Gotify(gotify::Options),
+    /// `pushover:<user key>@<app token>`, with the options after a `?`
+    Pushover(pushover::Options),
+    /// `matrix:<homeserver URL>?token=<access token>&room=<room ID>`
+    Matrix(matrix::Options),
+    /// `email:<SMTP URL>?from=<address>&to=<address>`
+    Email(email::Options),
+    /// `webhook:<URL>`, with the body from `--webhook-template`, signed with `--webhook-secret`,
+    /// sent with `--webhook-method` and `--webhook-header`
+    Webhook(webhook::Options),
+    /// `log:<name>`, which only logs the messages, like every target does with `--simulate`
+    Log(String),
+}
+
+impl Target {
+    /// Parses a target, like `slack:https://hooks.slack.com/services/...`, or an Apprise URL,
+    /// see [`apprise`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the kind of service is unknown, or the target is missing.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(translated) = apprise::translate(spec) {
+            return Self::parse(&translated?);
+        }
+        let Some((kind, target)) = spec.split_once(':') else {
+            return Err(format!(
+                "Expected <kind>:<target>, e.g. slack:https://hooks.slack.com/..., got: {spec}"
+            ));
+        };
+        if target.is_empty() {
+            return Err(format!("Missing the target for {kind}"));
+        }
+
+        match kind {
+            "discord" => Ok(Self::Discord(target.to_string())),
+            "slack" => Ok(Self::Slack(target.to_string())),
+            "telegram" => match target.rsplit_once('@') {
+                Some((token, chat_id)) if !token.is_empty() && !chat_id.is_empty() => {
+                    Ok(Self::Telegram {
+                        token: token.to_string(),
+                        chat_id: chat_id.to_string(),
+                    })
+                }
+                _ => Err(String::from("Expected telegram:<bot token>@<chat ID>")),
+            },
+            "ntfy" => ntfy::Options::parse(target).map(Self::Ntfy),
+            "gotify" => gotify::Options::parse(target).map(Self::Gotify),
+            "pushover" => pushover::Options::parse(target).map(Self::Pushover),
+            "matrix" => matrix::Options::parse(target).map(Self::Matrix),
+            "email" => email::Options::parse(target).map(Self::Email),
+            "webhook" => Ok(Self::Webhook(webhook::Options {
+                url: target.to_string(),
+                ..webhook::Options::default()
+            })),
+            "log" => Ok(Self::Log(target.to_string())),
+            _ => Err(format!(
+                "Unknown kind of notification: {kind}, expected one of: {}",
+                KINDS.join(", ")
+            )),
+        }
+    }
+
+    /// Returns the kind of service, which is safe to log, unlike the target itself.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Discord(_) => "discord",
+            Self::Slack(_) => "slack",
+            Self::Telegram { .. } => "telegram",
+            Self::Ntfy(_) => "ntfy",
+            Self::Gotify(_) => "gotify",
+            Self::Pushover(_) => "pushover",
+            Self::Matrix(_) => "matrix",
+            Self::Email(_) => "email",
+            Self::Webhook(_) => "webhook",
+            Self::Log(_) => "log",
+        }
+    }
+
+    /// Returns the host and port that messages to the target are sent to, to check it can be
+    /// reached without sending anything. Returns `None` if the URL has no host, or nothing is sent
+    /// anywhere.
+    #[must_use]
+    pub fn address(&self) -> Option<(String, u16)> {
+        let url = match self {
+            Self::Discord(url) | Self::Slack(url) => Url::parse(url).ok()?,
+            Self::Telegram { .. } => Url::parse(telegram::BASE_URL).ok()?,
+            Self::Ntfy(options) => options.url.clone(),
+            Self::Gotify(options) => options.url.clone(),
+            Self::Pushover(_) => Url::parse(pushover::URL).ok()?,
+            Self::Matrix(options) => options.url.clone(),
+            Self::Email(options) => {
+                return Some((options.url.host_str()?.to_string(), options.port()));
+            }
+            Self::Webhook(options) => Url::parse(&options.url).ok()?,
+            Self::Log(_) => return None,
+        };
+
+        Some((url.host_str()?.to_string(), url.port_or_known_default()?))
+    }
+
+    /// Returns an ID that stays the same for the same target, so a message that's queued for it
+    /// can be sent to it after a restart. It's a hash, as the target itself has secrets in it.
+    #[must_use]
+    pub fn id(&self) -> String {
+        // FNV-1a, as the hashers of the standard library may change between releases
+        let hash = format!("{self:?}")
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+
+        format!("{}-{hash:016x}", self.kind())
+    }
+
+    /// Returns the notifier that sends messages to the target.
+    #[must_use]
+    pub fn notifier(&self, client: &RqClient) -> Box<dyn Notifier> {
+        match self {
+            Self::Discord(url) => Box::new(webhook::Discord::new(client.clone(), url)),
+            Self::Slack(url) => Box::new(slack::Slack::new(client.clone(), url)),
+            Self::Telegram { token, chat_id } => {
+                Box::new(telegram::Telegram::new(client.clone(), token, chat_id))
+            }
+            Self::Ntfy(options) => Box::new(ntfy::Ntfy::new(client.clone(), options)),
+            Self::Gotify(options) => Box::new(gotify::Gotify::new(client.clone(), options)),
+            Self::Pushover(options) => Box::new(pushover::Pushover::new(client.clone(), options)),
+            Self::Matrix(options) => Box::new(matrix::Matrix::new(client.clone(), options)),
+            Self::Email(options) => Box::new(email::Smtp::new(options)),
+            Self::Webhook(options) => Box::new(webhook::Webhook::new(client.clone(), options)),
+            Self::Log(name) => Box::new(Log { name: name.clone() }),
+        }
+    }
+
+    /// Returns the target with everything but the URL from `settings`, like the template for the
+    /// body and the secret to sign it with, if it's a webhook.
+    #[must_use]
+    pub fn with_webhook_settings(self, settings: &webhook::Options) -> Self {
+        match self {
+            Self::Webhook(options) => Self::Webhook(webhook::Options {
+                url: options.url,
+                ..settings.clone()
+            }),
+            target => target,
+        }
+    }
+}
+
+/// Logs the messages instead of sending them.
+#[derive(Debug)]
+struct Log {
+    name: String,
+}
+
+#[async_trait]
+impl Notifier for Log {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        info!("Notification for {}: {message}", self.name);
+        Ok(())
+    }
+}
+
+/// The prefix of the environment variables that give a target each, like `CDU_NOTIFY_PHONE`.
+const ENV_PREFIX: &str = "CDU_NOTIFY_";
+
+/// Returns the targets given in the environment variables, as `CDU_NOTIFY_<NAME>=<kind>:<target>`,
+/// in the order of their names. For each target:
+///
+/// - `CDU_NOTIFY_<NAME>_ENABLED=false` turns it off, without removing it.
+/// - `CDU_NOTIFY_<NAME>_ON` gives the kinds of events it gets, instead of `--notify-on`.
+/// - `CDU_NOTIFY_<NAME>_SEVERITY` leaves out the messages that need less attention.
+///
+/// # Errors
+///
+/// Returns an error if a target or setting is invalid, or there's a setting for a target that
+/// isn't there.
+pub fn targets_from_env(
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<(Target, Subscription)>, String> {
+    let mut targets = BTreeMap::new();
+    let mut enabled = BTreeMap::new();
+    let mut subscriptions = BTreeMap::<String, Subscription>::new();
+    for (key, value) in vars {
+        let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        // That's --notify-on
+        if name == "ON" {
+            continue;
+        }
+
+        if let Some(name) = name.strip_suffix("_ENABLED") {
+            let value = match value.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => true,
+                "false" | "no" | "off" | "0" => false,
+                _ => return Err(format!("{key}: expected true or false, got: {value}")),
+            };
+            enabled.insert(name.to_string(), value);
+        } else if let Some(name) = name.strip_suffix("_ON") {
+            let on = value
+                .split(',')
+                .map(|kind| EventKind::parse(kind.trim()))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{key}: {e}"))?;
+            subscriptions.entry(name.to_string()).or_default().on = Some(on);
+        } else if let Some(name) = name.strip_suffix("_SEVERITY") {
+            let min_severity = Severity::parse(&value).map_err(|e| format!("{key}: {e}"))?;
+            subscriptions
+                .entry(name.to_string())
+                .or_default()
+                .min_severity = Some(min_severity);
+        } else {
+            let target = Target::parse(&value).map_err(|e| format!("{key}: {e}"))?;
+            targets.insert(name.to_string(), target);
+        }
+    }
+
+    if let Some(name) = enabled
+        .keys()
+        .chain(subscriptions.keys())
+        .find(|name| !targets.contains_key(*name))
+    {
+        return Err(format!(
+            "There are settings for {ENV_PREFIX}{name}, but there's no {ENV_PREFIX}{name}"
+        ));
+    }
+    Ok(targets
+        .into_iter()
+        .filter(|(name, _)| {
+            let enabled = enabled.get(name).copied().unwrap_or(true);
+            if !enabled {
+                debug!("Not sending notifications to {ENV_PREFIX}{name}, as it's turned off");
+            }
+            enabled
+        })
+        .map(|(name, target)| {
+            let subscription = Subscription {
+                name: Some(name.to_ascii_lowercase()),
+                ..subscriptions.remove(&name).unwrap_or_default()
+            };
+            (target, subscription)
+        })
+        .collect())
+}
+
+/// Sends the message to every destination, trying again as `retry` says, and returns the IDs of
+/// the ones it failed for. A destination that fails is logged, and doesn't stop the others.
+pub async fn send_all<'a>(
+    destinations: impl IntoIterator<Item = &'a Destination>,
+    message: &Message,
+    retry: retry::Policy,
+) -> Vec<&'a str> {
+    let mut failed = Vec::new();
+    for destination in destinations {
+        let name = destination.notifier.name();
+        let sent = retry
+            .run(
+                &format!("Sending the message to {name}"),
+                || destination.notifier.send(message),
+                retry::is_transient_any,
+            )
+            .await;
+        match sent {
+            Ok(()) => debug!("Sent message to {name}"),
+            Err(e) => {
+                error!("Failed to send message to {name}: {e:#}");
+                failed.push(destination.id.as_str());
+            }
+        }
+    }
+
+    failed
+}
+
+#[test]
+fn test_message() {
+    let ip = |last| IpAddr::from([192, 0, 2, last]);
+
+    assert_eq!(
+        Message::updated("example.com", Some(ip(1)), ip(2)).to_string(),
+        "Updated A record of example.com from 192.0.2.1 to 192.0.2.2"
+    );
+    assert_eq!(
+        Message::updated("example.com", None, ip(2)).to_string(),
+        "Updated A record of example.com to 192.0.2.2"
+    );
+    let message = Message::ip_changed("example.com", ip(1), ip(2));
+    assert_eq!(message.severity, Severity::Notice);
+    assert_eq!(
+        message.to_string(),
+        "The outside IP of example.com changed from 192.0.2.1 to 192.0.2.2"
+    );
+
+    let message = Message::failed(
+        EventKind::UpdateFailed,
+        "example.com",
+        Some(ip(2)),
+        "Cloudflare API error: Authentication error",
+    );
+    assert_eq!(message.severity, Severity::Error);
+    assert_eq!(
+        message.to_string(),
+        "Failed to update A record of example.com to 192.0.2.2: Cloudflare API error: Authentication error"
+    );
+    assert_eq!(EventKind::parse("mismatch"), Ok(EventKind::Mismatch));
+    assert_eq!(EventKind::parse("drift"), Ok(EventKind::Drift));
+    assert_eq!(
+        EventKind::parse("token-switched"),
+        Ok(EventKind::TokenSwitched)
+    );
+    assert!(EventKind::parse("failed").is_err());
+
+    let mut message = Message::recovered(
+        "example.com",
+        Recovery {
+            kind: EventKind::UpdateFailed,
+            since: Utc::now(),
+            held_back: 58,
+        },
+    );
+    message.at = message.recovery.unwrap().since + chrono::Duration::seconds(3725);
+    assert_eq!(
+        message.to_string(),
+        "example.com has recovered after 1h 2m (A record update failed), 58 repeated alerts were held back"
+    );
+    let digest = Message::digest(
+        vec![
+            RecordChange {
+                domain: String::from("example.com"),
+                old_ip: Some(ip(1)),
+            },
+            RecordChange {
+                domain: String::from("example.org"),
+                old_ip: Some(ip(3)),
+            },
+        ],
+        ip(2),
+    );
+    assert_eq!(digest.domain, "example.com, example.org");
+    assert_eq!(digest.old_ip, None);
+    assert_eq!(
+        digest.to_string(),
+        "Updated A records of 2 domains to 192.0.2.2: example.com from 192.0.2.1, example.org from 192.0.2.3"
+    );
+
+    let mut heartbeat = Message::heartbeat("example.com", ip(2), None);
+    heartbeat.previous_change_at = Some(heartbeat.at - chrono::Duration::days(9));
+    assert_eq!(
+        heartbeat.to_string(),
+        "cdu is still checking example.com, the outside IP is 192.0.2.2 and hasn't changed for 9days"
+    );
+
+    // It goes wherever the alerts went
+    let subscription = Subscription {
+        on: Some(vec![EventKind::UpdateFailed]),
+        min_severity: Some(Severity::Error),
+        name: None,
+    };
+    assert!(subscription.wants(&message, &[EventKind::Updated]));
+    assert!(!Subscription::default().wants(&message, &[EventKind::Updated]));
+}
+
+#[test]
+fn test_parse_target() {
+    assert_eq!(
+        Target::parse("slack:https://hooks.slack.com/services/T/B/X"),
+        Ok(Target::Slack(String::from(
+            "https://hooks.slack.com/services/T/B/X"
+        )))
+    );
+    assert!(Target::parse("https://hooks.slack.com/services/T/B/X").is_err());
+    assert!(Target::parse("pigeon:home").is_err());
+    assert!(Target::parse("slack:").is_err());
+    assert_eq!(
+        Target::parse("telegram:123456:ABC-def@-1001234"),
+        Ok(Target::Telegram {
+            token: String::from("123456:ABC-def"),
+            chat_id: String::from("-1001234")
+        })
+    );
+    assert!(Target::parse("telegram:123456:ABC-def").is_err());
+    assert_eq!(
+        Target::parse("tgram://123456:ABC-def/-1001234"),
+        Target::parse("telegram:123456:ABC-def@-1001234")
+    );
+
+    let address = |spec| Target::parse(spec).unwrap().address();
+    assert_eq!(
+        address("slack:https://hooks.slack.com/services/T/B/X"),
+        Some((String::from("hooks.slack.com"), 443))
+    );
+    assert_eq!(
+        address("gotify:http://gotify.local:8080?token=AbCd"),
+        Some((String::from("gotify.local"), 8080))
+    );
+    assert_eq!(
+        address("email:smtp://smtp.example.com?from=cdu@example.com&to=me@example.com"),
+        Some((String::from("smtp.example.com"), 587))
+    );
+    assert_eq!(address("log:slack"), None);
+}
+
+#[test]
+fn test_targets_from_env() {
+    let vars = |vars: &[(&str, &str)]| {
+        vars.iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    let targets = targets_from_env(vars(&[
+        (
+            "CDU_NOTIFY_TEAM",
+            "slack:https://hooks.slack.com/services/T/B/X",
+        ),
+        ("CDU_NOTIFY_PHONE", "ntfy:https://ntfy.sh/cdu"),
+        ("CDU_NOTIFY_PHONE_SEVERITY", "error"),
+        (
+            "CDU_NOTIFY_OLD",
+            "discord:https://discord.com/api/webhooks/1/x",
+        ),
+        ("CDU_NOTIFY_OLD_ENABLED", "false"),
+        ("CDU_NOTIFY_ON", "updated"),
+        ("CDU_DOMAIN", "example.com"),
+    ]))
+    .unwrap();
+    assert_eq!(
+        targets
+            .iter()
+            .map(|(target, _)| target.kind())
+            .collect::<Vec<_>>(),
+        ["ntfy", "slack"]
+    );
+
+    let (_, phone) = &targets[0];
+    assert_eq!(phone.name.as_deref(), Some("phone"));
+    let updated = Message::updated("example.com", None, IpAddr::from([192, 0, 2, 1]));
+    let failed = Message::failed(EventKind::UpdateFailed, "example.com", None, "Timed out");
+    let default_on = [EventKind::Updated, EventKind::UpdateFailed];
+    assert!(!phone.wants(&updated, &default_on));
+    assert!(phone.wants(&failed, &default_on));
+    assert!(!phone.wants(&failed, &[EventKind::Updated]));
+
+    assert!(targets_from_env(vars(&[("CDU_NOTIFY_PHONE", "ntfy")])).is_err());
+    assert!(targets_from_env(vars(&[("CDU_NOTIFY_PHONE_ENABLED", "true")])).is_err());
+    assert!(targets_from_env(vars(&[("CDU_NOTIFY_PHONE_ON", "updated")])).is_err());
+}
@@ -0,0 +1,142 @@
+//! Publishes messages to an ntfy topic, which pushes them to the phones subscribed to it.
+//!
+//! The target is the URL of the topic, like `https://ntfy.sh/my-cdu`, with these optional query
+//! parameters, which are sent as headers rather than passed on:
+//!
+//! - `token`, the access token for a protected topic.
+//! - `priority`, from `1` to `5`, or `min`, `low`, `default`, `high`, `max` or `urgent`.
+//! - `tags`, separated by commas, which ntfy shows as emojis if it knows them.
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{Client as RqClient, Url};
+
+use crate::notify::{Message, Notifier};
+use crate::retry::StatusError;
+
+const PRIORITIES: [&str; 11] = [
+    "1", "2", "3", "4", "5", "min", "low", "default", "high", "max", "urgent",
+];
+
+/// A topic, and how to publish to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The URL of the topic, without the options.
+    pub url: Url,
+    pub token: Option<String>,
+    pub priority: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Options {
+    /// Parses the URL of a topic, taking the options out of its query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid, or an option is unknown or has an invalid value.
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let mut url = Url::parse(target).map_err(|e| format!("Invalid ntfy topic URL: {e}"))?;
+        if url.path().trim_matches('/').is_empty() {
+            return Err(String::from("The ntfy URL has no topic"));
+        }
+
+        let mut options = Self {
+            url: url.clone(),
+            token: None,
+            priority: None,
+            tags: Vec::new(),
+        };
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "token" => options.token = Some(value.into_owned()),
+                "priority" if PRIORITIES.contains(&value.as_ref()) => {
+                    options.priority = Some(value.into_owned());
+                }
+                "priority" => {
+                    return Err(format!(
+                        "Invalid ntfy priority: {value}, expected 1 to 5 or min, low, default, high, max or urgent"
+                    ));
+                }
+                "tags" => options.tags.extend(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|tag| !tag.is_empty())
+                        .map(String::from),
+                ),
+                _ => return Err(format!("Unknown ntfy option: {name}")),
+            }
+        }
+        url.set_query(None);
+        options.url = url;
+
+        Ok(options)
+    }
+}
+
+/// An ntfy topic.
+#[derive(Debug)]
+pub struct Ntfy {
+    client: RqClient,
+    options: Options,
+}
+
+impl Ntfy {
+    #[must_use]
+    pub fn new(client: RqClient, options: &Options) -> Self {
+        Self {
+            client,
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let mut request = self
+            .client
+            .post(self.options.url.clone())
+            .header("Title", message.title())
+            .body(message.to_string());
+        if let Some(token) = &self.options.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(priority) = &self.options.priority {
+            request = request.header("Priority", priority);
+        }
+        if !self.options.tags.is_empty() {
+            request = request.header("Tags", self.options.tags.join(","));
+        }
+
+        let response = request.send().await.context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_options() {
+    let options = Options::parse(
+        "https://ntfy.example.com/cdu?token=tk_secret&priority=high&tags=globe,house",
+    )
+    .unwrap();
+    assert_eq!(options.url.as_str(), "https://ntfy.example.com/cdu");
+    assert_eq!(options.token.as_deref(), Some("tk_secret"));
+    assert_eq!(options.priority.as_deref(), Some("high"));
+    assert_eq!(options.tags, ["globe", "house"]);
+
+    assert!(Options::parse("https://ntfy.sh/cdu?priority=6").is_err());
+    assert!(Options::parse("https://ntfy.sh/cdu?title=Hi").is_err());
+    assert!(Options::parse("https://ntfy.sh/").is_err());
+}
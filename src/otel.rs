@@ -0,0 +1,97 @@
+//! Exports the spans of every run to an OpenTelemetry collector over OTLP, so the detection, the
+//! calls to Cloudflare and the notifications show up in Jaeger or Tempo next to the rest of the
+//! homelab.
+//!
+//! The spans are the ones of `#[tracing::instrument]`, sent in batches from a thread of their own,
+//! so a collector that's slow or down never holds up a run. What's left is sent when cdu exits.
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The path collectors take traces on, which is added to an endpoint without a path.
+const TRACES_PATH: &str = "v1/traces";
+
+static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+/// Returns the layer that exports the spans to the collector at `endpoint`, with `headers` on every
+/// request, like the key of a hosted collector.
+///
+/// # Errors
+///
+/// Returns an error if the exporter cannot be built.
+pub fn layer<S>(
+    endpoint: &reqwest::Url,
+    headers: &[(String, String)],
+) -> anyhow::Result<OpenTelemetryLayer<S, Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let mut endpoint = endpoint.clone();
+    if endpoint.path() == "/" {
+        endpoint.set_path(TRACES_PATH);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint.as_str())
+        .with_headers(headers.iter().cloned().collect::<HashMap<_, _>>())
+        .build()
+        .context("Failed to set up the OpenTelemetry exporter")?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::TokioCurrentThread)
+        .with_resource(Resource::new([
+            KeyValue::new("service.name", "cdu"),
+            KeyValue::new("service.version", clap::crate_version!()),
+        ]))
+        .build();
+    let tracer = provider.tracer("cdu");
+    let _ = PROVIDER.set(provider);
+
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Sends the spans that haven't been yet, before cdu exits.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        for result in provider.force_flush() {
+            if let Err(e) = result {
+                eprintln!("Failed to export the traces: {e}");
+            }
+        }
+        let _ = provider.shutdown();
+    }
+}
+
+/// Parses a header to send to the collector, as `<name>=<value>`, like in
+/// `OTEL_EXPORTER_OTLP_HEADERS`.
+///
+/// # Errors
+///
+/// Returns an error if there's no `=`, or the name is empty.
+pub fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("Not a header: {value}, use <name>=<value>")),
+    }
+}
+
+#[test]
+fn test_parse_header() {
+    assert_eq!(
+        parse_header("x-honeycomb-team=abc=="),
+        Ok((String::from("x-honeycomb-team"), String::from("abc==")))
+    );
+    assert!(parse_header("authorization").is_err());
+    assert!(parse_header("=value").is_err());
+}
@@ -0,0 +1,345 @@
+//! Prints what a run did as one JSON document on stdout, with `--output json`, so automation can
+//! read it instead of the log, which stays on stderr. With `--output ndjson`, every check prints
+//! its events as they happen instead, one JSON object per line, for log pipelines like Vector or
+//! Loki.
+//!
+//! For someone at a terminal, `--output human` prints a line per domain instead, colored by how it
+//! went, unless stdout isn't a terminal or `NO_COLOR` is set.
+//!
+//! A dry run prints what it would change in every A record, see [`plan`], unless the output is
+//! JSON.
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::net::IpAddr;
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::cloudflare::ARecord;
+use crate::exit;
+use crate::network::Family;
+use crate::updater::{Action, Outcome, Report};
+
+/// How the outcome of a run is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Only in the log.
+    Text,
+    Json,
+    /// An event per line, see [`emit`].
+    Ndjson,
+    /// A line per domain, see [`human`].
+    Human,
+}
+
+impl Output {
+    /// Parses an output, which is `text`, `json`, `ndjson` or `human`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it's none of them.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            "human" => Ok(Self::Human),
+            _ => Err(format!(
+                "Unknown output: {name}, expected text, json, ndjson or human"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Json => write!(f, "json"),
+            Self::Ndjson => write!(f, "ndjson"),
+            Self::Human => write!(f, "human"),
+        }
+    }
+}
+
+/// The document that's printed, with what happened, what the last cycle did and the code cdu exits
+/// with.
+#[derive(Debug, Serialize)]
+struct Document<'a> {
+    outcome: &'static str,
+    #[serde(flatten)]
+    report: &'a Report,
+    error: Option<String>,
+    exit_code: i32,
+}
+
+/// Returns the outcome of a run and what it did as JSON.
+///
+/// # Errors
+///
+/// Returns an error if it cannot be serialized.
+pub fn json(result: &anyhow::Result<Outcome>, report: &Report) -> anyhow::Result<String> {
+    let document = match result {
+        Ok(outcome) => Document {
+            outcome: outcome.name(),
+            report,
+            error: None,
+            exit_code: match outcome {
+                Outcome::Updated(_) => exit::UPDATED,
+                _ => exit::UNCHANGED,
+            },
+        },
+        Err(e) => Document {
+            outcome: "failed",
+            report,
+            error: Some(format!("{e:#}")),
+            exit_code: exit::code(e),
+        },
+    };
+
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+/// The colors of the status lines, as ANSI escape codes.
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Returns a line per domain with how it went, green when it's up to date, yellow when it was
+/// updated or left alone and red when it failed, if `color`. A run that failed before any domain was looked up
+/// gets a line of its own.
+#[must_use]
+pub fn human(result: &anyhow::Result<Outcome>, report: &Report, color: bool) -> String {
+    let width = report
+        .domains
+        .iter()
+        .map(|done| done.domain.len())
+        .max()
+        .unwrap_or_default();
+    let describe =
+        |ip: Option<IpAddr>| ip.map_or_else(|| String::from("unknown"), |ip| ip.to_string());
+
+    let mut lines = Vec::new();
+    for done in &report.domains {
+        let (status, paint, detail) = match done.action {
+            Action::Skipped if done.ip.is_some() && done.ip == report.ip => {
+                ("up to date", GREEN, describe(done.ip))
+            }
+            Action::Skipped => ("skipped", "", describe(done.ip)),
+            Action::UpToDate => ("up to date", GREEN, describe(done.ip)),
+            Action::Updated => (
+                "updated",
+                YELLOW,
+                format!("{} -> {}", describe(done.previous_ip), describe(done.ip)),
+            ),
+            Action::WouldUpdate => (
+                "would update",
+                YELLOW,
+                format!("{} -> {}", describe(done.previous_ip), describe(report.ip)),
+            ),
+            Action::LeftAlone => (
+                "left alone",
+                YELLOW,
+                format!("{} instead of {}", describe(done.ip), describe(report.ip)),
+            ),
+            Action::Failed => ("failed", RED, done.error.clone().unwrap_or_default()),
+        };
+        lines.push(format!(
+            "{:width$}  {}  {detail}",
+            done.domain,
+            paint_status(&format!("{status:12}"), paint, color)
+        ));
+    }
+    if let Err(e) = result {
+        if !report
+            .domains
+            .iter()
+            .any(|done| done.action == Action::Failed)
+        {
+            lines.push(format!("{}  {e:#}", paint_status("failed", RED, color)));
+        }
+    }
+
+    lines.join("\n")
+}
+
+fn paint_status(status: &str, paint: &str, color: bool) -> String {
+    if color && !paint.is_empty() {
+        format!("{paint}{status}{RESET}")
+    } else {
+        status.to_string()
+    }
+}
+
+/// Returns whether the status lines can be colored: stdout is a terminal, and `NO_COLOR` isn't set.
+#[must_use]
+pub fn use_color() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").unwrap_or_default().is_empty()
+}
+
+/// Returns what a dry run would change in the A record of `domain`, as a diff of `before` and
+/// `after`, where the fields that would change have a line for each.
+#[must_use]
+pub fn plan(domain: &str, before: &ARecord, after: &ARecord) -> String {
+    let record_type = Family::of(after.ip).record_type();
+    let mut lines = vec![format!(
+        "Would update the {record_type} record of {domain}:"
+    )];
+    lines.extend(diff(domain, before, after));
+
+    lines.join("\n")
+}
+
+/// Returns the lines of the diff of the record of `domain` from `before` to `after`, with a line
+/// for every field that stays and two for every one that changes.
+#[must_use]
+pub fn diff(domain: &str, before: &ARecord, after: &ARecord) -> Vec<String> {
+    let ttl = |record: &ARecord| match record.ttl {
+        Some(1) => String::from("auto"),
+        Some(ttl) => ttl.to_string(),
+        None => String::from("unknown"),
+    };
+    let proxied = |record: &ARecord| {
+        record
+            .proxied
+            .map_or_else(|| String::from("unknown"), |proxied| proxied.to_string())
+    };
+    let fields = [
+        ("name", domain.to_string(), domain.to_string()),
+        (
+            "type",
+            Family::of(before.ip).record_type().to_string(),
+            Family::of(after.ip).record_type().to_string(),
+        ),
+        ("content", before.ip.to_string(), after.ip.to_string()),
+        ("ttl", ttl(before), ttl(after)),
+        ("proxied", proxied(before), proxied(after)),
+    ];
+
+    let mut lines = Vec::new();
+    for (name, old, new) in fields {
+        if old == new {
+            lines.push(format!("  {name:8} {old}"));
+        } else {
+            lines.push(format!("- {name:8} {old}"));
+            lines.push(format!("+ {name:8} {new}"));
+        }
+    }
+
+    lines
+}
+
+/// Returns an event as one line of JSON, with its name, when it happened, and the fields of
+/// `fields`, which is an object.
+#[must_use]
+pub fn event(name: &str, fields: Value) -> String {
+    let mut event = serde_json::json!({ "event": name, "at": Utc::now() });
+    if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), fields) {
+        event.extend(fields);
+    }
+
+    event.to_string()
+}
+
+/// Prints an event, see [`event`], on stdout.
+pub fn emit(name: &str, fields: Value) {
+    println!("{}", event(name, fields));
+}
+
+#[test]
+fn test_json() {
+    use crate::updater::DomainResult;
+
+    let ip = IpAddr::from([192, 0, 2, 1]);
+    let report = Report {
+        ip: Some(ip),
+        source: Some(String::from("icanhazip.com")),
+        domains: vec![DomainResult {
+            domain: String::from("example.com"),
+            previous_ip: Some(IpAddr::from([192, 0, 2, 2])),
+            ip: Some(ip),
+            action: Action::Updated,
+            error: None,
+        }],
+    };
+
+    let document = json(&Ok(Outcome::Updated(ip)), &report).unwrap();
+    let document = serde_json::from_str::<serde_json::Value>(&document).unwrap();
+    assert_eq!(document["outcome"], "updated");
+    assert_eq!(document["ip"], "192.0.2.1");
+    assert_eq!(document["domains"][0]["previous_ip"], "192.0.2.2");
+    assert_eq!(document["domains"][0]["action"], "updated");
+    assert_eq!(document["exit_code"], 2);
+
+    let failed = exit::wrap(exit::DETECTION_FAILED, anyhow::anyhow!("No servers"));
+    let document = json(&Err(failed), &Report::default()).unwrap();
+    let document = serde_json::from_str::<serde_json::Value>(&document).unwrap();
+    assert_eq!(document["outcome"], "failed");
+    assert_eq!(document["error"], "No servers");
+    assert_eq!(document["exit_code"], 3);
+
+    assert_eq!(
+        human(&Ok(Outcome::Updated(ip)), &report, false),
+        "example.com  updated       192.0.2.2 -> 192.0.2.1"
+    );
+    assert!(human(&Ok(Outcome::Updated(ip)), &report, true).contains("\x1b[33mupdated"));
+
+    let mut report = report;
+    report.domains[0].action = Action::LeftAlone;
+    report.domains[0].ip = Some(IpAddr::from([198, 51, 100, 7]));
+    assert_eq!(
+        human(&Ok(Outcome::UpToDate(ip)), &report, false),
+        "example.com  left alone    198.51.100.7 instead of 192.0.2.1"
+    );
+
+    let line = event("ip_detected", serde_json::json!({ "ip": ip }));
+    assert!(!line.contains('\n'));
+    let line = serde_json::from_str::<Value>(&line).unwrap();
+    assert_eq!(line["event"], "ip_detected");
+    assert_eq!(line["ip"], "192.0.2.1");
+    assert!(line["at"].is_string());
+}
+
+#[test]
+fn test_plan() {
+    let before = ARecord {
+        id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
+        ip: IpAddr::from([192, 0, 2, 2]),
+        proxied: Some(false),
+        ttl: Some(1),
+    };
+    let after = ARecord {
+        ip: IpAddr::from([192, 0, 2, 1]),
+        ttl: Some(300),
+        ..before.clone()
+    };
+
+    assert_eq!(
+        plan("example.com", &before, &after),
+        "Would update the A record of example.com:
+  name     example.com
+  type     A
+- content  192.0.2.2
++ content  192.0.2.1
+- ttl      auto
++ ttl      300
+  proxied  false"
+    );
+
+    let after = ARecord {
+        ip: "2001:db8::1".parse().unwrap(),
+        ..before.clone()
+    };
+    assert_eq!(
+        diff("example.com", &before, &after)[1..5],
+        [
+            "- type     A",
+            "+ type     AAAA",
+            "- content  192.0.2.2",
+            "+ content  2001:db8::1",
+        ]
+    );
+}
@@ -0,0 +1,162 @@
+//! Resolves a reference to a secret in a password manager, given instead of the secret itself for
+//! the API key, `--webhook-url` and the targets of `--notify`, with the CLI of the password manager,
+//! which has to be installed and signed in:
+//!
+//! - `op://<vault>/<item>/<field>` with `op read` of 1Password, after `op signin`, or with a
+//!   service account in `OP_SERVICE_ACCOUNT_TOKEN`.
+//! - `bw://<item ID>` for the password of an item with `bw get` of Bitwarden, or
+//!   `bw://<item ID>/<field>` for its `username`, `uri`, `notes` or a custom field, unlocked with
+//!   `BW_SESSION`.
+//!
+//! A target of `--notify` is resolved after its kind, like `slack:op://Home/Slack/webhook`. Each
+//! reference is resolved once, as the arguments are parsed more than once, and again when the
+//! daemon reloads.
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::redact;
+
+/// What the references resolved to, or the error if they didn't.
+static RESOLVED: Mutex<Option<HashMap<String, Result<String, String>>>> = Mutex::new(None);
+
+/// The fields `bw get` has a command for, where anything else is a custom field.
+const BITWARDEN_FIELDS: [&str; 5] = ["username", "password", "uri", "notes", "totp"];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Reference<'a> {
+    OnePassword(&'a str),
+    Bitwarden { id: &'a str, field: &'a str },
+}
+
+impl<'a> Reference<'a> {
+    fn parse(value: &'a str) -> Option<Self> {
+        if value.starts_with("op://") {
+            return Some(Self::OnePassword(value));
+        }
+        let item = value.strip_prefix("bw://")?;
+        let (id, field) = item.split_once('/').unwrap_or((item, "password"));
+
+        Some(Self::Bitwarden { id, field })
+    }
+
+    fn read(&self) -> anyhow::Result<String> {
+        match *self {
+            Self::OnePassword(reference) => run("op", &["read", "--no-newline", reference]),
+            Self::Bitwarden { id, field } if BITWARDEN_FIELDS.contains(&field) => {
+                run("bw", &["get", field, id])
+            }
+            Self::Bitwarden { id, field } => {
+                let item = serde_json::from_str::<Value>(&run("bw", &["get", "item", id])?)
+                    .context("bw didn't answer with an item")?;
+                item["fields"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|custom| custom["name"] == field)
+                    .and_then(|custom| custom["value"].as_str())
+                    .map(ToString::to_string)
+                    .with_context(|| format!("The item {id} has no field {field}"))
+            }
+        }
+    }
+}
+
+/// Returns `value`, or the secret it refers to if it's a reference.
+///
+/// # Errors
+///
+/// Returns an error if the CLI of the password manager cannot be run, or fails.
+///
+/// # Panics
+///
+/// Panics if a thread panicked while resolving a reference.
+pub fn resolve(value: &str) -> Result<String, String> {
+    let Some(reference) = Reference::parse(value) else {
+        return Ok(value.to_string());
+    };
+
+    let mut resolved = RESOLVED.lock().unwrap();
+    resolved
+        .get_or_insert_with(HashMap::new)
+        .entry(value.to_string())
+        .or_insert_with(|| {
+            let secret = reference
+                .read()
+                .map_err(|e| format!("Failed to resolve {value}: {e:#}"))?;
+            redact::add(&secret);
+            Ok(secret)
+        })
+        .clone()
+}
+
+/// Like [`resolve`], for a target of `--notify` with its kind in front, like `slack:op://...`.
+///
+/// # Errors
+///
+/// Returns an error if the reference cannot be resolved.
+pub fn resolve_target(value: &str) -> Result<String, String> {
+    match value.split_once(':') {
+        Some((kind, target)) if Reference::parse(target).is_some() => {
+            Ok(format!("{kind}:{}", resolve(target)?))
+        }
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// Forgets what the references resolved to, so they're resolved again.
+///
+/// # Panics
+///
+/// Panics if a thread panicked while resolving a reference.
+pub fn forget() {
+    *RESOLVED.lock().unwrap() = None;
+}
+
+fn run(program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {program}, is it installed?"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let secret = String::from_utf8(output.stdout).context("The secret isn't text")?;
+    Ok(secret.trim_end().to_string())
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        Reference::parse("op://Home/Cloudflare/credential"),
+        Some(Reference::OnePassword("op://Home/Cloudflare/credential"))
+    );
+    assert_eq!(
+        Reference::parse("bw://0b8e6d5c"),
+        Some(Reference::Bitwarden {
+            id: "0b8e6d5c",
+            field: "password"
+        })
+    );
+    assert_eq!(
+        Reference::parse("bw://0b8e6d5c/zone"),
+        Some(Reference::Bitwarden {
+            id: "0b8e6d5c",
+            field: "zone"
+        })
+    );
+    assert_eq!(Reference::parse("https://discord.com/api/webhooks/1"), None);
+
+    assert_eq!(resolve("a-token").unwrap(), "a-token");
+    assert_eq!(
+        resolve_target("slack:https://hooks.slack.com/services/T0").unwrap(),
+        "slack:https://hooks.slack.com/services/T0"
+    );
+}
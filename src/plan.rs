@@ -0,0 +1,152 @@
+//! Splits an update in two, for setups where a change to the DNS is reviewed before it's made:
+//! `cdu plan` writes down which A records would change, from what to what, and `cdu apply` makes
+//! those changes later, but only if the A records still point where they did when the plan was
+//! made, so nothing that changed in between is overwritten.
+use std::fmt;
+use std::net::IpAddr;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::cloudflare::{ARecord, CloudflareError, Handler};
+use crate::network::Family;
+use crate::updater::DomainSettings;
+
+/// A change to the A record of a domain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub domain: String,
+    pub zone_id: String,
+    pub record_id: String,
+    pub old_ip: IpAddr,
+    pub new_ip: IpAddr,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.domain, self.old_ip, self.new_ip)
+    }
+}
+
+/// The changes that point the A records at the outside IP, as of when the plan was made.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub created_at: DateTime<Utc>,
+    pub outside_ip: IpAddr,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    /// Looks up the A record of every domain, in the zone of its own if it has one instead of
+    /// `zone_id`, and plans to point the ones that point elsewhere at `outside_ip`. The domains
+    /// whose records are of the other family are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an A record cannot be looked up.
+    pub async fn create(
+        cloudflare: &Handler,
+        zone_id: &str,
+        domains: &[String],
+        per_domain: &[DomainSettings],
+        outside_ip: IpAddr,
+    ) -> Result<Self, CloudflareError> {
+        let family = Family::of(outside_ip);
+        let mut changes = Vec::new();
+        for domain in domains {
+            let settings = per_domain.iter().find(|settings| &settings.name == domain);
+            if settings
+                .and_then(|settings| settings.family)
+                .is_some_and(|wanted| wanted != family)
+            {
+                continue;
+            }
+            let zone_id = settings
+                .and_then(|settings| settings.zone_id.as_deref())
+                .unwrap_or(zone_id);
+            let record = cloudflare.get_record(zone_id, domain, family).await?;
+
+            if record.ip != outside_ip {
+                changes.push(Change {
+                    domain: domain.clone(),
+                    zone_id: zone_id.to_string(),
+                    record_id: record.id,
+                    old_ip: record.ip,
+                    new_ip: outside_ip,
+                });
+            }
+        }
+
+        Ok(Self {
+            created_at: Utc::now(),
+            outside_ip,
+            changes,
+        })
+    }
+
+    /// Returns how the A records to change differ from when the plan was made, one line for each
+    /// one that was replaced, removed or points elsewhere now.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an A record cannot be looked up.
+    pub async fn drifts(&self, cloudflare: &Handler) -> Result<Vec<String>, CloudflareError> {
+        let mut drifts = Vec::new();
+        for change in &self.changes {
+            match cloudflare
+                .get_record(&change.zone_id, &change.domain, Family::of(change.new_ip))
+                .await
+            {
+                Ok(record) if record.id != change.record_id => {
+                    drifts.push(format!("{}: the A record was replaced", change.domain));
+                }
+                Ok(record) if record.ip != change.old_ip => drifts.push(format!(
+                    "{}: points at {} instead of {}",
+                    change.domain, record.ip, change.old_ip
+                )),
+                Ok(_) => {}
+                Err(CloudflareError::RecordNotFound(_)) => {
+                    drifts.push(format!("{}: the A record was removed", change.domain));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(drifts)
+    }
+
+    /// Makes the changes, once none of the A records turned out to have changed since the plan was
+    /// made. Only the IP of each is changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, without changing anything, if any of the A records changed, or one cannot
+    /// be looked up, and returns an error if a change fails.
+    pub async fn apply(&self, cloudflare: &Handler) -> anyhow::Result<()> {
+        let drifts = self.drifts(cloudflare).await?;
+        anyhow::ensure!(
+            drifts.is_empty(),
+            "The A records changed since the plan was made at {}, make a new one:\n{}",
+            self.created_at,
+            drifts.join("\n")
+        );
+
+        for change in &self.changes {
+            let record = ARecord {
+                id: change.record_id.clone(),
+                ip: change.new_ip,
+                proxied: None,
+                ttl: None,
+            };
+            cloudflare
+                .patch_a_record(&change.zone_id, &record, &change.domain)
+                .await
+                .with_context(|| {
+                    format!("Failed to point {} at {}", change.domain, change.new_ip)
+                })?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+//! Runs WASM modules of your own, with the `plugins` feature, to detect the outside IP in a way
+//! cdu doesn't know, or to point a domain at a provider other than Cloudflare, without building
+//! cdu again.
+//!
+//! A module runs in a sandbox: it has no files, no environment and no clock, only the functions
+//! below, and it's stopped once it used up its fuel or memory. It exports its `memory` and:
+//!
+//! - `cdu_alloc(len: i32) -> i32`, which returns where `len` bytes can be written.
+//! - `cdu_detect() -> i64`, for a detection plugin, which returns the outside IPv4 address as a
+//!   number, like `0xc0000201` for 192.0.2.1, or -1 if it couldn't tell.
+//! - `cdu_update(ptr: i32, len: i32) -> i32`, for a provider plugin, which gets
+//!   `{"domain": "...", "ip": "...", "options": {...}}` as JSON, and returns 0 if it pointed the
+//!   domain at the IP.
+//!
+//! A module can export both. It can import these from `cdu`:
+//!
+//! - `log(ptr: i32, len: i32)`, which logs the text.
+//! - `http(ptr: i32, len: i32) -> i64`, which sends the request in the JSON, like
+//!   `{"method": "GET", "url": "...", "headers": {...}, "body": "..."}`, and returns where the
+//!   answer is as `ptr << 32 | len`, like `{"status": 200, "body": "..."}` or `{"error": "..."}`.
+//!
+//! The options of a provider come from the query after its path, like
+//! `--plugin /etc/cdu/duckdns.wasm?token=<token>`.
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::{Client as RqClient, Method};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+use wasmtime::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+/// How much work a call may do before it's stopped, about a few seconds of it.
+const FUEL: u64 = 10_000_000_000;
+
+/// How much memory a module may have.
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// The most a module may send to or get from the functions of cdu at once.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+/// A WASM module that's compiled and ready to run.
+#[derive(Clone)]
+pub struct Plugin {
+    path: PathBuf,
+    /// What's given to `cdu_update`, from the query after the path.
+    options: HashMap<String, String>,
+    engine: Engine,
+    module: Module,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The options are left out, as they usually have a token
+        f.debug_struct("Plugin").field("path", &self.path).finish()
+    }
+}
+
+impl PartialEq for Plugin {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.options == other.options
+    }
+}
+
+impl Plugin {
+    /// Compiles the module at `spec`, a path with the options of a provider in the query after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, or isn't a WASM module that exports
+    /// `cdu_detect` or `cdu_update`.
+    pub fn load(spec: &str) -> anyhow::Result<Self> {
+        let (path, query) = spec.split_once('?').unwrap_or((spec, ""));
+        let options = reqwest::Url::parse(&format!("plugin:?{query}"))
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)
+            .with_context(|| format!("Failed to load the plugin {path}"))?;
+        let plugin = Self {
+            path: PathBuf::from(path),
+            options,
+            engine,
+            module,
+        };
+        anyhow::ensure!(
+            plugin.detects() || plugin.updates(),
+            "The plugin {path} exports neither cdu_detect nor cdu_update"
+        );
+
+        Ok(plugin)
+    }
+
+    /// Parses `--plugin`, compiling the module so a broken one is found right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module cannot be loaded, see [`Plugin::load`].
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        Self::load(spec).map_err(|e| format!("{e:#}"))
+    }
+
+    /// Returns the file name of the module, which names it in the logs.
+    pub fn name(&self) -> String {
+        self.path.file_name().map_or_else(
+            || self.path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    }
+
+    /// Returns whether the module detects the outside IP.
+    pub fn detects(&self) -> bool {
+        self.exports("cdu_detect")
+    }
+
+    /// Returns whether the module points domains at an IP.
+    pub fn updates(&self) -> bool {
+        self.exports("cdu_update")
+    }
+
+    fn exports(&self, name: &str) -> bool {
+        self.module.exports().any(|export| export.name() == name)
+    }
+
+    /// Asks the module for the outside IP, with its HTTP requests sent with `client`, giving up on
+    /// each of them after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module fails, runs out of fuel, or couldn't tell.
+    pub async fn detect(&self, client: &RqClient, timeout: Duration) -> anyhow::Result<Ipv4Addr> {
+        let ip = self
+            .call(client, timeout, |store, instance| {
+                let detect = instance.get_typed_func::<(), i64>(&mut *store, "cdu_detect")?;
+                detect.call(store, ())
+            })
+            .await?;
+
+        u32::try_from(ip)
+            .map(Ipv4Addr::from)
+            .map_err(|_| anyhow::anyhow!("The plugin {} couldn't tell the outside IP", self.name()))
+    }
+
+    /// Has the module point `domain` at `ip`, with its HTTP requests sent with `client`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module fails, runs out of fuel, or returns anything but 0.
+    pub async fn update(
+        &self,
+        client: &RqClient,
+        timeout: Duration,
+        domain: &str,
+        ip: IpAddr,
+    ) -> anyhow::Result<()> {
+        let request = json!({ "domain": domain, "ip": ip, "options": self.options }).to_string();
+        let code = self
+            .call(client, timeout, move |store, instance| {
+                let (ptr, len) = write(store, instance, request.as_bytes())?;
+                let update =
+                    instance.get_typed_func::<(i32, i32), i32>(&mut *store, "cdu_update")?;
+                update.call(store, (ptr, len))
+            })
+            .await?;
+
+        anyhow::ensure!(
+            code == 0,
+            "The plugin {} failed to update {domain}, with code {code}",
+            self.name()
+        );
+        Ok(())
+    }
+
+    /// Instantiates the module in a store of its own, and runs `run` on it away from the runtime,
+    /// as a module may take a while.
+    async fn call<T, F>(&self, client: &RqClient, timeout: Duration, run: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Store<Host>, &Instance) -> anyhow::Result<T> + Send + 'static,
+    {
+        let host = Host {
+            name: self.name(),
+            client: client.clone(),
+            timeout,
+            runtime: Handle::current(),
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+        };
+        let engine = self.engine.clone();
+        let module = self.module.clone();
+        let name = self.name();
+
+        tokio::task::spawn_blocking(move || {
+            let mut store = Store::new(&engine, host);
+            store.limiter(|host| &mut host.limits);
+            store.set_fuel(FUEL)?;
+            let instance = linker(&engine)?.instantiate(&mut store, &module)?;
+            run(&mut store, &instance)
+        })
+        .await
+        .context("The plugin stopped")?
+        .with_context(|| format!("The plugin {name} failed"))
+    }
+}
+
+/// What the functions of cdu that a module calls have at hand.
+struct Host {
+    /// The name of the module, for the logs.
+    name: String,
+    client: RqClient,
+    timeout: Duration,
+    /// Where the requests of `http` are sent from, as the module runs outside of it.
+    runtime: Handle,
+    limits: StoreLimits,
+}
+
+/// A request of a module, as JSON.
+#[derive(Debug, Deserialize)]
+struct HttpRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_method() -> String {
+    String::from("GET")
+}
+
+/// Returns the functions of cdu that a module can import.
+fn linker(engine: &Engine) -> anyhow::Result<Linker<Host>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        "cdu",
+        "log",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let text = String::from_utf8_lossy(&read(&mut caller, ptr, len)?).into_owned();
+            info!("Plugin {}: {text}", caller.data().name);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "cdu",
+        "http",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> anyhow::Result<i64> {
+            let request = read(&mut caller, ptr, len)?;
+            let answer = match serde_json::from_slice::<HttpRequest>(&request) {
+                Ok(request) => {
+                    let host = caller.data();
+                    host.runtime
+                        .block_on(send(&host.client, host.timeout, request))
+                }
+                Err(e) => json!({ "error": format!("Invalid request: {e}") }),
+            };
+
+            let (ptr, len) = write_from(&mut caller, answer.to_string().as_bytes())?;
+            Ok((i64::from(ptr) << 32) | i64::from(len))
+        },
+    )?;
+
+    Ok(linker)
+}
+
+/// Sends the request of a module, and returns the answer as it gets it.
+async fn send(client: &RqClient, timeout: Duration, request: HttpRequest) -> Value {
+    let method = match Method::from_bytes(request.method.to_ascii_uppercase().as_bytes()) {
+        Ok(method) => method,
+        Err(_) => return json!({ "error": format!("Invalid method: {}", request.method) }),
+    };
+    let mut builder = client.request(method, &request.url).timeout(timeout);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = request.body {
+        builder = builder.body(body);
+    }
+
+    let response = match builder.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("A request of a plugin to {} failed: {e}", request.url);
+            return json!({ "error": e.to_string() });
+        }
+    };
+    let status = response.status().as_u16();
+    match response.text().await {
+        Ok(body) => json!({ "status": status, "body": body }),
+        Err(e) => json!({ "status": status, "error": e.to_string() }),
+    }
+}
+
+/// Returns the memory of the module that's calling.
+fn memory(caller: &mut Caller<'_, Host>) -> anyhow::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .context("The plugin doesn't export its memory")
+}
+
+/// Returns the `len` bytes at `ptr` in the memory of the module that's calling.
+fn read(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> anyhow::Result<Vec<u8>> {
+    let (start, len) = (usize::try_from(ptr)?, usize::try_from(len)?);
+    anyhow::ensure!(len <= MAX_MESSAGE, "The plugin sent more than 1 MiB");
+    let memory = memory(caller)?;
+    memory
+        .data(&*caller)
+        .get(start..start + len)
+        .map(<[u8]>::to_vec)
+        .context("The plugin pointed outside of its memory")
+}
+
+/// Writes `bytes` to memory the module that's calling gave for it, and returns where they are.
+fn write_from(caller: &mut Caller<'_, Host>, bytes: &[u8]) -> anyhow::Result<(i32, i32)> {
+    anyhow::ensure!(bytes.len() <= MAX_MESSAGE, "The answer is more than 1 MiB");
+    let len = i32::try_from(bytes.len())?;
+    let alloc = caller
+        .get_export("cdu_alloc")
+        .and_then(Extern::into_func)
+        .context("The plugin doesn't export cdu_alloc")?
+        .typed::<i32, i32>(&*caller)?;
+    let ptr = alloc.call(&mut *caller, len)?;
+    memory(caller)?.write(&mut *caller, usize::try_from(ptr)?, bytes)?;
+
+    Ok((ptr, len))
+}
+
+/// Like [`write_from`], from outside of a call of the module.
+fn write(store: &mut Store<Host>, instance: &Instance, bytes: &[u8]) -> anyhow::Result<(i32, i32)> {
+    let len = i32::try_from(bytes.len())?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "cdu_alloc")?;
+    let ptr = alloc.call(&mut *store, len)?;
+    instance
+        .get_memory(&mut *store, "memory")
+        .context("The plugin doesn't export its memory")?
+        .write(&mut *store, usize::try_from(ptr)?, bytes)?;
+
+    Ok((ptr, len))
+}
+
+/// The plugins to run, with `--plugin`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plugins(pub Vec<Plugin>);
+
+impl Plugins {
+    /// Asks the detection plugins for the outside IP, in order, and returns the first answer with
+    /// the name of the plugin that gave it. A plugin that fails is logged, and the next one asked.
+    pub async fn detect(&self, client: &RqClient, timeout: Duration) -> Option<(Ipv4Addr, String)> {
+        for plugin in self.0.iter().filter(|plugin| plugin.detects()) {
+            match plugin.detect(client, timeout).await {
+                Ok(ip) => return Some((ip, plugin.name())),
+                Err(e) => warn!("{e:#}"),
+            }
+        }
+
+        None
+    }
+
+    /// Has every provider plugin point `domain` at `ip`. A plugin that fails is logged, and
+    /// doesn't stop the others or the run.
+    pub async fn update(&self, client: &RqClient, timeout: Duration, domain: &str, ip: IpAddr) {
+        for plugin in self.0.iter().filter(|plugin| plugin.updates()) {
+            match plugin.update(client, timeout, domain, ip).await {
+                Ok(()) => info!("The plugin {} pointed {domain} at {ip}", plugin.name()),
+                Err(e) => warn!("{e:#}"),
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_plugin() {
+    // A detection plugin that always answers 192.0.2.1, and a provider that takes any domain
+    let wat = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "cdu_alloc") (param i32) (result i32) (i32.const 1024))
+            (func (export "cdu_detect") (result i64) (i64.const 0xc0000201))
+            (func (export "cdu_update") (param i32 i32) (result i32) (i32.const 0)))
+    "#;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("static.wat");
+    std::fs::write(&path, wat).unwrap();
+
+    let plugin = Plugin::load(&format!("{}?token=secret", path.display())).unwrap();
+    assert!(plugin.detects() && plugin.updates());
+    assert_eq!(
+        plugin.options.get("token").map(String::as_str),
+        Some("secret")
+    );
+
+    let client = crate::network::client(crate::network::DEFAULT_TIMEOUT);
+    let timeout = crate::network::DEFAULT_TIMEOUT;
+    assert_eq!(
+        plugin.detect(&client, timeout).await.unwrap(),
+        Ipv4Addr::new(192, 0, 2, 1)
+    );
+    plugin
+        .update(
+            &client,
+            timeout,
+            "home.example.com",
+            IpAddr::from([192, 0, 2, 1]),
+        )
+        .await
+        .unwrap();
+
+    assert!(Plugin::load(&dir.path().join("missing.wasm").display().to_string()).is_err());
+}
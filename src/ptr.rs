@@ -0,0 +1,361 @@
+//! Points the PTR record of the outside IP back at a domain, for a mail server that's checked
+//! for a matching reverse DNS, with a dynamic update (RFC 2136) to the nameserver of the reverse
+//! zone, like BIND, Knot or PowerDNS, where the zone was delegated to you.
+//!
+//! The update replaces every PTR record of `<reversed IP>.in-addr.arpa` with one for the domain,
+//! in the zone of the /24 of the IP unless another one is given, or of `<reversed nibbles>.ip6.arpa`
+//! in the zone of the /64 for the IPv6 address of an AAAA record. It's signed with a TSIG key
+//! (RFC 8945) using HMAC-SHA256 if there is one, which is given as `<name>:<base64 secret>`, like
+//! the `secret` of a `key` in `named.conf` or from `tsig-keygen`.
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::Utc;
+use tokio::net::UdpSocket;
+
+use crate::crypto;
+
+/// The port nameservers listen on.
+const PORT: u16 = 53;
+
+/// The opcode of an update, in the flags of the header.
+const UPDATE: u16 = 5 << 11;
+
+/// The types and classes of records, as they're sent.
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+
+/// The algorithm the TSIG key is used with.
+const ALGORITHM: &str = "hmac-sha256";
+
+/// How far the clocks of cdu and the nameserver may be apart, in seconds.
+const FUDGE: u16 = 300;
+
+/// A TSIG key, to sign the updates with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    pub name: String,
+    secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Key {
+    /// Parses a key given as `<name>:<base64 secret>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name or secret is missing, or the secret isn't base64.
+    pub fn parse(key: &str) -> Result<Self, String> {
+        let Some((name, secret)) = key.split_once(':') else {
+            return Err(String::from("Expected <name>:<base64 secret>"));
+        };
+        if name.is_empty() {
+            return Err(String::from("Missing the name of the key"));
+        }
+        let secret = BASE64_STANDARD
+            .decode(secret.trim())
+            .ok()
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| format!("The secret of {name} isn't base64"))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            secret,
+        })
+    }
+}
+
+/// The nameserver that takes the updates of the reverse zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    /// The host and port of the nameserver, where the port is 53 unless it's given.
+    pub address: String,
+    /// The reverse zone, instead of the one of the /24 of the IP.
+    pub zone: Option<String>,
+    pub key: Option<Key>,
+    /// How long the nameserver may take to answer.
+    pub timeout: Duration,
+}
+
+impl Server {
+    /// Points the PTR record of `ip` at `domain`, with `ttl`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nameserver cannot be reached, doesn't answer in time, or refuses
+    /// the update.
+    pub async fn set_ptr(&self, ip: IpAddr, domain: &str, ttl: u32) -> anyhow::Result<()> {
+        let zone = self.zone.clone().unwrap_or_else(|| reverse_zone(ip));
+        let id = fastrand::u16(..);
+        let message = update(id, &zone, &reverse_name(ip), domain, ttl);
+        let message = match &self.key {
+            Some(key) => sign(message, key, Utc::now().timestamp()),
+            None => message,
+        };
+
+        let address = if self.address.contains(':') {
+            self.address.clone()
+        } else {
+            format!("{}:{PORT}", self.address)
+        };
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket
+            .connect(&address)
+            .await
+            .with_context(|| format!("Failed to reach {address}"))?;
+        socket.send(&message).await?;
+
+        let mut answer = [0; 512];
+        tokio::time::timeout(self.timeout, async {
+            // Anything that isn't the answer to this update is left alone
+            loop {
+                let length = socket.recv(&mut answer).await?;
+                if length >= 4 && answer[..2] == id.to_be_bytes() {
+                    return Ok::<_, std::io::Error>(length);
+                }
+            }
+        })
+        .await
+        .with_context(|| format!("{address} didn't answer the update in time"))??;
+
+        match u16::from_be_bytes([answer[2], answer[3]]) & 0xf {
+            0 => Ok(()),
+            code => Err(anyhow::anyhow!(
+                "{address} refused to update the PTR record of {ip} in {zone}: {}",
+                rcode(code)
+            )),
+        }
+    }
+}
+
+/// Returns the name of the PTR record of `ip`.
+#[must_use]
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => format!("{}ip6.arpa", nibbles(&ip.octets())),
+    }
+}
+
+/// Returns the reverse zone of the /24 `ip` is in, or of the /64 of an IPv6 address.
+fn reverse_zone(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => format!("{}ip6.arpa", nibbles(&ip.octets()[..8])),
+    }
+}
+
+/// Returns the nibbles of `bytes` the other way around, each followed by a dot.
+fn nibbles(bytes: &[u8]) -> String {
+    bytes.iter().rev().fold(String::new(), |mut nibbles, byte| {
+        let _ = write!(nibbles, "{:x}.{:x}.", byte & 0xf, byte >> 4);
+        nibbles
+    })
+}
+
+/// Returns an update of `zone` that replaces the PTR records of `name` with one for `domain`.
+fn update(id: u16, zone: &str, name: &str, domain: &str, ttl: u32) -> Vec<u8> {
+    let mut message = Vec::with_capacity(128);
+    message.extend(id.to_be_bytes());
+    message.extend(UPDATE.to_be_bytes());
+    // One zone, no prerequisites, two updates and no additional records
+    for count in [1_u16, 0, 2, 0] {
+        message.extend(count.to_be_bytes());
+    }
+
+    encode_name(&mut message, zone);
+    message.extend(TYPE_SOA.to_be_bytes());
+    message.extend(CLASS_IN.to_be_bytes());
+
+    // Deletes every PTR record of the name
+    encode_name(&mut message, name);
+    message.extend(TYPE_PTR.to_be_bytes());
+    message.extend(CLASS_ANY.to_be_bytes());
+    message.extend(0_u32.to_be_bytes());
+    message.extend(0_u16.to_be_bytes());
+
+    // And adds the one for the domain
+    encode_name(&mut message, name);
+    message.extend(TYPE_PTR.to_be_bytes());
+    message.extend(CLASS_IN.to_be_bytes());
+    message.extend(ttl.to_be_bytes());
+    let mut target = Vec::new();
+    encode_name(&mut target, domain);
+    message.extend(
+        u16::try_from(target.len())
+            .unwrap_or(u16::MAX)
+            .to_be_bytes(),
+    );
+    message.extend(target);
+
+    message
+}
+
+/// Appends a TSIG record to `message`, signed with `key` at `time`, in seconds since the epoch.
+fn sign(mut message: Vec<u8>, key: &Key, time: i64) -> Vec<u8> {
+    let mut key_name = Vec::new();
+    encode_name(&mut key_name, &key.name.to_ascii_lowercase());
+    let mut algorithm = Vec::new();
+    encode_name(&mut algorithm, ALGORITHM);
+    // 48 bits of seconds
+    let time = &time.to_be_bytes()[2..];
+
+    // The MAC is of the message, followed by the variables of the record
+    let mac = crypto::hmac_sha256(
+        &key.secret,
+        &[
+            &message,
+            &key_name,
+            &CLASS_ANY.to_be_bytes(),
+            &0_u32.to_be_bytes(),
+            &algorithm,
+            time,
+            &FUDGE.to_be_bytes(),
+            // No error, and no other data
+            &[0; 4],
+        ],
+    );
+
+    let mut data = algorithm;
+    data.extend(time);
+    data.extend(FUDGE.to_be_bytes());
+    data.extend(u16::try_from(mac.len()).unwrap_or_default().to_be_bytes());
+    data.extend(mac);
+    data.extend(&message[..2]);
+    data.extend([0; 4]);
+
+    message.extend(key_name);
+    message.extend(TYPE_TSIG.to_be_bytes());
+    message.extend(CLASS_ANY.to_be_bytes());
+    message.extend(0_u32.to_be_bytes());
+    message.extend(u16::try_from(data.len()).unwrap_or_default().to_be_bytes());
+    message.extend(data);
+    // The TSIG record is the one additional record
+    message[10..12].copy_from_slice(&1_u16.to_be_bytes());
+
+    message
+}
+
+/// Appends `name` to `message`, as labels that each start with their length.
+fn encode_name(message: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        message.push(u8::try_from(label.len()).unwrap_or_default());
+        message.extend(label);
+    }
+    message.push(0);
+}
+
+/// Describes the response code of a nameserver.
+fn rcode(code: u16) -> String {
+    match code {
+        1 => String::from("the update was malformed (FORMERR)"),
+        2 => String::from("the nameserver failed (SERVFAIL)"),
+        5 => String::from("the update was refused, check the key and the update policy (REFUSED)"),
+        8 => String::from("a record that should be there isn't (NXRRSET)"),
+        9 => String::from(
+            "the nameserver isn't authoritative for the zone, or the key is wrong (NOTAUTH)",
+        ),
+        10 => String::from("the name isn't in the zone (NOTZONE)"),
+        code => format!("response code {code}"),
+    }
+}
+
+#[test]
+fn test_update() {
+    let ip = IpAddr::from([192, 0, 2, 7]);
+    assert_eq!(reverse_name(ip), "7.2.0.192.in-addr.arpa");
+    assert_eq!(reverse_zone(ip), "2.0.192.in-addr.arpa");
+    let ipv6 = "2001:db8::567:89ab".parse().unwrap();
+    assert_eq!(
+        reverse_name(ipv6),
+        "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+    assert_eq!(
+        reverse_zone(ipv6),
+        "0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+
+    let message = update(
+        0x1234,
+        "2.0.192.in-addr.arpa",
+        &reverse_name(ip),
+        "mail.example.com",
+        3600,
+    );
+    assert_eq!(
+        &message[..12],
+        [0x12, 0x34, 0x28, 0, 0, 1, 0, 0, 0, 2, 0, 0]
+    );
+    assert_eq!(
+        &message[12..38],
+        b"\x012\x010\x03192\x07in-addr\x04arpa\x00\x00\x06\x00\x01"
+    );
+    assert!(message.ends_with(b"\x04mail\x07example\x03com\x00"));
+
+    let key = Key::parse("cdu:c2VjcmV0").unwrap();
+    assert_eq!(key.secret, b"secret");
+    let signed = sign(message.clone(), &key, 1_700_000_000);
+    assert_eq!(&signed[10..12], [0, 1]);
+    assert!(signed.starts_with(&message[..10]));
+    assert_eq!(&signed[message.len()..message.len() + 5], b"\x03cdu\x00");
+    // The same message signed at the same time has the same MAC
+    assert_eq!(sign(message.clone(), &key, 1_700_000_000), signed);
+    assert_ne!(sign(message, &key, 1_700_000_001), signed);
+
+    assert!(Key::parse("cdu").is_err());
+    assert!(Key::parse("cdu:not base64!").is_err());
+}
+
+#[tokio::test]
+async fn test_set_ptr() {
+    let nameserver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server = Server {
+        address: nameserver.local_addr().unwrap().to_string(),
+        zone: None,
+        key: None,
+        timeout: Duration::from_secs(5),
+    };
+    // Accepts the first update, and refuses the second one
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+        for rcode in [0, 5] {
+            let (length, from) = nameserver.recv_from(&mut buffer).await.unwrap();
+            assert_eq!(u16::from_be_bytes([buffer[2], buffer[3]]), UPDATE);
+            buffer[2] |= 0x80;
+            buffer[3] = rcode;
+            nameserver.send_to(&buffer[..length], from).await.unwrap();
+        }
+    });
+
+    let ip = IpAddr::from([192, 0, 2, 7]);
+    server.set_ptr(ip, "mail.example.com", 3600).await.unwrap();
+    let error = server
+        .set_ptr(ip, "mail.example.com", 3600)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("REFUSED"), "{error}");
+}
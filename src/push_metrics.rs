@@ -0,0 +1,333 @@
+//! Pushes the metrics of every run to StatsD or InfluxDB, for those who don't run Prometheus, or to
+//! the Pushgateway of Prometheus, for runs from cron that Prometheus cannot scrape.
+//!
+//! After every run, what it did is sent: how long it took, its outcome, whether an A record was
+//! changed and which server told the outside IP. To StatsD that's a few lines over UDP, like
+//! `cdu.run.duration:132|ms`, and to InfluxDB a point in the line protocol, like
+//! `cdu_run,outcome=updated,source=icanhazip.com duration_ms=132i,changed=true`, over UDP or HTTP.
+//! The Pushgateway gets the text format of Prometheus, like `cdu_run_changed 1`, which replaces
+//! what the last run pushed for the same job and instance.
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::{Client as RqClient, Url};
+use tokio::net::UdpSocket;
+
+use crate::updater::Outcome;
+
+/// The port StatsD listens on.
+const STATSD_PORT: u16 = 8125;
+
+/// The port InfluxDB listens on for the line protocol over UDP.
+const INFLUX_UDP_PORT: u16 = 8089;
+
+/// Where to push the metrics to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A StatsD server, by its address.
+    Statsd(String),
+    /// The UDP listener of InfluxDB, by its address.
+    InfluxUdp(String),
+    /// The HTTP write API of InfluxDB, like `http://localhost:8086/api/v2/write?org=home&bucket=cdu`.
+    InfluxHttp(Url),
+    /// The Pushgateway, by the URL of the group of the job and instance, like
+    /// `http://localhost:9091/metrics/job/cdu/instance/router`.
+    Pushgateway(Url),
+}
+
+impl Endpoint {
+    /// Parses `statsd://<host>[:<port>]`, `influx+udp://<host>[:<port>]`,
+    /// `pushgateway+http(s)://<host>[:<port>][?job=<job>&instance=<instance>]`, or the URL of the
+    /// write API of InfluxDB, which starts with `http://` or `https://`. The job of the Pushgateway
+    /// is `cdu` and the instance the hostname, unless they're given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it's none of these.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let with_port = |host: &str, port: u16| {
+            let host = host.trim_end_matches('/');
+            if host.is_empty() {
+                Err(format!("There's no host in {value}"))
+            } else if host
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+            {
+                Ok(host.to_string())
+            } else {
+                Ok(format!("{host}:{port}"))
+            }
+        };
+
+        if let Some(host) = value.strip_prefix("statsd://") {
+            return with_port(host, STATSD_PORT).map(Self::Statsd);
+        }
+        if let Some(host) = value.strip_prefix("influx+udp://") {
+            return with_port(host, INFLUX_UDP_PORT).map(Self::InfluxUdp);
+        }
+        if let Some(url) = value.strip_prefix("pushgateway+") {
+            return pushgateway_url(url).map(Self::Pushgateway);
+        }
+        if value.starts_with("http://") || value.starts_with("https://") {
+            return Url::parse(value)
+                .map(Self::InfluxHttp)
+                .map_err(|e| format!("Not a URL: {value}: {e}"));
+        }
+
+        Err(format!(
+            "Unknown metrics endpoint: {value}, use statsd://<host>, influx+udp://<host>, pushgateway+http://<host> or the URL of the InfluxDB write API"
+        ))
+    }
+}
+
+/// What a run did, as it's pushed.
+#[derive(Debug, PartialEq, Eq)]
+struct Run<'a> {
+    outcome: &'static str,
+    changed: bool,
+    source: Option<&'a str>,
+    took: Duration,
+}
+
+impl<'a> Run<'a> {
+    fn new(result: &anyhow::Result<Outcome>, source: Option<&'a str>, took: Duration) -> Self {
+        Self {
+            outcome: result.as_ref().map_or("failed", Outcome::name),
+            changed: matches!(result, Ok(Outcome::Updated(_))),
+            source,
+            took,
+        }
+    }
+
+    /// Returns the lines for StatsD.
+    fn statsd(&self) -> String {
+        let mut lines = format!(
+            "cdu.run.duration:{}|ms\ncdu.run.outcome.{}:1|c\ncdu.run.changed:{}|g\n",
+            self.took.as_millis(),
+            self.outcome.replace('-', "_"),
+            u8::from(self.changed)
+        );
+        if let Some(source) = self.source {
+            let _ = writeln!(
+                lines,
+                "cdu.detection.source.{}:1|c",
+                source.replace(['.', ':', '|', '@'], "_")
+            );
+        }
+
+        lines
+    }
+
+    /// Returns the point for InfluxDB, in the line protocol.
+    fn influx(&self) -> String {
+        let mut line = format!("cdu_run,outcome={}", escape_tag(self.outcome));
+        if let Some(source) = self.source {
+            let _ = write!(line, ",source={}", escape_tag(source));
+        }
+        let _ = writeln!(
+            line,
+            " duration_ms={}i,changed={}",
+            self.took.as_millis(),
+            self.changed
+        );
+
+        line
+    }
+
+    /// Returns the metrics for the Pushgateway, in the text format of Prometheus, as of `at`.
+    fn prometheus(&self, at: DateTime<Utc>) -> String {
+        format!(
+            "# HELP cdu_run_duration_seconds How long the last run took.\n\
+             # TYPE cdu_run_duration_seconds gauge\n\
+             cdu_run_duration_seconds {}\n\
+             # HELP cdu_run_outcome The outcome of the last run.\n\
+             # TYPE cdu_run_outcome gauge\n\
+             cdu_run_outcome{{outcome=\"{}\"}} 1\n\
+             # HELP cdu_run_changed Whether the last run changed an A record.\n\
+             # TYPE cdu_run_changed gauge\n\
+             cdu_run_changed {}\n\
+             # HELP cdu_run_timestamp_seconds When the last run finished.\n\
+             # TYPE cdu_run_timestamp_seconds gauge\n\
+             cdu_run_timestamp_seconds {}\n",
+            self.took.as_secs_f64(),
+            self.outcome,
+            u8::from(self.changed),
+            at.timestamp()
+        )
+    }
+}
+
+/// Pushes the metrics of every run to the endpoint.
+#[derive(Debug)]
+pub struct Pusher {
+    client: RqClient,
+    endpoint: Endpoint,
+    /// The API token of InfluxDB, for the HTTP write API.
+    token: Option<String>,
+}
+
+impl Pusher {
+    #[must_use]
+    pub fn new(client: RqClient, endpoint: Endpoint, token: Option<String>) -> Self {
+        Self {
+            client,
+            endpoint,
+            token,
+        }
+    }
+
+    /// Pushes what the run did, with the server that told the outside IP, and how long it took.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the endpoint cannot be reached, or InfluxDB doesn't accept the point.
+    #[tracing::instrument(skip_all)]
+    pub async fn push(
+        &self,
+        result: &anyhow::Result<Outcome>,
+        source: Option<&str>,
+        took: Duration,
+    ) -> anyhow::Result<()> {
+        let run = Run::new(result, source, took);
+
+        match &self.endpoint {
+            Endpoint::Statsd(address) => send_udp(address, &run.statsd()).await,
+            Endpoint::InfluxUdp(address) => send_udp(address, &run.influx()).await,
+            Endpoint::InfluxHttp(url) => {
+                let mut request = self.client.post(url.clone()).body(run.influx());
+                if let Some(token) = &self.token {
+                    request = request.header("Authorization", format!("Token {token}"));
+                }
+                let response = request
+                    .send()
+                    .await
+                    .context("Failed to push the metrics to InfluxDB")?;
+
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await.unwrap_or_default();
+                    anyhow::bail!("Received response status {status}: {body}");
+                }
+
+                Ok(())
+            }
+            Endpoint::Pushgateway(url) => {
+                // A PUT replaces every metric of the group, so no outcome of an earlier run stays
+                let response = self
+                    .client
+                    .put(url.clone())
+                    .header("Content-Type", "text/plain; version=0.0.4")
+                    .body(run.prometheus(Utc::now()))
+                    .send()
+                    .await
+                    .context("Failed to push the metrics to the Pushgateway")?;
+
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await.unwrap_or_default();
+                    anyhow::bail!("Received response status {status}: {body}");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns the URL of the group of the job and the instance at the Pushgateway at `url`, which
+/// gives them in its query, if at all.
+fn pushgateway_url(url: &str) -> Result<Url, String> {
+    let mut url = Url::parse(url).map_err(|e| format!("Not a URL: {url}: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Not an HTTP URL: {url}"));
+    }
+    let mut job = String::from("cdu");
+    let mut instance = gethostname::gethostname().to_string_lossy().into_owned();
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "job" => job = value.into_owned(),
+            "instance" => instance = value.into_owned(),
+            _ => return Err(format!("Unknown label: {key}, use job or instance")),
+        }
+    }
+    if job.is_empty() {
+        return Err(String::from("The job of the Pushgateway cannot be empty"));
+    }
+
+    url.set_query(None);
+    url.path_segments_mut()
+        .map_err(|()| String::from("The Pushgateway needs a URL with a host"))?
+        .pop_if_empty()
+        .extend(["metrics", "job", &job, "instance", &instance]);
+
+    Ok(url)
+}
+
+/// Sends `text` as a datagram to `address`.
+async fn send_udp(address: &str, text: &str) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))
+        .await
+        .context("Failed to open a UDP socket")?;
+    socket
+        .send_to(text.as_bytes(), address)
+        .await
+        .with_context(|| format!("Failed to push the metrics to {address}"))?;
+
+    Ok(())
+}
+
+/// Escapes the commas, equal signs and spaces in the value of a tag, which the line protocol would
+/// take for the end of it.
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+#[test]
+fn test_run() {
+    assert_eq!(
+        Endpoint::parse("statsd://localhost"),
+        Ok(Endpoint::Statsd(String::from("localhost:8125")))
+    );
+    assert_eq!(
+        Endpoint::parse("influx+udp://10.0.0.2:8090"),
+        Ok(Endpoint::InfluxUdp(String::from("10.0.0.2:8090")))
+    );
+    assert!(Endpoint::parse("localhost:8125").is_err());
+    assert_eq!(
+        Endpoint::parse("pushgateway+http://localhost:9091?job=ddns&instance=router"),
+        Ok(Endpoint::Pushgateway(
+            Url::parse("http://localhost:9091/metrics/job/ddns/instance/router").unwrap()
+        ))
+    );
+    assert!(Endpoint::parse("pushgateway+http://localhost:9091?group=home").is_err());
+
+    let result = Ok(Outcome::Updated(std::net::IpAddr::from([192, 0, 2, 1])));
+    let run = Run::new(&result, Some("icanhazip.com"), Duration::from_millis(132));
+    assert_eq!(
+        run.statsd(),
+        "cdu.run.duration:132|ms\ncdu.run.outcome.updated:1|c\ncdu.run.changed:1|g\ncdu.detection.source.icanhazip_com:1|c\n"
+    );
+    assert_eq!(
+        run.influx(),
+        "cdu_run,outcome=updated,source=icanhazip.com duration_ms=132i,changed=true\n"
+    );
+    let at = DateTime::from_timestamp(1_714_566_600, 0).unwrap();
+    let text = run.prometheus(at);
+    assert!(text.contains("\ncdu_run_duration_seconds 0.132\n"));
+    assert!(text.contains("\ncdu_run_outcome{outcome=\"updated\"} 1\n"));
+    assert!(text.contains("\ncdu_run_changed 1\n"));
+    assert!(text.ends_with("\ncdu_run_timestamp_seconds 1714566600\n"));
+
+    let result = Err(anyhow::anyhow!("Failed to get outside IP from all servers"));
+    let run = Run::new(&result, None, Duration::from_secs(2));
+    assert_eq!(
+        run.influx(),
+        "cdu_run,outcome=failed duration_ms=2000i,changed=false\n"
+    );
+}
@@ -0,0 +1,176 @@
+//! Sends messages through Pushover.
+//!
+//! The target is `<user key>@<app token>`, optionally followed by `?priority=<-2 to 2>`. Emergency
+//! messages, with priority `2`, are repeated every `retry` until they're acknowledged or `expire`
+//! has passed, which default to a minute and an hour, e.g. `?priority=2&retry=5m&expire=2h`.
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::Client as RqClient;
+use serde_json::{json, Value};
+
+use crate::notify::{Message, Notifier};
+use crate::retry::StatusError;
+
+pub const URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// The priority that makes Pushover repeat the message until it's acknowledged.
+const EMERGENCY: i8 = 2;
+
+/// Who to send messages to, and how.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub user_key: String,
+    pub app_token: String,
+    pub priority: Option<i8>,
+    pub retry: Duration,
+    pub expire: Duration,
+}
+
+impl Options {
+    /// Parses `<user key>@<app token>`, with the options after a `?`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key or token is missing, or an option is unknown or has an invalid
+    /// value.
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let (keys, query) = target.split_once('?').unwrap_or((target, ""));
+        let Some((user_key, app_token)) = keys
+            .split_once('@')
+            .filter(|(user_key, app_token)| !user_key.is_empty() && !app_token.is_empty())
+        else {
+            return Err(String::from("Expected pushover:<user key>@<app token>"));
+        };
+
+        let mut options = Self {
+            user_key: user_key.to_string(),
+            app_token: app_token.to_string(),
+            priority: None,
+            retry: Duration::from_secs(60),
+            expire: Duration::from_secs(60 * 60),
+        };
+        let mut repeats = false;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let duration = || {
+                humantime::parse_duration(value)
+                    .map_err(|e| format!("Invalid Pushover {name}: {value}, {e}"))
+            };
+            match name {
+                "priority" => match value.parse() {
+                    Ok(value @ -2..=2) => options.priority = Some(value),
+                    _ => {
+                        return Err(format!(
+                            "Invalid Pushover priority: {value}, expected -2 to 2"
+                        ))
+                    }
+                },
+                "retry" => options.retry = duration()?,
+                "expire" => options.expire = duration()?,
+                _ => return Err(format!("Unknown Pushover option: {name}")),
+            }
+            repeats |= matches!(name, "retry" | "expire");
+        }
+
+        if repeats && options.priority != Some(EMERGENCY) {
+            return Err(String::from(
+                "The Pushover retry and expire only apply to priority 2",
+            ));
+        }
+        if options.retry < Duration::from_secs(30) {
+            return Err(String::from("The Pushover retry must be at least 30s"));
+        }
+        if options.expire > Duration::from_secs(3 * 60 * 60) {
+            return Err(String::from("The Pushover expire must be at most 3h"));
+        }
+
+        Ok(options)
+    }
+}
+
+/// A Pushover user, and the application to send as.
+#[derive(Debug)]
+pub struct Pushover {
+    client: RqClient,
+    options: Options,
+}
+
+impl Pushover {
+    #[must_use]
+    pub fn new(client: RqClient, options: &Options) -> Self {
+        Self {
+            client,
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Pushover {
+    fn name(&self) -> &'static str {
+        "Pushover"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(URL)
+            .json(&payload(message, &self.options))
+            .send()
+            .await
+            .context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
+    }
+}
+
+fn payload(message: &Message, options: &Options) -> Value {
+    let mut payload = json!({
+        "token": options.app_token,
+        "user": options.user_key,
+        "title": message.title(),
+        "message": message.to_string(),
+        "timestamp": message.at.timestamp(),
+    });
+    if let Some(priority) = options.priority {
+        payload["priority"] = json!(priority);
+        if priority == EMERGENCY {
+            payload["retry"] = json!(options.retry.as_secs());
+            payload["expire"] = json!(options.expire.as_secs());
+        }
+    }
+
+    payload
+}
+
+#[test]
+fn test_parse_options() {
+    let options = Options::parse(
+        "uQiRzpo4DXghDmr9QzzfQu27cmVRsG@azGDORePK8gMaC0QOYAMyEEuzJnyUi?priority=2&retry=5m",
+    )
+    .unwrap();
+    assert_eq!(options.user_key, "uQiRzpo4DXghDmr9QzzfQu27cmVRsG");
+    assert_eq!(options.app_token, "azGDORePK8gMaC0QOYAMyEEuzJnyUi");
+    assert_eq!(options.priority, Some(2));
+    assert_eq!(options.retry, Duration::from_secs(300));
+    assert_eq!(options.expire, Duration::from_secs(3600));
+
+    let message = Message::updated("example.com", None, std::net::IpAddr::from([192, 0, 2, 1]));
+    let payload = payload(&message, &options);
+    assert_eq!(payload["retry"], 300);
+    assert_eq!(payload["expire"], 3600);
+
+    assert!(Options::parse("uQiRzpo4DXghDmr9QzzfQu27cmVRsG").is_err());
+    assert!(Options::parse("user@app?priority=3").is_err());
+    assert!(Options::parse("user@app?priority=1&retry=5m").is_err());
+    assert!(Options::parse("user@app?priority=2&retry=10s").is_err());
+}
@@ -0,0 +1,191 @@
+//! Keeps the notifications that couldn't be sent, in a file next to the configuration, so they're
+//! sent again on a later check, even after a restart.
+//!
+//! Every message is tried again after a minute, then after two, four and so on, up to an hour, until
+//! it's sent or it's older than the retry period.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::notify::Message;
+
+const QUEUE_FILE: &str = "cdu-queue.toml";
+
+/// How long a message is tried again, unless told otherwise.
+pub const DEFAULT_RETRY_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+const FIRST_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// A message that has yet to be sent to a target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pending {
+    /// The ID of the target, see [`crate::notify::Target::id`].
+    pub target: String,
+    pub message: Message,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueueFile {
+    #[serde(default)]
+    pending: Vec<Pending>,
+}
+
+/// The messages that have yet to be sent.
+#[derive(Debug)]
+pub struct Queue {
+    path: PathBuf,
+    pending: Vec<Pending>,
+    retry_for: Duration,
+    /// Whether the file is behind.
+    changed: bool,
+}
+
+impl Queue {
+    /// Loads the queue from the directory of the configuration. A file that cannot be read is
+    /// logged and left alone, as a lost notification isn't worth stopping over.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(QUEUE_FILE);
+        let pending = match fs::read_to_string(&path) {
+            Ok(text) => match toml::from_str::<QueueFile>(&text) {
+                Ok(file) => file.pending,
+                Err(e) => {
+                    warn!("Ignoring queued notifications in {}: {e}", path.display());
+                    Vec::new()
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+        if !pending.is_empty() {
+            debug!("{} notifications queued", pending.len());
+        }
+
+        Self {
+            path,
+            pending,
+            retry_for: DEFAULT_RETRY_FOR,
+            changed: false,
+        }
+    }
+
+    /// Stops trying to send a message once it's older than `retry_for`.
+    pub fn set_retry_for(&mut self, retry_for: Duration) {
+        self.retry_for = retry_for;
+    }
+
+    /// Takes the messages that are waiting from `other`, like the queue before a reload.
+    pub fn adopt(&mut self, other: &Self) {
+        self.pending.clone_from(&other.pending);
+        self.changed = other.changed || self.path != other.path;
+    }
+
+    /// Queues a message that just failed to be sent, or failed again after being taken from the
+    /// queue.
+    pub fn push(&mut self, target: &str, message: Message, attempts: u32, now: DateTime<Utc>) {
+        let backoff = FIRST_BACKOFF
+            .saturating_mul(2_u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(MAX_BACKOFF);
+
+        self.pending.push(Pending {
+            target: target.to_string(),
+            message,
+            attempts,
+            next_attempt_at: now + chrono::Duration::from_std(backoff).unwrap_or_default(),
+        });
+        self.changed = true;
+    }
+
+    /// Takes the messages that are due to be tried again at `now`, and drops the ones that are too
+    /// old.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<Pending> {
+        let retry_for = self.retry_for;
+        let before = self.pending.len();
+        self.pending.retain(|pending| {
+            let expired = (now - pending.message.at)
+                .to_std()
+                .is_ok_and(|age| age > retry_for);
+            if expired {
+                warn!(
+                    "Giving up on sending notification after {} attempts: {}",
+                    pending.attempts, pending.message
+                );
+            }
+            !expired
+        });
+
+        let (due, later) = self
+            .pending
+            .drain(..)
+            .partition(|pending| pending.next_attempt_at <= now);
+        self.pending = later;
+        self.changed |= self.pending.len() != before;
+
+        due
+    }
+
+    /// Writes the queue to the file if it changed, or removes the file once it's empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written or removed.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        if self.pending.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path)
+                    .with_context(|| format!("Failed to remove file: {}", self.path.display()))?;
+            }
+        } else {
+            let file = QueueFile {
+                pending: self.pending.clone(),
+            };
+            let text = toml::to_string_pretty(&file).context("Failed to serialize the queue")?;
+            crate::config::write_atomically(&self.path, text.as_bytes())?;
+        }
+        self.changed = false;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_queue() {
+    use std::net::IpAddr;
+
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now();
+    let message = Message::updated("example.com", None, IpAddr::from([192, 0, 2, 1]));
+
+    let mut queue = Queue::load(dir.path());
+    queue.push("slack-0123", message.clone(), 1, now);
+    queue.push("ntfy-4567", message.clone(), 3, now);
+    queue.save().unwrap();
+
+    let mut queue = Queue::load(dir.path());
+    assert_eq!(queue.pending.len(), 2);
+    assert!(queue.take_due(now).is_empty());
+    let due = queue.take_due(now + chrono::Duration::minutes(1));
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].target, "slack-0123");
+    let due = queue.take_due(now + chrono::Duration::minutes(4));
+    assert_eq!(due[0].target, "ntfy-4567");
+    queue.save().unwrap();
+    assert!(!dir.path().join(QUEUE_FILE).exists());
+
+    queue.set_retry_for(Duration::from_secs(60 * 60));
+    queue.push("slack-0123", message, 10, now);
+    assert!(queue
+        .take_due(now + chrono::Duration::minutes(61))
+        .is_empty());
+    assert!(queue.pending.is_empty());
+}
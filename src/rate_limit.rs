@@ -0,0 +1,95 @@
+//! Keeps cdu from sending more than so many requests a minute to the Cloudflare API, as the API
+//! allows an account 1200 requests per five minutes, shared with every other tool that uses it.
+//!
+//! It's a token bucket: it holds a minute's worth of requests, which a burst may use up at once, and
+//! fills up again at the rate, so a request that finds it empty waits for the next one.
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tracing::debug;
+
+/// The requests that can be sent right away, as of when it was last filled up.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    filled_at: Instant,
+}
+
+/// How many requests a minute may be sent, shared by everything that sends them.
+#[derive(Debug)]
+pub struct RateLimit {
+    per_minute: u32,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimit {
+    /// Returns a limit of `per_minute` requests a minute, with the bucket full. At least one
+    /// request a minute is allowed.
+    #[must_use]
+    pub fn new(per_minute: u32) -> Self {
+        let per_minute = per_minute.max(1);
+
+        Self {
+            per_minute,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(per_minute),
+                filled_at: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn per_minute(&self) -> u32 {
+        self.per_minute
+    }
+
+    /// Waits until a request may be sent, and counts it. Requests wait their turn in the order they
+    /// came in.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        while let Some(wait) = take(&mut bucket, self.per_minute, Instant::now()) {
+            debug!(
+                "Waiting {}ms to stay under {} requests a minute to Cloudflare",
+                wait.as_millis(),
+                self.per_minute
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Fills up the bucket for the time since it was last filled, and takes a token out of it. Returns
+/// how long to wait for one if it's empty.
+fn take(bucket: &mut Bucket, per_minute: u32, now: Instant) -> Option<Duration> {
+    let rate = f64::from(per_minute) / 60.0;
+    let elapsed = now
+        .saturating_duration_since(bucket.filled_at)
+        .as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * rate).min(f64::from(per_minute));
+    bucket.filled_at = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        None
+    } else {
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+#[test]
+fn test_take() {
+    let now = Instant::now();
+    let mut bucket = Bucket {
+        tokens: 2.0,
+        filled_at: now,
+    };
+
+    assert_eq!(take(&mut bucket, 60, now), None);
+    assert_eq!(take(&mut bucket, 60, now), None);
+    let wait = take(&mut bucket, 60, now).unwrap();
+    assert_eq!(wait.as_millis(), 1000);
+    assert_eq!(take(&mut bucket, 60, now + wait), None);
+
+    // It never holds more than a minute's worth
+    assert_eq!(take(&mut bucket, 60, now + Duration::from_secs(3600)), None);
+    assert!((bucket.tokens - 59.0).abs() < 1e-9);
+}
@@ -0,0 +1,186 @@
+//! Tries a request again when it failed in a way the next try may not, like a timeout, a connection
+//! that was reset or an error of the server, so a blip doesn't fail the whole run until the next
+//! one.
+//!
+//! The delay doubles after every try, up to [`MAX_DELAY`], with a random amount up to the jitter
+//! added to it, so instances that failed at the same time don't all try again at the same time.
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tracing::warn;
+
+/// How many times a request is sent in all, unless told otherwise.
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// How long to wait before the first retry, unless told otherwise.
+pub const DEFAULT_DELAY: Duration = Duration::from_secs(1);
+
+/// The most that's added to a delay at random, unless told otherwise.
+pub const DEFAULT_JITTER: Duration = Duration::from_millis(500);
+
+/// The longest delay between two tries, before the jitter.
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How often, and how long apart, a request that failed is tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// How many times a request is sent in all, where 1 is not trying again.
+    pub attempts: u32,
+    /// How long to wait before the first retry.
+    pub delay: Duration,
+    /// The most that's added to every delay at random.
+    pub jitter: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_ATTEMPTS,
+            delay: DEFAULT_DELAY,
+            jitter: DEFAULT_JITTER,
+        }
+    }
+}
+
+impl Policy {
+    /// Sends every request once.
+    pub const NEVER: Self = Self {
+        attempts: 1,
+        delay: Duration::ZERO,
+        jitter: Duration::ZERO,
+    };
+
+    /// Returns how long to wait before the `retry`th retry, the first one being 1, without the
+    /// jitter.
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(retry.saturating_sub(1));
+        self.delay.saturating_mul(factor).min(MAX_DELAY)
+    }
+
+    /// Runs `attempt` until it succeeds, fails in a way that `is_transient` says isn't worth
+    /// trying again, or has been tried [`Policy::attempts`] times, returning what the last try did.
+    /// Every retry is logged, saying what failed, with `what`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last try, if it failed.
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        what: &str,
+        mut attempt: F,
+        is_transient: impl Fn(&E) -> bool,
+    ) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut tries = 1;
+        loop {
+            match attempt().await {
+                Err(e) if tries < self.attempts && is_transient(&e) => {
+                    let millis = |duration: Duration| {
+                        u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+                    };
+                    let delay = Duration::from_millis(
+                        millis(self.backoff(tries))
+                            .saturating_add(fastrand::u64(..=millis(self.jitter))),
+                    );
+                    warn!(
+                        "{what} failed, trying again in {}: {e}",
+                        humantime::format_duration(delay)
+                    );
+                    tokio::time::sleep(delay).await;
+                    tries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// An answer of a server that isn't a success, which is worth trying again if it's an error of
+/// the server.
+#[derive(Debug, thiserror::Error)]
+#[error("Received response status {status}: {body}")]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+/// Returns whether a request that failed with `error` may succeed if it's sent again: it timed
+/// out, couldn't connect, broke off, or the server answered with an error of its own.
+pub fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_connect()
+        || error.is_request()
+        || error.is_body()
+        || error.status().is_some_and(is_transient_status)
+}
+
+/// Returns whether a response with `status` may be different if the request is sent again.
+#[must_use]
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+}
+
+/// Like [`is_transient`], for an error with a failed request, or a [`StatusError`], somewhere in
+/// its chain.
+#[must_use]
+pub fn is_transient_any(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(is_transient)
+            || cause
+                .downcast_ref::<StatusError>()
+                .is_some_and(|e| is_transient_status(e.status))
+    })
+}
+
+#[tokio::test]
+async fn test_run() {
+    let policy = Policy {
+        attempts: 3,
+        delay: Duration::from_millis(1),
+        jitter: Duration::from_millis(1),
+    };
+    assert_eq!(policy.backoff(1), Duration::from_millis(1));
+    assert_eq!(policy.backoff(3), Duration::from_millis(4));
+    assert_eq!(Policy::default().backoff(10), MAX_DELAY);
+
+    let mut tries = 0;
+    let result = policy
+        .run(
+            "Test",
+            || {
+                tries += 1;
+                async move {
+                    if tries < 3 {
+                        Err("timed out")
+                    } else {
+                        Ok(tries)
+                    }
+                }
+            },
+            |_| true,
+        )
+        .await;
+    assert_eq!(result, Ok(3));
+
+    let mut tries = 0;
+    let result: Result<(), _> = policy
+        .run(
+            "Test",
+            || {
+                tries += 1;
+                async { Err("not found") }
+            },
+            |e| *e != "not found",
+        )
+        .await;
+    assert_eq!(result, Err("not found"));
+    assert_eq!(tries, 1);
+}
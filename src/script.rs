@@ -0,0 +1,229 @@
+//! Runs a Rhai script of your own, with the `scripting` feature, that decides whether a change of
+//! the outside IP is worth updating the A records for, and to what, for rules no setting covers,
+//! like only updating at night, or ignoring a change within the same /24.
+//!
+//! The script is run every time the outside IP changed, before anything is updated, with:
+//!
+//! - `new_ip`, the outside IP that was detected, and `source`, the server that told it.
+//! - `old_ip`, the outside IP of the last check, or `()` if there wasn't one.
+//! - `records`, a map of every domain to the IP its A record points at as far as the state knows,
+//!   or `()`.
+//! - `hour` and `minute`, the local time, and `weekday`, from 1 for Monday to 7 for Sunday.
+//! - `same_network(a, b, prefix)`, which returns whether two IPs are in the same network, like
+//!   `same_network(old_ip, new_ip, 24)` for the same /24, or `same_network(old_ip, new_ip, 64)`
+//!   for the same /64 of two IPv6 addresses.
+//!
+//! It returns `true` to update, `false` to leave the A records alone until the next check, or
+//! another IP as a string to update them to that instead.
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, AST};
+
+/// How much work a script may do before it's stopped, far more than a few rules need.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// What the script decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Update the A records to the outside IP.
+    Update,
+    /// Leave the A records alone, so the next check asks again.
+    Skip,
+    /// Update the A records to this IP instead.
+    UpdateTo(IpAddr),
+}
+
+/// What the script is told about the change.
+#[derive(Debug, Clone)]
+pub struct Change<'a> {
+    pub new_ip: IpAddr,
+    pub source: &'a str,
+    pub old_ip: Option<IpAddr>,
+    /// Every domain, with the IP its A record points at as far as the state knows.
+    pub records: Vec<(&'a str, Option<IpAddr>)>,
+    pub now: DateTime<Local>,
+}
+
+/// A script that's compiled and ready to run.
+#[derive(Clone)]
+pub struct Script {
+    path: PathBuf,
+    engine: Arc<Engine>,
+    ast: AST,
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script").field("path", &self.path).finish()
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Script {
+    /// Compiles the script at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it can't be read, or isn't valid Rhai.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::compile(path, &source)
+    }
+
+    /// Like [`Script::load`], for a value of the command line.
+    pub fn parse(path: &str) -> Result<Self, String> {
+        Self::load(Path::new(path)).map_err(|e| format!("{e:#}"))
+    }
+
+    fn compile(path: &Path, source: &str) -> anyhow::Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.register_fn("same_network", same_network);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| anyhow!("Failed to compile {}: {e}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            engine: Arc::new(engine),
+            ast,
+        })
+    }
+
+    /// Runs the script on `change`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script failed, or returned something other than a boolean or an
+    /// IP.
+    pub fn decide(&self, change: &Change<'_>) -> anyhow::Result<Verdict> {
+        let ip = |ip: Option<IpAddr>| ip.map_or(Dynamic::UNIT, |ip| Dynamic::from(ip.to_string()));
+        let records = change
+            .records
+            .iter()
+            .map(|&(domain, record_ip)| (domain.into(), ip(record_ip)))
+            .collect::<Map>();
+
+        let mut scope = Scope::new();
+        scope.push("new_ip", change.new_ip.to_string());
+        scope.push("source", change.source.to_string());
+        scope.push_dynamic("old_ip", ip(change.old_ip));
+        scope.push("records", records);
+        scope.push("hour", i64::from(change.now.hour()));
+        scope.push("minute", i64::from(change.now.minute()));
+        scope.push(
+            "weekday",
+            i64::from(change.now.weekday().number_from_monday()),
+        );
+
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("{} failed: {e}", self.path.display()))?;
+        if let Ok(update) = result.as_bool() {
+            return Ok(if update {
+                Verdict::Update
+            } else {
+                Verdict::Skip
+            });
+        }
+        let type_name = result.type_name();
+        let text = result.into_string().map_err(|_| {
+            anyhow!(
+                "{} returned a {type_name}, not true, false or an IP",
+                self.path.display()
+            )
+        })?;
+        let ip = text
+            .parse()
+            .with_context(|| format!("{} returned {text:?}, not an IP", self.path.display()))?;
+
+        Ok(if ip == change.new_ip {
+            Verdict::Update
+        } else {
+            Verdict::UpdateTo(ip)
+        })
+    }
+}
+
+/// Returns whether `a` and `b` are IPs in the same network with a `prefix` that long.
+fn same_network(a: ImmutableString, b: ImmutableString, prefix: i64) -> bool {
+    match (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+        (Ok(IpAddr::V4(a)), Ok(IpAddr::V4(b))) => {
+            let mask = match prefix.clamp(0, 32) {
+                0 => 0,
+                bits => u32::MAX << (32 - bits),
+            };
+            u32::from(a) & mask == u32::from(b) & mask
+        }
+        (Ok(IpAddr::V6(a)), Ok(IpAddr::V6(b))) => {
+            let mask = match prefix.clamp(0, 128) {
+                0 => 0,
+                bits => u128::MAX << (128 - bits),
+            };
+            u128::from(a) & mask == u128::from(b) & mask
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn test_decide() {
+    let script = Script::compile(
+        Path::new("policy.rhai"),
+        r#"
+        if type_of(old_ip) == "string" && same_network(old_ip, new_ip, 24) {
+            false
+        } else if hour >= 2 && hour < 4 {
+            true
+        } else {
+            records["home.example.com"]
+        }
+        "#,
+    )
+    .unwrap();
+    let change = |old_ip: &str, hour| Change {
+        new_ip: IpAddr::from([198, 51, 100, 7]),
+        source: "icanhazip.com",
+        old_ip: old_ip.parse().ok(),
+        records: vec![("home.example.com", Some(IpAddr::from([192, 0, 2, 1])))],
+        now: Local::now().with_hour(hour).unwrap(),
+    };
+
+    assert_eq!(
+        script.decide(&change("198.51.100.1", 3)).unwrap(),
+        Verdict::Skip
+    );
+    assert_eq!(script.decide(&change("", 3)).unwrap(), Verdict::Update);
+    assert_eq!(
+        script.decide(&change("203.0.113.1", 12)).unwrap(),
+        Verdict::UpdateTo(IpAddr::from([192, 0, 2, 1]))
+    );
+
+    assert!(same_network("10.0.0.1".into(), "10.0.255.1".into(), 16));
+    assert!(!same_network("10.0.0.1".into(), "10.1.0.1".into(), 16));
+    assert!(same_network("10.0.0.1".into(), "192.0.2.1".into(), 0));
+    assert!(!same_network("10.0.0.1".into(), "10.0.0.2".into(), 32));
+    assert!(same_network("2001:db8::1".into(), "2001:db8::2".into(), 64));
+    assert!(!same_network(
+        "2001:db8::1".into(),
+        "2001:db8:1::1".into(),
+        64
+    ));
+    assert!(!same_network(
+        "10.0.0.1".into(),
+        "::ffff:10.0.0.1".into(),
+        0
+    ));
+}
@@ -0,0 +1,133 @@
+//! Keeps the API token in the credential store of the OS, with the `keyring` feature: the Secret
+//! Service of GNOME Keyring or KWallet on Linux, the Keychain on macOS, and the Credential Manager
+//! on Windows. `cdu secret set` asks for the token and stores it, so it's in neither an environment
+//! file nor the history of the shell.
+//!
+//! The token is stored for the service `cdu`, with the profile as the user, or `default`. It's used
+//! when no `CDU_API_KEY` is set, by the environment, the environment file, the secrets directory or
+//! the settings file, which all win over it.
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::Context;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use keyring::Entry;
+use tracing::debug;
+
+/// The service the token is stored for.
+const SERVICE: &str = "cdu";
+
+/// The variable the token is loaded into.
+const VARIABLE: &str = "CDU_API_KEY";
+
+fn entry(profile: Option<&str>) -> anyhow::Result<Entry> {
+    Entry::new(SERVICE, profile.unwrap_or("default"))
+        .context("Failed to open the credential store of the OS")
+}
+
+/// Loads the token of `profile` from the credential store into `CDU_API_KEY`, unless it's set
+/// already. Without a credential store, like on a server without a Secret Service, there's no
+/// token to load.
+///
+/// # Errors
+///
+/// Returns an error if the credential store has the token, but it cannot be read.
+pub fn load(profile: Option<&str>) -> anyhow::Result<()> {
+    if env::var_os(VARIABLE).is_some() {
+        return Ok(());
+    }
+    if let Some(token) = read(&entry(profile)?)? {
+        debug!("Using the API token from the credential store");
+        env::set_var(VARIABLE, token);
+    }
+
+    Ok(())
+}
+
+fn read(entry: &Entry) -> anyhow::Result<Option<String>> {
+    match entry.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e @ (keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_))) => {
+            debug!("No credential store to get the API token from: {e}");
+            Ok(None)
+        }
+        Err(e) => Err(e).context("Failed to read the API token from the credential store"),
+    }
+}
+
+/// Stores the token of `profile` in the credential store, asking for it at a terminal without
+/// showing it, or reading it from stdin otherwise.
+///
+/// # Errors
+///
+/// Returns an error if no token is given, or it cannot be stored.
+pub fn set(profile: Option<&str>) -> anyhow::Result<()> {
+    let token = if io::stdin().is_terminal() {
+        print!("API token: ");
+        io::stdout().flush()?;
+        read_hidden()?
+    } else {
+        let mut token = String::new();
+        io::stdin().lock().read_line(&mut token)?;
+        token
+    };
+    let token = token.trim();
+    anyhow::ensure!(!token.is_empty(), "No API token was given");
+
+    entry(profile)?
+        .set_password(token)
+        .context("Failed to store the API token in the credential store")
+}
+
+/// Removes the token of `profile` from the credential store, and returns whether there was one.
+///
+/// # Errors
+///
+/// Returns an error if it cannot be removed.
+pub fn delete(profile: Option<&str>) -> anyhow::Result<bool> {
+    match entry(profile)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).context("Failed to remove the API token from the credential store"),
+    }
+}
+
+/// Reads a line from the terminal without echoing it.
+fn read_hidden() -> anyhow::Result<String> {
+    terminal::enable_raw_mode()?;
+    let mut line = String::new();
+    let result = loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.into()),
+        };
+        match key.code {
+            KeyCode::Enter => break Ok(line),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                break Err(anyhow::anyhow!("Cancelled, nothing was stored"));
+            }
+            KeyCode::Char(c) => line.push(c),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            _ => {}
+        }
+    };
+    terminal::disable_raw_mode()?;
+    println!();
+
+    result
+}
+
+#[test]
+fn test_read() {
+    keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+
+    let entry = entry(Some("home")).unwrap();
+    assert_eq!(read(&entry).unwrap(), None);
+    entry.set_password("token").unwrap();
+    assert_eq!(read(&entry).unwrap().as_deref(), Some("token"));
+}
@@ -0,0 +1,246 @@
+//! Replaces the running binary with the one of the latest release on GitHub, for routers and NAS
+//! boxes without a package manager. It's only there when cdu is built with the `self-update`
+//! feature, as the releases are.
+//!
+//! The archive is only unpacked once its SHA-256 matches the checksum that's published along with
+//! it, so a download that broke or was tampered with on the way is refused.
+use std::env;
+use std::ffi::OsStr;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use reqwest::Client as RqClient;
+use serde::Deserialize;
+
+use crate::network;
+use crate::{confirm, crypto};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/agingorange/cdu/releases/latest";
+
+/// A release, as far as it matters here.
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+/// A file of a release.
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    /// Returns the URL of the asset called `name`.
+    fn url_of(&self, name: &str) -> anyhow::Result<&str> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.as_str())
+            .with_context(|| format!("Release {} has no {name}", self.tag_name))
+    }
+}
+
+/// Updates cdu to the latest release, if it's newer than this one, or only tells whether there is
+/// one with `check_only`. It asks first, unless `yes`, see [`confirm::confirm`].
+///
+/// # Errors
+///
+/// Returns an error if the latest release cannot be found or downloaded, has no binary for this
+/// platform, doesn't match its checksum, or the binary cannot be replaced.
+pub async fn run(check_only: bool, yes: bool) -> anyhow::Result<()> {
+    let client = RqClient::builder()
+        .user_agent(network::USER_AGENT)
+        .build()?;
+    let release = client
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .context("Failed to ask GitHub for the latest release")?
+        .json::<Release>()
+        .await
+        .context("Failed to read the latest release")?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current) {
+        println!("cdu {current} is the latest version");
+        return Ok(());
+    }
+    if check_only {
+        println!("cdu {latest} is available, this is {current}");
+        return Ok(());
+    }
+    let exe = env::current_exe().context("Failed to find the binary of cdu")?;
+    confirm::confirm(
+        &[format!(
+            "Replace {} (cdu {current}) with cdu {latest}",
+            exe.display()
+        )],
+        yes,
+    )?;
+
+    let target = target().with_context(|| {
+        format!(
+            "There are no releases for {} on {}",
+            env::consts::ARCH,
+            env::consts::OS
+        )
+    })?;
+    let archive_name = format!(
+        "cdu-{target}.{}",
+        if cfg!(windows) { "zip" } else { "tar.gz" }
+    );
+    let archive = download(&client, release.url_of(&archive_name)?).await?;
+    let checksum = download(&client, release.url_of(&format!("{archive_name}.sha256"))?).await?;
+    verify(&archive, &String::from_utf8_lossy(&checksum))
+        .with_context(|| format!("Refusing to install {archive_name}"))?;
+
+    let mut binary = tempfile::NamedTempFile::new()?;
+    binary.write_all(&unpack(&archive_name, &archive)?)?;
+    self_replace::self_replace(binary.path()).context("Failed to replace the binary of cdu")?;
+    println!("Updated cdu from {current} to {latest}");
+
+    Ok(())
+}
+
+/// Returns the target that the releases are built for which runs here, as in the names of their
+/// archives.
+fn target() -> Option<&'static str> {
+    match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("windows", "x86_64") => Some("x86_64-pc-windows-gnu"),
+        ("macos", _) => Some("universal-apple-darwin"),
+        _ => None,
+    }
+}
+
+/// Returns whether version `latest` comes after `current`. A version that cannot be read never
+/// does, so nothing is replaced by mistake.
+fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |version: &str| {
+        let mut numbers = version
+            .split(['-', '+'])
+            .next()?
+            .split('.')
+            .map(|number| number.parse::<u64>().ok());
+        Some((numbers.next()??, numbers.next()??, numbers.next()??))
+    };
+
+    match (parse(latest), parse(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+async fn download(client: &RqClient, url: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to download {url}"))?
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to download {url}"))?;
+
+    Ok(bytes.to_vec())
+}
+
+/// Checks that the SHA-256 of `archive` is the one in `checksum`, which is written like `sha256sum`
+/// does, with the name of the file after it.
+fn verify(archive: &[u8], checksum: &str) -> anyhow::Result<()> {
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .context("The checksum is empty")?
+        .to_ascii_lowercase();
+    let actual = crypto::sha256_hex(archive);
+    anyhow::ensure!(
+        actual == expected,
+        "Its SHA-256 is {actual}, but the checksum says {expected}"
+    );
+
+    Ok(())
+}
+
+/// Returns the binary in the archive, a `.zip` on Windows and a `.tar.gz` elsewhere.
+fn unpack(archive_name: &str, archive: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let name = OsStr::new(if cfg!(windows) { "cdu.exe" } else { "cdu" });
+    let mut binary = Vec::new();
+
+    let is_zip = Path::new(archive_name)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+    if is_zip {
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive))?;
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index)?;
+            if file
+                .enclosed_name()
+                .is_some_and(|path| path.file_name() == Some(name))
+            {
+                file.read_to_end(&mut binary)?;
+                return Ok(binary);
+            }
+        }
+    } else {
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.file_name() == Some(name) {
+                entry.read_to_end(&mut binary)?;
+                return Ok(binary);
+            }
+        }
+    }
+
+    anyhow::bail!("There's no {} in {archive_name}", name.to_string_lossy())
+}
+
+#[test]
+fn test_update() {
+    assert!(is_newer("0.2.0", "0.1.4"));
+    assert!(is_newer("0.1.10", "0.1.4"));
+    assert!(!is_newer("0.1.4", "0.1.4"));
+    assert!(!is_newer("0.1.3", "0.1.4"));
+    assert!(!is_newer("latest", "0.1.4"));
+
+    let mut tar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(6);
+    header.set_mode(0o755);
+    header.set_cksum();
+    tar.append_data(
+        &mut header,
+        "cdu-x86_64-unknown-linux-gnu/README.md",
+        &b"readme"[..],
+    )
+    .unwrap();
+    tar.append_data(
+        &mut header,
+        "cdu-x86_64-unknown-linux-gnu/cdu",
+        &b"binary"[..],
+    )
+    .unwrap();
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&tar.into_inner().unwrap()).unwrap();
+    let archive = gz.finish().unwrap();
+
+    let checksum = crypto::sha256_hex(&archive);
+    assert!(verify(
+        &archive,
+        &format!("{checksum}  cdu-x86_64-unknown-linux-gnu.tar.gz\n")
+    )
+    .is_ok());
+    assert!(verify(&archive, &"0".repeat(64)).is_err());
+    if !cfg!(windows) {
+        assert_eq!(
+            unpack("cdu-x86_64-unknown-linux-gnu.tar.gz", &archive).unwrap(),
+            b"binary"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Runs cdu as a native Windows service.
+//!
+//! The service runs the daemon, with its settings in `%ProgramData%\cdu\.env`, and its
+//! configuration file in the same directory. Logs are written to `cdu.log` there too, as a service
+//! has nowhere to write to otherwise, and the important events to the Event Log.
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{error, info};
+use windows_service::define_windows_service;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use crate::daemon::Event;
+use crate::event_log;
+
+pub const SERVICE_NAME: &str = "cdu";
+const DISPLAY_NAME: &str = "Cloudflare DNS Updater";
+
+/// The custom control code that makes the service check right away: `sc control cdu 128`.
+pub const FORCE_CHECK_CONTROL: u32 = 128;
+
+/// Runs the daemon, with events coming from the service control manager.
+pub type Daemon = fn(UnboundedSender<Event>, UnboundedReceiver<Event>) -> anyhow::Result<()>;
+
+static DAEMON: OnceLock<Daemon> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Returns the directory with the settings, configuration file and logs of the service.
+pub fn data_dir() -> PathBuf {
+    env::var_os("ProgramData")
+        .map_or_else(|| PathBuf::from(r"C:\ProgramData"), PathBuf::from)
+        .join("cdu")
+}
+
+/// Returns the path of the environment file with the settings of the service.
+pub fn env_file() -> PathBuf {
+    data_dir().join(".env")
+}
+
+/// Registers the service, starting automatically at boot, as the local system account, and its
+/// source of the Event Log.
+///
+/// # Errors
+///
+/// Returns an error if the service cannot be created, which is usually because this isn't run as
+/// an administrator, or because it already exists.
+pub fn install() -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to connect to the service manager, are you an administrator?")?;
+
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: env::current_exe().context("Failed to find the cdu executable")?,
+        launch_arguments: vec![OsString::from("service"), OsString::from("run")],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("Failed to create the service")?;
+    service
+        .set_description(clap::crate_description!())
+        .context("Failed to set the description of the service")?;
+
+    let data_dir = data_dir();
+    fs::create_dir_all(&data_dir)
+        .with_context(|| format!("Failed to create directory: {}", data_dir.display()))?;
+    event_log::register()?;
+
+    println!(
+        "Service installed. Put your settings in {}, then start it with:\n\n    sc start {SERVICE_NAME}",
+        env_file().display()
+    );
+
+    Ok(())
+}
+
+/// Stops the service if it's running, and removes it, along with its source of the Event Log.
+///
+/// # Errors
+///
+/// Returns an error if the service doesn't exist, or cannot be stopped or removed.
+pub fn uninstall() -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to connect to the service manager, are you an administrator?")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("Failed to open the service")?;
+
+    service.delete().context("Failed to remove the service")?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("Failed to stop the service")?;
+    }
+    event_log::unregister()?;
+
+    println!(
+        "Service removed. The files in {} were left alone.",
+        data_dir().display()
+    );
+
+    Ok(())
+}
+
+/// Hands control to the service control manager, which calls back to run `daemon`. This only
+/// works when Windows started cdu as a service.
+///
+/// # Errors
+///
+/// Returns an error if cdu wasn't started as a service.
+pub fn run(daemon: Daemon) -> anyhow::Result<()> {
+    let _ = DAEMON.set(daemon);
+
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("Failed to start the service, this only works when Windows starts it")?;
+
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Service failed: {e:#}");
+    }
+}
+
+fn run_service() -> anyhow::Result<()> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let control_sender = sender.clone();
+    let status_handle = service_control_handler::register(SERVICE_NAME, move |control| {
+        let event = match control {
+            ServiceControl::Interrogate => return ServiceControlHandlerResult::NoError,
+            ServiceControl::Stop | ServiceControl::Shutdown | ServiceControl::Preshutdown => {
+                Event::Shutdown
+            }
+            ServiceControl::ParamChange => Event::Reload,
+            ServiceControl::UserEvent(code) if code.to_raw() == FORCE_CHECK_CONTROL => {
+                Event::ForceCheck
+            }
+            _ => return ServiceControlHandlerResult::NotImplemented,
+        };
+
+        if control_sender.send(event).is_ok() {
+            ServiceControlHandlerResult::NoError
+        } else {
+            ServiceControlHandlerResult::Other(1)
+        }
+    })?;
+
+    set_status(
+        &status_handle,
+        ServiceState::Running,
+        ServiceExitCode::Win32(0),
+    )?;
+    info!("Service started");
+
+    let daemon = DAEMON.get().context("No daemon to run")?;
+    let result = daemon(sender, receiver);
+
+    let exit_code = match &result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+    set_status(&status_handle, ServiceState::Stopped, exit_code)?;
+
+    result
+}
+
+fn set_status(
+    status_handle: &ServiceStatusHandle,
+    state: ServiceState,
+    exit_code: ServiceExitCode,
+) -> anyhow::Result<()> {
+    let controls_accepted = if state == ServiceState::Running {
+        ServiceControlAccept::STOP
+            | ServiceControlAccept::SHUTDOWN
+            | ServiceControlAccept::PARAM_CHANGE
+    } else {
+        ServiceControlAccept::empty()
+    };
+
+    status_handle
+        .set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+        .context("Failed to report the status of the service")?;
+
+    Ok(())
+}
+
+/// Returns whether the arguments are the ones the service is started with, so logging can be set
+/// up for it before anything else happens.
+pub fn is_service_run(args: &[OsString]) -> bool {
+    args.iter()
+        .skip(1)
+        .map(OsString::as_os_str)
+        .eq([OsStr::new("service"), OsStr::new("run")])
+}
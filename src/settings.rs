@@ -0,0 +1,1070 @@
+//! Reads the settings from a TOML file, so a setup with many domains or notification targets
+//! doesn't have to be written out on the command line.
+//!
+//! A file ending in `.yaml`, `.yml` or `.json` is read as YAML or JSON instead, with the same keys
+//! and layout, for settings generated by tools like Ansible or Helm.
+//!
+//! Every key is the name of an argument, with underscores, like `zone_id` or `notify_on`, and a
+//! list is given as an array. Named targets are tables under `notify`, with the `target` and the
+//! `on`, `severity` and `enabled` settings their `CDU_NOTIFY_<NAME>` variables have:
+//!
+//! ```toml
+//! zone_id = "..."
+//! api_key = { file = "/run/secrets/cloudflare" }
+//! domain = ["home.example.com", "vpn.example.com"]
+//! interval = "10m"
+//!
+//! [notify.phone]
+//! target = "ntfy:https://ntfy.sh/my-cdu"
+//! on = ["updated", "update-failed"]
+//! ```
+//!
+//! A domain can have settings of its own, in a `[[domains]]` table with its `name`: the `zone_id`
+//! it's in, whether it's `proxied`, its `ttl`, the `notify_on` events for messages about it, the
+//! named targets in `notify` they're sent to instead of every target, the `notify_severity` they
+//! need to be sent at all, whether to point the PTR record of the outside IP back at it with `ptr`,
+//! which needs `--ptr-server`, the `wan` of `--wan` whose outside IP it's pointed at, on a host
+//! with more than one connection, its record `type`, `"A"` or `"AAAA"` for the outside IPv6
+//! address, and the `server` its outside IP is asked for first. Those domains are updated along
+//! with the ones in `domain`.
+//!
+//! Instead of a value, a table can refer to where the value is: `{ file = "<path>" }` for the
+//! contents of a file, like a Docker secret, and `{ env = "<name>" }` for another environment
+//! variable. A secret can also be written as `${NAME}` inside any string, like
+//! `"https://ntfy.sh/${NTFY_TOPIC}"`, which is replaced with the variable when the file is read, so
+//! the file itself can be shared without them. `$$` stands for a `$` of its own.
+//!
+//! One file can hold more than one setup, as `[profiles.<name>]` tables picked with `--profile`.
+//! The settings of the profile win over the ones around it, which the profiles share:
+//!
+//! ```toml
+//! api_key = { file = "/run/secrets/cloudflare" }
+//!
+//! [profiles.home]
+//! zone_id = "..."
+//! domain = ["home.example.com"]
+//!
+//! [profiles.office]
+//! zone_id = "..."
+//! domain = ["office.example.com"]
+//! ```
+//!
+//! Settings can be split up into fragments, like a file of domains per host that's written by
+//! other tooling, with `include = ["domains.d/*.toml"]`. The patterns are relative to the file, and
+//! the fragments they match are merged into it in order of their names: lists and `[[domains]]` are
+//! added to, tables are merged, and whatever else is set in the file wins over the fragments.
+//!
+//! A file can say which layout it's in with `version = 1`, so one written for a newer version of
+//! cdu is refused instead of being misread.
+//!
+//! The settings become the environment variables of the arguments, for those that aren't set
+//! already, so those and the arguments win over the file.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches};
+use serde::Deserialize;
+use toml::{Table, Value};
+use toml_edit::DocumentMut;
+
+use cdu::crypt;
+use cdu::network::Family;
+use cdu::notify::{EventKind, Severity};
+use cdu::redact::MASK;
+use cdu::updater::DomainSettings;
+
+/// The layout of the settings file, which it can say it's in as its `version`.
+const VERSION: i64 = 1;
+
+/// The keys older versions kept the state in, in the same file, which aren't settings.
+const STATE: [&str; 8] = [
+    "outside_ip",
+    "cloudflare_ip",
+    "last_updated",
+    "last_reconciled",
+    "last_heartbeat",
+    "save_dir",
+    "file_name",
+    "webhook_url",
+];
+
+/// Reads the settings file at `path`, returning the environment variables it sets.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, or has a setting cdu doesn't know.
+pub fn read(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let table = read_table(path, crate::profile().as_deref())?;
+    let cli = crate::args::cli();
+    let daemon_args = crate::args::daemon_args();
+    let args = cli.get_arguments().chain(&daemon_args).collect::<Vec<_>>();
+
+    variables(&table, &args)
+        .map_err(|e| anyhow::anyhow!("Invalid settings in {}: {e:#}", path.display()))
+}
+
+/// A `[[domains]]` table.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DomainSection {
+    name: String,
+    zone_id: Option<String>,
+    #[serde(rename = "type")]
+    record_type: Option<String>,
+    proxied: Option<bool>,
+    ttl: Option<u32>,
+    notify_on: Option<Vec<String>>,
+    notify: Option<Vec<String>>,
+    notify_severity: Option<String>,
+    ptr: Option<bool>,
+    wan: Option<String>,
+    server: Option<String>,
+}
+
+/// Reads the settings of the domains that have their own, from the settings file at `path`, with
+/// the ones of `profile` if there is one.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, the profile isn't in it, or the settings
+/// of a domain are invalid.
+pub fn domains(path: &Path, profile: Option<&str>) -> anyhow::Result<Vec<DomainSettings>> {
+    let mut table = read_table(path, profile)?;
+    let Some(sections) = table.remove("domains") else {
+        return Ok(Vec::new());
+    };
+
+    sections
+        .try_into::<Vec<DomainSection>>()
+        .map_err(anyhow::Error::msg)
+        .and_then(|sections| sections.into_iter().map(domain_settings).collect())
+        .map_err(|e| anyhow::anyhow!("Invalid domains in {}: {e:#}", path.display()))
+}
+
+fn domain_settings(section: DomainSection) -> anyhow::Result<DomainSettings> {
+    let name = section.name;
+    let family = match section.record_type.as_deref() {
+        None => None,
+        Some("A") => Some(Family::V4),
+        Some("AAAA") => Some(Family::V6),
+        Some(record_type) => {
+            anyhow::bail!("{name}: only A and AAAA records can be updated, not {record_type}")
+        }
+    };
+    let notify_on = section
+        .notify_on
+        .map(|kinds| {
+            kinds
+                .iter()
+                .map(|kind| EventKind::parse(kind))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+    let notify_severity = section
+        .notify_severity
+        .map(|severity| Severity::parse(&severity))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("{name}: {e}"))?;
+
+    Ok(DomainSettings {
+        zone_id: section.zone_id,
+        proxied: section.proxied,
+        ttl: section.ttl,
+        notify_on,
+        notify: section.notify,
+        notify_severity,
+        ptr: section.ptr.unwrap_or_default(),
+        wan: section.wan,
+        family,
+        server: section.server,
+        name,
+    })
+}
+
+/// Reads the domains in the file at `path`, which has one on every line. Everything after a `#` is
+/// a comment, and blank lines are left out.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, or a line has more than a domain on it.
+pub fn domains_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read domains file: {}", path.display()))?;
+
+    let mut domains = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let domain = line.split('#').next().unwrap_or_default().trim();
+        if domain.is_empty() {
+            continue;
+        }
+        anyhow::ensure!(
+            !domain.contains(char::is_whitespace),
+            "Invalid domain on line {} of {}: {domain}",
+            index + 1,
+            path.display()
+        );
+        domains.push(domain.to_string());
+    }
+
+    Ok(domains)
+}
+
+/// Parses a profile name, which is also the name of the directory its state is kept in.
+///
+/// # Errors
+///
+/// Returns an error if the name is empty, or has anything but letters, digits, `-` and `_`.
+pub fn parse_profile(name: &str) -> Result<String, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Expected letters, digits, - and _ for the profile, got: {name}"
+        ));
+    }
+
+    Ok(name.to_string())
+}
+
+/// Reads the settings file at `path`, with its fragments, and the settings of `profile` over the
+/// others.
+fn read_table(path: &Path, profile: Option<&str>) -> anyhow::Result<Table> {
+    let mut table = parse(path)?;
+    if let Some(patterns) = table.remove("include") {
+        for fragment in fragments(path, &patterns)? {
+            let included = parse(&fragment)?;
+            anyhow::ensure!(
+                !included.contains_key("include"),
+                "Only the settings file can include others, not {}",
+                fragment.display()
+            );
+            merge(&mut table, included);
+        }
+    }
+
+    let profiles = table.remove("profiles");
+    if let Some(profile) = profile {
+        let Some(Value::Table(settings)) = profiles.as_ref().and_then(|p| p.get(profile)) else {
+            anyhow::bail!("There's no profile {profile} in {}", path.display());
+        };
+        table.extend(settings.clone());
+    }
+    for (_, value) in &mut table {
+        expand_all(value)
+            .map_err(|e| anyhow::anyhow!("Invalid settings in {}: {e:#}", path.display()))?;
+    }
+
+    Ok(table)
+}
+
+/// Parses the file at `path` as TOML, or as YAML or JSON if its extension says so.
+fn parse(path: &Path) -> anyhow::Result<Table> {
+    // The reasons are part of the message, as only the message is shown at the end
+    let text = if crypt::is_encrypted(path) {
+        crypt::read_to_string(path).map_err(|e| anyhow::anyhow!("{e:#}"))?
+    } else {
+        fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read settings from {}: {e}", path.display()))?
+    };
+
+    // The format of an encrypted file is the extension before .age
+    let plain_path = if crypt::is_encrypted(path) {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    let extension = plain_path
+        .extension()
+        .and_then(|extension| extension.to_str());
+    let mut table = match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("yaml" | "yml") => serde_yaml::from_str::<Table>(&text).map_err(anyhow::Error::msg),
+        Some("json") => serde_json::from_str::<Table>(&text).map_err(anyhow::Error::msg),
+        _ => text.parse::<Table>().map_err(anyhow::Error::msg),
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to parse settings from {}: {e}", path.display()))?;
+
+    // There's only been the one layout, so there's nothing to upgrade yet
+    match table.remove("version") {
+        None => {}
+        Some(Value::Integer(version)) if (1..=VERSION).contains(&version) => {}
+        Some(version) => anyhow::bail!(
+            "The settings in {} are in layout {version}, and this version of cdu only knows up to {VERSION}",
+            path.display()
+        ),
+    }
+
+    Ok(table)
+}
+
+/// Returns the files the `include` patterns of the settings file at `path` match, in order.
+fn fragments(path: &Path, patterns: &Value) -> anyhow::Result<Vec<PathBuf>> {
+    let patterns = match patterns {
+        Value::String(pattern) => vec![pattern.as_str()],
+        Value::Array(patterns) => patterns
+            .iter()
+            .map(|pattern| pattern.as_str())
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| format!("Expected paths to include in {}", path.display()))?,
+        _ => anyhow::bail!("Expected paths to include in {}", path.display()),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut fragments = Vec::new();
+    for pattern in patterns {
+        let pattern = dir.join(pattern);
+        let pattern = pattern.to_string_lossy();
+        let mut matched = glob::glob(&pattern)
+            .map_err(|e| anyhow::anyhow!("Invalid include {pattern}: {e}"))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("Failed to include {pattern}: {e}"))?;
+        matched.sort();
+        fragments.extend(matched);
+    }
+
+    Ok(fragments)
+}
+
+/// Merges a fragment into the settings: lists are added to, tables are merged, and the settings
+/// win over the fragment otherwise.
+fn merge(table: &mut Table, fragment: Table) {
+    for (key, value) in fragment {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Array(values)), Value::Array(more)) => values.extend(more),
+            (Some(Value::Table(table)), Value::Table(more)) => merge(table, more),
+            (Some(_), _) => {}
+            (None, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replaces the `${NAME}` references in every string in `value`, see [`expand`].
+fn expand_all(value: &mut Value) -> anyhow::Result<()> {
+    match value {
+        Value::String(text) => *text = expand(text)?,
+        Value::Array(values) => values.iter_mut().try_for_each(expand_all)?,
+        Value::Table(table) => table
+            .iter_mut()
+            .try_for_each(|(_, value)| expand_all(value))?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Replaces every `${NAME}` in `text` with the environment variable, and every `$$` with a `$`.
+fn expand(text: &str) -> anyhow::Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = after
+                .find('}')
+                .with_context(|| format!("Missing the }} after ${{ in: {text}"))?;
+            let name = &after[..end];
+            let value = env::var(name).with_context(|| format!("${{{name}}} isn't set"))?;
+            expanded.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            expanded.push('$');
+        }
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Returns the environment variables for the settings, of the arguments in `args`.
+fn variables(table: &Table, args: &[&Arg]) -> anyhow::Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+    for (key, value) in table {
+        // The domains with settings of their own are read by themselves
+        if STATE.contains(&key.as_str()) || key == "domains" {
+            continue;
+        }
+        if let ("notify", Value::Table(targets)) = (key.as_str(), value) {
+            variables.extend(named_targets(targets)?);
+            continue;
+        }
+
+        let arg = args
+            .iter()
+            .find(|arg| arg.get_id() == key.as_str())
+            .with_context(|| format!("Unknown setting: {key}"))?;
+        let env = arg
+            .get_env()
+            .with_context(|| format!("{key} cannot be set in the settings file"))?;
+        let value = list(key, value, arg.get_value_delimiter())?;
+        variables.push((env.to_string_lossy().into_owned(), value));
+    }
+
+    Ok(variables)
+}
+
+/// Returns the variables of the named targets, as `CDU_NOTIFY_<NAME>` and its settings.
+fn named_targets(targets: &Table) -> anyhow::Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+    for (name, target) in targets {
+        let Value::Table(target) = target else {
+            anyhow::bail!("Expected a table with the target for notify.{name}");
+        };
+        anyhow::ensure!(
+            target.contains_key("target"),
+            "Missing the target for notify.{name}"
+        );
+
+        let prefix = format!("CDU_NOTIFY_{}", name.to_ascii_uppercase().replace('-', "_"));
+        for (key, value) in target {
+            let (suffix, delimiter) = match key.as_str() {
+                "target" => ("", None),
+                "on" => ("_ON", Some(',')),
+                "severity" => ("_SEVERITY", None),
+                "enabled" => ("_ENABLED", None),
+                _ => anyhow::bail!("Unknown setting: notify.{name}.{key}"),
+            };
+            let value = list(&format!("notify.{name}.{key}"), value, delimiter)?;
+            variables.push((format!("{prefix}{suffix}"), value));
+        }
+    }
+
+    Ok(variables)
+}
+
+/// Returns the value as it's written in an environment variable, with the values of an array
+/// separated by `delimiter`.
+fn list(key: &str, value: &Value, delimiter: Option<char>) -> anyhow::Result<String> {
+    let Value::Array(values) = value else {
+        return text(key, value);
+    };
+    let delimiter = delimiter.with_context(|| format!("{key} takes a single value"))?;
+
+    Ok(values
+        .iter()
+        .map(|value| text(key, value))
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .join(&delimiter.to_string()))
+}
+
+fn text(key: &str, value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Integer(number) => Ok(number.to_string()),
+        Value::Float(number) => Ok(number.to_string()),
+        Value::Boolean(flag) => Ok(flag.to_string()),
+        Value::Table(reference) => match (reference.get("file"), reference.get("env")) {
+            (Some(Value::String(path)), None) => fs::read_to_string(path)
+                .map(|text| text.trim_end().to_string())
+                .with_context(|| format!("Failed to read {key} from: {path}")),
+            (None, Some(Value::String(name))) => {
+                env::var(name).with_context(|| format!("Failed to read {key} from ${name}"))
+            }
+            _ => anyhow::bail!(
+                "Expected {{ file = \"<path>\" }} or {{ env = \"<name>\" }} for {key}"
+            ),
+        },
+        _ => anyhow::bail!("Expected a string, a number or true or false for {key}"),
+    }
+}
+
+/// Returns the settings in effect for the arguments in `args`, as TOML, with where each one comes
+/// from. Secrets, which are the values the help doesn't show, are masked, so it can be shared.
+pub fn show(arg_matches: &ArgMatches, args: &[&Arg]) -> String {
+    let mut lines = Vec::new();
+    for arg in args {
+        let id = arg.get_id().as_str();
+        let Some(raw) = arg_matches.get_raw(id) else {
+            continue;
+        };
+        // Only what can go in the settings file
+        if arg.get_env().is_none() {
+            continue;
+        }
+
+        let raw = raw
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let value = if arg.is_hide_env_values_set() {
+            Value::String(String::from(MASK))
+        } else if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+            Value::Boolean(raw.first().is_some_and(|value| value == "true"))
+        } else if raw.len() > 1 || arg.get_value_delimiter().is_some() {
+            Value::Array(raw.into_iter().map(Value::String).collect())
+        } else {
+            Value::String(raw.join(""))
+        };
+        let source = match arg_matches.value_source(id) {
+            Some(ValueSource::CommandLine) => "argument",
+            Some(ValueSource::EnvVariable) => "environment",
+            _ => "default",
+        };
+        lines.push(format!("{id} = {value} # {source}"));
+    }
+
+    lines.join("\n")
+}
+
+/// Returns the settings in effect for the arguments in `args`, as assignments of their `CDU_*`
+/// variables for an environment file, like `CDU_ZONE_ID=abc`. The defaults are left out, so they
+/// follow cdu. Secrets are masked and commented out, unless `with_secrets`.
+pub fn export_env(arg_matches: &ArgMatches, args: &[&Arg], with_secrets: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    for arg in args {
+        let id = arg.get_id().as_str();
+        let Some(name) = arg.get_env().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let (Some(ValueSource::CommandLine | ValueSource::EnvVariable), Some(raw)) =
+            (arg_matches.value_source(id), arg_matches.get_raw(id))
+        else {
+            continue;
+        };
+
+        let delimiter = arg.get_value_delimiter().unwrap_or(',').to_string();
+        let value = raw
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join(&delimiter);
+        lines.push(assignment(
+            name,
+            &value,
+            arg.is_hide_env_values_set() && !with_secrets,
+        ));
+    }
+
+    lines
+}
+
+/// Returns the variables of the named notification targets in `vars`, like `CDU_NOTIFY_PHONE`, as
+/// assignments for an environment file, in order of their names. The targets are masked and
+/// commented out, unless `with_secrets`.
+pub fn export_targets(
+    vars: impl IntoIterator<Item = (String, String)>,
+    with_secrets: bool,
+) -> Vec<String> {
+    let cli = crate::args::cli();
+    let daemon_args = crate::args::daemon_args();
+    let arguments = cli
+        .get_arguments()
+        .chain(&daemon_args)
+        .filter_map(|arg| arg.get_env()?.to_str())
+        .collect::<Vec<_>>();
+
+    let mut targets = vars
+        .into_iter()
+        .filter(|(key, _)| key.starts_with("CDU_NOTIFY_") && !arguments.contains(&key.as_str()))
+        .collect::<Vec<_>>();
+    targets.sort();
+    targets
+        .iter()
+        .map(|(key, value)| {
+            let setting = ["_ENABLED", "_ON", "_SEVERITY"]
+                .iter()
+                .any(|suffix| key.ends_with(suffix));
+            assignment(key, value, !setting && !with_secrets)
+        })
+        .collect()
+}
+
+/// Returns the assignment of `value` to the variable `name`, or a masked one that's commented out.
+fn assignment(name: &str, value: &str, masked: bool) -> String {
+    if masked {
+        format!("# {name}={MASK} (a secret, included with --with-secrets)")
+    } else {
+        format!("{name}={}", quoted(value))
+    }
+}
+
+/// Quotes `value` if an environment file would read it as something else, in single quotes,
+/// which systemd and dotenv take as they are, or in double quotes if it has a single quote.
+fn quoted(value: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_.,:/@+=%".contains(c);
+    if !value.is_empty() && value.chars().all(plain) {
+        value.to_string()
+    } else if !value.contains('\'') {
+        format!("'{value}'")
+    } else {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$");
+        format!("\"{escaped}\"")
+    }
+}
+
+/// Sets `key` to `values` in the settings file at `path`, in the table of `profile` if there is
+/// one, keeping the rest of the file as it is. More than one value is a list, and a flag takes
+/// `true` or `false`.
+///
+/// # Errors
+///
+/// Returns an error if the key isn't a setting, a value isn't valid for it, or the file cannot be
+/// read, parsed or written.
+pub fn set(path: &Path, profile: Option<&str>, key: &str, values: &[String]) -> anyhow::Result<()> {
+    let arg = settable(key)?;
+    let value = if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+        let [flag] = values else {
+            anyhow::bail!("{key} takes true or false");
+        };
+        let flag = flag
+            .parse::<bool>()
+            .map_err(|_| anyhow::anyhow!("{key} takes true or false, not {flag}"))?;
+        toml_edit::value(flag)
+    } else {
+        let long = arg
+            .get_long()
+            .with_context(|| format!("{key} cannot be set in the settings file"))?;
+        // The value is checked the same way it will be when it's read
+        for value in values {
+            clap::Command::new("cdu")
+                .no_binary_name(true)
+                .arg(arg.clone().env(None::<&str>))
+                .try_get_matches_from([format!("--{long}={value}")])
+                .map_err(|e| {
+                    let e = e.to_string();
+                    let reason = e.lines().next().unwrap_or_default();
+                    anyhow::anyhow!("{}", reason.trim_start_matches("error: "))
+                })?;
+        }
+        match values {
+            [value] if arg.get_value_delimiter().is_none() => toml_edit::value(value.as_str()),
+            [] => anyhow::bail!("Missing the value for {key}"),
+            _ => {
+                anyhow::ensure!(
+                    arg.get_value_delimiter().is_some(),
+                    "{key} takes a single value"
+                );
+                toml_edit::value(values.iter().collect::<toml_edit::Array>())
+            }
+        }
+    };
+
+    let mut document = edit_document(path)?;
+    table_of(&mut document, profile)?[key] = value;
+
+    write_document(path, &document)
+}
+
+/// Removes `key` from the settings file at `path`, or from the table of `profile` if there is
+/// one. Returns whether it was there.
+///
+/// # Errors
+///
+/// Returns an error if the key isn't a setting, or the file cannot be read, parsed or written.
+pub fn unset(path: &Path, profile: Option<&str>, key: &str) -> anyhow::Result<bool> {
+    settable(key)?;
+    let mut document = edit_document(path)?;
+    let removed = table_of(&mut document, profile)?.remove(key).is_some();
+    if removed {
+        write_document(path, &document)?;
+    }
+
+    Ok(removed)
+}
+
+/// Returns the argument `key` sets, if it can be set in the settings file.
+fn settable(key: &str) -> anyhow::Result<Arg> {
+    crate::args::cli()
+        .get_arguments()
+        .chain(&crate::args::daemon_args())
+        .find(|arg| arg.get_id() == key && arg.get_env().is_some())
+        .cloned()
+        .with_context(|| format!("Unknown setting: {key}"))
+}
+
+fn edit_document(path: &Path) -> anyhow::Result<DocumentMut> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    anyhow::ensure!(
+        !matches!(
+            extension.map(str::to_ascii_lowercase).as_deref(),
+            Some("yaml" | "yml" | "json" | crypt::EXTENSION)
+        ),
+        "Only plain TOML settings can be changed, change {} by hand",
+        path.display()
+    );
+    // A file that doesn't exist yet starts out empty
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => anyhow::bail!("Failed to read settings from {}: {e}", path.display()),
+    };
+
+    text.parse::<DocumentMut>()
+        .map_err(|e| anyhow::anyhow!("Failed to parse settings from {}: {e}", path.display()))
+}
+
+/// Returns the table of `profile`, or the document itself, making the table if it's not there.
+fn table_of<'a>(
+    document: &'a mut DocumentMut,
+    profile: Option<&str>,
+) -> anyhow::Result<&'a mut toml_edit::Table> {
+    let Some(profile) = profile else {
+        return Ok(document.as_table_mut());
+    };
+    let profiles = document
+        .entry("profiles")
+        .or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        })
+        .as_table_mut()
+        .context("Expected a table of profiles")?;
+
+    profiles
+        .entry(profile)
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .with_context(|| format!("Expected a table for the profile {profile}"))
+}
+
+fn write_document(path: &Path, document: &DocumentMut) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+
+    fs::write(path, document.to_string())
+        .with_context(|| format!("Failed to write to file: {}", path.display()))
+}
+
+#[test]
+fn test_variables() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret = dir.path().join("api_key");
+    fs::write(&secret, "s3cret\n").unwrap();
+
+    let table = format!(
+        r#"
+        outside_ip = "192.0.2.1"
+        api_key = {{ file = {secret:?} }}
+        domain = ["home.example.com", "vpn.example.com"]
+        notify = {{ phone = {{ target = "ntfy:https://ntfy.sh/my-cdu", on = ["updated", "mismatch"] }} }}
+        dry_run = true
+        interval = "10m"
+        "#
+    )
+    .parse::<Table>()
+    .unwrap();
+    let cli = crate::args::cli();
+    let daemon_args = crate::args::daemon_args();
+    let args = cli.get_arguments().chain(&daemon_args).collect::<Vec<_>>();
+
+    let mut variables = variables(&table, &args).unwrap();
+    variables.sort();
+    let expected = [
+        ("CDU_API_KEY", "s3cret"),
+        ("CDU_DOMAIN", "home.example.com,vpn.example.com"),
+        ("CDU_DRY_RUN", "true"),
+        ("CDU_INTERVAL", "10m"),
+        ("CDU_NOTIFY_PHONE", "ntfy:https://ntfy.sh/my-cdu"),
+        ("CDU_NOTIFY_PHONE_ON", "updated,mismatch"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+    assert_eq!(variables, expected);
+
+    let unknown = "zone = \"abc\"".parse::<Table>().unwrap();
+    assert!(self::variables(&unknown, &args).is_err());
+    let not_a_list = "zone_id = [\"a\", \"b\"]".parse::<Table>().unwrap();
+    assert!(self::variables(&not_a_list, &args).is_err());
+}
+
+#[test]
+fn test_export_env() {
+    let cli = crate::args::cli();
+    let arg_matches = cli.clone().get_matches_from([
+        "cdu",
+        "--zone-id",
+        "abc",
+        "--api-key",
+        "s3cret",
+        "--domain",
+        "home.example.com,vpn.example.com",
+        "--hook-before",
+        "echo 'checking'",
+    ]);
+    let args = cli.get_arguments().collect::<Vec<_>>();
+
+    let lines = export_env(&arg_matches, &args, false);
+    assert!(lines.contains(&String::from("CDU_ZONE_ID=abc")));
+    assert!(lines.contains(&String::from("CDU_DOMAIN=home.example.com,vpn.example.com")));
+    assert!(lines.contains(&format!(
+        "# CDU_API_KEY={MASK} (a secret, included with --with-secrets)"
+    )));
+    assert!(lines.contains(&String::from(r#"CDU_HOOK_BEFORE="echo 'checking'""#)));
+    // The defaults follow cdu
+    assert!(!lines.iter().any(|line| line.starts_with("CDU_INTERVAL")));
+    let lines = export_env(&arg_matches, &args, true);
+    assert!(lines.contains(&String::from("CDU_API_KEY=s3cret")));
+
+    let vars = [
+        ("CDU_NOTIFY_PHONE_ON", "updated,mismatch"),
+        ("CDU_NOTIFY_PHONE", "ntfy:https://ntfy.sh/my-cdu"),
+        ("CDU_NOTIFY_ON", "updated"),
+        ("HOME", "/root"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+    assert_eq!(
+        export_targets(vars, false),
+        [
+            format!("# CDU_NOTIFY_PHONE={MASK} (a secret, included with --with-secrets)"),
+            String::from("CDU_NOTIFY_PHONE_ON=updated,mismatch"),
+        ]
+    );
+
+    assert_eq!(quoted("a b"), "'a b'");
+    assert_eq!(quoted(""), "''");
+    assert_eq!(quoted("it's $5"), r#""it's \$5""#);
+}
+
+#[test]
+fn test_domains_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hosts.txt");
+    fs::write(
+        &path,
+        "# Managed by Ansible\nexample.com\n\n  vpn.example.com  # the office\n",
+    )
+    .unwrap();
+    assert_eq!(
+        domains_file(&path).unwrap(),
+        ["example.com", "vpn.example.com"]
+    );
+
+    fs::write(&path, "example.com www.example.com\n").unwrap();
+    assert!(domains_file(&path).is_err());
+    assert!(domains_file(&dir.path().join("missing.txt")).is_err());
+}
+
+#[test]
+fn test_domains() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cdu.toml");
+    fs::write(
+        &path,
+        r#"
+        domain = ["example.com"]
+
+        [[domains]]
+        name = "www.example.com"
+        proxied = true
+
+        [[domains]]
+        name = "vpn.example.net"
+        zone_id = "other"
+        type = "A"
+        ttl = 60
+        notify_on = ["updated", "mismatch"]
+        notify = ["parents"]
+        notify_severity = "notice"
+        ptr = true
+        wan = "backup"
+
+        [[domains]]
+        name = "v6.example.net"
+        type = "AAAA"
+        server = "https://api6.ipify.org"
+        "#,
+    )
+    .unwrap();
+
+    let domains = domains(&path, None).unwrap();
+    assert_eq!(domains.len(), 3);
+    assert_eq!(domains[0].proxied, Some(true));
+    assert_eq!(
+        domains[1],
+        DomainSettings {
+            name: String::from("vpn.example.net"),
+            zone_id: Some(String::from("other")),
+            proxied: None,
+            ttl: Some(60),
+            notify_on: Some(vec![EventKind::Updated, EventKind::Mismatch]),
+            notify: Some(vec![String::from("parents")]),
+            notify_severity: Some(Severity::Notice),
+            ptr: true,
+            wan: Some(String::from("backup")),
+            family: Some(Family::V4),
+            server: None,
+        }
+    );
+    assert_eq!(domains[2].family, Some(Family::V6));
+    assert_eq!(domains[2].server.as_deref(), Some("https://api6.ipify.org"));
+    let cli = crate::args::cli();
+    let args = cli.get_arguments().collect::<Vec<_>>();
+    assert_eq!(
+        variables(&read_table(&path, None).unwrap(), &args).unwrap(),
+        [(String::from("CDU_DOMAIN"), String::from("example.com"))]
+    );
+
+    fs::write(
+        &path,
+        r#"
+        zone_id = "home"
+        domain = ["home.example.com"]
+        [[domains]]
+        name = "example.com"
+        type = "MX"
+
+        [profiles.office]
+        zone_id = "office"
+        domain = ["office.example.com"]
+        domains = []
+        "#,
+    )
+    .unwrap();
+    assert!(self::domains(&path, None).is_err());
+    assert!(self::domains(&path, Some("office")).unwrap().is_empty());
+    assert!(self::domains(&path, Some("garage")).is_err());
+    let mut office = variables(&read_table(&path, Some("office")).unwrap(), &args).unwrap();
+    office.sort();
+    assert_eq!(
+        office,
+        [
+            (
+                String::from("CDU_DOMAIN"),
+                String::from("office.example.com")
+            ),
+            (String::from("CDU_ZONE_ID"), String::from("office")),
+        ]
+    );
+}
+
+#[test]
+fn test_expand() {
+    env::set_var("CDU_TEST_EXPAND_TOPIC", "my-cdu");
+
+    assert_eq!(
+        expand("https://ntfy.sh/${CDU_TEST_EXPAND_TOPIC}?cost=$$5&tip=$").unwrap(),
+        "https://ntfy.sh/my-cdu?cost=$5&tip=$"
+    );
+    assert!(expand("${CDU_TEST_EXPAND_UNSET}").is_err());
+    assert!(expand("${CDU_TEST_EXPAND_TOPIC").is_err());
+}
+
+#[test]
+fn test_include() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cdu.toml");
+    fs::create_dir(dir.path().join("domains.d")).unwrap();
+    fs::write(
+        &path,
+        "include = [\"domains.d/*.toml\"]\nzone_id = \"main\"\ndomain = [\"example.com\"]\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("domains.d/b.toml"),
+        "zone_id = \"b\"\n[[domains]]\nname = \"b.example.com\"\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("domains.d/a.toml"),
+        "domain = [\"a.example.com\"]\n[[domains]]\nname = \"a.example.com\"\nttl = 60\n",
+    )
+    .unwrap();
+
+    let table = read_table(&path, None).unwrap();
+    assert_eq!(table["zone_id"].as_str(), Some("main"));
+    assert_eq!(
+        table["domain"],
+        Value::Array(vec!["example.com".into(), "a.example.com".into()])
+    );
+    let names = domains(&path, None)
+        .unwrap()
+        .into_iter()
+        .map(|domain| domain.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a.example.com", "b.example.com"]);
+
+    fs::write(
+        dir.path().join("domains.d/c.toml"),
+        "include = \"*.toml\"\n",
+    )
+    .unwrap();
+    assert!(read_table(&path, None).is_err());
+}
+
+#[test]
+fn test_parse() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = r#"
+        zone_id = "abc"
+        domain = ["example.com", "www.example.com"]
+        [notify.phone]
+        target = "ntfy:https://ntfy.sh/my-cdu"
+        [[domains]]
+        name = "vpn.example.com"
+        ttl = 60
+        "#
+    .parse::<Table>()
+    .unwrap();
+
+    let yaml = dir.path().join("cdu.yaml");
+    fs::write(
+        &yaml,
+        "zone_id: abc\ndomain: [example.com, www.example.com]\nnotify:\n  phone:\n    target: \
+         ntfy:https://ntfy.sh/my-cdu\ndomains:\n  - name: vpn.example.com\n    ttl: 60\n",
+    )
+    .unwrap();
+    assert_eq!(parse(&yaml).unwrap(), expected);
+
+    let json = dir.path().join("cdu.json");
+    fs::write(
+        &json,
+        r#"{"zone_id": "abc", "domain": ["example.com", "www.example.com"],
+            "notify": {"phone": {"target": "ntfy:https://ntfy.sh/my-cdu"}},
+            "domains": [{"name": "vpn.example.com", "ttl": 60}]}"#,
+    )
+    .unwrap();
+    assert_eq!(parse(&json).unwrap(), expected);
+
+    fs::write(&json, "zone_id = \"abc\"").unwrap();
+    assert!(parse(&json).is_err());
+    let toml = dir.path().join("cdu.toml");
+    fs::write(&toml, "version = 1\nzone_id = \"abc\"").unwrap();
+    assert_eq!(parse(&toml).unwrap().len(), 1);
+    fs::write(&toml, "version = 2\nzone_id = \"abc\"").unwrap();
+    assert!(parse(&toml).is_err());
+}
+
+#[test]
+fn test_set() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cdu.toml");
+    fs::write(&path, "# Mine\nzone_id = \"abc\" # the home zone\n").unwrap();
+    let values = |values: &[&str]| values.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+    set(
+        &path,
+        None,
+        "domain",
+        &values(&["example.com", "www.example.com"]),
+    )
+    .unwrap();
+    set(&path, None, "dry_run", &values(&["true"])).unwrap();
+    set(&path, Some("office"), "zone_id", &values(&["def"])).unwrap();
+    assert!(set(&path, None, "parallelism", &values(&["many"])).is_err());
+    assert!(set(&path, None, "zone", &values(&["abc"])).is_err());
+    assert!(set(&path, None, "zone_id", &values(&["a", "b"])).is_err());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "# Mine\nzone_id = \"abc\" # the home zone\ndomain = [\"example.com\", \"www.example.com\"]\n\
+         dry_run = true\n\n[profiles.office]\nzone_id = \"def\"\n"
+    );
+
+    assert!(unset(&path, None, "dry_run").unwrap());
+    assert!(!unset(&path, None, "dry_run").unwrap());
+    let table = read_table(&path, Some("office")).unwrap();
+    assert_eq!(table["zone_id"].as_str(), Some("def"));
+    assert!(!table.contains_key("dry_run"));
+}
@@ -0,0 +1,40 @@
+//! Turns Unix signals into daemon events.
+use std::io;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+
+use crate::daemon::Event;
+
+/// Handles `SIGTERM`, `SIGINT`, `SIGHUP` and `SIGUSR1`, by sending the matching event to the
+/// daemon. This replaces the default behaviour of these signals, so the daemon decides when to
+/// stop.
+///
+/// # Errors
+///
+/// Returns an error if the signal handlers cannot be registered.
+pub fn spawn(sender: UnboundedSender<Event>) -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut user_defined1 = signal(SignalKind::user_defined1())?;
+
+    tokio::spawn(async move {
+        loop {
+            let (name, event) = tokio::select! {
+                _ = terminate.recv() => ("SIGTERM", Event::Shutdown),
+                _ = interrupt.recv() => ("SIGINT", Event::Shutdown),
+                _ = hangup.recv() => ("SIGHUP", Event::Reload),
+                _ = user_defined1.recv() => ("SIGUSR1", Event::ForceCheck),
+            };
+            debug!("Received signal {name}");
+
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! Stands in for Cloudflare and for the servers that tell the outside IP, for `--simulate`, so a
+//! run can go through everything it does, from the settings to the state and the notifications,
+//! without a single request leaving the machine.
+//!
+//! It answers on a port of the loopback interface, in a thread of its own:
+//!
+//! - `GET /ip` with the outside IP it was given.
+//! - `GET /zones/<zone>/dns_records?name=<domain>` with the A record of the domain, which is made
+//!   up the first time it's asked for, pointing at [`RECORDED_IP`].
+//! - `PUT` and `PATCH /zones/<zone>/dns_records/<ID>` by changing it, in memory only.
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use axum::extract::{Path, RawQuery, State};
+use axum::http::StatusCode;
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use tracing::{debug, error, info};
+
+/// What an A record points at before it's changed.
+pub const RECORDED_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+/// The error code of the API for a record that isn't there.
+const RECORD_NOT_FOUND: u64 = 81044;
+
+/// A fake Cloudflare and server of the outside IP, that's running.
+#[derive(Debug)]
+pub struct Simulation {
+    address: SocketAddr,
+    outside_ip: Ipv4Addr,
+}
+
+/// The A records, by name.
+type Records = Arc<Mutex<BTreeMap<String, Value>>>;
+
+#[derive(Clone)]
+struct Simulated {
+    outside_ip: Ipv4Addr,
+    records: Records,
+}
+
+impl Simulation {
+    /// Starts answering on a free port of the loopback interface, telling `outside_ip` as the
+    /// outside IP, until the program exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no port can be listened on.
+    pub fn start(outside_ip: Ipv4Addr) -> anyhow::Result<Self> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .context("Failed to listen on the loopback interface")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let app = router(Simulated {
+            outside_ip,
+            records: Records::default(),
+        });
+
+        std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let served = match tokio::net::TcpListener::from_std(listener) {
+                    Ok(listener) => axum::serve(listener, app).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = served {
+                    error!("Stopped simulating: {e}");
+                }
+            });
+        });
+
+        debug!("Simulating Cloudflare on {address}");
+
+        Ok(Self {
+            address,
+            outside_ip,
+        })
+    }
+
+    /// Returns where the API is, for [`crate::config::Config::api_url`].
+    #[must_use]
+    pub fn api_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Returns the server that tells the outside IP.
+    #[must_use]
+    pub fn detection_url(&self) -> String {
+        format!("http://{}/ip", self.address)
+    }
+
+    /// Returns the outside IP that's told.
+    #[must_use]
+    pub fn outside_ip(&self) -> Ipv4Addr {
+        self.outside_ip
+    }
+}
+
+fn router(simulated: Simulated) -> Router {
+    Router::new()
+        .route("/ip", get(outside_ip))
+        .route("/zones/:zone/dns_records", get(list))
+        .route("/zones/:zone/dns_records/:id", put(write).patch(write))
+        .with_state(simulated)
+}
+
+async fn outside_ip(State(simulated): State<Simulated>) -> String {
+    simulated.outside_ip.to_string()
+}
+
+/// Returns the A record of the domain in the query, made up if it wasn't asked for before, or
+/// every one without a domain.
+async fn list(State(simulated): State<Simulated>, RawQuery(query): RawQuery) -> Json<Value> {
+    let name = query
+        .as_deref()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| {
+            let value = pair.strip_prefix("name=")?;
+            Some(percent_decode_str(value).decode_utf8_lossy().into_owned())
+        });
+    let mut records = simulated.records.lock().unwrap();
+    let result = match name {
+        Some(name) => {
+            let id = format!("{:032x}", records.len() + 1);
+            let record = records.entry(name.clone()).or_insert_with(|| {
+                json!({
+                    "id": id,
+                    "type": "A",
+                    "name": name,
+                    "content": RECORDED_IP.to_string(),
+                    "proxied": false,
+                    "ttl": 1,
+                })
+            });
+            vec![record.clone()]
+        }
+        None => records.values().cloned().collect(),
+    };
+
+    Json(json!({ "success": true, "errors": [], "result": result }))
+}
+
+/// Changes the A record with the ID to what's in the body.
+async fn write(
+    State(simulated): State<Simulated>,
+    Path((_, id)): Path<(String, String)>,
+    Json(body): Json<Value>,
+) -> (StatusCode, Json<Value>) {
+    let mut records = simulated.records.lock().unwrap();
+    let Some(record) = records
+        .values_mut()
+        .find(|record| record["id"] == id.as_str())
+    else {
+        return not_found();
+    };
+    for field in ["content", "proxied", "ttl"] {
+        if !body[field].is_null() {
+            record[field] = body[field].clone();
+        }
+    }
+    info!(
+        "Simulated {} now points at {}",
+        record["name"].as_str().unwrap_or_default(),
+        record["content"].as_str().unwrap_or_default()
+    );
+
+    (
+        StatusCode::OK,
+        Json(json!({ "success": true, "errors": [], "result": record })),
+    )
+}
+
+fn not_found() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "success": false,
+            "errors": [{ "code": RECORD_NOT_FOUND, "message": "Record does not exist." }],
+        })),
+    )
+}
+
+#[tokio::test]
+async fn test_simulation() {
+    use crate::cloudflare::{CloudflareError, Handler};
+    use crate::network;
+
+    let simulation = Simulation::start(Ipv4Addr::new(203, 0, 113, 7)).unwrap();
+    let client = network::client(network::DEFAULT_TIMEOUT);
+    let ip = network::ask(
+        &client,
+        &simulation.detection_url(),
+        network::DEFAULT_TIMEOUT,
+    )
+    .await
+    .unwrap();
+    assert_eq!(ip, simulation.outside_ip());
+
+    let handler = Handler::try_new("token")
+        .unwrap()
+        .with_api_url(&simulation.api_url());
+    let mut record = handler
+        .get_a_record("zone", "home.example.com")
+        .await
+        .unwrap();
+    assert_eq!(record.ip, RECORDED_IP);
+
+    record.ip = ip.into();
+    let (updated, _) = handler
+        .patch_a_record("zone", &record, "home.example.com")
+        .await
+        .unwrap();
+    assert_eq!(updated.ip, ip);
+    let found = handler
+        .get_a_record("zone", "home.example.com")
+        .await
+        .unwrap();
+    assert_eq!(found, updated);
+
+    record.id = String::from("deleted");
+    let result = handler
+        .patch_a_record("zone", &record, "home.example.com")
+        .await;
+    assert!(
+        matches!(result, Err(CloudflareError::RecordNotFound(_))),
+        "Expected the record to be gone, got {result:?}"
+    );
+}
@@ -0,0 +1,115 @@
+//! Sends messages to a Slack incoming webhook, formatted with Block Kit.
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::Client as RqClient;
+use serde_json::{json, Value};
+
+use crate::notify::{Message, Notifier};
+use crate::retry::StatusError;
+
+/// A Slack incoming webhook.
+#[derive(Debug)]
+pub struct Slack {
+    client: RqClient,
+    url: String,
+}
+
+impl Slack {
+    #[must_use]
+    pub fn new(client: RqClient, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Slack {
+    fn name(&self) -> &'static str {
+        "Slack"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&payload(message))
+            .send()
+            .await
+            .context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the Block Kit message, with the text as a fallback for notifications.
+fn payload(message: &Message) -> Value {
+    let ip = |ip: Option<std::net::IpAddr>| {
+        ip.map_or_else(|| String::from("unknown"), |ip| format!("`{ip}`"))
+    };
+    // Slack shows the time in the time zone of whoever reads it
+    let at = format!(
+        "<!date^{}^{{date_short_pretty}} {{time_secs}}|{}>",
+        message.at.timestamp(),
+        message.at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+
+    let mut payload = json!({
+        "text": message.to_string(),
+        "blocks": [
+            {
+                "type": "header",
+                "text": { "type": "plain_text", "text": message.title() },
+            },
+            {
+                "type": "section",
+                "fields": [
+                    { "type": "mrkdwn", "text": format!("*Domain*\n{}", message.domain) },
+                    {
+                        "type": "mrkdwn",
+                        "text": format!("*IP*\n{} → {}", ip(message.old_ip), ip(message.new_ip)),
+                    },
+                    { "type": "mrkdwn", "text": format!("*When*\n{at}") },
+                ],
+            },
+        ],
+    });
+    if let (Some(error), Some(blocks)) = (&message.error, payload["blocks"].as_array_mut()) {
+        blocks.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": format!("*Error*\n{error}") },
+        }));
+    }
+
+    payload
+}
+
+#[test]
+fn test_payload() {
+    use std::net::IpAddr;
+
+    let message = Message::updated(
+        "example.com",
+        Some(IpAddr::from([192, 0, 2, 1])),
+        IpAddr::from([192, 0, 2, 2]),
+    );
+    let payload = payload(&message);
+
+    assert_eq!(payload["text"], message.to_string());
+    assert_eq!(payload["blocks"][0]["text"]["text"], "A record updated");
+    let fields = &payload["blocks"][1]["fields"];
+    assert_eq!(fields[0]["text"], "*Domain*\nexample.com");
+    assert_eq!(fields[1]["text"], "*IP*\n`192.0.2.1` → `192.0.2.2`");
+    assert!(fields[2]["text"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("*When*\n<!date^{}^", message.at.timestamp())));
+}
@@ -0,0 +1,232 @@
+//! Keeps the state in an SQLite database, `cdu.state.db`, instead of `cdu.state.toml`, with
+//! `--state-store sqlite`. It's only there when cdu is built with the `sqlite` feature.
+//!
+//! The values of the state are kept in `state`, by key, what's known about the A record of every
+//! domain in `records`, a row per domain, and the changes of the outside IP in `history`, so they
+//! can be queried. They're read into and written from the same table the TOML file would have, so
+//! the state goes through the same upgrades either way. Every write replaces the whole state in
+//! one transaction.
+use std::path::Path;
+
+use anyhow::Context;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use toml::{Table, Value};
+
+/// The arrays of tables of the state that are kept in tables of their own, with their columns,
+/// which are the keys of every table in the array.
+const TABLES: [(&str, &[&str]); 2] = [
+    (
+        "records",
+        &["domain", "record_id", "ip", "checked_at", "last_updated"],
+    ),
+    ("history", &["at", "old_ip", "new_ip", "source", "updated"]),
+];
+/// The columns with a list of strings, which are kept separated by spaces.
+const LISTS: [&str; 1] = ["updated"];
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS state (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS records (
+        domain TEXT PRIMARY KEY,
+        record_id TEXT,
+        ip TEXT,
+        checked_at TEXT,
+        last_updated TEXT
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        at TEXT NOT NULL,
+        old_ip TEXT,
+        new_ip TEXT,
+        source TEXT,
+        updated TEXT
+    );
+";
+
+/// Opens the database, creating it so only its owner can read it if it doesn't exist yet.
+fn open(path: &Path) -> anyhow::Result<Connection> {
+    let existed = path.exists();
+    let connection = Connection::open(path)
+        .with_context(|| format!("Failed to open database: {}", path.display()))?;
+    #[cfg(unix)]
+    if !existed {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to set permissions of: {}", path.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = existed;
+    connection
+        .execute_batch(SCHEMA)
+        .with_context(|| format!("Failed to create the tables in: {}", path.display()))?;
+
+    Ok(connection)
+}
+
+/// Reads the state from the database, as the table the TOML file would have.
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be opened or read, or a value isn't valid TOML.
+pub fn read(path: &Path) -> anyhow::Result<Table> {
+    let connection = open(path)?;
+    let mut table = Table::new();
+
+    let mut statement = connection.prepare("SELECT key, value FROM state ORDER BY key")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (key, value) = row?;
+        table.insert(
+            key.clone(),
+            parse_value(&value).with_context(|| format!("Invalid value of {key} in the state"))?,
+        );
+    }
+
+    for (name, columns) in TABLES {
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM {name} ORDER BY rowid",
+            columns.join(", ")
+        ))?;
+        let rows = statement.query_map([], |row| {
+            let mut entry = Table::new();
+            for (index, column) in columns.iter().enumerate() {
+                let Some(value) = row.get::<_, Option<String>>(index)? else {
+                    continue;
+                };
+                let value = if LISTS.contains(column) {
+                    Value::Array(
+                        value
+                            .split_whitespace()
+                            .map(|item| Value::String(item.to_string()))
+                            .collect(),
+                    )
+                } else {
+                    Value::String(value)
+                };
+                entry.insert((*column).to_string(), value);
+            }
+            Ok(Value::Table(entry))
+        })?;
+        let entries = rows.collect::<Result<Vec<_>, _>>()?;
+        if !entries.is_empty() {
+            table.insert(name.to_string(), Value::Array(entries));
+        }
+    }
+
+    Ok(table)
+}
+
+/// Replaces the state in the database with `table`, in one transaction.
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be opened or written to.
+pub fn write(path: &Path, table: &Table) -> anyhow::Result<()> {
+    let mut connection = open(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute("DELETE FROM state", [])?;
+    for (name, _) in TABLES {
+        transaction.execute(&format!("DELETE FROM {name}"), [])?;
+    }
+
+    for (key, value) in table {
+        if TABLES.iter().any(|(name, _)| name == key) {
+            continue;
+        }
+        transaction.execute(
+            "INSERT INTO state (key, value) VALUES (?1, ?2)",
+            params![key, value.to_string()],
+        )?;
+    }
+    for (name, columns) in TABLES {
+        let insert = format!(
+            "INSERT INTO {name} ({}) VALUES ({})",
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+        for entry in table
+            .get(name)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_table)
+        {
+            let values = columns.iter().map(|column| match entry.get(*column) {
+                Some(Value::Array(items)) => Some(
+                    items
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                Some(value) => value.as_str().map(String::from),
+                None => None,
+            });
+            transaction.execute(&insert, params_from_iter(values))?;
+        }
+    }
+
+    transaction
+        .commit()
+        .with_context(|| format!("Failed to write to database: {}", path.display()))
+}
+
+/// Returns whether there's a state in the database at `path` yet.
+///
+/// # Errors
+///
+/// Returns an error if the database exists but cannot be read.
+pub fn has_state(path: &Path) -> anyhow::Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    Ok(open(path)?
+        .query_row("SELECT 1 FROM state LIMIT 1", [], |_| Ok(()))
+        .optional()?
+        .is_some())
+}
+
+/// Parses a value as it's written in TOML, which is how it's kept in `state`.
+fn parse_value(text: &str) -> anyhow::Result<Value> {
+    let mut table = format!("value = {text}").parse::<Table>()?;
+    table.remove("value").context("The value is missing")
+}
+
+#[test]
+fn test_read_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cdu.state.db");
+    let table = r#"
+        version = 2
+        outside_ip = "192.0.2.1"
+        last_updated = "2024-03-10T13:54:04.032435Z"
+
+        [[records]]
+        domain = "example.com"
+        record_id = "abc"
+        ip = "192.0.2.1"
+
+        [[records]]
+        domain = "www.example.com"
+
+        [[history]]
+        at = "2024-03-10T13:54:04.032435Z"
+        old_ip = "192.0.2.2"
+        new_ip = "192.0.2.1"
+        source = "api.ipify.org"
+        updated = ["example.com", "www.example.com"]
+    "#
+    .parse::<Table>()
+    .unwrap();
+
+    assert!(!has_state(&path).unwrap());
+    write(&path, &table).unwrap();
+    assert!(has_state(&path).unwrap());
+    assert_eq!(read(&path).unwrap(), table);
+}
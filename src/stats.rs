@@ -0,0 +1,496 @@
+//! Tells how the outside IP has behaved, from the history of its changes, for `cdu stats`: how
+//! often it changes, how long an address lasts, which servers that tell the outside IP can be
+//! trusted, and how soon the A records follow a change.
+//!
+//! A server is counted as wrong about a change when the outside IP went back to what it was within
+//! an hour, as an address rarely comes back that soon unless it never left. How soon the A records
+//! follow is read from the audit log, from when the change was seen to when the last of its A
+//! records was updated. Weeks and months are in UTC.
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Months, Utc};
+use serde_json::json;
+
+use crate::audit::Entry;
+use crate::config::IpChange;
+
+/// A change that's undone within this long is counted against the server that told it.
+const REVERTED_WITHIN: Duration = Duration::from_secs(60 * 60);
+
+/// How many of the most recent weeks and months are told about.
+const WEEKS: i64 = 8;
+const MONTHS: u32 = 12;
+
+/// How many changes there were in a week or month.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Period {
+    /// Like `2024-W05` for a week, or `2024-05` for a month.
+    pub name: String,
+    pub changes: usize,
+}
+
+/// The longest time the outside IP stayed the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stable {
+    pub from: DateTime<Utc>,
+    /// When it changed again, or `None` if it hasn't yet.
+    pub until: Option<DateTime<Utc>>,
+    pub duration: Duration,
+}
+
+/// How a server that tells the outside IP did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    /// The changes it told about.
+    pub changes: usize,
+    /// Those that were undone within an hour.
+    pub reverted: usize,
+}
+
+impl Source {
+    /// Returns the share of its changes that weren't undone, in percent.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn reliability(&self) -> f64 {
+        (self.changes - self.reverted) as f64 * 100.0 / self.changes as f64
+    }
+}
+
+/// How soon the A records were updated after a change was seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub median: Duration,
+    pub max: Duration,
+    /// How many changes it was measured for.
+    pub changes: usize,
+}
+
+/// Everything that's told about the history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub changes: usize,
+    pub since: Option<DateTime<Utc>>,
+    pub weeks: Vec<Period>,
+    pub months: Vec<Period>,
+    /// The changes per week and per month, since the first one.
+    pub per_week: f64,
+    pub per_month: f64,
+    /// How long an address lasted on average, until it was replaced.
+    pub average_lifetime: Option<Duration>,
+    pub longest_stable: Option<Stable>,
+    pub sources: Vec<Source>,
+    pub latency: Option<Latency>,
+}
+
+impl Stats {
+    /// Works out the stats of `history`, which is in order, with the A records that were updated as
+    /// `audit` says, as of `now`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(history: &[IpChange], audit: &[Entry], now: DateTime<Utc>) -> Self {
+        let since = history.first().map(|change| change.at);
+        let span = since.map_or(Duration::ZERO, |since| duration(since, now));
+        let weeks_spanned = (span.as_secs_f64() / (7.0 * 24.0 * 3600.0)).max(1.0);
+        let months_spanned = (span.as_secs_f64() / (30.44 * 24.0 * 3600.0)).max(1.0);
+
+        let lifetimes = history
+            .windows(2)
+            .map(|pair| (pair[0].at, pair[1].at))
+            .collect::<Vec<_>>();
+        let average_lifetime = (!lifetimes.is_empty()).then(|| {
+            let total = lifetimes
+                .iter()
+                .map(|&(from, until)| duration(from, until))
+                .sum::<Duration>();
+            total / u32::try_from(lifetimes.len()).unwrap_or(u32::MAX)
+        });
+        let ongoing = history.last().map(|change| (change.at, None));
+        let longest_stable = lifetimes
+            .iter()
+            .map(|&(from, until)| (from, Some(until)))
+            .chain(ongoing)
+            .map(|(from, until)| Stable {
+                from,
+                until,
+                duration: duration(from, until.unwrap_or(now)),
+            })
+            .max_by_key(|stable| stable.duration);
+
+        Self {
+            changes: history.len(),
+            since,
+            weeks: weeks(history, now),
+            months: months(history, now),
+            per_week: history.len() as f64 / weeks_spanned,
+            per_month: history.len() as f64 / months_spanned,
+            average_lifetime,
+            longest_stable,
+            sources: sources(history),
+            latency: latency(history, audit),
+        }
+    }
+
+    /// Returns the stats as JSON, with the durations in seconds.
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        let periods = |periods: &[Period]| {
+            periods
+                .iter()
+                .map(|period| json!({ "name": period.name, "changes": period.changes }))
+                .collect::<Vec<_>>()
+        };
+
+        json!({
+            "changes": self.changes,
+            "since": self.since,
+            "weeks": periods(&self.weeks),
+            "months": periods(&self.months),
+            "per_week": self.per_week,
+            "per_month": self.per_month,
+            "average_lifetime_secs": self.average_lifetime.map(|lifetime| lifetime.as_secs()),
+            "longest_stable": self.longest_stable.map(|stable| json!({
+                "from": stable.from,
+                "until": stable.until,
+                "duration_secs": stable.duration.as_secs(),
+            })),
+            "sources": self
+                .sources
+                .iter()
+                .map(|source| json!({
+                    "name": source.name,
+                    "changes": source.changes,
+                    "reverted": source.reverted,
+                    "reliability": source.reliability(),
+                }))
+                .collect::<Vec<_>>(),
+            "latency": self.latency.map(|latency| json!({
+                "median_secs": latency.median.as_secs_f64(),
+                "max_secs": latency.max.as_secs_f64(),
+                "changes": latency.changes,
+            })),
+        })
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(since) = self.since else {
+            return writeln!(f, "The outside IP hasn't changed yet");
+        };
+
+        writeln!(
+            f,
+            "{} changes of the outside IP since {}",
+            self.changes,
+            since.format("%Y-%m-%d")
+        )?;
+        writeln!(f)?;
+        writeln!(f, "Per week, {:.1} on average:", self.per_week)?;
+        for week in &self.weeks {
+            writeln!(f, "  {}  {}", week.name, week.changes)?;
+        }
+        writeln!(f, "Per month, {:.1} on average:", self.per_month)?;
+        for month in &self.months {
+            writeln!(f, "  {}  {}", month.name, month.changes)?;
+        }
+        writeln!(f)?;
+
+        match self.average_lifetime {
+            Some(lifetime) => writeln!(f, "Average lifetime of an address: {}", rounded(lifetime))?,
+            None => writeln!(
+                f,
+                "Average lifetime of an address: not known until it changes again"
+            )?,
+        }
+        if let Some(stable) = &self.longest_stable {
+            let until = match stable.until {
+                Some(until) => format!("to {}", until.format("%Y-%m-%d")),
+                None => String::from("and counting"),
+            };
+            writeln!(
+                f,
+                "Longest stable period: {}, from {} {until}",
+                rounded(stable.duration),
+                stable.from.format("%Y-%m-%d")
+            )?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "Servers that told the changes:")?;
+        for source in &self.sources {
+            writeln!(
+                f,
+                "  {}  {} changes, {} undone within an hour ({:.0}% reliable)",
+                source.name,
+                source.changes,
+                source.reverted,
+                source.reliability()
+            )?;
+        }
+        match &self.latency {
+            Some(latency) => writeln!(
+                f,
+                "Update latency: {} median, {} at most, over {} changes",
+                humantime::format_duration(whole_millis(latency.median)),
+                humantime::format_duration(whole_millis(latency.max)),
+                latency.changes
+            ),
+            None => writeln!(
+                f,
+                "Update latency: not known, as the audit log has no updates"
+            ),
+        }
+    }
+}
+
+/// Returns the changes in each of the most recent weeks, since the week of the first change.
+fn weeks(history: &[IpChange], now: DateTime<Utc>) -> Vec<Period> {
+    let name = |at: DateTime<Utc>| {
+        let week = at.iso_week();
+        format!("{}-W{:02}", week.year(), week.week())
+    };
+    let Some(first) = history.first().map(|change| name(change.at)) else {
+        return Vec::new();
+    };
+
+    let mut weeks = Vec::new();
+    for back in 0..WEEKS {
+        let week = name(now - chrono::Duration::weeks(back));
+        let changes = history
+            .iter()
+            .filter(|change| name(change.at) == week)
+            .count();
+        let reached_first = week == first;
+        weeks.push(Period {
+            name: week,
+            changes,
+        });
+        if reached_first {
+            break;
+        }
+    }
+    weeks.reverse();
+
+    weeks
+}
+
+/// Returns the changes in each of the most recent months, since the month of the first change.
+fn months(history: &[IpChange], now: DateTime<Utc>) -> Vec<Period> {
+    let name = |at: DateTime<Utc>| at.format("%Y-%m").to_string();
+    let Some(first) = history.first().map(|change| name(change.at)) else {
+        return Vec::new();
+    };
+
+    let mut months = Vec::new();
+    let start_of_month = now.date_naive().with_day(1).unwrap_or(now.date_naive());
+    for back in 0..MONTHS {
+        let Some(month) = start_of_month.checked_sub_months(Months::new(back)) else {
+            break;
+        };
+        let month = month.format("%Y-%m").to_string();
+        let changes = history
+            .iter()
+            .filter(|change| name(change.at) == month)
+            .count();
+        let reached_first = month == first;
+        months.push(Period {
+            name: month,
+            changes,
+        });
+        if reached_first {
+            break;
+        }
+    }
+    months.reverse();
+
+    months
+}
+
+/// Returns how each server did, in order of the changes it told about, the most first.
+fn sources(history: &[IpChange]) -> Vec<Source> {
+    let mut sources = Vec::<Source>::new();
+    for (index, change) in history.iter().enumerate() {
+        let reverted = history.get(index + 1).is_some_and(|next| {
+            next.new_ip == change.old_ip && duration(change.at, next.at) <= REVERTED_WITHIN
+        });
+
+        let position = if let Some(position) = sources
+            .iter()
+            .position(|source| source.name == change.source)
+        {
+            position
+        } else {
+            sources.push(Source {
+                name: change.source.clone(),
+                changes: 0,
+                reverted: 0,
+            });
+            sources.len() - 1
+        };
+        sources[position].changes += 1;
+        sources[position].reverted += usize::from(reverted);
+    }
+    sources.sort_by(|a, b| b.changes.cmp(&a.changes).then_with(|| a.name.cmp(&b.name)));
+
+    sources
+}
+
+/// Returns how soon the A records were updated after the changes were seen, for the changes with
+/// updates in the audit log. An update counts for a change if it was to its IP, within an hour.
+fn latency(history: &[IpChange], audit: &[Entry]) -> Option<Latency> {
+    let mut latencies = history
+        .iter()
+        .filter_map(|change| {
+            audit
+                .iter()
+                .filter(|entry| entry.error.is_none() && change.updated.contains(&entry.domain))
+                .filter(|entry| {
+                    entry
+                        .new
+                        .as_ref()
+                        .is_some_and(|new| new.ip == change.new_ip)
+                })
+                .filter(|entry| {
+                    entry.at >= change.at && duration(change.at, entry.at) <= REVERTED_WITHIN
+                })
+                .map(|entry| duration(change.at, entry.at))
+                .max()
+        })
+        .collect::<Vec<_>>();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort_unstable();
+
+    Some(Latency {
+        median: latencies[latencies.len() / 2],
+        max: latencies[latencies.len() - 1],
+        changes: latencies.len(),
+    })
+}
+
+/// Returns the time from `from` to `until`, which is none if `until` is before it.
+fn duration(from: DateTime<Utc>, until: DateTime<Utc>) -> Duration {
+    (until - from).to_std().unwrap_or_default()
+}
+
+/// Returns `duration` to the minute, or to the second if it's shorter, for telling it.
+fn rounded(duration: Duration) -> humantime::FormattedDuration {
+    let secs = duration.as_secs();
+    let secs = if secs >= 60 { secs - secs % 60 } else { secs };
+    humantime::format_duration(Duration::from_secs(secs))
+}
+
+/// Returns `duration` to the millisecond, for telling it.
+fn whole_millis(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs())
+        + Duration::from_millis(u64::from(duration.subsec_millis()))
+}
+
+#[test]
+fn test_stats() {
+    use std::net::IpAddr;
+
+    use chrono::TimeZone;
+
+    use crate::audit::Content;
+
+    let at = |day, hour| Utc.with_ymd_and_hms(2024, 5, day, hour, 0, 0).unwrap();
+    let ip = |last| IpAddr::from([192, 0, 2, last]);
+    let change = |at, old_ip, new_ip, source: &str| IpChange {
+        at,
+        old_ip,
+        new_ip,
+        source: String::from(source),
+        updated: vec![String::from("example.com")],
+    };
+    let history = [
+        change(at(1, 0), ip(1), ip(2), "api.ipify.org"),
+        // Undone half an hour later, so it was wrong
+        change(at(11, 0), ip(2), ip(3), "icanhazip.com"),
+        change(
+            at(11, 0) + chrono::Duration::minutes(30),
+            ip(3),
+            ip(2),
+            "api.ipify.org",
+        ),
+        change(at(21, 0), ip(2), ip(4), "api.ipify.org"),
+    ];
+    let mut entry = Entry::update(
+        "example.com",
+        "zone",
+        &crate::cloudflare::ARecord {
+            id: String::from("id"),
+            ip: ip(1),
+            proxied: None,
+            ttl: None,
+        },
+        &crate::cloudflare::ARecord {
+            id: String::from("id"),
+            ip: ip(2),
+            proxied: None,
+            ttl: None,
+        },
+        None,
+        None,
+    );
+    entry.at = at(1, 0) + chrono::Duration::seconds(2);
+    let mut later = entry.clone();
+    later.new = Some(Content {
+        ip: ip(4),
+        proxied: None,
+        ttl: None,
+    });
+    later.at = at(21, 0) + chrono::Duration::seconds(4);
+
+    let stats = Stats::new(&history, &[entry, later], at(30, 0));
+    assert_eq!(stats.changes, 4);
+    assert_eq!(
+        stats.months,
+        [Period {
+            name: String::from("2024-05"),
+            changes: 4
+        }]
+    );
+    assert_eq!(stats.weeks.first().unwrap().name, "2024-W18");
+    assert_eq!(stats.weeks.last().unwrap().name, "2024-W22");
+    assert_eq!(
+        stats.weeks.iter().map(|week| week.changes).sum::<usize>(),
+        4
+    );
+
+    // 10 days, half an hour, and the rest of the 20 days
+    assert_eq!(
+        stats.average_lifetime,
+        Some(Duration::from_secs(20 * 24 * 3600 / 3))
+    );
+    let longest = stats.longest_stable.unwrap();
+    assert_eq!(longest.from, at(1, 0));
+    assert_eq!(longest.until, Some(at(11, 0)));
+
+    assert_eq!(
+        stats.sources,
+        [
+            Source {
+                name: String::from("api.ipify.org"),
+                changes: 3,
+                reverted: 0,
+            },
+            Source {
+                name: String::from("icanhazip.com"),
+                changes: 1,
+                reverted: 1,
+            },
+        ]
+    );
+
+    let latency = stats.latency.unwrap();
+    assert_eq!(latency.changes, 2);
+    assert_eq!(latency.max, Duration::from_secs(4));
+
+    let empty = Stats::new(&[], &[], at(30, 0));
+    assert_eq!(empty.to_string(), "The outside IP hasn't changed yet\n");
+    assert_eq!(empty.latency, None);
+}
@@ -0,0 +1,493 @@
+//! Keeps track of what the daemon has been doing, for anything that wants to report on it, and
+//! prints it with `cdu status`, next to the state and the A records at Cloudflare.
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "grpc")]
+use tokio::sync::broadcast;
+
+use crate::api;
+use crate::cloudflare::Handler;
+use crate::config::Config;
+use crate::network;
+use crate::notify::format_duration;
+use crate::updater::{DomainRecord, DomainSettings, Outcome, Updater};
+
+/// How many failed checks are remembered.
+const RECENT_ERRORS: usize = 10;
+
+/// How many finished checks a subscriber may fall behind, before it misses some.
+#[cfg(feature = "grpc")]
+const CHECK_EVENTS: usize = 16;
+
+/// The state of the daemon, shared between the daemon and whatever reports on it.
+#[derive(Debug, Clone)]
+pub struct SharedStatus {
+    status: Arc<Mutex<Status>>,
+    /// Every check as it finishes, for whoever subscribed.
+    #[cfg(feature = "grpc")]
+    checks: broadcast::Sender<CheckEvent>,
+}
+
+impl SharedStatus {
+    pub fn new(updater: &Updater) -> Self {
+        let mut status = Status {
+            version: clap::crate_version!(),
+            started_at: Utc::now(),
+            ..Status::default()
+        };
+        status.configure(updater);
+
+        Self {
+            status: Arc::new(Mutex::new(status)),
+            #[cfg(feature = "grpc")]
+            checks: broadcast::channel(CHECK_EVENTS).0,
+        }
+    }
+
+    /// Locks the status, to read or change it.
+    pub fn lock(&self) -> MutexGuard<'_, Status> {
+        // A panic while holding the lock can't leave the status in a state that's worth giving up on
+        self.status
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Records the result of a check/update cycle, and tells the subscribers about it.
+    pub fn record(&self, result: &anyhow::Result<Outcome>) {
+        let mut status = self.lock();
+        status.record(result);
+
+        #[cfg(feature = "grpc")]
+        {
+            let check = status.last_check.clone().unwrap_or_default();
+            drop(status);
+            // Nobody may be listening, which is fine
+            let _ = self.checks.send(CheckEvent {
+                check,
+                outcome: result.as_ref().map_or("failed", Outcome::name),
+                outside_ip: result.as_ref().ok().map(Outcome::ip),
+                changed: matches!(result, Ok(Outcome::Updated(_))),
+            });
+        }
+    }
+
+    /// Returns the checks that finish from now on.
+    #[cfg(feature = "grpc")]
+    pub fn subscribe(&self) -> broadcast::Receiver<CheckEvent> {
+        self.checks.subscribe()
+    }
+}
+
+/// The state of the daemon.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub started_at: DateTime<Utc>,
+    pub domains: Vec<String>,
+    /// The A record of each domain, in the same order.
+    pub records: Vec<DomainRecord>,
+    pub dry_run: bool,
+    /// The outside IP seen during the last successful check.
+    pub outside_ip: Option<IpAddr>,
+    /// The last check, successful or not.
+    pub last_check: Option<Check>,
+    /// The last time the A record was changed.
+    pub last_change: Option<Change>,
+    pub next_check_at: Option<DateTime<Utc>>,
+    /// The last time a check succeeded.
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// The last few checks that failed, the most recent first.
+    pub recent_errors: VecDeque<Check>,
+    /// How long ago the last successful check may be, for the daemon to be ready.
+    #[serde(skip)]
+    pub ready_within: Option<Duration>,
+}
+
+/// A check/update cycle that has finished.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Check {
+    pub at: DateTime<Utc>,
+    pub success: bool,
+    pub message: String,
+}
+
+/// A check/update cycle that has just finished, for the subscribers.
+#[cfg(feature = "grpc")]
+#[derive(Debug, Clone)]
+pub struct CheckEvent {
+    pub check: Check,
+    /// What the cycle did as one word, like `updated`, or `failed`.
+    pub outcome: &'static str,
+    pub outside_ip: Option<IpAddr>,
+    /// Whether an A record was changed.
+    pub changed: bool,
+}
+
+/// A change of the A record.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Change {
+    pub at: DateTime<Utc>,
+    pub ip: IpAddr,
+}
+
+impl Status {
+    /// Takes over the settings of the updater, which change when the daemon reloads.
+    pub fn configure(&mut self, updater: &Updater) {
+        self.domains = updater.domains().to_vec();
+        self.update_records(updater.records());
+        self.dry_run = updater.dry_run();
+        if self.last_change.is_none() {
+            self.last_change = updater.last_change().map(|(ip, at)| Change { at, ip });
+        }
+    }
+
+    /// Returns whether the daemon is ready at `now`, or why it isn't. That's when the last check
+    /// succeeded, not too long ago.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the daemon isn't ready.
+    pub fn is_ready(&self, now: DateTime<Utc>) -> Result<(), String> {
+        let Some(last_success_at) = self.last_success_at else {
+            return Err(String::from("no check has succeeded yet"));
+        };
+
+        match self.ready_within {
+            Some(ready_within) if now - last_success_at > ready_within => Err(format!(
+                "the last successful check was at {last_success_at}"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes over the A records the updater found. Domains it hasn't looked up yet keep what was
+    /// known about them, which matters after reloading.
+    pub fn update_records(&mut self, records: &[DomainRecord]) {
+        self.records = records
+            .iter()
+            .map(|record| match &record.checked_at {
+                Some(_) => record.clone(),
+                None => self
+                    .records
+                    .iter()
+                    .find(|known| known.domain == record.domain)
+                    .unwrap_or(record)
+                    .clone(),
+            })
+            .collect();
+    }
+
+    /// Records the result of a check/update cycle.
+    pub fn record(&mut self, result: &anyhow::Result<Outcome>) {
+        let at = Utc::now();
+
+        self.last_check = Some(match result {
+            Ok(outcome) => {
+                let ip = outcome.ip();
+                self.outside_ip = Some(ip);
+                self.last_success_at = Some(at);
+                if let Outcome::Updated(ip) = outcome {
+                    self.last_change = Some(Change { at, ip: *ip });
+                }
+
+                Check {
+                    at,
+                    success: true,
+                    message: outcome.to_string(),
+                }
+            }
+            Err(e) => {
+                let check = Check {
+                    at,
+                    success: false,
+                    message: format!("{e:#}"),
+                };
+                self.recent_errors.push_front(check.clone());
+                self.recent_errors.truncate(RECENT_ERRORS);
+
+                check
+            }
+        });
+    }
+}
+
+/// What `cdu status` asks the daemon for, from what its API returns for `GET /status`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DaemonStatus {
+    next_check_at: Option<DateTime<Utc>>,
+    last_check: Option<Check>,
+    recent_errors: Vec<Check>,
+}
+
+/// Prints whether DDNS is healthy: what the state says, where the A records at Cloudflare point,
+/// and, if the daemon serves its API at `api`, when it checks next and what went wrong recently. A
+/// domain with a zone of its own is looked up there, instead of in `zone_id`. Returns whether it's
+/// healthy, which is when every A record points at the outside IP cdu last saw, and the last check
+/// of the daemon didn't fail.
+///
+/// # Errors
+///
+/// Returns an error if the Cloudflare client cannot be set up.
+pub async fn show(
+    config: &Config,
+    api_key: &str,
+    zone_id: &str,
+    domains: &[String],
+    per_domain: &[DomainSettings],
+    api: Option<&api::Options>,
+) -> anyhow::Result<bool> {
+    let now = Utc::now();
+    let mut problems = Vec::new();
+
+    if config.stateless {
+        println!("State: none is kept");
+    } else {
+        println!("State: {}", config.state_path().display());
+    }
+    let outside_ip = config.outside_ip;
+    println!(
+        "  Outside IP   {}",
+        outside_ip.map_or_else(|| String::from("not known yet"), |ip| ip.to_string())
+    );
+    match config.cloudflare_ip {
+        Some(ip) => println!(
+            "  Last change  {}, {} ago, to {ip}",
+            local(config.last_updated),
+            ago(config.last_updated, now)
+        ),
+        None => println!("  Last change  none yet"),
+    }
+    if let Some(at) = config.last_reconciled {
+        println!("  Last check   {}, {} ago", local(at), ago(at, now));
+    }
+
+    println!("A records at Cloudflare:");
+    let cloudflare = Handler::try_new(api_key)?
+        .with_api_url(&config.api_url)
+        .with_timeout(config.timeouts.api);
+    let width = domains.iter().map(String::len).max().unwrap_or_default();
+    for domain in domains {
+        let zone_id = per_domain
+            .iter()
+            .find(|settings| &settings.name == domain)
+            .and_then(|settings| settings.zone_id.as_deref())
+            .unwrap_or(zone_id);
+        match cloudflare
+            .get_a_record(zone_id, domain)
+            .await
+            .map_err(anyhow::Error::from)
+        {
+            Ok(record) if outside_ip.unwrap_or(record.ip) == record.ip => {
+                println!("  ok      {domain:width$}  {}", record.ip);
+            }
+            Ok(record) => {
+                println!("  drift   {domain:width$}  {}", record.ip);
+                problems.push(format!(
+                    "{domain} points at {}, not at the outside IP",
+                    record.ip
+                ));
+            }
+            Err(e) => {
+                println!("  failed  {domain:width$}  {e:#}");
+                problems.push(format!("{domain} cannot be looked up"));
+            }
+        }
+    }
+
+    match api {
+        Some(api) => match daemon_status(api).await {
+            Ok(daemon) => {
+                println!("Daemon at {}:", api.listen);
+                if let Some(at) = daemon.next_check_at {
+                    println!("  Next check   {}, in {}", local(at), ago(now, at));
+                }
+                if let Some(check) = &daemon.last_check {
+                    let outcome = if check.success { "succeeded" } else { "failed" };
+                    println!(
+                        "  Last check   {}, {outcome}: {}",
+                        local(check.at),
+                        check.message
+                    );
+                    if !check.success {
+                        problems.push(String::from("the last check of the daemon failed"));
+                    }
+                }
+                if !daemon.recent_errors.is_empty() {
+                    println!("  Recent errors:");
+                    for check in &daemon.recent_errors {
+                        println!("    {}  {}", local(check.at), check.message);
+                    }
+                }
+            }
+            Err(e) => println!("Daemon: {e:#}"),
+        },
+        None => println!("Daemon: set --api-listen and --api-token to ask it too"),
+    }
+
+    if problems.is_empty() {
+        println!("Healthy");
+    } else {
+        println!("Not healthy: {}", problems.join("; "));
+    }
+
+    Ok(problems.is_empty())
+}
+
+/// Tells whether cdu is still checking: the last check succeeded no longer than `within` ago. The
+/// daemon is asked if it serves its API at `api`, and otherwise the state tells when a check last
+/// succeeded. It asks nothing of Cloudflare, so it's cheap enough for a Docker `HEALTHCHECK`.
+/// Prints why, and returns whether it's healthy.
+pub async fn healthcheck(config: &Config, api: Option<&api::Options>, within: Duration) -> bool {
+    let now = Utc::now();
+    let last_success_at = match api {
+        Some(api) => match daemon_status(api).await {
+            Ok(DaemonStatus {
+                last_check: Some(check),
+                ..
+            }) if check.success => Some(check.at),
+            Ok(DaemonStatus {
+                last_check: Some(check),
+                ..
+            }) => {
+                println!("Not healthy: the last check failed: {}", check.message);
+                return false;
+            }
+            Ok(_) => None,
+            Err(e) => {
+                println!("Not healthy: {e:#}");
+                return false;
+            }
+        },
+        None => config.last_success_at,
+    };
+
+    match last_success_at {
+        Some(at) if now - at <= within => {
+            println!("Healthy: the last check succeeded {} ago", ago(at, now));
+            true
+        }
+        Some(at) => {
+            println!(
+                "Not healthy: the last check that succeeded was {} ago",
+                ago(at, now)
+            );
+            false
+        }
+        None => {
+            println!("Not healthy: no check has succeeded yet");
+            false
+        }
+    }
+}
+
+/// Asks the daemon for its status, through its API.
+async fn daemon_status(api: &api::Options) -> anyhow::Result<DaemonStatus> {
+    let token = api
+        .token
+        .as_deref()
+        .context("The API of the daemon only tells its status with --api-token")?;
+    let response = network::client(network::DEFAULT_TIMEOUT)
+        .get(format!("http://{}/status", reachable(api.listen)))
+        .bearer_auth(token)
+        .send()
+        .await
+        .with_context(|| format!("No daemon answered at {}", api.listen))?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("The daemon answered with {status}");
+    }
+
+    response
+        .json()
+        .await
+        .context("Failed to parse the status of the daemon")
+}
+
+/// Returns where to reach the daemon listening at `listen`, which is this machine if it listens
+/// on every address.
+fn reachable(listen: SocketAddr) -> SocketAddr {
+    match listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, listen.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            (std::net::Ipv6Addr::LOCALHOST, listen.port()).into()
+        }
+        _ => listen,
+    }
+}
+
+/// Formats `at` in the time zone cdu runs in.
+fn local(at: DateTime<Utc>) -> String {
+    at.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Formats how long it is from `from` to `to`.
+fn ago(from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+    format_duration((to - from).to_std().unwrap_or_default())
+}
+
+#[test]
+fn test_record() {
+    use crate::config::Config;
+
+    let updater =
+        Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    let status = SharedStatus::new(&updater);
+    assert_eq!(status.lock().domains, ["example.com"]);
+    assert!(status.lock().last_change.is_none());
+
+    status
+        .lock()
+        .record(&Ok(Outcome::Updated(IpAddr::from([192, 0, 2, 1]))));
+    status.lock().record(&Err(anyhow::anyhow!("no network")));
+
+    let mut status = status.lock();
+    assert_eq!(status.outside_ip, Some(IpAddr::from([192, 0, 2, 1])));
+    assert_eq!(
+        status.last_change.map(|change| change.ip),
+        Some(IpAddr::from([192, 0, 2, 1]))
+    );
+    let last_check = status.last_check.as_ref().unwrap();
+    assert!(!last_check.success);
+    assert_eq!(last_check.message, "no network");
+    assert_eq!(status.recent_errors.len(), 1);
+
+    // The failed check doesn't count against readiness, as long as the last success is recent
+    let now = Utc::now();
+    status.ready_within = Some(Duration::minutes(15));
+    assert!(status.is_ready(now).is_ok());
+    assert!(status.is_ready(now + Duration::minutes(20)).is_err());
+}
+
+#[test]
+fn test_reachable() {
+    assert_eq!(
+        reachable("0.0.0.0:8080".parse().unwrap()),
+        "127.0.0.1:8080".parse().unwrap()
+    );
+    assert_eq!(
+        reachable("[::]:8080".parse().unwrap()),
+        "[::1]:8080".parse().unwrap()
+    );
+    assert_eq!(
+        reachable("192.168.1.2:8080".parse().unwrap()),
+        "192.168.1.2:8080".parse().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_healthcheck() {
+    let mut config = Config::default();
+    assert!(!healthcheck(&config, None, Duration::minutes(15)).await);
+
+    config.last_success_at = Some(Utc::now() - Duration::minutes(5));
+    assert!(healthcheck(&config, None, Duration::minutes(15)).await);
+    assert!(!healthcheck(&config, None, Duration::minutes(1)).await);
+}
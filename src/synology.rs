@@ -0,0 +1,127 @@
+//! Answers the way a custom DDNS provider of Synology DSM does, so cdu can be added to the list of
+//! providers of DSM, which then has it update the A record at Cloudflare when the outside IP
+//! changes.
+//!
+//! DSM runs the module of a provider with the username, password, hostname and IP filled in for it,
+//! and goes by the word it prints: `good` or `nochg` with the IP when it worked, `badauth` when the
+//! API token doesn't work, `nohost` when the zone or the A record isn't there, `badparam` when the
+//! IP isn't an IPv4 address, and `911` when anything else went wrong. The username is the zone, by
+//! its ID or its name, and the password is the API token.
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use reqwest::Client as RqClient;
+use tracing::warn;
+
+use crate::cloudflare::{ARecord, CloudflareError, Handler};
+use crate::hint::Category;
+
+/// What DSM is told.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    /// The A record was pointed at the IP.
+    Good(Ipv4Addr),
+    /// The A record already pointed at the IP.
+    NoChange(Ipv4Addr),
+    BadAuth,
+    NoHost,
+    BadParam,
+    /// Something else went wrong, like the API not answering.
+    ServerError,
+}
+
+impl Response {
+    fn of(error: &CloudflareError) -> Self {
+        match error.category() {
+            Some(Category::Authentication | Category::Authorization) => Self::BadAuth,
+            Some(Category::NotFound) => Self::NoHost,
+            _ => Self::ServerError,
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Good(ip) => write!(f, "good {ip}"),
+            Self::NoChange(ip) => write!(f, "nochg {ip}"),
+            Self::BadAuth => f.write_str("badauth"),
+            Self::NoHost => f.write_str("nohost"),
+            Self::BadParam => f.write_str("badparam"),
+            Self::ServerError => f.write_str("911"),
+        }
+    }
+}
+
+/// Points the A record of `hostname` in the zone `username` at `ip`, with `password` as the API
+/// token, as DSM asks of a provider. The requests go to the Cloudflare API at `api_url` through
+/// `client`.
+pub async fn run(
+    username: &str,
+    password: &str,
+    hostname: &str,
+    ip: &str,
+    api_url: &str,
+    client: RqClient,
+) -> Response {
+    let Ok(ip) = ip.parse::<Ipv4Addr>() else {
+        warn!("DSM asked to point {hostname} at {ip}, which isn't an IPv4 address");
+        return Response::BadParam;
+    };
+
+    match update(username, password, hostname, ip, api_url, client).await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Failed to point {hostname} at {ip}: {e}");
+            Response::of(&e)
+        }
+    }
+}
+
+async fn update(
+    zone: &str,
+    api_key: &str,
+    hostname: &str,
+    ip: Ipv4Addr,
+    api_url: &str,
+    client: RqClient,
+) -> Result<Response, CloudflareError> {
+    let cloudflare = Handler::try_new(api_key)?
+        .with_api_url(api_url)
+        .with_client(client);
+    let zone_id = if is_zone_id(zone) {
+        zone.to_string()
+    } else {
+        let zones = cloudflare.list_zones().await?;
+        let Some(zone) = zones
+            .into_iter()
+            .find(|z| z.name.eq_ignore_ascii_case(zone))
+        else {
+            warn!("The API token has no access to a zone called {zone}");
+            return Ok(Response::NoHost);
+        };
+        zone.id
+    };
+
+    let record = cloudflare.get_a_record(&zone_id, hostname).await?;
+    if record.ip == ip {
+        return Ok(Response::NoChange(ip));
+    }
+    // Whether it's proxied and its TTL stay as they are
+    let record = ARecord {
+        ip: ip.into(),
+        proxied: None,
+        ttl: None,
+        ..record
+    };
+    cloudflare
+        .patch_a_record(&zone_id, &record, hostname)
+        .await?;
+
+    Ok(Response::Good(ip))
+}
+
+/// Returns whether `zone` is the ID of a zone, 32 hexadecimal digits, rather than its name.
+fn is_zone_id(zone: &str) -> bool {
+    zone.len() == 32 && zone.chars().all(|c| c.is_ascii_hexdigit())
+}
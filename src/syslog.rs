@@ -0,0 +1,305 @@
+//! Sends the logs to syslog, for routers and BSD-style systems that collect everything there.
+//!
+//! Every event is one message in the BSD format of RFC 3164, `<PRI>Mmm dd hh:mm:ss HOST TAG[PID]:
+//! MESSAGE`, which every syslog daemon understands, with its severity from the level of the event.
+//! It's sent to the local socket, or over UDP or TCP to a remote server, a line per message.
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::Mutex;
+
+use chrono::Local;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The port syslog servers listen on.
+const DEFAULT_PORT: u16 = 514;
+
+/// The sockets local syslog daemons listen on: Linux, macOS and FreeBSD.
+#[cfg(unix)]
+const LOCAL_SOCKETS: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+/// The facilities, by name, and their number.
+const FACILITIES: [(&str, u8); 20] = [
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// Where to send the messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The socket of the syslog daemon on this machine.
+    Local,
+    Udp(String),
+    Tcp(String),
+}
+
+impl Target {
+    /// Parses `local`, `udp://<host>[:<port>]` or `tcp://<host>[:<port>]`, where the port is 514
+    /// unless it's given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it's neither of these.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value == "local" {
+            return Ok(Self::Local);
+        }
+
+        let with_port = |host: &str| {
+            if host.is_empty() {
+                Err(format!("There's no host in {value}"))
+            } else if host
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+            {
+                Ok(host.to_string())
+            } else {
+                Ok(format!("{host}:{DEFAULT_PORT}"))
+            }
+        };
+        if let Some(host) = value.strip_prefix("udp://") {
+            return with_port(host.trim_end_matches('/')).map(Self::Udp);
+        }
+        if let Some(host) = value.strip_prefix("tcp://") {
+            return with_port(host.trim_end_matches('/')).map(Self::Tcp);
+        }
+
+        Err(format!(
+            "Unknown syslog target: {value}, use local, udp://<host>[:<port>] or tcp://<host>[:<port>]"
+        ))
+    }
+}
+
+/// Parses the name of a facility, like `daemon` or `local0`, into its number.
+///
+/// # Errors
+///
+/// Returns an error if there's no facility with that name.
+pub fn parse_facility(value: &str) -> Result<u8, String> {
+    FACILITIES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|(_, number)| *number)
+        .ok_or_else(|| {
+            format!("Unknown facility: {value}, use one like daemon, user or local0 to local7")
+        })
+}
+
+/// The connection to syslog, which the logs are written to.
+pub struct Syslog {
+    connection: Mutex<Connection>,
+    facility: u8,
+    tag: String,
+    hostname: String,
+}
+
+enum Connection {
+    #[cfg(unix)]
+    Local(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+    Tcp {
+        address: String,
+        stream: Option<TcpStream>,
+    },
+}
+
+impl Syslog {
+    /// Connects to syslog at `target`, to send messages with `facility` and `tag`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the local socket or the remote server cannot be reached.
+    pub fn connect(target: &Target, facility: u8, tag: &str) -> anyhow::Result<Self> {
+        let connection = match target {
+            #[cfg(unix)]
+            Target::Local => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                LOCAL_SOCKETS
+                    .iter()
+                    .find(|path| socket.connect(path).is_ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Failed to find the syslog socket, tried {}",
+                            LOCAL_SOCKETS.join(", ")
+                        )
+                    })?;
+                Connection::Local(socket)
+            }
+            #[cfg(not(unix))]
+            Target::Local => {
+                anyhow::bail!("Logging to the local syslog is only supported on Unix")
+            }
+            Target::Udp(address) => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket
+                    .connect(address.as_str())
+                    .map_err(|e| anyhow::anyhow!("Failed to reach syslog server {address}: {e}"))?;
+                Connection::Udp(socket)
+            }
+            Target::Tcp(address) => Connection::Tcp {
+                stream: Some(TcpStream::connect(address.as_str()).map_err(|e| {
+                    anyhow::anyhow!("Failed to connect to syslog server {address}: {e}")
+                })?),
+                address: address.clone(),
+            },
+        };
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            facility,
+            tag: tag.to_string(),
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+        })
+    }
+
+    /// Sends `message` with `severity`, dropping it if syslog cannot be reached, as there's nowhere
+    /// left to tell about that.
+    fn send(&self, severity: u8, message: &str) {
+        let line = format!(
+            "<{}>{} {} {}[{}]: {}",
+            u16::from(self.facility) * 8 + u16::from(severity),
+            Local::now().format("%b %e %H:%M:%S"),
+            self.hostname,
+            self.tag,
+            std::process::id(),
+            message
+        );
+
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let _ = match &mut *connection {
+            #[cfg(unix)]
+            Connection::Local(socket) => socket.send(line.as_bytes()).map(drop),
+            Connection::Udp(socket) => socket.send(line.as_bytes()).map(drop),
+            Connection::Tcp { address, stream } => send_tcp(address, stream, &line),
+        };
+    }
+}
+
+/// Sends `line` over the TCP stream, connecting again once if the server went away.
+fn send_tcp(address: &str, stream: &mut Option<TcpStream>, line: &str) -> io::Result<()> {
+    let framed = format!("{line}\n");
+    if let Some(connected) = stream {
+        if connected.write_all(framed.as_bytes()).is_ok() {
+            return Ok(());
+        }
+    }
+
+    *stream = None;
+    let mut connected = TcpStream::connect(address)?;
+    connected.write_all(framed.as_bytes())?;
+    *stream = Some(connected);
+    Ok(())
+}
+
+/// Returns the severity of syslog for `level`.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = Message<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Message {
+            syslog: self,
+            severity: severity(Level::INFO),
+            buf: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        Message {
+            syslog: self,
+            severity: severity(*meta.level()),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// An event that's being formatted, which is sent once it's complete.
+pub struct Message<'a> {
+    syslog: &'a Syslog,
+    severity: u8,
+    buf: Vec<u8>,
+}
+
+impl Write for Message<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message<'_> {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.buf);
+        let message = message.trim_end();
+        if !message.is_empty() {
+            self.syslog.send(self.severity, message);
+        }
+    }
+}
+
+#[test]
+fn test_udp() {
+    assert_eq!(Target::parse("local"), Ok(Target::Local));
+    assert_eq!(
+        Target::parse("udp://logs.lan"),
+        Ok(Target::Udp(String::from("logs.lan:514")))
+    );
+    assert_eq!(
+        Target::parse("tcp://10.0.0.2:1514"),
+        Ok(Target::Tcp(String::from("10.0.0.2:1514")))
+    );
+    assert!(Target::parse("logs.lan").is_err());
+    assert_eq!(parse_facility("local3"), Ok(19));
+    assert!(parse_facility("local8").is_err());
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = Target::Udp(server.local_addr().unwrap().to_string());
+    let syslog = Syslog::connect(&target, 3, "cdu").unwrap();
+    let mut message = Message {
+        syslog: &syslog,
+        severity: severity(Level::WARN),
+        buf: Vec::new(),
+    };
+    message.write_all(b"Outside IP has changed\n").unwrap();
+    drop(message);
+
+    let mut buf = [0; 256];
+    let len = server.recv(&mut buf).unwrap();
+    let received = String::from_utf8_lossy(&buf[..len]);
+    assert!(received.starts_with("<28>"), "{received}");
+    let pid = std::process::id();
+    assert!(received.ends_with(&format!(" cdu[{pid}]: Outside IP has changed")));
+}
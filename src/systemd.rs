@@ -0,0 +1,130 @@
+//! Talks to systemd using the `sd_notify` protocol, when running as a `Type=notify` service.
+//!
+//! systemd passes the socket to talk to in `NOTIFY_SOCKET`. Without it, which is always the case
+//! outside of systemd, every function here does nothing.
+use std::env;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+/// Tells systemd that the daemon has started.
+pub fn ready() {
+    notify("READY=1");
+}
+
+/// Tells systemd that the daemon is reloading its configuration. Call [`ready`] when done.
+pub fn reloading() {
+    notify("RELOADING=1");
+}
+
+/// Tells systemd that the daemon is shutting down.
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// Sets the status line shown by `systemctl status`.
+pub fn status(status: &str) {
+    // A newline would start a new variable assignment
+    notify(&format!("STATUS={}", status.replace('\n', " ")));
+}
+
+/// Tells the systemd watchdog that the daemon is still alive.
+pub fn watchdog() {
+    notify("WATCHDOG=1");
+}
+
+/// Returns how often [`watchdog`] should be called, if the watchdog is enabled for this process.
+/// This is half of `WatchdogSec=`, as recommended by systemd.
+#[must_use]
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?;
+    let pid = env::var("WATCHDOG_PID").ok();
+
+    parse_watchdog(&usec, pid.as_deref(), std::process::id())
+}
+
+fn parse_watchdog(usec: &str, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    // The watchdog could be meant for another process, like the shell that started us
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+
+    let usec = usec.parse::<u64>().ok().filter(|usec| *usec > 0)?;
+
+    Some(Duration::from_micros(usec) / 2)
+}
+
+fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    debug!("Notifying systemd: {state}");
+
+    #[cfg(unix)]
+    if let Err(e) = send(&path, state) {
+        warn!("Failed to notify systemd: {e}");
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        warn!("Notifying systemd is only supported on Unix");
+    }
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+
+    // A leading '@' means the socket is in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let addr = SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+
+        return Ok(());
+    }
+
+    socket.send_to(state.as_bytes(), path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_watchdog() {
+    assert_eq!(
+        parse_watchdog("30000000", None, 42),
+        Some(Duration::from_secs(15))
+    );
+    assert_eq!(
+        parse_watchdog("30000000", Some("42"), 42),
+        Some(Duration::from_secs(15))
+    );
+    assert_eq!(parse_watchdog("30000000", Some("41"), 42), None);
+    assert_eq!(parse_watchdog("0", None, 42), None);
+    assert_eq!(parse_watchdog("soon", None, 42), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_send() {
+    use std::os::unix::net::UnixDatagram;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notify.sock");
+    let receiver = UnixDatagram::bind(&path).unwrap();
+
+    send(path.as_os_str(), "READY=1").unwrap();
+
+    let mut buf = [0; 64];
+    let len = receiver.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"READY=1");
+}
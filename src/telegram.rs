@@ -0,0 +1,124 @@
+//! Sends messages through a Telegram bot, formatted with MarkdownV2.
+use std::fmt::Write as _;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::Client as RqClient;
+use serde_json::{json, Value};
+
+use crate::notify::{Message, Notifier};
+
+pub const BASE_URL: &str = "https://api.telegram.org";
+
+/// A Telegram bot, sending to a single chat.
+#[derive(Debug)]
+pub struct Telegram {
+    client: RqClient,
+    token: String,
+    chat_id: String,
+}
+
+impl Telegram {
+    #[must_use]
+    pub fn new(client: RqClient, token: &str, chat_id: &str) -> Self {
+        Self {
+            client,
+            token: token.to_string(),
+            chat_id: chat_id.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(format!("{BASE_URL}/bot{}/sendMessage", self.token))
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": text(message),
+                "parse_mode": "MarkdownV2",
+            }))
+            .send()
+            .await
+            .context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            // The URL has the token in it, so only the description is worth showing
+            let body = response.json::<Value>().await.unwrap_or_default();
+            anyhow::bail!(
+                "Received response status {status}: {}",
+                body["description"].as_str().unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the message in MarkdownV2.
+fn text(message: &Message) -> String {
+    let mut text = format!("*{}*\n", escape(message.title()));
+
+    let _ = writeln!(text, "Domain: `{}`", escape_code(&message.domain));
+    match (message.old_ip, message.new_ip) {
+        (Some(old_ip), Some(new_ip)) => {
+            let _ = writeln!(text, "IP: `{old_ip}` → `{new_ip}`");
+        }
+        (None, Some(ip)) | (Some(ip), None) => {
+            let _ = writeln!(text, "IP: `{ip}`");
+        }
+        (None, None) => {}
+    }
+    if let Some(error) = &message.error {
+        let _ = writeln!(text, "Error: {}", escape(error));
+    }
+    text.push_str(&escape(
+        &message.at.format("At: %Y-%m-%d %H:%M:%S UTC").to_string(),
+    ));
+
+    text
+}
+
+/// Escapes the characters that mean something in MarkdownV2.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Escapes the characters that mean something in a code span.
+fn escape_code(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+#[test]
+fn test_text() {
+    use std::net::IpAddr;
+
+    let mut message = Message::updated(
+        "home.example.com",
+        Some(IpAddr::from([192, 0, 2, 1])),
+        IpAddr::from([192, 0, 2, 2]),
+    );
+    message.at = chrono::DateTime::from_timestamp(1_718_000_000, 0).unwrap();
+
+    assert_eq!(
+        text(&message),
+        "*A record updated*\nDomain: `home.example.com`\nIP: `192.0.2.1` → `192.0.2.2`\nAt: 2024\\-06\\-10 06:13:20 UTC"
+    );
+    assert_eq!(escape("1.2 (ok)!"), "1\\.2 \\(ok\\)\\!");
+}
@@ -0,0 +1,265 @@
+//! Holds back repeated alerts, so a failure that lasts for hours sends one alert, a reminder now and
+//! then, and a message once it's over, instead of one alert per check. With a threshold, a failure
+//! is only alerted about once it came up in that many checks in a row, so a single blip goes by
+//! without one.
+//!
+//! The failures that are going on are kept in a file next to the configuration, so it works just
+//! as well when cdu is run by a timer as it does in the daemon.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::notify::{EventKind, Message, Severity};
+
+const ALERTS_FILE: &str = "cdu-alerts.toml";
+
+/// How long the same alert is held back for, unless told otherwise.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// A failure that's going on, for one kind of event and domain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: EventKind,
+    pub domain: String,
+    /// When it first failed.
+    pub since: DateTime<Utc>,
+    pub last_sent_at: DateTime<Utc>,
+    /// How many alerts were held back since the last one was sent.
+    pub held_back: u32,
+    /// How many checks in a row it came up in.
+    #[serde(default)]
+    pub failures: u32,
+    /// Whether it was alerted about, which it isn't until it came up often enough in a row.
+    #[serde(default = "alerted")]
+    pub alerted: bool,
+}
+
+/// The failures from before there was a threshold were all alerted about.
+fn alerted() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AlertsFile {
+    #[serde(default)]
+    alerts: Vec<Alert>,
+}
+
+/// The failures that are going on, and whether to alert about them again.
+#[derive(Debug)]
+pub struct Throttle {
+    path: PathBuf,
+    alerts: Vec<Alert>,
+    cooldown: Duration,
+    /// How many checks in a row a failure has to come up in, before it's alerted about.
+    alert_after: u32,
+    /// Whether the file is behind.
+    changed: bool,
+}
+
+impl Throttle {
+    /// Loads the failures from the directory of the configuration. A file that cannot be read is
+    /// logged and left alone, which at worst sends an alert again.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(ALERTS_FILE);
+        let alerts = match fs::read_to_string(&path) {
+            Ok(text) => match toml::from_str::<AlertsFile>(&text) {
+                Ok(file) => file.alerts,
+                Err(e) => {
+                    warn!("Ignoring ongoing failures in {}: {e}", path.display());
+                    Vec::new()
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+
+        Self {
+            path,
+            alerts,
+            cooldown: DEFAULT_COOLDOWN,
+            alert_after: 1,
+            changed: false,
+        }
+    }
+
+    /// Sends the same alert again once per `cooldown` at most. Zero sends every one of them.
+    pub fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+
+    /// Only alerts about a failure once it came up in `alert_after` checks in a row.
+    pub fn set_alert_after(&mut self, alert_after: u32) {
+        self.alert_after = alert_after.max(1);
+    }
+
+    /// Takes the failures that are going on from `other`, like the throttle before a reload,
+    /// keeping its own cool-down and threshold.
+    pub fn adopt(&mut self, other: &Self) {
+        self.alerts.clone_from(&other.alerts);
+        self.changed = other.changed || self.path != other.path;
+    }
+
+    /// Returns whether the message should be sent at `now`. Only failures are ever held back.
+    pub fn allows(&mut self, message: &Message, now: DateTime<Utc>) -> bool {
+        if message.severity < Severity::Error {
+            return true;
+        }
+
+        let cooldown = self.cooldown;
+        self.changed = true;
+        let index = if let Some(index) = self
+            .alerts
+            .iter()
+            .position(|alert| alert.kind == message.kind && alert.domain == message.domain)
+        {
+            index
+        } else {
+            self.alerts.push(Alert {
+                kind: message.kind,
+                domain: message.domain.clone(),
+                since: now,
+                last_sent_at: now,
+                held_back: 0,
+                failures: 0,
+                alerted: false,
+            });
+            self.alerts.len() - 1
+        };
+        let alert = &mut self.alerts[index];
+
+        alert.failures = alert.failures.saturating_add(1);
+        if !alert.alerted {
+            if alert.failures < self.alert_after {
+                debug!(
+                    "Holding back alert until it came up {} times in a row: {message}",
+                    self.alert_after
+                );
+                return false;
+            }
+            alert.alerted = true;
+            alert.last_sent_at = now;
+            return true;
+        }
+
+        let cooling_down = (now - alert.last_sent_at)
+            .to_std()
+            .is_ok_and(|elapsed| elapsed < cooldown);
+        if cooling_down {
+            debug!("Holding back repeated alert: {message}");
+            alert.held_back += 1;
+            false
+        } else {
+            alert.last_sent_at = now;
+            alert.held_back = 0;
+            true
+        }
+    }
+
+    /// Ends the failure of this kind for `domain`, if there was one, returning it if it was alerted
+    /// about.
+    pub fn resolve(&mut self, kind: EventKind, domain: &str) -> Option<Alert> {
+        let index = self
+            .alerts
+            .iter()
+            .position(|alert| alert.kind == kind && alert.domain == domain)?;
+        self.changed = true;
+
+        let alert = self.alerts.remove(index);
+        alert.alerted.then_some(alert)
+    }
+
+    /// Writes the failures to the file if they changed, or removes the file once there are none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written or removed.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        if self.alerts.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path)
+                    .with_context(|| format!("Failed to remove file: {}", self.path.display()))?;
+            }
+        } else {
+            let file = AlertsFile {
+                alerts: self.alerts.clone(),
+            };
+            let text = toml::to_string_pretty(&file).context("Failed to serialize the failures")?;
+            crate::config::write_atomically(&self.path, text.as_bytes())?;
+        }
+        self.changed = false;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_throttle() {
+    use std::net::IpAddr;
+
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now();
+    let minutes = chrono::Duration::minutes;
+    let failed = Message::failed(
+        EventKind::UpdateFailed,
+        "example.com",
+        None,
+        "Cloudflare is down",
+    );
+
+    let mut throttle = Throttle::load(dir.path());
+    assert!(throttle.allows(&failed, now));
+    assert!(!throttle.allows(&failed, now + minutes(1)));
+    assert!(!throttle.allows(&failed, now + minutes(2)));
+    let other = Message::failed(
+        EventKind::UpdateFailed,
+        "example.org",
+        None,
+        "Cloudflare is down",
+    );
+    assert!(throttle.allows(&other, now + minutes(2)));
+    assert!(throttle.allows(
+        &Message::updated("example.com", None, IpAddr::from([192, 0, 2, 1])),
+        now
+    ));
+    throttle.save().unwrap();
+
+    let mut throttle = Throttle::load(dir.path());
+    assert_eq!(throttle.alerts[0].held_back, 2);
+    assert!(throttle.allows(&failed, now + minutes(61)));
+    assert_eq!(throttle.alerts[0].held_back, 0);
+
+    assert!(throttle
+        .resolve(EventKind::Mismatch, "example.com")
+        .is_none());
+    let alert = throttle
+        .resolve(EventKind::UpdateFailed, "example.com")
+        .unwrap();
+    assert_eq!(alert.since, now);
+    throttle.resolve(EventKind::UpdateFailed, "example.org");
+    throttle.save().unwrap();
+    assert!(!dir.path().join(ALERTS_FILE).exists());
+
+    // A blip isn't alerted about, nor is it over, but a failure that lasts is
+    throttle.set_alert_after(3);
+    assert!(!throttle.allows(&failed, now));
+    assert!(throttle
+        .resolve(EventKind::UpdateFailed, "example.com")
+        .is_none());
+    assert!(!throttle.allows(&failed, now));
+    assert!(!throttle.allows(&failed, now + minutes(5)));
+    assert!(throttle.allows(&failed, now + minutes(10)));
+    assert!(!throttle.allows(&failed, now + minutes(15)));
+    let alert = throttle
+        .resolve(EventKind::UpdateFailed, "example.com")
+        .unwrap();
+    assert_eq!((alert.since, alert.failures, alert.held_back), (now, 4, 1));
+}
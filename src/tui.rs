@@ -0,0 +1,378 @@
+//! An interactive screen with the live state, for troubleshooting.
+//!
+//! It checks on an interval, just like the daemon, and shows the outside IP, the A record of every
+//! domain, the last change, the next check and the most recent log lines. Keys:
+//!
+//! - `u` checks and updates right away.
+//! - `d` only detects the outside IP again, without touching Cloudflare.
+//! - `q` or `Esc` quits.
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, Utc};
+use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use futures_util::StreamExt;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+
+use crate::network::{self, DEFAULT_TIMEOUT};
+use crate::status::{SharedStatus, Status};
+use crate::updater::Updater;
+
+/// How many log lines are kept for the screen.
+const LOG_LINES: usize = 200;
+
+/// Whether the screen is showing, in which case logs go to it instead of stderr.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Where logs are written to: the screen while it's showing, and stderr otherwise.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !ACTIVE.load(Ordering::Relaxed) {
+            return io::stderr().write(buf);
+        }
+
+        let text = strip_ansi(&String::from_utf8_lossy(buf));
+        let mut logs = LOGS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            logs.push_back(line.to_string());
+        }
+        while logs.len() > LOG_LINES {
+            logs.pop_front();
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if ACTIVE.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            io::stderr().flush()
+        }
+    }
+}
+
+/// Removes the escape codes that color the logs, which the screen would show as garbage.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to and including the letter that ends the sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+/// Something the worker is asked to do.
+enum Command {
+    Update,
+    Detect,
+}
+
+/// The state of the screen, besides the status.
+#[derive(Default)]
+struct Screen {
+    /// The last outside IP detected with `d`.
+    detected: Option<(DateTime<Utc>, Result<IpAddr, String>)>,
+    busy: Option<&'static str>,
+    next_check: Option<DateTime<Utc>>,
+}
+
+/// What the worker reports back.
+enum Report {
+    Busy(Option<&'static str>),
+    Detected(Result<IpAddr, String>),
+    NextCheck(DateTime<Utc>),
+}
+
+/// Shows the screen until the user quits, checking every `interval`.
+///
+/// # Errors
+///
+/// Returns an error if the terminal cannot be used.
+pub async fn run(updater: Updater, interval: Duration) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !interval.is_zero(),
+        "The check interval must be greater than zero"
+    );
+
+    let status = SharedStatus::new(&updater);
+    let (commands, command_receiver) = mpsc::unbounded_channel();
+    let (report_sender, mut reports) = mpsc::unbounded_channel();
+
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    ACTIVE.store(true, Ordering::Relaxed);
+
+    // The checks run alongside the screen, and stop with it
+    let result = tokio::select! {
+        result = show(&mut terminal, &status, &commands, &mut reports) => result,
+        () = work(updater, interval, status.clone(), command_receiver, report_sender) => Ok(()),
+    };
+
+    ACTIVE.store(false, Ordering::Relaxed);
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+    let _ = terminal.show_cursor();
+
+    result
+}
+
+async fn show(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    status: &SharedStatus,
+    commands: &UnboundedSender<Command>,
+    reports: &mut UnboundedReceiver<Report>,
+) -> anyhow::Result<()> {
+    let mut screen = Screen::default();
+    let mut events = EventStream::new();
+    let mut redraw = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        terminal.draw(|frame| draw(frame, &status.lock(), &screen))?;
+
+        tokio::select! {
+            _ = redraw.tick() => {}
+            Some(report) = reports.recv() => match report {
+                Report::Busy(busy) => screen.busy = busy,
+                Report::Detected(result) => screen.detected = Some((Utc::now(), result)),
+                Report::NextCheck(at) => screen.next_check = Some(at),
+            },
+            event = events.next() => match event {
+                Some(Ok(TermEvent::Key(key))) if key.kind == KeyEventKind::Press => {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            return Ok(());
+                        }
+                        KeyCode::Char('u') => {
+                            let _ = commands.send(Command::Update);
+                        }
+                        KeyCode::Char('d') => {
+                            let _ = commands.send(Command::Detect);
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Runs the checks, on the interval and when asked to.
+async fn work(
+    mut updater: Updater,
+    interval: Duration,
+    status: SharedStatus,
+    mut commands: UnboundedReceiver<Command>,
+    reports: UnboundedSender<Report>,
+) {
+    let client = updater.detection_client().clone();
+    let family = updater.family();
+    let mut deadline = Instant::now();
+
+    loop {
+        let command = tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => command,
+                None => return,
+            },
+            () = tokio::time::sleep_until(deadline) => Command::Update,
+        };
+
+        match command {
+            Command::Update => {
+                let _ = reports.send(Report::Busy(Some("checking")));
+                let result = updater.run().await;
+                status.lock().record(&result);
+                status.lock().update_records(updater.records());
+
+                deadline = Instant::now() + interval;
+                let next_check = chrono::Duration::from_std(interval)
+                    .map_or_else(|_| Utc::now(), |interval| Utc::now() + interval);
+                let _ = reports.send(Report::NextCheck(next_check));
+            }
+            Command::Detect => {
+                let _ = reports.send(Report::Busy(Some("detecting")));
+                let result =
+                    network::detect_family(&client, family.servers(), family, DEFAULT_TIMEOUT)
+                        .await
+                        .map(|(ip, _)| ip)
+                        .map_err(|e| format!("{e:#}"));
+                let _ = reports.send(Report::Detected(result));
+            }
+        }
+        let _ = reports.send(Report::Busy(None));
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn draw(frame: &mut Frame, status: &Status, screen: &Screen) {
+    let [summary, records, logs, help] = Layout::vertical([
+        Constraint::Length(7),
+        Constraint::Length(u16::try_from(status.records.len()).unwrap_or(u16::MAX) + 3),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let label = |text: &'static str| Span::styled(text, Style::new().add_modifier(Modifier::BOLD));
+    let time = |at: Option<DateTime<Utc>>| {
+        at.map_or_else(
+            || String::from("never"),
+            |at| {
+                at.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            },
+        )
+    };
+
+    let detected = match &screen.detected {
+        Some((at, Ok(ip))) => format!("{ip}, at {}", time(Some(*at))),
+        Some((at, Err(e))) => format!("failed at {}: {e}", time(Some(*at))),
+        None => String::from("press d to detect"),
+    };
+    let last_check = match &status.last_check {
+        Some(check) => Line::from(vec![
+            label("Last check:  "),
+            Span::styled(
+                format!("{}, {}", time(Some(check.at)), check.message),
+                if check.success {
+                    Style::new()
+                } else {
+                    Style::new().fg(Color::Red)
+                },
+            ),
+        ]),
+        None => Line::from(vec![label("Last check:  "), Span::raw("never")]),
+    };
+    let last_change = status.last_change.map_or_else(
+        || String::from("never"),
+        |change| format!("{}, to {}", time(Some(change.at)), change.ip),
+    );
+    let next_check = match screen.busy {
+        Some(busy) => format!("{busy} now…"),
+        None => time(screen.next_check),
+    };
+
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(vec![
+                label("Outside IP:  "),
+                Span::raw(
+                    status
+                        .outside_ip
+                        .map_or_else(|| String::from("unknown"), |ip| ip.to_string()),
+                ),
+            ]),
+            Line::from(vec![label("Detected:    "), Span::raw(detected)]),
+            last_check,
+            Line::from(vec![label("Last change: "), Span::raw(last_change)]),
+            Line::from(vec![label("Next check:  "), Span::raw(next_check)]),
+        ])
+        .block(
+            Block::new()
+                .borders(Borders::ALL)
+                .title(format!(" cdu {} ", status.version)),
+        ),
+        summary,
+    );
+
+    let rows = status.records.iter().map(|record| {
+        let ip = record
+            .ip
+            .map_or_else(|| String::from("unknown"), |ip| ip.to_string());
+        let style = match (&record.error, record.ip) {
+            (Some(_), _) => Style::new().fg(Color::Red),
+            (None, Some(ip)) if Some(ip) == status.outside_ip => Style::new().fg(Color::Green),
+            _ => Style::new(),
+        };
+
+        Row::new(vec![
+            record.domain.clone(),
+            ip,
+            time(record.checked_at),
+            record.error.clone().unwrap_or_default(),
+        ])
+        .style(style)
+    });
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Percentage(30),
+                Constraint::Length(15),
+                Constraint::Length(19),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["Domain", "A record", "Checked", "Error"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::new().borders(Borders::ALL).title(" Domains ")),
+        records,
+    );
+
+    let height = usize::from(logs.height.saturating_sub(2));
+    let lines = {
+        let logs = LOGS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        logs.iter()
+            .skip(logs.len().saturating_sub(height))
+            .map(|line| Line::raw(line.clone()))
+            .collect::<Vec<_>>()
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::new().borders(Borders::ALL).title(" Log ")),
+        logs,
+    );
+
+    frame.render_widget(
+        Paragraph::new(" u: update now   d: detect the outside IP   q: quit")
+            .style(Style::new().fg(Color::DarkGray)),
+        help,
+    );
+}
+
+#[test]
+fn test_strip_ansi() {
+    assert_eq!(
+        strip_ansi("\x1b[32m INFO\x1b[0m \x1b[1mapp\x1b[0m: Config saved"),
+        " INFO app: Config saved"
+    );
+    assert_eq!(strip_ansi("plain"), "plain");
+}
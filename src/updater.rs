@@ -0,0 +1,2856 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::{stream, StreamExt};
+use reqwest::{Client as RqClient, Url};
+use serde::Serialize;
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+
+use crate::audit::{AuditLog, Entry};
+use crate::breaker::Breaker;
+use crate::cloudflare::{self, ARecord, CloudflareError};
+use crate::config::{Config, ConfigError, IpChange};
+use crate::confirm;
+use crate::dnsomatic;
+use crate::exit;
+use crate::geoip::{self, GeoIp};
+use crate::healthchecks::Healthchecks;
+use crate::hint::{self, Category};
+use crate::hooks::Hooks;
+use crate::metrics;
+use crate::monitor::{self, Monitor};
+use crate::network::{self, DetectionError, Family};
+use crate::notify::{
+    self, Destination, EventKind, Message, RecordChange, Recovery, Severity, Subscription, Target,
+};
+use crate::output::{self, Output};
+#[cfg(feature = "plugins")]
+use crate::plugin::Plugins;
+use crate::ptr;
+use crate::push_metrics::{Endpoint, Pusher};
+use crate::queue::Queue;
+use crate::retry;
+#[cfg(feature = "scripting")]
+use crate::script::{Change, Script, Verdict};
+use crate::throttle::Throttle;
+use crate::uptime_kuma::UptimeKuma;
+
+/// How many domains are updated at the same time, unless told otherwise.
+const DEFAULT_PARALLELISM: usize = 4;
+
+/// How many domains a check has to look up, before it tells how far it got along the way.
+const PROGRESS_FROM: usize = 20;
+
+/// The result of a single check/update cycle. With multiple domains, it's the most notable result of
+/// any of them: updated, then dry run, then up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The outside IP is the same as the one seen during the previous cycle.
+    Unchanged(IpAddr),
+    /// The outside IP changed, but Cloudflare already has it.
+    UpToDate(IpAddr),
+    /// The A record at Cloudflare was updated to the outside IP.
+    Updated(IpAddr),
+    /// The A record would have been updated, but this is a dry run.
+    DryRun(IpAddr),
+    /// The outside IP changed, but the A record was updated too recently to update it again. The
+    /// cooldown ends after the duration.
+    Cooldown(IpAddr, Duration),
+    /// The outside IP changed, but the policy script said to leave the A record alone.
+    Vetoed(IpAddr),
+}
+
+impl Outcome {
+    /// Returns what happened as one word, like `up-to-date`.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unchanged(_) => "unchanged",
+            Self::UpToDate(_) => "up-to-date",
+            Self::Updated(_) => "updated",
+            Self::DryRun(_) => "dry-run",
+            Self::Cooldown(..) => "cooldown",
+            Self::Vetoed(_) => "vetoed",
+        }
+    }
+
+    /// Returns the outside IP that was seen.
+    #[must_use]
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Self::Unchanged(ip)
+            | Self::UpToDate(ip)
+            | Self::Updated(ip)
+            | Self::DryRun(ip)
+            | Self::Cooldown(ip, _)
+            | Self::Vetoed(ip) => *ip,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // An AAAA record for the outside IPv6 address
+        let record_type = Family::of(self.ip()).record_type();
+        match self {
+            Self::Unchanged(ip) => write!(f, "outside IP {ip} has not changed"),
+            Self::UpToDate(ip) => write!(f, "Cloudflare IP {ip} is already up to date"),
+            Self::Updated(ip) => write!(f, "{record_type} record updated to {ip}"),
+            Self::DryRun(ip) => write!(f, "dry run, {record_type} record would be updated to {ip}"),
+            Self::Cooldown(ip, remaining) => write!(
+                f,
+                "outside IP changed to {ip}, but the {record_type} record can't be updated for another {}",
+                humantime::format_duration(Duration::from_secs(remaining.as_secs()))
+            ),
+            Self::Vetoed(ip) => write!(
+                f,
+                "outside IP changed to {ip}, but the policy script left the {record_type} record alone"
+            ),
+        }
+    }
+}
+
+/// What's known about the A record of a domain, as of the last time it was looked up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DomainRecord {
+    pub domain: String,
+    /// The IP the A record points at.
+    pub ip: Option<IpAddr>,
+    pub checked_at: Option<DateTime<Utc>>,
+    /// Why the last lookup or update failed, if it did.
+    pub error: Option<String>,
+}
+
+impl DomainRecord {
+    fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            ip: None,
+            checked_at: None,
+            error: None,
+        }
+    }
+}
+
+/// What the last cycle did, with the outside IP it saw and the server that told it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub ip: Option<IpAddr>,
+    pub source: Option<String>,
+    pub domains: Vec<DomainResult>,
+}
+
+/// What the last cycle did with the A record of a domain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DomainResult {
+    pub domain: String,
+    /// The IP the A record pointed at before, as it was looked up or was known.
+    pub previous_ip: Option<IpAddr>,
+    pub ip: Option<IpAddr>,
+    pub action: Action,
+    pub error: Option<String>,
+}
+
+impl DomainResult {
+    /// The A record wasn't looked up, so it's as it was known.
+    fn skipped(record: &DomainRecord) -> Self {
+        Self {
+            domain: record.domain.clone(),
+            previous_ip: record.ip,
+            ip: record.ip,
+            action: Action::Skipped,
+            error: None,
+        }
+    }
+}
+
+/// What was done with an A record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// It wasn't looked up, as it's known to be up to date, or it's too soon to update it.
+    Skipped,
+    UpToDate,
+    Updated,
+    /// It would have been updated, but this is a dry run.
+    WouldUpdate,
+    /// It was changed elsewhere, and left alone as it was asked to be.
+    LeftAlone,
+    Failed,
+}
+
+/// The settings of a domain of its own, which win over the ones for every domain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainSettings {
+    pub name: String,
+    /// The zone the domain is in, instead of `--zone-id`.
+    pub zone_id: Option<String>,
+    /// Whether the traffic goes through Cloudflare, which is left as it is if not set.
+    pub proxied: Option<bool>,
+    /// The TTL in seconds, where 1 is automatic, which is left as it is if not set.
+    pub ttl: Option<u32>,
+    /// The kinds of events to notify about, instead of `--notify-on`.
+    pub notify_on: Option<Vec<EventKind>>,
+    /// The names of the named targets its messages are sent to, instead of every target.
+    pub notify: Option<Vec<String>>,
+    /// Its messages that need less attention than this aren't sent, wherever they'd go.
+    pub notify_severity: Option<Severity>,
+    /// Whether the PTR record of the outside IP is pointed back at the domain.
+    pub ptr: bool,
+    /// The name of the connection of `--wan` whose outside IP the domain is pointed at, instead of
+    /// the one of the default connection.
+    pub wan: Option<String>,
+    /// Whether the domain is pointed at the outside IPv4 address with its A record, or at the
+    /// outside IPv6 address with its AAAA record, instead of following `--ipv6-only`.
+    pub family: Option<Family>,
+    /// The server its outside IP is asked for first, before the others of its family.
+    pub server: Option<String>,
+}
+
+/// Where the outside IP that domains are pointed at is detected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Source {
+    /// The connection of `--wan` it's asked through, instead of the default one.
+    wan: Option<String>,
+    family: Family,
+    /// The server asked first.
+    server: Option<String>,
+}
+
+impl Source {
+    /// Returns the source of the domains without settings of their own.
+    fn default_of(family: Family) -> Self {
+        Self {
+            family,
+            ..Self::default()
+        }
+    }
+}
+
+impl DomainSettings {
+    /// Returns whether a message about the domain goes to the target with `subscription`, as far as
+    /// the domain is concerned. Like [`Subscription::wants`], a recovery goes wherever the alerts
+    /// about the failure went.
+    #[must_use]
+    pub fn routes_to(&self, subscription: &Subscription, message: &Message) -> bool {
+        let named = match &self.notify {
+            Some(names) => subscription
+                .name
+                .as_ref()
+                .is_some_and(|name| names.iter().any(|wanted| wanted.eq_ignore_ascii_case(name))),
+            None => true,
+        };
+        let severity = message
+            .recovery
+            .map_or(message.severity, |recovery| recovery.kind.severity());
+
+        named
+            && match self.notify_severity {
+                Some(min_severity) => severity >= min_severity,
+                None => true,
+            }
+    }
+
+    /// Returns whether the messages about the domain go to some targets only.
+    fn is_routed(&self) -> bool {
+        self.notify.is_some() || self.notify_severity.is_some()
+    }
+}
+
+/// Gives an A record a low TTL when it's pointed at a new IP, as the outside IP tends to change
+/// again soon after, so resolvers don't hold on to the old one for long, and raises it again once
+/// the outside IP stays the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LowTtl {
+    /// The TTL in seconds an A record gets with a change.
+    pub ttl: u32,
+    /// How long the A record has to stay the same, before its TTL is raised again, to the one in
+    /// the settings of the domain, or to automatic.
+    pub until_stable_for: Duration,
+}
+
+/// The A record doesn't point at the outside IP, right after it was changed to it.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub domain: String,
+    pub expected: IpAddr,
+    pub actual: IpAddr,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The A record of {} points at {} instead of {} after updating it",
+            self.domain, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Performs the check/update cycle.
+///
+/// The HTTP client, Cloudflare handler and configuration are kept between cycles, so that
+/// running it repeatedly (as the daemon does) doesn't start from scratch every time.
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Updater {
+    /// Sends every request, with its connections kept open between cycles. The notifications, and
+    /// everything else that isn't part of the update itself, take its timeout, and the detection
+    /// and Cloudflare their own.
+    client: RqClient,
+    /// Asks the servers that tell the outside IP, which is `client` unless they're asked without
+    /// the proxy.
+    detection_client: RqClient,
+    cloudflare: cloudflare::Handler,
+    config: Config,
+    notifiers: Vec<Destination>,
+    queue: Queue,
+    throttle: Throttle,
+    notify: Vec<(Target, Subscription)>,
+    notify_on: Vec<EventKind>,
+    notify_per_domain: bool,
+    heartbeat_every: Option<Duration>,
+    monitors: Vec<Box<dyn Monitor>>,
+    push_metrics: Option<Pusher>,
+    audit_log: Option<AuditLog>,
+    /// How often a request that failed is sent again.
+    retry: retry::Policy,
+    /// How many requests a minute may be sent to Cloudflare, if there's a limit.
+    rate_limit: Option<u32>,
+    /// Skips the servers that tell the outside IP, and the notification targets, that keep
+    /// failing.
+    breaker: Breaker,
+    /// Whether the domains are pointed at the outside IPv4 address with their A records, or at the
+    /// outside IPv6 address with their AAAA records.
+    family: Family,
+    /// The servers that tell the outside IP, in the order they're asked.
+    servers: Vec<String>,
+    /// The connections of `--wan`, with a client that asks the servers through each.
+    wans: Vec<(String, RqClient)>,
+    /// The outside IP of each source other than the default one that domains are pointed at, as
+    /// of this check.
+    source_ips: Vec<(Source, IpAddr)>,
+    geoip: Option<GeoIp>,
+    hooks: Hooks,
+    /// Points the PTR record of the outside IP at the domains that want it.
+    ptr: Option<ptr::Server>,
+    /// Keeps a TXT record next to the A record of every domain, telling when it was updated.
+    txt_record: bool,
+    /// Passes the outside IP on to the services linked to an account of DNS-O-Matic.
+    dnsomatic: Option<dnsomatic::Account>,
+    /// Detects the outside IP before the servers are asked, and points the domains at it with
+    /// other providers.
+    #[cfg(feature = "plugins")]
+    plugins: Plugins,
+    /// Decides whether a change of the outside IP is updated, and to what.
+    #[cfg(feature = "scripting")]
+    policy: Option<Script>,
+    api_key: String,
+    /// The API token that's used instead when Cloudflare refuses `api_key`, and the other way
+    /// around.
+    fallback_api_key: Option<String>,
+    zone_id: String,
+    domains: Vec<String>,
+    domain_settings: Vec<DomainSettings>,
+    records: Vec<DomainRecord>,
+    report: Report,
+    dry_run: bool,
+    cooldown: Option<Duration>,
+    reconcile_every: Option<Duration>,
+    max_state_age: Option<Duration>,
+    low_ttl: Option<LowTtl>,
+    /// Leaves an A record that was changed elsewhere alone, instead of changing it back.
+    leave_drift: bool,
+    /// Asks at the terminal before changing a record, for a run by hand.
+    confirm: bool,
+    force: bool,
+    output: Output,
+    parallelism: usize,
+}
+
+impl Updater {
+    /// Returns the updater of the A records of `domains`, in the zone `zone_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no domain, or the API token has characters a header can't have.
+    pub fn try_new(
+        api_key: &str,
+        zone_id: &str,
+        domains: &[&str],
+        dry_run: bool,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(!domains.is_empty(), "No domain to update");
+
+        let client = config.transport.client(config.timeouts.notify);
+        let detection_client = if config.transport.direct_detection {
+            config.transport.detection_client(config.timeouts.detection)
+        } else {
+            client.clone()
+        };
+        let mut notifiers = Vec::new();
+        if let Some(url) = &config.webhook_url {
+            notifiers.push(Destination::new(
+                &Target::Discord(url.clone()),
+                &Subscription::default(),
+                &client,
+            ));
+        }
+        let queue = Queue::load(&config.save_dir);
+        let throttle = Throttle::load(&config.save_dir);
+        let mut breaker = Breaker::default();
+        breaker.restore(&config.health, Instant::now(), Utc::now());
+
+        Ok(Self {
+            cloudflare: cloudflare::Handler::try_new(api_key)?
+                .with_api_url(&config.api_url)
+                .with_client(client.clone())
+                .with_timeout(config.timeouts.api),
+            client,
+            detection_client,
+            notifiers,
+            queue,
+            throttle,
+            notify: Vec::new(),
+            notify_on: vec![EventKind::Updated],
+            notify_per_domain: false,
+            heartbeat_every: None,
+            monitors: Vec::new(),
+            push_metrics: None,
+            audit_log: None,
+            retry: retry::Policy::default(),
+            rate_limit: None,
+            breaker,
+            family: Family::V4,
+            servers: network::SERVERS.iter().map(ToString::to_string).collect(),
+            wans: Vec::new(),
+            source_ips: Vec::new(),
+            geoip: None,
+            hooks: Hooks::default(),
+            ptr: None,
+            txt_record: false,
+            dnsomatic: None,
+            #[cfg(feature = "plugins")]
+            plugins: Plugins::default(),
+            #[cfg(feature = "scripting")]
+            policy: None,
+            api_key: api_key.to_string(),
+            fallback_api_key: None,
+            zone_id: zone_id.to_string(),
+            domains: domains.iter().map(ToString::to_string).collect(),
+            domain_settings: Vec::new(),
+            // What's known from the last run, until the records are looked up again
+            records: domains
+                .iter()
+                .map(|domain| {
+                    let mut record = DomainRecord::new(domain);
+                    if let Some(state) = config.record(domain) {
+                        record.ip = state.ip;
+                        record.checked_at = state.checked_at;
+                    }
+                    record
+                })
+                .collect(),
+            report: Report::default(),
+            dry_run,
+            cooldown: None,
+            reconcile_every: None,
+            max_state_age: None,
+            low_ttl: None,
+            leave_drift: false,
+            confirm: false,
+            force: false,
+            output: Output::Text,
+            parallelism: DEFAULT_PARALLELISM,
+            config,
+        })
+    }
+
+    /// Sends notifications to the targets, besides the webhook from the configuration.
+    #[must_use]
+    pub fn with_notify(mut self, notify: Vec<(Target, Subscription)>) -> Self {
+        let destinations = notify
+            .iter()
+            .map(|(target, subscription)| {
+                let host = target.address().map(|(host, _)| host);
+                Destination::new(target, subscription, &self.client_for(host.as_deref()))
+            })
+            .collect::<Vec<_>>();
+        self.notifiers.extend(destinations);
+        self.notify = notify;
+        self
+    }
+
+    /// Keeps trying to send a notification that failed for this long.
+    #[must_use]
+    pub fn with_notify_retry_for(mut self, retry_for: Duration) -> Self {
+        self.queue.set_retry_for(retry_for);
+        self
+    }
+
+    /// Holds back the same alert for this long, after sending it.
+    #[must_use]
+    pub fn with_notify_cooldown(mut self, cooldown: Duration) -> Self {
+        self.throttle.set_cooldown(cooldown);
+        self
+    }
+
+    /// Only alerts about a failure once it came up in `alert_after` checks in a row, and only tells
+    /// it's over if it was alerted about.
+    #[must_use]
+    pub fn with_alert_after(mut self, alert_after: u32) -> Self {
+        self.throttle.set_alert_after(alert_after);
+        self
+    }
+
+    /// Only sends notifications about these kinds of events, instead of just updates.
+    #[must_use]
+    pub fn with_notify_on(mut self, notify_on: Vec<EventKind>) -> Self {
+        self.notify_on = notify_on;
+        self
+    }
+
+    /// Sends a message for every domain that was updated, instead of one for all of them.
+    #[must_use]
+    pub fn with_notify_per_domain(mut self, notify_per_domain: bool) -> Self {
+        self.notify_per_domain = notify_per_domain;
+        self
+    }
+
+    /// Tells cdu is still checking once per `heartbeat_every`, after a check that went well.
+    #[must_use]
+    pub fn with_heartbeat_every(mut self, heartbeat_every: Option<Duration>) -> Self {
+        self.heartbeat_every = heartbeat_every;
+        self
+    }
+
+    /// Pings the Healthchecks.io check at `url` when a run starts and ends.
+    #[must_use]
+    pub fn with_healthchecks(mut self, url: Option<&Url>) -> Self {
+        if let Some(url) = url {
+            let client = self.client_for(url.host_str());
+            self.monitors.push(Box::new(Healthchecks::new(client, url)));
+        }
+        self
+    }
+
+    /// Pushes to the Uptime Kuma push monitor at `url` when a run ends.
+    #[must_use]
+    pub fn with_uptime_kuma(mut self, url: Option<&Url>) -> Self {
+        if let Some(url) = url {
+            let client = self.client_for(url.host_str());
+            self.monitors.push(Box::new(UptimeKuma::new(client, url)));
+        }
+        self
+    }
+
+    /// Pushes the metrics of every run to StatsD, InfluxDB or the Pushgateway at `endpoint`, with
+    /// the API token of InfluxDB if it needs one.
+    #[must_use]
+    pub fn with_push_metrics(mut self, endpoint: Option<&Endpoint>, token: Option<String>) -> Self {
+        self.push_metrics = endpoint.map(|endpoint| {
+            let host = match endpoint {
+                Endpoint::InfluxHttp(url) | Endpoint::Pushgateway(url) => url.host_str(),
+                Endpoint::Statsd(_) | Endpoint::InfluxUdp(_) => None,
+            };
+            Pusher::new(self.client_for(host), endpoint.clone(), token)
+        });
+        self
+    }
+
+    /// Appends every change of an A record, and every one that failed, to the audit log at `path`.
+    #[must_use]
+    pub fn with_audit_log(mut self, path: Option<&Path>) -> Self {
+        self.audit_log = path.map(AuditLog::new);
+        self
+    }
+
+    /// Sends the requests to Cloudflare, the detection and the notifications again when they fail
+    /// in a way the next try may not, as `retry` says.
+    #[must_use]
+    pub fn with_retry(mut self, retry: retry::Policy) -> Self {
+        self.cloudflare = self.cloudflare.with_retry(retry);
+        self.retry = retry;
+        self
+    }
+
+    /// Sends no more than `per_minute` requests a minute to Cloudflare, over every domain, so cdu
+    /// leaves room in the limit of the account for the other tools that use it.
+    #[must_use]
+    pub fn with_rate_limit(mut self, per_minute: Option<u32>) -> Self {
+        self.cloudflare = self.cloudflare.with_rate_limit(per_minute);
+        self.rate_limit = per_minute;
+        self
+    }
+
+    /// Skips a server that tells the outside IP, or a notification target, for `cooldown` after
+    /// `failures` in a row. With zero failures, none is ever skipped.
+    #[must_use]
+    pub fn with_circuit_breaker(mut self, failures: u32, cooldown: Duration) -> Self {
+        let mut breaker = Breaker::new(failures, cooldown);
+        breaker.adopt(&self.breaker);
+        self.breaker = breaker;
+        self
+    }
+
+    /// Points the domains at the outside IP of `family`, with their AAAA records for
+    /// [`Family::V6`], asking the servers of the family for it, unless they're set after.
+    #[must_use]
+    pub fn with_family(mut self, family: Family) -> Self {
+        self.family = family;
+        self.servers = family.servers().iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Asks these servers for the outside IP instead of the ones of the family, like
+    /// [`network::SERVERS`], as host names or URLs.
+    #[must_use]
+    pub fn with_servers(mut self, servers: Vec<String>) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// Detects the outside IP of each of `wans` as well, for the domains with it in their
+    /// settings.
+    #[must_use]
+    pub fn with_wans(mut self, wans: Vec<(String, RqClient)>) -> Self {
+        self.wans = wans;
+        self
+    }
+
+    /// Tells who the new IP belongs to, and where it is, in the notifications about updates,
+    /// looking it up in these sources.
+    #[must_use]
+    pub fn with_geoip(mut self, sources: Vec<geoip::Source>) -> Self {
+        self.geoip = (!sources.is_empty()).then(|| GeoIp::new(self.client.clone(), sources));
+        self
+    }
+
+    /// Runs these commands before detecting the outside IP, after updating a domain and after a
+    /// failure.
+    #[must_use]
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Points the PTR record of the outside IP at every domain with `ptr` in its settings, with
+    /// this nameserver, after it's updated.
+    #[must_use]
+    pub fn with_ptr(mut self, ptr: Option<ptr::Server>) -> Self {
+        self.ptr = ptr;
+        self
+    }
+
+    /// Sets the TXT record `_cdu.<domain>` of every domain that was updated to when it was, the
+    /// version of cdu and where the outside IP came from, so monitoring can tell from the DNS alone
+    /// that it's fresh.
+    #[must_use]
+    pub fn with_txt_record(mut self, txt_record: bool) -> Self {
+        self.txt_record = txt_record;
+        self
+    }
+
+    /// Passes the outside IP on to DNS-O-Matic with this account, whenever it changes or an A
+    /// record is updated to it.
+    #[must_use]
+    pub fn with_dnsomatic(mut self, dnsomatic: Option<dnsomatic::Account>) -> Self {
+        self.dnsomatic = dnsomatic;
+        self
+    }
+
+    /// Asks the detection plugins for the outside IP before the servers, and has the provider
+    /// plugins point every domain that was updated at it.
+    #[cfg(feature = "plugins")]
+    pub fn with_plugins(mut self, plugins: Plugins) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    /// Runs the policy script every time the outside IP changed, to decide whether to update the A
+    /// records, and to what.
+    #[cfg(feature = "scripting")]
+    pub fn with_policy(mut self, policy: Option<Script>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Switches to `api_key` when Cloudflare refuses the API token, and back when it refuses that
+    /// one, starting with the one the state says was in use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API token has characters a header can't have.
+    pub fn with_fallback_api_key(mut self, api_key: Option<&str>) -> anyhow::Result<Self> {
+        self.fallback_api_key = api_key.map(ToString::to_string);
+        match api_key {
+            Some(api_key) if self.config.fallback_token => self.cloudflare.set_api_key(api_key)?,
+            Some(_) => {}
+            None => self.config.fallback_token = false,
+        }
+
+        Ok(self)
+    }
+
+    /// Gives domains settings of their own, like the zone they're in.
+    #[must_use]
+    pub fn with_domain_settings(mut self, domain_settings: Vec<DomainSettings>) -> Self {
+        self.domain_settings = domain_settings;
+        self
+    }
+
+    /// Makes sure no more than `parallelism` domains are looked up or updated at the same time.
+    #[must_use]
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Makes sure the A record is checked at least once per `reconcile_every`, even if the outside
+    /// IP didn't change, so changes made elsewhere are undone.
+    #[must_use]
+    pub fn with_reconcile_every(mut self, reconcile_every: Option<Duration>) -> Self {
+        self.reconcile_every = reconcile_every;
+        self
+    }
+
+    /// Checks the A record of a domain at Cloudflare anyway once what's known about it is older
+    /// than `max_state_age`, so a state that's wrong can't keep cdu from doing its work for longer.
+    #[must_use]
+    pub fn with_max_state_age(mut self, max_state_age: Option<Duration>) -> Self {
+        self.max_state_age = max_state_age;
+        self
+    }
+
+    /// Lowers the TTL of an A record when it's changed, and raises it again once it stayed the
+    /// same for long enough, see [`LowTtl`].
+    #[must_use]
+    pub fn with_low_ttl(mut self, low_ttl: Option<LowTtl>) -> Self {
+        self.low_ttl = low_ttl;
+        self
+    }
+
+    /// Leaves an A record that points at another IP than the one cdu last pointed it at, as it was
+    /// changed elsewhere, instead of changing it back. Either way, it's alerted about.
+    #[must_use]
+    pub fn with_leave_drift(mut self, leave_drift: bool) -> Self {
+        self.leave_drift = leave_drift;
+        self
+    }
+
+    /// Prints exactly how each record would change and asks whether to go ahead first, like
+    /// [`confirm::confirm`], for a run by hand. One that isn't confirmed is left as it is, as in a
+    /// dry run.
+    #[must_use]
+    pub fn with_confirm(mut self, confirm: bool) -> Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// Checks every A record at Cloudflare on the next cycle, even if the outside IP didn't change,
+    /// and updates the ones that don't point at it, whatever the state says.
+    #[must_use]
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Prints the events of every check on stdout as they happen, with [`Output::Ndjson`].
+    #[must_use]
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Prints an event, if they're printed.
+    fn emit(&self, name: &str, fields: serde_json::Value) {
+        if self.output == Output::Ndjson {
+            output::emit(name, fields);
+        }
+    }
+
+    /// Makes sure the A record isn't updated more than once per `cooldown`, no matter how often
+    /// the outside IP changes.
+    #[must_use]
+    pub fn with_cooldown(mut self, cooldown: Option<Duration>) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// Returns the family of the outside IP the domains are pointed at.
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    /// Returns the HTTP client, for requests that should share its connections.
+    pub fn client(&self) -> &RqClient {
+        &self.client
+    }
+
+    /// Returns the HTTP client for the requests to `host`, which doesn't check its certificate if
+    /// it's one of the insecure hosts of the transport.
+    fn client_for(&self, host: Option<&str>) -> RqClient {
+        self.config
+            .transport
+            .client_for(&self.client, host, self.config.timeouts.notify)
+    }
+
+    /// Returns the HTTP client the outside IP is detected with.
+    pub fn detection_client(&self) -> &RqClient {
+        &self.detection_client
+    }
+
+    /// Returns the A records of the domains, in the same order.
+    pub fn records(&self) -> &[DomainRecord] {
+        &self.records
+    }
+
+    /// Returns what the last cycle did.
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Returns the IP the A record was last changed to, and when, as far as the configuration file
+    /// knows.
+    pub fn last_change(&self) -> Option<(IpAddr, DateTime<Utc>)> {
+        self.config
+            .cloudflare_ip
+            .map(|ip| (ip, self.config.last_updated))
+    }
+
+    /// Returns the settings that are durations, by their names.
+    fn durations(&self) -> [(&'static str, Option<Duration>); 6] {
+        let timeouts = self.config.timeouts;
+
+        [
+            ("cooldown", self.cooldown),
+            ("reconcile every", self.reconcile_every),
+            ("max state age", self.max_state_age),
+            ("detection timeout", Some(timeouts.detection)),
+            ("API timeout", Some(timeouts.api)),
+            ("notify timeout", Some(timeouts.notify)),
+        ]
+    }
+
+    /// Describes how the settings of `other` differ from these, without giving away any secrets.
+    #[allow(clippy::too_many_lines)]
+    pub fn changes(&self, other: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        if self.domains != other.domains {
+            changes.push(format!(
+                "domains: {} -> {}",
+                self.domains.join(", "),
+                other.domains.join(", ")
+            ));
+        }
+        if self.zone_id != other.zone_id {
+            changes.push(format!("zone ID: {} -> {}", self.zone_id, other.zone_id));
+        }
+        if self.domain_settings != other.domain_settings {
+            changes.push(String::from("domain settings"));
+        }
+        if self.api_key != other.api_key {
+            changes.push(String::from("API key"));
+        }
+        match (&self.fallback_api_key, &other.fallback_api_key) {
+            (None, Some(_)) => changes.push(String::from("fallback API key: added")),
+            (Some(_), None) => changes.push(String::from("fallback API key: removed")),
+            (Some(old), Some(new)) if old != new => changes.push(String::from("fallback API key")),
+            _ => {}
+        }
+        if self.dry_run != other.dry_run {
+            changes.push(format!("dry run: {} -> {}", self.dry_run, other.dry_run));
+        }
+        if self.family != other.family {
+            changes.push(format!(
+                "records: {} -> {}",
+                self.family.record_type(),
+                other.family.record_type()
+            ));
+        }
+        if self.rate_limit != other.rate_limit {
+            let describe = |rate_limit: Option<u32>| {
+                rate_limit.map_or_else(|| String::from("off"), |rate| format!("{rate}/min"))
+            };
+            changes.push(format!(
+                "API rate limit: {} -> {}",
+                describe(self.rate_limit),
+                describe(other.rate_limit)
+            ));
+        }
+        if self.parallelism != other.parallelism {
+            changes.push(format!(
+                "parallelism: {} -> {}",
+                self.parallelism, other.parallelism
+            ));
+        }
+        let describe = |duration: Option<Duration>| {
+            duration.map_or_else(
+                || String::from("off"),
+                |duration| humantime::format_duration(duration).to_string(),
+            )
+        };
+        for ((name, old), (_, new)) in self.durations().into_iter().zip(other.durations()) {
+            if old != new {
+                changes.push(format!("{name}: {} -> {}", describe(old), describe(new)));
+            }
+        }
+        if self.leave_drift != other.leave_drift {
+            let describe = |leave_drift| if leave_drift { "leave" } else { "repair" };
+            changes.push(format!(
+                "on drift: {} -> {}",
+                describe(self.leave_drift),
+                describe(other.leave_drift)
+            ));
+        }
+        if self.low_ttl != other.low_ttl {
+            let describe = |low_ttl: Option<LowTtl>| {
+                low_ttl.map_or_else(
+                    || String::from("off"),
+                    |low_ttl| {
+                        format!(
+                            "{}s until stable for {}",
+                            low_ttl.ttl,
+                            humantime::format_duration(low_ttl.until_stable_for)
+                        )
+                    },
+                )
+            };
+            changes.push(format!(
+                "low TTL: {} -> {}",
+                describe(self.low_ttl),
+                describe(other.low_ttl)
+            ));
+        }
+        if self.ptr != other.ptr {
+            changes.push(String::from("PTR server"));
+        }
+        if self.dnsomatic != other.dnsomatic {
+            changes.push(String::from("DNS-O-Matic account"));
+        }
+        if self.txt_record != other.txt_record {
+            let describe = |txt_record| if txt_record { "on" } else { "off" };
+            changes.push(format!(
+                "TXT record: {} -> {}",
+                describe(self.txt_record),
+                describe(other.txt_record)
+            ));
+        }
+        let names = |wans: &[(String, RqClient)]| {
+            wans.iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if names(&self.wans) != names(&other.wans) {
+            let describe = |names: String| {
+                if names.is_empty() {
+                    String::from("off")
+                } else {
+                    names
+                }
+            };
+            changes.push(format!(
+                "WAN connections: {} -> {}",
+                describe(names(&self.wans)),
+                describe(names(&other.wans))
+            ));
+        }
+        if self.output != other.output {
+            changes.push(format!("output: {} -> {}", self.output, other.output));
+        }
+        match (&self.config.webhook_url, &other.config.webhook_url) {
+            (None, Some(_)) => changes.push(String::from("webhook URL: added")),
+            (Some(_), None) => changes.push(String::from("webhook URL: removed")),
+            (Some(old), Some(new)) if old != new => changes.push(String::from("webhook URL")),
+            _ => {}
+        }
+        if self.notify != other.notify {
+            let describe = |notify: &[(Target, Subscription)]| {
+                if notify.is_empty() {
+                    String::from("off")
+                } else {
+                    notify
+                        .iter()
+                        .map(|(target, _)| target.kind())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            };
+            changes.push(format!(
+                "notify: {} -> {}",
+                describe(&self.notify),
+                describe(&other.notify)
+            ));
+        }
+        if self.notify_on != other.notify_on {
+            let describe = |notify_on: &[EventKind]| {
+                EventKind::ALL
+                    .iter()
+                    .filter(|(_, kind)| notify_on.contains(kind))
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            changes.push(format!(
+                "notify on: {} -> {}",
+                describe(&self.notify_on),
+                describe(&other.notify_on)
+            ));
+        }
+        if self.config.save_dir != other.config.save_dir {
+            changes.push(format!(
+                "config directory: {} -> {}",
+                self.config.save_dir.display(),
+                other.config.save_dir.display()
+            ));
+        }
+
+        changes
+    }
+
+    /// Runs a single check/update cycle.
+    ///
+    /// The outside IP is only determined once, after which the domains are looked up and updated
+    /// concurrently. A domain that fails doesn't stop the others.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outside IP cannot be determined, or if Cloudflare cannot be
+    /// queried or updated for any of the domains.
+    #[tracing::instrument(skip(self), fields(domains = %self.domains.join(",")))]
+    pub async fn run(&mut self) -> anyhow::Result<Outcome> {
+        let started = Instant::now();
+        monitor::start_all(&self.monitors).await;
+        self.send_queued().await;
+        self.emit("check_started", json!({ "domains": self.domains }));
+
+        let result = self.check().await;
+        match &result {
+            Ok(outcome) => self.emit(
+                "check_finished",
+                json!({ "outcome": outcome.name(), "ip": outcome.ip() }),
+            ),
+            Err(e) => self.emit(
+                "error",
+                json!({ "error": format!("{e:#}"), "exit_code": exit::code(e) }),
+            ),
+        }
+        if let Ok(Outcome::Unchanged(ip) | Outcome::UpToDate(ip)) = &result {
+            self.raise_ttls(*ip).await;
+        }
+        let now = Utc::now();
+        if let Ok(outcome) = &result {
+            if self.is_heartbeat_due(now) {
+                let domains = self.domains.join(", ");
+                let changed_at = self.last_change().map(|(_, at)| at);
+                self.notify(Message::heartbeat(&domains, outcome.ip(), changed_at))
+                    .await;
+                self.config.last_heartbeat = Some(now);
+            }
+            // Saved after every check that succeeded, so `cdu healthcheck` can tell from the state
+            self.config.last_success_at = Some(now);
+            if let Err(e) = self.save_state() {
+                error!("Error: {e}");
+            }
+        }
+
+        monitor::finish_all(&self.monitors, &result, started.elapsed()).await;
+        if let Some(pusher) = &self.push_metrics {
+            let source = self.report.source.as_deref();
+            match pusher.push(&result, source, started.elapsed()).await {
+                Ok(()) => debug!("Pushed the metrics of the run"),
+                Err(e) => warn!("Failed to push the metrics of the run: {e:#}"),
+            }
+        }
+
+        result
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn check(&mut self) -> anyhow::Result<Outcome> {
+        self.hooks.before(&self.domains.join(", ")).await;
+        self.report = Report {
+            domains: self.records.iter().map(DomainResult::skipped).collect(),
+            ..Report::default()
+        };
+
+        let (outside_ip, source) = match self.detect().await {
+            Ok(detected) => {
+                let domains = self.domains.join(", ");
+                self.resolve(EventKind::DetectionFailed, &domains).await;
+
+                detected
+            }
+            Err(e) => {
+                let domains = self.domains.join(", ");
+                let message = Message::failed(
+                    EventKind::DetectionFailed,
+                    &domains,
+                    None,
+                    &format!("{e:#}"),
+                );
+                self.hooks.after(&message).await;
+                self.notify(message).await;
+
+                return Err(exit::wrap(exit::DETECTION_FAILED, e));
+            }
+        };
+
+        self.report.ip = Some(outside_ip);
+        self.report.source = Some(source.clone());
+        self.emit("ip_detected", json!({ "ip": outside_ip, "source": source }));
+        // The domains of a connection whose outside IP isn't known are left out of this check
+        let cut_off = self.detect_sources().await;
+
+        let now = Utc::now();
+        let mut out_of_sync = self.out_of_sync(outside_ip);
+        out_of_sync.retain(|index| !cut_off.contains(index));
+        let unchanged = out_of_sync.is_empty();
+        let force = std::mem::take(&mut self.force);
+        let reconcile = force || self.is_reconcile_due(now);
+        let mut too_old = self.stale(now);
+        too_old.retain(|index| !cut_off.contains(index));
+
+        if unchanged && !reconcile && too_old.is_empty() {
+            info!("Outside IP has not changed. Nothing to do.");
+            let domains = self.domains.join(", ");
+            self.notify(Message::unchanged(&domains, outside_ip).with_source(&source))
+                .await;
+
+            return Ok(Outcome::Unchanged(outside_ip));
+        }
+        if unchanged && force {
+            info!("Outside IP has not changed, but checking the A record anyway, as it's forced");
+        } else if unchanged && reconcile {
+            info!("Outside IP has not changed, but it's time to check the A record anyway");
+        } else if unchanged {
+            let domains = too_old
+                .iter()
+                .map(|&index| self.domains[index].as_str())
+                .collect::<Vec<_>>();
+            info!(
+                "Outside IP has not changed, but the state of {} is too old to trust, checking anyway",
+                domains.join(", ")
+            );
+        }
+
+        // Leave the outside IP alone, so the next cycle tries again
+        if !unchanged {
+            if let Some(remaining) = self.cooldown_remaining(now) {
+                info!("The A record was updated too recently to update it again");
+
+                return Ok(Outcome::Cooldown(outside_ip, remaining));
+            }
+        }
+        #[cfg(feature = "scripting")]
+        let outside_ip = match self.decide(outside_ip, &source, unchanged)? {
+            Verdict::Update => outside_ip,
+            Verdict::UpdateTo(ip) => {
+                info!("The policy script updates to {ip} instead of {outside_ip}");
+                ip
+            }
+            Verdict::Skip => {
+                info!("The policy script leaves the A record alone for now");
+
+                return Ok(Outcome::Vetoed(outside_ip));
+            }
+        };
+
+        debug!("Outside IP: {}", outside_ip);
+
+        // Every domain when reconciling, and otherwise the ones that aren't known to be up to date
+        let checking = if reconcile {
+            (0..self.domains.len())
+                .filter(|index| !cut_off.contains(index))
+                .collect()
+        } else {
+            let mut checking = out_of_sync;
+            checking.extend(too_old);
+            checking.sort_unstable();
+            checking.dedup();
+            checking
+        };
+        let mut results = self.update_domains(&checking, outside_ip).await;
+        // Cloudflare refusing every domain means it refuses the API token, which the other may not
+        let refused = results.iter().all(|result| {
+            result.as_ref().is_err_and(|e| {
+                matches!(
+                    hint::category_of(e),
+                    Some(Category::Authentication | Category::Authorization)
+                )
+            })
+        });
+        if !results.is_empty() && refused && self.switch_token(&results).await {
+            results = self.update_domains(&checking, outside_ip).await;
+        }
+
+        let mut outcome = Outcome::UpToDate(outside_ip);
+        let lowering = self.low_ttl.is_some();
+        let mut updated = Vec::new();
+        let mut failures = Vec::new();
+        let mut succeeded = Vec::new();
+        let mut drifts = Vec::new();
+        for (&index, result) in checking.iter().zip(results) {
+            let domain = self.domains[index].clone();
+            let target_ip = self.target_ip(&domain, outside_ip);
+            // Changed elsewhere, as it points at neither the outside IP nor the IP it was last
+            // pointed at, and left alone if it's still up to date with that
+            let written = self.config.record(&domain).and_then(|state| state.ip);
+            let drifted = result
+                .as_ref()
+                .ok()
+                .map(|(_, found)| found.ip)
+                .filter(|&ip| ip != target_ip && written.is_some_and(|written| written != ip));
+            let left_alone = drifted.is_some() && matches!(result, Ok((Outcome::UpToDate(_), _)));
+            let record = &mut self.records[index];
+            let checked_at = Utc::now();
+            record.checked_at = Some(checked_at);
+            record.error = result.as_ref().err().map(|e| format!("{e:#}"));
+            if let Ok((outcome, found)) = &result {
+                record.ip = Some(match outcome {
+                    Outcome::Updated(ip) => *ip,
+                    _ => found.ip,
+                });
+            }
+            let done = &mut self.report.domains[index];
+            if let Ok((outcome, found)) = &result {
+                done.previous_ip = Some(found.ip);
+                done.ip = record.ip;
+                done.action = match outcome {
+                    Outcome::Updated(_) => Action::Updated,
+                    Outcome::DryRun(_) => Action::WouldUpdate,
+                    _ if left_alone => Action::LeftAlone,
+                    _ => Action::UpToDate,
+                };
+            } else {
+                done.action = Action::Failed;
+                done.error.clone_from(&record.error);
+            }
+            if self.output == Output::Ndjson {
+                let name = match done.action {
+                    Action::Skipped => "record_skipped",
+                    Action::UpToDate => "record_up_to_date",
+                    Action::Updated => "record_updated",
+                    Action::WouldUpdate => "record_would_update",
+                    Action::LeftAlone => "record_left_alone",
+                    Action::Failed => "record_failed",
+                };
+                output::emit(
+                    name,
+                    json!({
+                        "domain": done.domain,
+                        "previous_ip": done.previous_ip,
+                        "ip": done.ip,
+                        "error": done.error,
+                    }),
+                );
+            }
+
+            // A domain that failed is tried again on the next cycle, whatever the outside IP is,
+            // and one that was left alone isn't pointed at the IP it was changed to
+            let state = self.config.record_mut(&domain);
+            state.checked_at = Some(checked_at);
+            state.ip = if left_alone {
+                written
+            } else {
+                record.ip.filter(|_| result.is_ok())
+            };
+            if let Ok((outcome, found)) = &result {
+                state.record_id = Some(found.id.clone());
+                if let Outcome::Updated(_) = outcome {
+                    state.last_updated = Some(checked_at);
+                    if lowering && !self.config.lowered_ttls.contains(&domain) {
+                        self.config.lowered_ttls.push(domain.clone());
+                    }
+                }
+            }
+
+            if result.is_ok() {
+                succeeded.push(domain.clone());
+            }
+            if let (Some(ip), Some(written)) = (drifted, written) {
+                drifts.push((domain.clone(), ip, written, left_alone));
+            }
+            match result {
+                Ok((
+                    Outcome::Updated(_),
+                    ARecord {
+                        ip: previous_ip, ..
+                    },
+                )) => {
+                    updated.push((domain.clone(), previous_ip, target_ip));
+                    outcome = Outcome::Updated(outside_ip);
+                }
+                Ok((Outcome::DryRun(_), _)) if outcome == Outcome::UpToDate(outside_ip) => {
+                    outcome = Outcome::DryRun(outside_ip);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!(event = "update_failed", %domain, "Failed to update {domain}: {e:#}");
+                    failures.push((domain.clone(), e));
+                }
+            }
+        }
+        if self.domains.len() > 1 {
+            info!(
+                "{} of {} domains updated, {} failed",
+                updated.len(),
+                self.domains.len(),
+                failures.len()
+            );
+        }
+
+        // Save the state of the records, so the next cycle can exit early if none of them need a
+        // change, and the outside IP, which only says what it was
+        let domains = &self.domains;
+        self.config
+            .records
+            .retain(|state| domains.contains(&state.domain));
+        self.config
+            .lowered_ttls
+            .retain(|domain| domains.contains(domain));
+        let changed_from = self.config.outside_ip.filter(|&ip| ip != outside_ip);
+        if let Some(old_ip) = changed_from {
+            info!(
+                event = "ip_changed",
+                %old_ip,
+                new_ip = %outside_ip,
+                "Outside IP changed from {old_ip} to {outside_ip}"
+            );
+            self.config.record_change(IpChange {
+                at: now,
+                old_ip,
+                new_ip: outside_ip,
+                source: source.clone(),
+                updated: updated.iter().map(|(domain, ..)| domain.clone()).collect(),
+            });
+        }
+        self.config.outside_ip = Some(outside_ip);
+        if reconcile && failures.len() < checking.len() {
+            self.config.last_reconciled = Some(now);
+        }
+        let previous_change_at = self.last_change().map(|(_, at)| at);
+        if !updated.is_empty() {
+            self.config.cloudflare_ip = Some(outside_ip);
+            self.config.last_updated = Utc::now();
+        }
+        self.save_config();
+
+        if let Some(old_ip) = changed_from {
+            let domains = self.domains.join(", ");
+            self.notify(Message::ip_changed(&domains, old_ip, outside_ip).with_source(&source))
+                .await;
+        }
+        for (domain, previous_ip, ip) in &updated {
+            self.hooks
+                .after(&Message::updated(domain, Some(*previous_ip), *ip))
+                .await;
+            self.set_ptr(domain, *ip).await;
+            self.set_txt_record(domain, &source).await;
+            #[cfg(feature = "plugins")]
+            self.plugins
+                .update(&self.client, self.config.timeouts.notify, domain, *ip)
+                .await;
+        }
+        if changed_from.is_some() || updated.iter().any(|(_, _, ip)| *ip == outside_ip) {
+            self.update_dnsomatic(outside_ip).await;
+        }
+        // Domains pointed at the outside IPs of different connections, or whose messages go to
+        // some targets only, are told about one by one
+        let routed = |domain: &str| {
+            self.settings_of(domain)
+                .is_some_and(DomainSettings::is_routed)
+        };
+        let together = updated
+            .iter()
+            .all(|(domain, _, ip)| *ip == outside_ip && !routed(domain));
+        let messages = if updated.len() > 1 && !self.notify_per_domain && together {
+            let records = updated
+                .into_iter()
+                .map(|(domain, previous_ip, _)| RecordChange {
+                    domain,
+                    old_ip: Some(previous_ip),
+                })
+                .collect();
+            vec![Message::digest(records, outside_ip)]
+        } else {
+            updated
+                .into_iter()
+                .map(|(domain, previous_ip, ip)| Message::updated(&domain, Some(previous_ip), ip))
+                .collect()
+        };
+        let network = match &self.geoip {
+            Some(geoip) if !messages.is_empty() => match geoip.lookup(outside_ip).await {
+                Ok(network) => Some(network.to_string()),
+                Err(e) => {
+                    warn!("Failed to look up the network of {outside_ip}: {e:#}");
+                    None
+                }
+            },
+            _ => None,
+        };
+        for message in messages {
+            let network = network
+                .clone()
+                .filter(|_| message.new_ip == Some(outside_ip));
+            let message = message
+                .with_source(&source)
+                .with_network(network)
+                .with_previous_change_at(previous_change_at);
+            self.notify(message).await;
+        }
+        for (domain, ip, written, left_alone) in &drifts {
+            let outside_ip = self.target_ip(domain, outside_ip);
+            let error = format!(
+                "The A record of {domain} was changed elsewhere to {ip}, from {written}, {}",
+                if *left_alone {
+                    String::from("and was left alone")
+                } else if self.dry_run {
+                    format!("and would be changed back to {outside_ip}")
+                } else {
+                    format!("and was changed back to {outside_ip}")
+                }
+            );
+            let message = Message::failed(EventKind::Drift, domain, Some(outside_ip), &error)
+                .with_source(&source);
+            self.hooks.after(&message).await;
+            self.notify(message).await;
+        }
+        for (domain, e) in &failures {
+            let kind = if e.downcast_ref::<Mismatch>().is_some() {
+                EventKind::Mismatch
+            } else {
+                EventKind::UpdateFailed
+            };
+            let ip = self.target_ip(domain, outside_ip);
+            let message =
+                Message::failed(kind, domain, Some(ip), &format!("{e:#}")).with_source(&source);
+            self.hooks.after(&message).await;
+            self.notify(message).await;
+        }
+        for domain in succeeded {
+            self.resolve(EventKind::UpdateFailed, &domain).await;
+            self.resolve(EventKind::Mismatch, &domain).await;
+            if !drifts.iter().any(|(drifted, ..)| *drifted == domain) {
+                self.resolve(EventKind::Drift, &domain).await;
+            }
+        }
+
+        if failures.len() == 1 && self.domains.len() == 1 {
+            let (_, e) = failures.remove(0);
+            return Err(exit::wrap(exit::CLOUDFLARE_FAILED, e));
+        }
+        if !failures.is_empty() {
+            let failures = failures
+                .iter()
+                .map(|(domain, e)| format!("{domain}: {e:#}"))
+                .collect::<Vec<_>>();
+            let error = anyhow::anyhow!(
+                "Failed to update {} of {} domains: {}",
+                failures.len(),
+                self.domains.len(),
+                failures.join("; ")
+            );
+            return Err(exit::wrap(exit::CLOUDFLARE_FAILED, error));
+        }
+
+        Ok(outcome)
+    }
+
+    /// Looks up and updates the A records of the domains at `indexes`, which are in order, with the
+    /// results in the same order.
+    ///
+    /// No more than `parallelism` are worked on at the same time, taking turns between the zones, so
+    /// a zone with many domains doesn't hold up the others, and a check of many domains tells how
+    /// far it got along the way.
+    async fn update_domains(
+        &self,
+        indexes: &[usize],
+        outside_ip: IpAddr,
+    ) -> Vec<anyhow::Result<(Outcome, ARecord)>> {
+        let order = fair_order(indexes, |index| self.zone_of(&self.domains[index]));
+        let total = order.len();
+        let mut done = 0;
+        let mut results = stream::iter(order)
+            .map(|index| async move {
+                let domain = &self.domains[index];
+                (index, self.update_domain(domain, outside_ip).await)
+            })
+            // One at a time when each is asked about, so the questions don't mix
+            .buffer_unordered(if self.confirm { 1 } else { self.parallelism })
+            .inspect(|_| {
+                done += 1;
+                self.progress(done, total);
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Tells how many of the `total` domains were looked up, every tenth of the way, if there are
+    /// enough of them for it to take a while.
+    fn progress(&self, done: usize, total: usize) {
+        // Only once `done` reaches another tenth of `total`, which the last one always does
+        if total < PROGRESS_FROM || done * 10 / total == (done - 1) * 10 / total {
+            return;
+        }
+
+        info!(event = "progress", "Checked {done} of {total} domains");
+        self.emit("progress", json!({ "done": done, "total": total }));
+    }
+
+    /// Returns the zone `domain` is in.
+    fn zone_of(&self, domain: &str) -> &str {
+        self.settings_of(domain)
+            .and_then(|settings| settings.zone_id.as_deref())
+            .unwrap_or(&self.zone_id)
+    }
+
+    /// Switches to the other API token, after Cloudflare refused the one in use with `results`,
+    /// and returns whether there's one to switch to.
+    async fn switch_token(&mut self, results: &[anyhow::Result<(Outcome, ARecord)>]) -> bool {
+        let Some(fallback_api_key) = &self.fallback_api_key else {
+            return false;
+        };
+        let (api_key, from, to) = if self.config.fallback_token {
+            (&self.api_key, "fallback", "primary")
+        } else {
+            (fallback_api_key, "primary", "fallback")
+        };
+        if let Err(e) = self.cloudflare.set_api_key(api_key) {
+            error!("Failed to switch to the {to} API token: {e}");
+            return false;
+        }
+
+        let refusal = results
+            .iter()
+            .find_map(|result| result.as_ref().err())
+            .map(|e| format!("{e:#}"))
+            .unwrap_or_default();
+        warn!(
+            event = "token_switched",
+            %from,
+            %to,
+            "Cloudflare refused the {from} API token, switching to the {to} one: {refusal}"
+        );
+        self.config.fallback_token = !self.config.fallback_token;
+        self.save_config();
+
+        let domains = self.domains.join(", ");
+        let error = format!(
+            "Cloudflare refused the {from} API token for {domains}, so cdu switched to the {to} one: {refusal}"
+        );
+        let message = Message::failed(EventKind::TokenSwitched, &domains, None, &error);
+        self.notify(message).await;
+
+        true
+    }
+
+    /// Makes sure the A record of `domain` points at `outside_ip`, for one cycle. Returns what
+    /// happened, and the A record as it was before.
+    #[tracing::instrument(skip(self, outside_ip))]
+    async fn update_domain(
+        &self,
+        domain: &str,
+        outside_ip: IpAddr,
+    ) -> anyhow::Result<(Outcome, ARecord)> {
+        debug!("Processing domain: {}", domain);
+        let outside_ip = self.target_ip(domain, outside_ip);
+        let family = Family::of(outside_ip);
+        let settings = self.settings_of(domain);
+        let zone_id = self.zone_of(domain);
+
+        // Get the A record, by the ID it had on the last check if that's known, so it's still
+        // told whether it was changed elsewhere since
+        let known = self.get_known_record(domain, zone_id, family).await?;
+        let by_id = known.is_some();
+        let record = match known {
+            Some(record) => record,
+            None => self
+                .cloudflare
+                .get_record(zone_id, domain, family)
+                .await
+                .inspect_err(|_| metrics::record_cloudflare_error())?,
+        };
+
+        debug!("Cloudflare IP: {}", record.ip);
+
+        let wanted = ARecord {
+            id: record.id.clone(),
+            ip: outside_ip,
+            proxied: settings
+                .and_then(|settings| settings.proxied)
+                .or(record.proxied),
+            ttl: self
+                .wanted_ttl(domain, record.ip != outside_ip)
+                .or(record.ttl),
+        };
+        if wanted == record {
+            info!(
+                event = "up_to_date",
+                domain, "Cloudflare IP is already up to date"
+            );
+
+            return Ok((Outcome::UpToDate(outside_ip), record));
+        }
+
+        let drifted = record.ip != outside_ip
+            && self
+                .config
+                .record(domain)
+                .and_then(|state| state.ip)
+                .is_some_and(|written| written != record.ip);
+        if drifted && self.leave_drift {
+            warn!(
+                event = "drift",
+                domain,
+                ip = %record.ip,
+                "The A record of {domain} was changed to {} elsewhere, leaving it alone",
+                record.ip
+            );
+
+            return Ok((Outcome::UpToDate(outside_ip), record));
+        }
+        if drifted {
+            warn!(
+                event = "drift",
+                domain,
+                ip = %record.ip,
+                "The A record of {domain} was changed to {} elsewhere, changing it back",
+                record.ip
+            );
+        }
+        info!("Need to update Cloudflare IP");
+        if self.dry_run {
+            debug!("Dry run: Would update A record for {domain}: {outside_ip}");
+            // All at once, so the plans of domains checked at the same time don't mix
+            if matches!(self.output, Output::Text | Output::Human) {
+                println!("{}", output::plan(domain, &record, &wanted));
+            }
+
+            return Ok((Outcome::DryRun(outside_ip), record));
+        }
+        let mut changes = vec![format!(
+            "Update the {} record of {domain}:",
+            family.record_type()
+        )];
+        changes.extend(output::diff(domain, &record, &wanted));
+        if self.txt_record {
+            changes.push(format!("Set the TXT record {}", txt_record_name(domain)));
+        }
+        if self.ptr.is_some() && settings.is_some_and(|settings| settings.ptr) {
+            changes.push(format!(
+                "Point {} at {domain}",
+                ptr::reverse_name(outside_ip)
+            ));
+        }
+        if !self.confirmed(&changes) {
+            return Ok((Outcome::DryRun(outside_ip), record));
+        }
+
+        let updated = if by_id {
+            self.patch_record(domain, zone_id, &record, &wanted).await?
+        } else {
+            self.put_record(domain, zone_id, &record, &wanted).await?
+        };
+        if updated.ip != outside_ip {
+            return Err(Mismatch {
+                domain: domain.to_string(),
+                expected: outside_ip,
+                actual: updated.ip,
+            }
+            .into());
+        }
+
+        Ok((Outcome::Updated(outside_ip), record))
+    }
+
+    /// Returns whether to go ahead with `changes`, which is asked at the terminal if it's wanted,
+    /// and nothing else is printed there. A no is logged.
+    fn confirmed(&self, changes: &[String]) -> bool {
+        if !self.confirm || !matches!(self.output, Output::Text | Output::Human) {
+            return true;
+        }
+
+        match confirm::confirm(changes, false) {
+            Ok(()) => true,
+            Err(e) => {
+                info!("{e:#}");
+                false
+            }
+        }
+    }
+
+    /// Returns the A record of `domain` with the ID it had on the last check, or `None` if the ID
+    /// isn't known, or the zone has no record with the ID anymore, for it to be looked up by name.
+    async fn get_known_record(
+        &self,
+        domain: &str,
+        zone_id: &str,
+        family: Family,
+    ) -> anyhow::Result<Option<ARecord>> {
+        let Some(id) = self
+            .config
+            .record(domain)
+            .and_then(|state| state.record_id.as_deref())
+        else {
+            return Ok(None);
+        };
+
+        match self
+            .cloudflare
+            .get_record_by_id(zone_id, id, domain, family)
+            .await
+        {
+            Ok(record) => Ok(Some(record)),
+            Err(CloudflareError::RecordNotFound(_)) => {
+                debug!("The A record of {domain} isn't there anymore, looking it up again");
+                Ok(None)
+            }
+            Err(e) => {
+                metrics::record_cloudflare_error();
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Changes `record` of `domain` to `wanted` in one request, which answers with the A record as
+    /// it is after the change.
+    async fn patch_record(
+        &self,
+        domain: &str,
+        zone_id: &str,
+        record: &ARecord,
+        wanted: &ARecord,
+    ) -> anyhow::Result<ARecord> {
+        let result = self
+            .cloudflare
+            .patch_a_record(zone_id, wanted, domain)
+            .await
+            .map_err(anyhow::Error::from)
+            .inspect_err(|_| metrics::record_cloudflare_error());
+        let ray_id = result.as_ref().map(|(_, ray_id)| ray_id.clone());
+        self.audit(domain, zone_id, record, wanted, ray_id);
+        let (updated, _) = result?;
+        log_update(domain, record, wanted);
+
+        Ok(updated)
+    }
+
+    /// Replaces `record` of `domain` with `wanted`, and looks it up again after, as Cloudflare has
+    /// been known to accept a change and keep serving the old record.
+    async fn put_record(
+        &self,
+        domain: &str,
+        zone_id: &str,
+        record: &ARecord,
+        wanted: &ARecord,
+    ) -> anyhow::Result<ARecord> {
+        let result = self
+            .cloudflare
+            .set_a_record(zone_id, wanted, domain)
+            .await
+            .map_err(anyhow::Error::from)
+            .inspect_err(|_| metrics::record_cloudflare_error());
+        self.audit(domain, zone_id, record, wanted, result.as_ref().cloned());
+        result?;
+        log_update(domain, record, wanted);
+
+        self.cloudflare
+            .get_record(zone_id, domain, Family::of(wanted.ip))
+            .await
+            .inspect_err(|_| metrics::record_cloudflare_error())
+            .context("Failed to look up the A record after updating it")
+    }
+
+    /// Returns the TTL the A record of `domain` should have, when it's pointed at a new IP with
+    /// `changing`, or checked otherwise, or `None` to leave it as it is. A TTL that was lowered
+    /// is left to [`Updater::raise_ttls`].
+    fn wanted_ttl(&self, domain: &str, changing: bool) -> Option<u32> {
+        let lowered = self
+            .config
+            .lowered_ttls
+            .iter()
+            .any(|lowered| lowered == domain);
+        match self.low_ttl {
+            Some(low_ttl) if changing => Some(low_ttl.ttl),
+            Some(_) if lowered => None,
+            _ => self.settings_of(domain).and_then(|settings| settings.ttl),
+        }
+    }
+
+    /// Raises the TTL of the A records that were lowered with a change, and have pointed at
+    /// `outside_ip` for long enough since, to the one in the settings of the domain, or to
+    /// automatic. One that fails is tried again on the next check.
+    async fn raise_ttls(&mut self, outside_ip: IpAddr) {
+        let Some(low_ttl) = self.low_ttl else {
+            return;
+        };
+        if self.dry_run || self.config.lowered_ttls.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let mut raised = Vec::new();
+        for domain in &self.config.lowered_ttls {
+            let Some(state) = self.config.record(domain) else {
+                continue;
+            };
+            // Without knowing when it changed, it's been long enough
+            let stable_for = match state.last_updated {
+                Some(at) => (now - at).to_std().unwrap_or_default(),
+                None => Duration::MAX,
+            };
+            let (Some(id), Some(ip)) = (&state.record_id, state.ip) else {
+                continue;
+            };
+            if stable_for < low_ttl.until_stable_for || ip != self.target_ip(domain, outside_ip) {
+                continue;
+            }
+
+            let settings = self.settings_of(domain);
+            let zone_id = settings
+                .and_then(|settings| settings.zone_id.as_deref())
+                .unwrap_or(&self.zone_id);
+            let lowered = ARecord {
+                id: id.clone(),
+                ip,
+                proxied: None,
+                ttl: Some(low_ttl.ttl),
+            };
+            let wanted = ARecord {
+                proxied: settings.and_then(|settings| settings.proxied),
+                ttl: Some(settings.and_then(|settings| settings.ttl).unwrap_or(1)),
+                ..lowered.clone()
+            };
+            let mut changes = vec![format!("Raise the TTL of {domain} again:")];
+            changes.extend(output::diff(domain, &lowered, &wanted));
+            if !self.confirmed(&changes) {
+                continue;
+            }
+            let result = self
+                .cloudflare
+                .patch_a_record(zone_id, &wanted, domain)
+                .await
+                .map_err(anyhow::Error::from)
+                .inspect_err(|_| metrics::record_cloudflare_error());
+            let ray_id = result.as_ref().map(|(_, ray_id)| ray_id.clone());
+            self.audit(domain, zone_id, &lowered, &wanted, ray_id);
+            match result {
+                Ok(_) => {
+                    info!(
+                        "Raised the TTL of {domain} again, as it has pointed at {ip} for {}",
+                        humantime::format_duration(low_ttl.until_stable_for)
+                    );
+                    raised.push(domain.clone());
+                }
+                Err(e) => warn!("Failed to raise the TTL of {domain} again: {e:#}"),
+            }
+        }
+
+        self.config
+            .lowered_ttls
+            .retain(|domain| !raised.contains(domain));
+    }
+
+    /// Points the PTR record of `outside_ip` at `domain`, if its settings want that, with the TTL
+    /// of the A record, or an hour for an automatic one. A failure leaves the A record updated.
+    async fn set_ptr(&self, domain: &str, outside_ip: IpAddr) {
+        let Some(server) = &self.ptr else {
+            return;
+        };
+        let Some(settings) = self.settings_of(domain).filter(|settings| settings.ptr) else {
+            return;
+        };
+        let ttl = settings.ttl.filter(|&ttl| ttl > 1).unwrap_or(3600);
+        match server.set_ptr(outside_ip, domain, ttl).await {
+            Ok(()) => info!("Pointed {} at {domain}", ptr::reverse_name(outside_ip)),
+            Err(e) => warn!("Failed to point the PTR record of {outside_ip} at {domain}: {e:#}"),
+        }
+    }
+
+    /// Passes `outside_ip` on to DNS-O-Matic, if there's an account of it, and it isn't a dry run.
+    /// A failure leaves the A records updated.
+    async fn update_dnsomatic(&self, outside_ip: IpAddr) {
+        let Some(account) = self.dnsomatic.as_ref().filter(|_| !self.dry_run) else {
+            return;
+        };
+        match account.update(&self.client, outside_ip).await {
+            Ok(()) => info!("Passed {outside_ip} on to DNS-O-Matic"),
+            Err(e) => warn!("Failed to pass {outside_ip} on to DNS-O-Matic: {e:#}"),
+        }
+    }
+
+    /// Sets the TXT record `_cdu.<domain>` after the A record of `domain` was updated, if that's
+    /// wanted. A failure leaves the A record updated.
+    async fn set_txt_record(&self, domain: &str, source: &str) {
+        if !self.txt_record {
+            return;
+        }
+        let name = txt_record_name(domain);
+        let content = txt_record_content(Utc::now(), source);
+        match self
+            .cloudflare
+            .set_txt_record(self.zone_of(domain), &name, &content)
+            .await
+        {
+            Ok(()) => debug!("Set the TXT record {name} to {content}"),
+            Err(e) => warn!("Failed to set the TXT record {name}: {e:#}"),
+        }
+    }
+
+    /// Writes the change of the A record of `domain` from `record` to `wanted` to the audit log,
+    /// with the Ray ID of the response, or the error if it failed.
+    fn audit(
+        &self,
+        domain: &str,
+        zone_id: &str,
+        record: &ARecord,
+        wanted: &ARecord,
+        ray_id: Result<Option<String>, &anyhow::Error>,
+    ) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let (ray_id, error) = match ray_id {
+            Ok(ray_id) => (ray_id, None),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+        let entry = Entry::update(domain, zone_id, record, wanted, ray_id, error);
+        if let Err(e) = audit_log.record(&entry) {
+            warn!("Failed to write the change of {domain} to the audit log: {e:#}");
+        }
+    }
+
+    /// Sends the message to every notifier that's subscribed to it, and queues it for the ones it
+    /// failed for.
+    #[tracing::instrument(skip_all)]
+    async fn notify(&mut self, message: Message) {
+        if self.notifiers.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let allowed = self.throttle.allows(&message, now);
+        self.save_alerts();
+        if !allowed {
+            return;
+        }
+
+        let settings = self.settings_of(&message.domain);
+        let notify_on = settings
+            .and_then(|settings| settings.notify_on.as_deref())
+            .unwrap_or(&self.notify_on);
+        let started = Instant::now();
+        let (destinations, skipped): (Vec<_>, Vec<_>) = self
+            .notifiers
+            .iter()
+            .filter(|destination| destination.subscription.wants(&message, notify_on))
+            .filter(|destination| match settings {
+                Some(settings) => settings.routes_to(&destination.subscription, &message),
+                None => true,
+            })
+            .partition(|destination| self.breaker.allows(&destination.id, started));
+        let mut failed = notify::send_all(destinations.iter().copied(), &message, self.retry).await;
+        for destination in &destinations {
+            if failed.contains(&destination.id.as_str()) {
+                self.breaker.failed(&destination.id, started);
+            } else {
+                self.breaker.succeeded(&destination.id);
+            }
+        }
+        for destination in skipped {
+            debug!(
+                "Not sending to {}, which keeps failing, queueing the message instead",
+                destination.notifier.name()
+            );
+            failed.push(destination.id.as_str());
+        }
+
+        for id in failed {
+            self.queue.push(id, message.clone(), 1, now);
+        }
+        self.save_queue();
+    }
+
+    /// Asks the servers for the outside IP, trying again as [`Updater::with_retry`] says, but
+    /// skipping the ones that keep failing, unless all of them do. The servers before the one that
+    /// answered failed, and all of them if none did. The detection plugins are asked first, if
+    /// there are any.
+    async fn detect(&mut self) -> Result<(IpAddr, String), DetectionError> {
+        // The plugins only tell the outside IPv4 address
+        #[cfg(feature = "plugins")]
+        if self.family == Family::V4 {
+            if let Some((ip, source)) = self
+                .plugins
+                .detect(&self.detection_client, self.config.timeouts.detection)
+                .await
+            {
+                return Ok((ip.into(), source));
+            }
+        }
+
+        let started = Instant::now();
+        let mut servers = self
+            .servers
+            .iter()
+            .map(String::as_str)
+            .filter(|server| self.breaker.allows(server, started))
+            .collect::<Vec<_>>();
+        if servers.is_empty() {
+            servers = self.servers.iter().map(String::as_str).collect();
+        }
+
+        let timeout = self.config.timeouts.detection;
+        let detected = self
+            .retry
+            .run(
+                "Detecting the outside IP",
+                || network::detect_family(&self.detection_client, &servers, self.family, timeout),
+                DetectionError::is_transient,
+            )
+            .await;
+
+        let answered = detected.as_ref().ok().map(|(_, source)| source.as_str());
+        for server in servers {
+            if Some(server) == answered {
+                self.breaker.succeeded(server);
+                break;
+            }
+            self.breaker.failed(server, started);
+        }
+
+        detected
+    }
+
+    /// Detects the outside IP of every source that domains are pointed at other than the default
+    /// one, like a connection of `--wan` or the other family, and returns the indices of the
+    /// domains of those that failed, after telling about them.
+    async fn detect_sources(&mut self) -> Vec<usize> {
+        self.source_ips.clear();
+        let default = Source::default_of(self.family);
+        let mut sources = Vec::new();
+        for domain in &self.domains {
+            let source = self.source_of(domain);
+            if source != default && !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+
+        let mut cut_off = Vec::new();
+        for source in sources {
+            let indexes = (0..self.domains.len())
+                .filter(|&index| self.source_of(&self.domains[index]) == source)
+                .collect::<Vec<_>>();
+            let domains = indexes
+                .iter()
+                .map(|&index| self.domains[index].as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let name = source.wan.clone().unwrap_or_else(|| domains.clone());
+
+            let client = source
+                .wan
+                .as_ref()
+                .and_then(|wan| self.wans.iter().find(|(name, _)| name == wan))
+                .map_or_else(
+                    || self.detection_client.clone(),
+                    |(_, client)| client.clone(),
+                );
+            let servers = if source.family == self.family {
+                self.servers.clone()
+            } else {
+                source
+                    .family
+                    .servers()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            };
+            let servers = source
+                .server
+                .iter()
+                .chain(&servers)
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            let timeout = self.config.timeouts.detection;
+            let detected = self
+                .retry
+                .run(
+                    &format!("Detecting the outside IP of {name}"),
+                    || network::detect_family(&client, &servers, source.family, timeout),
+                    DetectionError::is_transient,
+                )
+                .await;
+            match detected {
+                Ok((ip, from)) => {
+                    debug!("Outside IP of {name}: {ip}, from {from}");
+                    self.emit(
+                        "ip_detected",
+                        json!({ "ip": ip, "source": from, "wan": source.wan }),
+                    );
+                    self.source_ips.push((source, ip));
+                    self.resolve(EventKind::DetectionFailed, &domains).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to detect the outside IP of {name}, leaving {domains} alone: {e:#}"
+                    );
+                    let message = Message::failed(
+                        EventKind::DetectionFailed,
+                        &domains,
+                        None,
+                        &format!("Failed to detect the outside IP of {name}: {e:#}"),
+                    );
+                    self.hooks.after(&message).await;
+                    self.notify(message).await;
+                    cut_off.extend(indexes);
+                }
+            }
+        }
+
+        cut_off
+    }
+
+    /// Sends the queued messages that are due, and queues them again if they fail again.
+    async fn send_queued(&mut self) {
+        let now = Utc::now();
+        for pending in self.queue.take_due(now) {
+            let Some(destination) = self.notifiers.iter().find(|d| d.id == pending.target) else {
+                warn!(
+                    "Dropping queued notification for a target that's gone: {}",
+                    pending.message
+                );
+                continue;
+            };
+
+            let name = destination.notifier.name();
+            match destination.notifier.send(&pending.message).await {
+                Ok(()) => info!(
+                    "Sent queued message to {name}, after {} attempts",
+                    pending.attempts + 1
+                ),
+                Err(e) => {
+                    warn!("Failed to send queued message to {name} again: {e:#}");
+                    self.queue
+                        .push(&pending.target, pending.message, pending.attempts + 1, now);
+                }
+            }
+        }
+        self.save_queue();
+    }
+
+    /// Returns the settings of `domain` of its own, if it has any.
+    fn settings_of(&self, domain: &str) -> Option<&DomainSettings> {
+        self.domain_settings
+            .iter()
+            .find(|settings| settings.name == domain)
+    }
+
+    /// Ends the failure of this kind for `domain`, and tells whoever was alerted about it.
+    async fn resolve(&mut self, kind: EventKind, domain: &str) {
+        let Some(alert) = self.throttle.resolve(kind, domain) else {
+            return;
+        };
+        self.save_alerts();
+
+        info!("{domain} has recovered ({})", kind.title());
+        let recovery = Recovery {
+            kind,
+            since: alert.since,
+            held_back: alert.held_back,
+        };
+        self.notify(Message::recovered(domain, recovery)).await;
+    }
+
+    fn save_alerts(&mut self) {
+        if let Err(e) = self.throttle.save() {
+            error!("Failed to save the ongoing failures: {e:#}");
+        }
+    }
+
+    fn save_queue(&mut self) {
+        if let Err(e) = self.queue.save() {
+            error!("Failed to save the notification queue: {e:#}");
+        }
+    }
+
+    /// Asks the policy script what to do with `outside_ip`, if there is one and the outside IP
+    /// changed, leaving the outside IP alone when it says to skip, so the next cycle asks again.
+    #[cfg(feature = "scripting")]
+    fn decide(&self, outside_ip: IpAddr, source: &str, unchanged: bool) -> anyhow::Result<Verdict> {
+        let Some(policy) = self.policy.as_ref().filter(|_| !unchanged) else {
+            return Ok(Verdict::Update);
+        };
+        let change = Change {
+            new_ip: outside_ip,
+            source,
+            old_ip: self.config.outside_ip,
+            records: self
+                .domains
+                .iter()
+                .map(|domain| {
+                    let ip = self.config.record(domain).and_then(|state| state.ip);
+                    (domain.as_str(), ip)
+                })
+                .collect(),
+            now: chrono::Local::now(),
+        };
+
+        policy.decide(&change)
+    }
+
+    /// Returns the indices of the domains whose A record isn't known to point at `outside_ip`.
+    fn out_of_sync(&self, outside_ip: IpAddr) -> Vec<usize> {
+        (0..self.domains.len())
+            .filter(|&index| !self.is_in_sync(&self.domains[index], outside_ip))
+            .collect()
+    }
+
+    /// Nothing is known to be in sync without a state, so every A record is compared at Cloudflare.
+    fn is_in_sync(&self, domain: &str, outside_ip: IpAddr) -> bool {
+        !self.config.stateless
+            && self
+                .config
+                .record(domain)
+                .is_some_and(|state| state.ip == Some(self.target_ip(domain, outside_ip)))
+    }
+
+    /// Returns the IP `domain` is pointed at: the outside IP of its own source, like its connection
+    /// of `--wan`, if it has one, and `outside_ip` otherwise.
+    fn target_ip(&self, domain: &str, outside_ip: IpAddr) -> IpAddr {
+        let source = self.source_of(domain);
+        self.source_ips
+            .iter()
+            .find(|(detected, _)| *detected == source)
+            .map_or(outside_ip, |&(_, ip)| ip)
+    }
+
+    /// Returns where the outside IP `domain` is pointed at is detected, following its settings.
+    fn source_of(&self, domain: &str) -> Source {
+        let settings = self.settings_of(domain);
+        Source {
+            wan: settings.and_then(|settings| settings.wan.clone()),
+            family: settings
+                .and_then(|settings| settings.family)
+                .unwrap_or(self.family),
+            server: settings.and_then(|settings| settings.server.clone()),
+        }
+    }
+
+    /// Returns the domains whose state is older than the maximum age at `now`, counting from when
+    /// their A record was last checked.
+    fn stale(&self, now: DateTime<Utc>) -> Vec<usize> {
+        let Some(max_state_age) = self
+            .max_state_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+        else {
+            return Vec::new();
+        };
+
+        (0..self.domains.len())
+            .filter(|&index| {
+                self.config
+                    .record(&self.domains[index])
+                    .and_then(|state| state.checked_at)
+                    .filter(|checked_at| now - *checked_at < max_state_age)
+                    .is_none()
+            })
+            .collect()
+    }
+
+    /// Returns whether the A record should be checked at `now`, even if the outside IP didn't
+    /// change.
+    fn is_reconcile_due(&self, now: DateTime<Utc>) -> bool {
+        let Some(reconcile_every) = self
+            .reconcile_every
+            .and_then(|every| chrono::Duration::from_std(every).ok())
+        else {
+            return false;
+        };
+
+        self.config
+            .last_reconciled
+            .filter(|last_reconciled| now - *last_reconciled < reconcile_every)
+            .is_none()
+    }
+
+    /// Returns whether it's time to tell cdu is still checking.
+    fn is_heartbeat_due(&self, now: DateTime<Utc>) -> bool {
+        let Some(heartbeat_every) = self
+            .heartbeat_every
+            .and_then(|every| chrono::Duration::from_std(every).ok())
+        else {
+            return false;
+        };
+
+        self.config
+            .last_heartbeat
+            .filter(|last_heartbeat| now - *last_heartbeat < heartbeat_every)
+            .is_none()
+    }
+
+    /// Returns how long the cooldown still lasts at `now`, if it isn't over yet.
+    fn cooldown_remaining(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let cooldown = chrono::Duration::from_std(self.cooldown?).ok()?;
+        let (_, last_change) = self.last_change()?;
+
+        (last_change + cooldown - now)
+            .to_std()
+            .ok()
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Carries over what `old` found out while it ran, like the updater before a reload: the
+    /// servers and notification targets that keep failing, the failures that are going on and the
+    /// messages that are waiting to be sent again. A reload would otherwise start them over.
+    pub fn adopt_runtime(&mut self, old: &Self) {
+        self.breaker.adopt(&old.breaker);
+        self.throttle.adopt(&old.throttle);
+        self.queue.adopt(&old.queue);
+    }
+
+    /// Saves the configuration. Failing to do so is logged, but isn't fatal, as it only means the
+    /// next cycle can't exit early.
+    pub fn save_config(&mut self) {
+        if let Err(e) = self.save_state() {
+            error!("Error: {e}");
+        } else {
+            info!("Config saved");
+        }
+    }
+
+    /// Saves the state, with how the servers and the notification targets have been doing.
+    fn save_state(&mut self) -> Result<(), ConfigError> {
+        self.config.health = self.breaker.health(Instant::now(), Utc::now());
+        self.config.save()
+    }
+}
+
+/// Returns `indexes` in an order that takes turns between the zones they're in, by `zone_of`,
+/// starting with the zone of the first one, and keeping their order within a zone.
+fn fair_order<'a>(indexes: &[usize], zone_of: impl Fn(usize) -> &'a str) -> Vec<usize> {
+    let mut zones: Vec<(&str, Vec<usize>)> = Vec::new();
+    for &index in indexes {
+        let zone = zone_of(index);
+        match zones.iter_mut().find(|(name, _)| *name == zone) {
+            Some((_, queue)) => queue.push(index),
+            None => zones.push((zone, vec![index])),
+        }
+    }
+
+    let turns = zones.iter().map(|(_, queue)| queue.len()).max();
+    (0..turns.unwrap_or_default())
+        .flat_map(|turn| {
+            zones
+                .iter()
+                .filter_map(move |(_, queue)| queue.get(turn).copied())
+        })
+        .collect()
+}
+
+/// Returns the name of the TXT record next to the A record of `domain`, where a wildcard is left
+/// out, as a TXT record cannot be put in front of one.
+fn txt_record_name(domain: &str) -> String {
+    format!("_cdu.{}", domain.trim_start_matches("*."))
+}
+
+/// Returns what the TXT record says, as `key=value` pairs: when the A record was updated, by
+/// which version of cdu, and which server told the outside IP.
+fn txt_record_content(at: DateTime<Utc>, source: &str) -> String {
+    format!(
+        "updated={} version={} source={source}",
+        at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Logs the change of the A record of `domain` from `record` to `wanted`.
+fn log_update(domain: &str, record: &ARecord, wanted: &ARecord) {
+    info!(
+        event = "updated",
+        domain,
+        old_ip = %record.ip,
+        new_ip = %wanted.ip,
+        "{} record for {domain} updated with {} at Cloudflare",
+        Family::of(wanted.ip).record_type(),
+        wanted.ip
+    );
+}
+
+#[test]
+fn test_changes() {
+    let updater =
+        Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    let same = Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    assert!(updater.changes(&same).is_empty());
+
+    let config = Config {
+        webhook_url: Some(String::from("https://discord.com/api/webhooks/secret")),
+        ..Config::default()
+    };
+    let other = Updater::try_new("new key", "zone", &["www.example.com"], true, config).unwrap();
+    assert_eq!(
+        updater.changes(&other),
+        [
+            "domains: example.com -> www.example.com",
+            "API key",
+            "dry run: false -> true",
+            "webhook URL: added"
+        ]
+    );
+
+    let config = Config {
+        timeouts: network::Timeouts {
+            api: Duration::from_secs(5),
+            ..network::Timeouts::default()
+        },
+        ..Config::default()
+    };
+    let other = Updater::try_new("key", "zone", &["example.com"], false, config).unwrap();
+    assert_eq!(updater.changes(&other), ["API timeout: 30s -> 5s"]);
+}
+
+#[test]
+fn test_adopt_runtime() {
+    let now = Instant::now();
+    let mut updater = Updater::try_new("key", "zone", &["example.com"], false, Config::default())
+        .unwrap()
+        .with_circuit_breaker(1, Duration::from_secs(600));
+    updater.breaker.failed("icanhazip.com", now);
+    assert!(!updater.breaker.allows("icanhazip.com", now));
+
+    // A reload builds another updater, which keeps the circuit open
+    let mut reloaded = Updater::try_new("key", "zone", &["example.com"], false, Config::default())
+        .unwrap()
+        .with_circuit_breaker(3, Duration::from_secs(600));
+    assert!(reloaded.breaker.allows("icanhazip.com", now));
+    reloaded.adopt_runtime(&updater);
+    assert!(!reloaded.breaker.allows("icanhazip.com", now));
+}
+
+#[tokio::test]
+async fn test_leave_drift() {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/ip"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.3"))
+        .mount(&server)
+        .await;
+    // Changed elsewhere to 198.51.100.1, since cdu pointed it at 192.0.2.1
+    Mock::given(method("GET"))
+        .and(path("/zones/zone/dns_records/record-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": {
+                "id": "record-1",
+                "type": "A",
+                "name": "home.example.com",
+                "content": "198.51.100.1",
+            },
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/zones/zone/dns_records/record-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": {
+                "id": "record-1",
+                "type": "A",
+                "name": "home.example.com",
+                "content": "192.0.2.3",
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let updater = || {
+        let mut config = Config {
+            save_dir: dir.path().to_path_buf(),
+            api_url: server.uri(),
+            outside_ip: Some(IpAddr::from([192, 0, 2, 1])),
+            ..Config::default()
+        };
+        let state = config.record_mut("home.example.com");
+        state.record_id = Some(String::from("record-1"));
+        state.ip = Some(IpAddr::from([192, 0, 2, 1]));
+        Updater::try_new("key", "zone", &["home.example.com"], false, config)
+            .unwrap()
+            .with_servers(vec![format!("{}/ip", server.uri())])
+            .with_retry(retry::Policy::NEVER)
+    };
+
+    // Found by its ID, and left alone, pointing at the IP it was changed to
+    let mut leaving = updater().with_leave_drift(true);
+    let outcome = leaving.run().await.unwrap();
+    assert_eq!(outcome, Outcome::UpToDate(IpAddr::from([192, 0, 2, 3])));
+    assert_eq!(leaving.report().domains[0].action, Action::LeftAlone);
+
+    // Or changed back
+    let mut repairing = updater();
+    let outcome = repairing.run().await.unwrap();
+    assert_eq!(outcome, Outcome::Updated(IpAddr::from([192, 0, 2, 3])));
+    assert_eq!(
+        repairing.report().domains[0].previous_ip,
+        Some(IpAddr::from([198, 51, 100, 1]))
+    );
+}
+
+#[tokio::test]
+async fn test_family_v6() {
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let record = |content: &str| {
+        json!({
+            "success": true,
+            "errors": [],
+            "result": [{
+                "id": "record-6",
+                "type": "AAAA",
+                "name": "home.example.com",
+                "content": content,
+            }],
+        })
+    };
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/ip"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("2001:db8::2"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone/dns_records"))
+        .and(query_param("type", "AAAA"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(record("2001:db8::1")))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone/dns_records"))
+        .and(query_param("type", "AAAA"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(record("2001:db8::2")))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/zones/zone/dns_records/record-6"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": {},
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        save_dir: dir.path().to_path_buf(),
+        api_url: server.uri(),
+        ..Config::default()
+    };
+    let mut updater = Updater::try_new("key", "zone", &["home.example.com"], false, config)
+        .unwrap()
+        .with_family(Family::V6)
+        .with_servers(vec![format!("{}/ip", server.uri())])
+        .with_retry(retry::Policy::NEVER);
+
+    let outcome = updater.run().await.unwrap();
+    let ip: IpAddr = "2001:db8::2".parse().unwrap();
+    assert_eq!(outcome, Outcome::Updated(ip));
+    assert_eq!(outcome.to_string(), "AAAA record updated to 2001:db8::2");
+    assert_eq!(
+        updater.report().domains[0].previous_ip,
+        Some("2001:db8::1".parse().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn test_domain_family() {
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let record = |id: &str, kind: &str, name: &str, content: &str| {
+        json!({
+            "success": true,
+            "errors": [],
+            "result": [{ "id": id, "type": kind, "name": name, "content": content }],
+        })
+    };
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/ip"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.3"))
+        .mount(&server)
+        .await;
+    // Its own server, asked before the others of IPv6
+    Mock::given(method("GET"))
+        .and(path("/ip6"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("2001:db8::2"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone/dns_records"))
+        .and(query_param("type", "A"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(record(
+            "record-4",
+            "A",
+            "home.example.com",
+            "192.0.2.3",
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone/dns_records"))
+        .and(query_param("type", "AAAA"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(record(
+            "record-6",
+            "AAAA",
+            "v6.example.com",
+            "2001:db8::1",
+        )))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone/dns_records"))
+        .and(query_param("type", "AAAA"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(record(
+            "record-6",
+            "AAAA",
+            "v6.example.com",
+            "2001:db8::2",
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/zones/zone/dns_records/record-6"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": {},
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        save_dir: dir.path().to_path_buf(),
+        api_url: server.uri(),
+        ..Config::default()
+    };
+    let domains = ["home.example.com", "v6.example.com"];
+    let mut updater = Updater::try_new("key", "zone", &domains, false, config)
+        .unwrap()
+        .with_servers(vec![format!("{}/ip", server.uri())])
+        .with_domain_settings(vec![DomainSettings {
+            name: String::from("v6.example.com"),
+            family: Some(Family::V6),
+            server: Some(format!("{}/ip6", server.uri())),
+            ..DomainSettings::default()
+        }])
+        .with_retry(retry::Policy::NEVER);
+
+    updater.run().await.unwrap();
+    let report = updater.report();
+    assert_eq!(report.domains[0].action, Action::UpToDate);
+    assert_eq!(report.domains[1].action, Action::Updated);
+    assert_eq!(report.domains[1].ip, Some("2001:db8::2".parse().unwrap()));
+    assert_eq!(
+        report.domains[1].previous_ip,
+        Some("2001:db8::1".parse().unwrap())
+    );
+}
+
+#[test]
+fn test_cooldown_remaining() {
+    let now = Utc::now();
+    let config = Config {
+        cloudflare_ip: Some(IpAddr::from([192, 0, 2, 1])),
+        last_updated: now - chrono::Duration::minutes(4),
+        ..Config::default()
+    };
+    let updater = Updater::try_new("key", "zone", &["example.com"], false, config).unwrap();
+    assert_eq!(updater.cooldown_remaining(now), None);
+
+    let updater = updater.with_cooldown(Some(Duration::from_secs(10 * 60)));
+    assert_eq!(
+        updater.cooldown_remaining(now),
+        Some(Duration::from_secs(6 * 60))
+    );
+    assert_eq!(
+        updater.cooldown_remaining(now + chrono::Duration::minutes(6)),
+        None
+    );
+
+    // Never updated, so there's nothing to cool down from
+    let updater = Updater::try_new("key", "zone", &["example.com"], false, Config::default())
+        .unwrap()
+        .with_cooldown(Some(Duration::from_secs(10 * 60)));
+    assert_eq!(updater.cooldown_remaining(now), None);
+}
+
+#[test]
+fn test_is_reconcile_due() {
+    let now = Utc::now();
+    let updater =
+        Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    assert!(!updater.is_reconcile_due(now));
+
+    let mut updater = updater.with_reconcile_every(Some(Duration::from_secs(6 * 60 * 60)));
+    assert!(updater.is_reconcile_due(now));
+
+    updater.config.last_reconciled = Some(now - chrono::Duration::hours(1));
+    assert!(!updater.is_reconcile_due(now));
+    assert!(updater.is_reconcile_due(now + chrono::Duration::hours(5)));
+}
+
+#[test]
+fn test_is_heartbeat_due() {
+    let now = Utc::now();
+    let updater =
+        Updater::try_new("key", "zone", &["example.com"], false, Config::default()).unwrap();
+    assert!(!updater.is_heartbeat_due(now));
+
+    let mut updater = updater.with_heartbeat_every(Some(Duration::from_secs(7 * 24 * 60 * 60)));
+    assert!(updater.is_heartbeat_due(now));
+
+    updater.config.last_heartbeat = Some(now - chrono::Duration::days(1));
+    assert!(!updater.is_heartbeat_due(now));
+    assert!(updater.is_heartbeat_due(now + chrono::Duration::days(6)));
+}
+
+#[test]
+fn test_out_of_sync() {
+    let ip = IpAddr::from([192, 0, 2, 1]);
+    let mut config = Config::default();
+    config.record_mut("example.com").ip = Some(ip);
+    config.record_mut("www.example.com").ip = Some(IpAddr::from([192, 0, 2, 2]));
+    // Failed the last time
+    config.record_mut("vpn.example.com").ip = None;
+
+    let domains = [
+        "example.com",
+        "www.example.com",
+        "vpn.example.com",
+        "new.example.com",
+    ];
+    let now = Utc::now();
+    config.record_mut("example.com").checked_at = Some(now - chrono::Duration::hours(1));
+    config.record_mut("www.example.com").checked_at = Some(now - chrono::Duration::days(2));
+
+    let updater = Updater::try_new("key", "zone", &domains, false, config).unwrap();
+    assert_eq!(updater.out_of_sync(ip), [1, 2, 3]);
+    assert_eq!(updater.records()[0].ip, Some(ip));
+    assert!(updater.stale(now).is_empty());
+
+    let mut updater = updater.with_max_state_age(Some(Duration::from_secs(24 * 60 * 60)));
+    assert_eq!(updater.stale(now), [1, 2, 3]);
+
+    updater.config.stateless = true;
+    assert_eq!(updater.out_of_sync(ip), [0, 1, 2, 3]);
+}
+
+#[test]
+fn test_wanted_ttl() {
+    let mut updater = Updater::try_new("key", "zone", &["example.com"], false, Config::default())
+        .unwrap()
+        .with_domain_settings(vec![DomainSettings {
+            name: String::from("example.com"),
+            ttl: Some(3600),
+            ..DomainSettings::default()
+        }]);
+    assert_eq!(updater.wanted_ttl("example.com", true), Some(3600));
+    assert_eq!(updater.wanted_ttl("www.example.com", true), None);
+
+    updater = updater.with_low_ttl(Some(LowTtl {
+        ttl: 60,
+        until_stable_for: Duration::from_secs(24 * 60 * 60),
+    }));
+    assert_eq!(updater.wanted_ttl("example.com", true), Some(60));
+    assert_eq!(updater.wanted_ttl("example.com", false), Some(3600));
+
+    // Lowered, so it's left for raise_ttls
+    updater.config.lowered_ttls = vec![String::from("example.com")];
+    assert_eq!(updater.wanted_ttl("example.com", false), None);
+    assert_eq!(updater.wanted_ttl("example.com", true), Some(60));
+}
+
+#[test]
+fn test_fair_order() {
+    let zones = ["a", "a", "a", "b", "c", "c"];
+    let zone_of = |index: usize| zones[index];
+
+    assert_eq!(fair_order(&[0, 1, 2, 3, 4, 5], zone_of), [0, 3, 4, 1, 5, 2]);
+    assert_eq!(fair_order(&[1, 2], zone_of), [1, 2]);
+    assert!(fair_order(&[], zone_of).is_empty());
+}
+
+#[test]
+fn test_routes_to() {
+    let settings = DomainSettings {
+        name: String::from("nas.example.com"),
+        notify: Some(vec![String::from("Parents")]),
+        notify_severity: Some(Severity::Notice),
+        ..DomainSettings::default()
+    };
+    let named = |name: &str| Subscription {
+        name: Some(String::from(name)),
+        ..Subscription::default()
+    };
+    let ip = IpAddr::from([192, 0, 2, 1]);
+    let updated = Message::updated("nas.example.com", None, ip);
+    let unchanged = Message::unchanged("nas.example.com", ip);
+
+    assert!(settings.routes_to(&named("parents"), &updated));
+    assert!(!settings.routes_to(&named("discord"), &updated));
+    assert!(!settings.routes_to(&Subscription::default(), &updated));
+    assert!(!settings.routes_to(&named("parents"), &unchanged));
+    assert!(DomainSettings::default().routes_to(&Subscription::default(), &unchanged));
+}
+
+#[test]
+fn test_txt_record() {
+    assert_eq!(txt_record_name("home.example.com"), "_cdu.home.example.com");
+    assert_eq!(txt_record_name("*.example.com"), "_cdu.example.com");
+
+    let at = DateTime::parse_from_rfc3339("2024-05-01T12:30:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    assert_eq!(
+        txt_record_content(at, "api.ipify.org"),
+        format!(
+            "updated=2024-05-01T12:30:00Z version={} source=api.ipify.org",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+}
@@ -0,0 +1,107 @@
+//! Pushes to an [Uptime Kuma](https://uptime.kuma.pet) push monitor when a run ends.
+//!
+//! The URL is the push URL Uptime Kuma shows for the monitor, like
+//! `https://kuma.example.com/api/push/<token>`, without the example query it's shown with. It's
+//! given `status=up` or `status=down`, what happened as `msg`, and how long the run took in
+//! milliseconds as `ping`. Uptime Kuma marks the monitor down once pushes stop coming, so a cron
+//! job that stopped running is noticed too.
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{Client as RqClient, Url};
+
+use crate::monitor::Monitor;
+use crate::updater::Outcome;
+
+/// How long to wait for Uptime Kuma, which shouldn't hold up the run for long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A push monitor, by its push URL.
+#[derive(Debug)]
+pub struct UptimeKuma {
+    client: RqClient,
+    url: Url,
+}
+
+impl UptimeKuma {
+    #[must_use]
+    pub fn new(client: RqClient, url: &Url) -> Self {
+        let mut url = url.clone();
+        url.set_query(None);
+
+        Self { client, url }
+    }
+
+    /// Returns the URL to push to, for how the run ended.
+    fn push_url(&self, result: &anyhow::Result<Outcome>, took: Duration) -> Url {
+        let (status, msg) = match result {
+            Ok(outcome) => ("up", outcome.to_string()),
+            Err(e) => ("down", format!("{e:#}")),
+        };
+
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("status", status)
+            .append_pair("msg", &msg)
+            .append_pair("ping", &took.as_millis().to_string());
+
+        url
+    }
+}
+
+#[async_trait]
+impl Monitor for UptimeKuma {
+    fn name(&self) -> &'static str {
+        "Uptime Kuma"
+    }
+
+    /// Push monitors only know about the end of a run.
+    async fn start(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn finish(&self, result: &anyhow::Result<Outcome>, took: Duration) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .get(self.push_url(result, took))
+            .timeout(TIMEOUT)
+            .send()
+            .await
+            .context("Failed to push to the monitor")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Received response status {status}: {body}");
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_push_url() {
+    use std::net::IpAddr;
+
+    let kuma = UptimeKuma::new(
+        RqClient::new(),
+        &Url::parse("https://kuma.example.com/api/push/Ab12?status=up&msg=OK&ping=").unwrap(),
+    );
+
+    let up = kuma.push_url(
+        &Ok(Outcome::Unchanged(IpAddr::from([192, 0, 2, 1]))),
+        Duration::from_millis(1234),
+    );
+    assert_eq!(
+        up.as_str(),
+        "https://kuma.example.com/api/push/Ab12?status=up&msg=outside+IP+192.0.2.1+has+not+changed&ping=1234"
+    );
+
+    let down = kuma.push_url(
+        &Err(anyhow::anyhow!("Failed to get outside IP from all servers")),
+        Duration::from_secs(3),
+    );
+    assert!(down.as_str().contains("status=down&msg=Failed+to+get"));
+}
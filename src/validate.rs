@@ -0,0 +1,226 @@
+//! Checks the settings before cdu is left to run on its own: that nothing required is missing,
+//! the API token is active and can read every zone and A record, and the outside IP and the
+//! notification targets can be reached. Every check is run, so all the problems show at once.
+//!
+//! Nothing is changed or sent. That's why whether the token may also edit the A records only shows
+//! on the first update.
+use std::fmt;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+
+use crate::cloudflare::Handler;
+use crate::network::{detect_outside_ip, Transport, DEFAULT_TIMEOUT};
+use crate::notify::Target;
+use crate::updater::DomainSettings;
+
+/// How long to wait for a notification target to accept the connection.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The settings that are checked.
+#[derive(Debug, Default)]
+pub struct Settings {
+    pub api_key: Option<String>,
+    pub zone_id: Option<String>,
+    pub domains: Vec<String>,
+    /// The domains with settings of their own, which can have a zone of their own.
+    pub per_domain: Vec<DomainSettings>,
+    pub targets: Vec<Target>,
+    /// Where the Cloudflare API is.
+    pub api_url: String,
+    /// How the requests reach the servers.
+    pub transport: Transport,
+}
+
+impl Settings {
+    /// Returns the zone of the domain, which is its own if it has one.
+    fn zone_of(&self, domain: &str) -> Option<&str> {
+        self.per_domain
+            .iter()
+            .find(|settings| settings.name == domain)
+            .and_then(|settings| settings.zone_id.as_deref())
+            .or(self.zone_id.as_deref())
+    }
+}
+
+/// Prints the outcome of every check, and counts the problems.
+#[derive(Debug, Default)]
+pub struct Report {
+    passed: usize,
+    problems: usize,
+    warnings: usize,
+}
+
+impl Report {
+    pub fn ok(&mut self, check: &str) {
+        println!("ok      {check}");
+        self.passed += 1;
+    }
+
+    pub fn failed(&mut self, check: &str, problem: impl fmt::Display) {
+        println!("FAILED  {check}: {problem}");
+        self.problems += 1;
+    }
+
+    /// Prints a problem that cdu can work around, which doesn't make the checks fail.
+    pub fn warning(&mut self, check: &str, problem: impl fmt::Display) {
+        println!("WARNING {check}: {problem}");
+        self.warnings += 1;
+    }
+
+    /// Returns an error that counts the problems with `what`, if there were any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any check failed.
+    pub fn finish(&self, what: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.problems == 0,
+            "Found {} problems with {what}, and {} checks passed",
+            self.problems,
+            self.passed
+        );
+        if self.warnings > 0 {
+            println!("{} warnings, which cdu works around", self.warnings);
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs every check, printing how each went.
+///
+/// # Errors
+///
+/// Returns an error if any check failed.
+pub async fn run(settings: &Settings) -> anyhow::Result<()> {
+    let mut report = Report::default();
+
+    if let Some(api_key) = check_required(&mut report, settings) {
+        check_cloudflare(&mut report, settings, api_key).await?;
+    }
+
+    let client = settings.transport.detection_client(DEFAULT_TIMEOUT);
+    match detect_outside_ip(&client, None, DEFAULT_TIMEOUT).await {
+        Ok((ip, server)) => report.ok(&format!("Outside IP is {ip}, from {server}")),
+        Err(e) => report.failed(
+            "Outside IP",
+            format!("{e:#}, check that this machine can reach the internet"),
+        ),
+    }
+
+    for (index, target) in settings.targets.iter().enumerate() {
+        let check = format!("Notification target {}, {}", index + 1, target.kind());
+        let Some((host, port)) = target.address() else {
+            report.failed(&check, "the URL has no host");
+            continue;
+        };
+        match tokio::time::timeout(TIMEOUT, TcpStream::connect((host.as_str(), port))).await {
+            Ok(Ok(_)) => report.ok(&format!("{check}, reached {host}:{port}")),
+            Ok(Err(e)) => report.failed(
+                &check,
+                format!("cannot reach {host}:{port}: {e}, check the host and the port"),
+            ),
+            Err(_) => report.failed(
+                &check,
+                format!(
+                    "{host}:{port} didn't answer within {TIMEOUT:?}, check the host and the port"
+                ),
+            ),
+        }
+    }
+
+    report.finish("the settings")
+}
+
+/// Checks that the token, the domains and their zones are set, and returns the token if it is.
+pub fn check_required<'a>(report: &mut Report, settings: &'a Settings) -> Option<&'a str> {
+    let api_key = settings.api_key.as_deref().filter(|key| !key.is_empty());
+    if api_key.is_none() {
+        report.failed("API token", "not set, set it with --api-key or CDU_API_KEY");
+    }
+    if settings.domains.is_empty() {
+        report.failed(
+            "Domains",
+            "none set, set them with --domain or CDU_DOMAIN, or as [[domains]] in the settings file",
+        );
+    }
+    let without_zone = settings
+        .domains
+        .iter()
+        .filter(|domain| settings.zone_of(domain).is_none())
+        .cloned()
+        .collect::<Vec<_>>();
+    if !without_zone.is_empty() {
+        report.failed(
+            "Zone ID",
+            format!(
+                "not set for {}, set it with --zone-id or CDU_ZONE_ID",
+                without_zone.join(", ")
+            ),
+        );
+    }
+
+    api_key
+}
+
+/// Checks the token, and then every zone and A record with it.
+///
+/// # Errors
+///
+/// Returns an error if the token cannot be used in a request at all.
+pub async fn check_cloudflare(
+    report: &mut Report,
+    settings: &Settings,
+    api_key: &str,
+) -> anyhow::Result<()> {
+    let cloudflare = Handler::try_new(api_key)?
+        .with_api_url(&settings.api_url)
+        .with_client(settings.transport.client(DEFAULT_TIMEOUT));
+    if let Err(e) = cloudflare.verify_token().await.map_err(anyhow::Error::from) {
+        // Nothing else can be checked with a token that doesn't work
+        report.failed(
+            "API token",
+            format!("{e:#}, check that it was copied whole, and is still active at Cloudflare"),
+        );
+        return Ok(());
+    }
+    report.ok("API token is active");
+
+    let mut zones = Vec::new();
+    for domain in &settings.domains {
+        let Some(zone_id) = settings.zone_of(domain) else {
+            continue;
+        };
+        if !zones.contains(&zone_id) {
+            zones.push(zone_id);
+            match cloudflare
+                .get_zone(zone_id)
+                .await
+                .map_err(anyhow::Error::from)
+            {
+                Ok(zone) => report.ok(&format!("Zone {} ({zone_id})", zone.name)),
+                Err(e) => report.failed(
+                    &format!("Zone {zone_id}"),
+                    format!("{e:#}, check the zone ID, and that the token has Zone Read on it"),
+                ),
+            }
+        }
+
+        match cloudflare
+            .get_a_record(zone_id, domain)
+            .await
+            .map_err(anyhow::Error::from)
+        {
+            Ok(record) => report.ok(&format!("A record of {domain} points at {}", record.ip)),
+            Err(e) => report.failed(
+                &format!("A record of {domain}"),
+                format!(
+                    "{e:#}, check that it exists in the zone, and that the token has DNS Edit on it"
+                ),
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Reads a secret, like the API token, from the KV secrets engine (version 2) of
+//! [HashiCorp Vault](https://www.vaultproject.io), for setups that keep their secrets there rather
+//! than on the machine cdu runs on.
+//!
+//! A secret is referred to as `<mount>/<path>#<key>`, like `secret/cdu#api_key` for the `api_key`
+//! of the secret at `cdu` in the engine mounted at `secret`. Vault is logged in to with a token, or
+//! with the role ID and secret ID of an AppRole, which gets a token that's only used for the read.
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::{Client as RqClient, Url};
+use serde_json::{json, Value};
+
+/// How Vault is logged in to.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth {
+    Token(String),
+    AppRole { role_id: String, secret_id: String },
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Token(_) => f.write_str("Token"),
+            Self::AppRole { role_id, .. } => {
+                f.debug_struct("AppRole").field("role_id", role_id).finish()
+            }
+        }
+    }
+}
+
+/// Where a secret is, as `<mount>/<path>#<key>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub mount: String,
+    pub path: String,
+    pub key: String,
+}
+
+impl FromStr for Reference {
+    type Err = String;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid secret {reference}, expected <mount>/<path>#<key>");
+        let (location, key) = reference.split_once('#').ok_or_else(invalid)?;
+        let (mount, path) = location
+            .trim_matches('/')
+            .split_once('/')
+            .ok_or_else(invalid)?;
+        if mount.is_empty() || path.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            mount: mount.to_string(),
+            path: path.trim_matches('/').to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}#{}", self.mount, self.path, self.key)
+    }
+}
+
+/// A Vault server, and how to log in to it.
+#[derive(Debug, Clone)]
+pub struct Vault {
+    client: RqClient,
+    address: Url,
+    auth: Auth,
+    timeout: Duration,
+}
+
+impl Vault {
+    #[must_use]
+    pub fn new(client: RqClient, address: Url, auth: Auth, timeout: Duration) -> Self {
+        Self {
+            client,
+            address,
+            auth,
+            timeout,
+        }
+    }
+
+    /// Reads the secret at `reference`, logging in first with the AppRole if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Vault cannot be reached, refuses to log in or to read the secret, or the
+    /// secret doesn't have the key, or it isn't a string.
+    pub async fn read(&self, reference: &Reference) -> anyhow::Result<String> {
+        let token = match &self.auth {
+            Auth::Token(token) => token.clone(),
+            Auth::AppRole { role_id, secret_id } => self.log_in(role_id, secret_id).await?,
+        };
+
+        let url = self.url(&format!("v1/{}/data/{}", reference.mount, reference.path))?;
+        let body = self
+            .send(self.client.get(url).header("X-Vault-Token", token))
+            .await
+            .with_context(|| format!("Failed to read {reference} from Vault"))?;
+
+        match &body["data"]["data"][&reference.key] {
+            Value::String(secret) => Ok(secret.clone()),
+            Value::Null => anyhow::bail!("{reference} isn't in Vault"),
+            _ => anyhow::bail!("{reference} in Vault isn't a string"),
+        }
+    }
+
+    /// Logs in with the AppRole, and returns the token.
+    async fn log_in(&self, role_id: &str, secret_id: &str) -> anyhow::Result<String> {
+        let url = self.url("v1/auth/approle/login")?;
+        let body = self
+            .send(self.client.post(url).json(&json!({
+                "role_id": role_id,
+                "secret_id": secret_id,
+            })))
+            .await
+            .context("Failed to log in to Vault with the AppRole")?;
+
+        body["auth"]["client_token"]
+            .as_str()
+            .map(ToString::to_string)
+            .context("Vault didn't answer the login with a token")
+    }
+
+    fn url(&self, path: &str) -> anyhow::Result<Url> {
+        let mut address = self.address.clone();
+        if !address.path().ends_with('/') {
+            address.set_path(&format!("{}/", address.path()));
+        }
+
+        address.join(path).context("Invalid address of Vault")
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<Value> {
+        let response = request.timeout(self.timeout).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Received response status {status}: {body}");
+        }
+
+        Ok(response.json().await?)
+    }
+}
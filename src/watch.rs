@@ -0,0 +1,78 @@
+//! Watches the settings file, so the daemon can reload it as soon as it changes.
+use std::path::Path;
+
+use anyhow::Context;
+use notify::{Event as FileEvent, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, warn};
+
+use crate::daemon::Event;
+
+/// Starts watching the file at `path`, sending an event whenever it's written, replaced or
+/// removed. The file is watched for as long as the returned watcher is kept around.
+///
+/// The directory is watched rather than the file itself, as most editors save a file by writing a
+/// new one and renaming it, which would end the watch on the original.
+///
+/// # Errors
+///
+/// Returns an error if the directory of the file cannot be watched.
+pub fn spawn(path: &Path, sender: UnboundedSender<Event>) -> anyhow::Result<RecommendedWatcher> {
+    let path = path.to_path_buf();
+    let dir = path
+        .parent()
+        .map(Path::to_path_buf)
+        .context("The settings file has no directory")?;
+
+    let handler_path = path.clone();
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<FileEvent>| match result {
+            Ok(event) if is_relevant(&event, &handler_path) => {
+                debug!("Settings file changed: {:?}", event.kind);
+                let _ = sender.send(Event::ConfigChange);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to watch the settings file: {e}"),
+        })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch directory: {}", dir.display()))?;
+
+    debug!("Watching settings file: {}", path.display());
+
+    Ok(watcher)
+}
+
+/// Returns whether the event changes the contents of the file at `path`.
+fn is_relevant(event: &FileEvent, path: &Path) -> bool {
+    let changes_contents = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Any
+    );
+
+    changes_contents && event.paths.iter().any(|changed| changed == path)
+}
+
+#[test]
+fn test_is_relevant() {
+    use std::path::PathBuf;
+
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RenameMode};
+
+    let path = PathBuf::from("/etc/cdu/.env");
+
+    let written = FileEvent::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone());
+    assert!(is_relevant(&written, &path));
+
+    let renamed = FileEvent::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+        .add_path(PathBuf::from("/etc/cdu/.env.swp"))
+        .add_path(path.clone());
+    assert!(is_relevant(&renamed, &path));
+
+    let read = FileEvent::new(EventKind::Access(AccessKind::Any)).add_path(path.clone());
+    assert!(!is_relevant(&read, &path));
+
+    let other = FileEvent::new(EventKind::Create(CreateKind::File))
+        .add_path(PathBuf::from("/etc/cdu/cdu.toml"));
+    assert!(!is_relevant(&other, &path));
+}
@@ -1,24 +1,346 @@
-use reqwest::blocking::Response;
-use serde_json::json;
-use tracing::error;
-use tracing::info;
-
-#[tracing::instrument(skip_all)]
-pub fn send(webhook_url: &str, message: &str) -> anyhow::Result<()> {
-    let client = reqwest::blocking::Client::new();
-    let params = json!({
-        "content": message
-    });
-    let response: Response = client.post(webhook_url).json(&params).send()?;
-
-    if response.status().is_success() {
-        info!("Message successfully sent to webhoook");
-    } else {
+//! Sends messages to webhooks: Discord's, and any other, with a body made from a template.
+//!
+//! A template is rendered with [MiniJinja](https://docs.rs/minijinja), which has these variables:
+//!
+//! - `domain`, `old_ip` and `new_ip`, where an IP that isn't known is `none`.
+//! - `timestamp`, when it happened, in RFC 3339.
+//! - `error`, what went wrong, for a failure, and `none` otherwise.
+//! - `kind`, `severity`, `title` and `message`, which is the text other services get.
+//!
+//! If the rendered body is valid JSON it's sent as JSON, and otherwise as a form. Without a
+//! template, the body is the message as JSON, with the same names as the variables.
+//!
+//! With a secret, the body is signed the way GitHub signs its webhooks, so the receiver can tell
+//! it comes from cdu: the `X-Signature` header is `sha256=` and the HMAC-SHA256 of the body, in
+//! hex.
+//!
+//! The body is sent with POST, or PUT if the endpoint wants that, with any headers of your own, like
+//! `Authorization: Bearer <token>` or `X-Api-Key: <key>` for endpoints that need them.
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use minijinja::value::Serde;
+use minijinja::Environment;
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Client as RqClient;
+use serde_json::{json, Value};
+
+use crate::crypto;
+use crate::notify::{self, Message, Notifier, Severity};
+use crate::retry::StatusError;
+
+/// The colors of the embeds: gray for information, green for changes and red for errors.
+const INFO_COLOR: u32 = 0x95_a5a6;
+const NOTICE_COLOR: u32 = 0x2e_cc71;
+const ERROR_COLOR: u32 = 0xe7_4c3c;
+
+/// A Discord webhook, which gets the message as an embed.
+#[derive(Debug)]
+pub struct Discord {
+    client: RqClient,
+    url: String,
+}
+
+impl Discord {
+    #[must_use]
+    pub fn new(client: RqClient, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "Discord webhook"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&embed(message))
+            .send()
+            .await
+            .context("Failed to send the message")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the message as a Discord embed, with a field for everything that's known.
+fn embed(message: &Message) -> Value {
+    let mut fields = vec![json!({ "name": "Domain", "value": message.domain })];
+    let mut field = |name: &str, value: Option<String>| {
+        if let Some(value) = value {
+            fields.push(json!({ "name": name, "value": value, "inline": true }));
+        }
+    };
+    field("Old IP", message.old_ip.map(|ip| ip.to_string()));
+    field("New IP", message.new_ip.map(|ip| ip.to_string()));
+    field("Network", message.network.clone());
+    field("Detected by", message.source.clone());
+    field("Error", message.error.clone());
+    field(
+        "Since last change",
+        message.since_previous_change().map(notify::format_duration),
+    );
+
+    let color = match message.severity {
+        Severity::Info => INFO_COLOR,
+        Severity::Notice => NOTICE_COLOR,
+        Severity::Error => ERROR_COLOR,
+    };
+
+    json!({
+        "embeds": [{
+            "title": message.title(),
+            "description": message.to_string(),
+            "color": color,
+            "fields": fields,
+            "timestamp": message.at.to_rfc3339(),
+        }]
+    })
+}
+
+/// How the body is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Method {
+    #[default]
+    Post,
+    Put,
+}
+
+impl Method {
+    /// Parses `post` or `put`, in any case.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it's any other method.
+    pub fn parse(method: &str) -> Result<Self, String> {
+        match method.to_ascii_lowercase().as_str() {
+            "post" => Ok(Self::Post),
+            "put" => Ok(Self::Put),
+            _ => Err(format!("Expected post or put, got: {method}")),
+        }
+    }
+}
+
+/// A webhook, and the template of its body.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    pub url: String,
+    pub template: Option<String>,
+    /// The secret to sign the body with.
+    pub secret: Option<String>,
+    pub method: Method,
+    /// Headers of your own, sent with every message.
+    pub headers: Vec<(String, String)>,
+}
+
+/// Parses a header, as `<name>: <value>`.
+///
+/// # Errors
+///
+/// Returns an error if there's no colon, or the name or value isn't valid in a header.
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = header.split_once(':') else {
+        return Err(format!(
+            "Expected <name>: <value>, e.g. X-Api-Key: <key>, got: {header}"
+        ));
+    };
+    let (name, value) = (name.trim(), value.trim());
+    HeaderName::try_from(name).map_err(|_| format!("Invalid header name: {name}"))?;
+    // The value is left out, as it's usually a secret
+    HeaderValue::try_from(value).map_err(|_| format!("Invalid value for header {name}"))?;
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Reads the template at `path`, making sure it can be rendered.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, or the template has a syntax error.
+pub fn load_template(path: &Path) -> anyhow::Result<String> {
+    let template = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read webhook template: {}", path.display()))?;
+    Environment::new()
+        .template_from_str(&template)
+        .with_context(|| format!("Invalid webhook template: {}", path.display()))?;
+
+    Ok(template)
+}
+
+/// Any webhook, which gets the body from the template.
+#[derive(Debug)]
+pub struct Webhook {
+    client: RqClient,
+    options: Options,
+}
+
+impl Webhook {
+    #[must_use]
+    pub fn new(client: RqClient, options: &Options) -> Self {
+        Self {
+            client,
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn send(&self, message: &Message) -> anyhow::Result<()> {
+        let variables = variables(message);
+        let body = match &self.options.template {
+            Some(template) => Environment::new()
+                .render_str(template, Serde(&variables))
+                .context("Failed to render the webhook template")?,
+            None => variables.to_string(),
+        };
+        let content_type = if serde_json::from_str::<Value>(&body).is_ok() {
+            "application/json"
+        } else {
+            "application/x-www-form-urlencoded"
+        };
+
+        let mut request = match self.options.method {
+            Method::Post => self.client.post(&self.options.url),
+            Method::Put => self.client.put(&self.options.url),
+        }
+        .header(CONTENT_TYPE, content_type);
+        for (name, value) in &self.options.headers {
+            request = request.header(name, value);
+        }
+        if let Some(secret) = &self.options.secret {
+            request = request.header("X-Signature", signature(secret, &body));
+        }
+        let request = request.body(body);
+
+        let response = request.send().await.context("Failed to send the message")?;
+
         let status = response.status();
-        error!("Received response status: {status:?}");
-        let body = response.text()?;
-        error!("Response body: {body}");
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(())
     }
+}
+
+/// Returns the signature of the body, as `sha256=<hex>`.
+fn signature(secret: &str, body: &str) -> String {
+    format!(
+        "sha256={}",
+        crypto::hmac_sha256_hex(secret.as_bytes(), body.as_bytes())
+    )
+}
+
+/// Returns the variables the template can use.
+fn variables(message: &Message) -> Value {
+    json!({
+        "domain": message.domain,
+        "old_ip": message.old_ip,
+        "new_ip": message.new_ip,
+        "records": message.records,
+        "network": message.network,
+        "timestamp": message.at.to_rfc3339(),
+        "error": message.error,
+        "kind": message.kind,
+        "severity": message.severity,
+        "title": message.title(),
+        "message": message.to_string(),
+    })
+}
+
+#[test]
+fn test_render_template() {
+    use std::net::IpAddr;
+
+    let mut message = Message::updated("example.com", None, IpAddr::from([192, 0, 2, 2]));
+    message.at = chrono::DateTime::from_timestamp(1_718_000_000, 0).unwrap();
+    let render = |template| {
+        Environment::new()
+            .render_str(template, Serde(variables(&message)))
+            .unwrap()
+    };
+
+    assert_eq!(
+        render(
+            r#"{"text": {{ message | tojson }}, "old": {{ old_ip | tojson }}, "at": "{{ timestamp }}"}"#
+        ),
+        r#"{"text": "Updated A record of example.com to 192.0.2.2", "old": null, "at": "2024-06-10T06:13:20+00:00"}"#
+    );
+    assert_eq!(
+        parse_header("Authorization:  Bearer abc "),
+        Ok((String::from("Authorization"), String::from("Bearer abc")))
+    );
+    assert!(parse_header("Authorization Bearer abc").is_err());
+    assert!(parse_header("X Api Key: abc").is_err());
+    assert_eq!(Method::parse("PUT"), Ok(Method::Put));
+    // The example from the GitHub docs
+    assert_eq!(
+        signature("It's a Secret to Everybody", "Hello, World!"),
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+    );
+    assert_eq!(
+        render("domain={{ domain | urlencode }}&ip={{ new_ip }}{% if old_ip %}&old={{ old_ip }}{% endif %}"),
+        "domain=example.com&ip=192.0.2.2"
+    );
+}
+
+#[test]
+fn test_embed() {
+    use std::net::IpAddr;
+
+    let mut message = Message::updated(
+        "example.com",
+        Some(IpAddr::from([192, 0, 2, 1])),
+        IpAddr::from([192, 0, 2, 2]),
+    )
+    .with_source("icanhazip.com");
+    message.previous_change_at = Some(message.at - chrono::Duration::seconds(26 * 60 * 60 + 61));
 
-    Ok(())
+    let embed = &embed(&message)["embeds"][0];
+    assert_eq!(embed["title"], "A record updated");
+    assert_eq!(embed["color"], NOTICE_COLOR);
+    let fields = embed["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| {
+            (
+                field["name"].as_str().unwrap(),
+                field["value"].as_str().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        [
+            ("Domain", "example.com"),
+            ("Old IP", "192.0.2.1"),
+            ("New IP", "192.0.2.2"),
+            ("Detected by", "icanhazip.com"),
+            ("Since last change", "1day 2h 1m"),
+        ]
+    );
 }
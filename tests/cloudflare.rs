@@ -0,0 +1,447 @@
+//! Talks to a server that answers as the Cloudflare API would, to check what the handler makes of
+//! its answers.
+use std::net::IpAddr;
+use std::time::Duration;
+
+use cdu::cloudflare::{ARecord, CloudflareError, Handler};
+use cdu::hint::Category;
+use cdu::network::Family;
+use cdu::retry::Policy;
+use serde_json::json;
+use wiremock::matchers::{body_partial_json, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+fn handler(server: &MockServer) -> Handler {
+    Handler::try_new("token")
+        .unwrap()
+        .with_api_url(&server.uri())
+        .with_retry(Policy::NEVER)
+}
+
+fn record(name: &str, id: &str, ip: &str) -> serde_json::Value {
+    json!({
+        "id": id,
+        "type": "A",
+        "name": name,
+        "content": ip,
+        "proxied": false,
+        "ttl": 300,
+    })
+}
+
+#[tokio::test]
+async fn test_get_a_record() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+        .and(query_param("name", "home.example.com"))
+        .and(header("Authorization", "Bearer token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": [record("home.example.com", "372e67954025e0ba6aaa6d586b9e0b59", "192.0.2.1")],
+        })))
+        .mount(&server)
+        .await;
+
+    let record = handler(&server)
+        .get_a_record(ZONE_ID, "home.example.com")
+        .await
+        .unwrap();
+    assert_eq!(
+        record,
+        ARecord {
+            id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
+            ip: IpAddr::from([192, 0, 2, 1]),
+            proxied: Some(false),
+            ttl: Some(300),
+        }
+    );
+
+    // Cloudflare answers a lookup that matches nothing with an empty list
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+        .and(query_param("name", "www.example.com"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": [],
+        })))
+        .mount(&server)
+        .await;
+    let result = handler(&server)
+        .get_a_record(ZONE_ID, "www.example.com")
+        .await;
+    assert!(
+        matches!(result, Err(CloudflareError::RecordNotFound(_))),
+        "Expected no A record, got {result:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_set_a_record() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path(format!(
+            "/zones/{ZONE_ID}/dns_records/372e67954025e0ba6aaa6d586b9e0b59"
+        )))
+        .and(body_partial_json(
+            json!({ "content": "192.0.2.2", "ttl": 300 }),
+        ))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cf-ray", "8a1f2b3c4d5e6f70-AMS")
+                .set_body_json(json!({ "success": true, "errors": [] })),
+        )
+        .mount(&server)
+        .await;
+
+    let record = ARecord {
+        id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
+        ip: IpAddr::from([192, 0, 2, 2]),
+        proxied: None,
+        ttl: Some(300),
+    };
+    let ray_id = handler(&server)
+        .set_a_record(ZONE_ID, &record, "home.example.com")
+        .await
+        .unwrap();
+    assert_eq!(ray_id.as_deref(), Some("8a1f2b3c4d5e6f70-AMS"));
+}
+
+#[tokio::test]
+async fn test_patch_a_record() {
+    let server = MockServer::start().await;
+    Mock::given(method("PATCH"))
+        .and(path(format!(
+            "/zones/{ZONE_ID}/dns_records/372e67954025e0ba6aaa6d586b9e0b59"
+        )))
+        .and(body_partial_json(json!({ "content": "192.0.2.2" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": record("home.example.com", "372e67954025e0ba6aaa6d586b9e0b59", "192.0.2.2"),
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": 81044, "message": "Record does not exist." }],
+        })))
+        .mount(&server)
+        .await;
+
+    let mut record = ARecord {
+        id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
+        ip: IpAddr::from([192, 0, 2, 2]),
+        proxied: None,
+        ttl: None,
+    };
+    let (updated, _) = handler(&server)
+        .patch_a_record(ZONE_ID, &record, "home.example.com")
+        .await
+        .unwrap();
+    assert_eq!(updated.ip, IpAddr::from([192, 0, 2, 2]));
+    assert_eq!(updated.ttl, Some(300));
+
+    record.id = String::from("deleted");
+    let result = handler(&server)
+        .patch_a_record(ZONE_ID, &record, "home.example.com")
+        .await;
+    assert!(
+        matches!(result, Err(CloudflareError::RecordNotFound(_))),
+        "Expected the record to be gone, got {result:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_get_record_by_id() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/zones/{ZONE_ID}/dns_records/372e67954025e0ba6aaa6d586b9e0b59"
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": record("home.example.com", "372e67954025e0ba6aaa6d586b9e0b59", "192.0.2.1"),
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": 81044, "message": "Record does not exist." }],
+        })))
+        .mount(&server)
+        .await;
+
+    let record = handler(&server)
+        .get_record_by_id(
+            ZONE_ID,
+            "372e67954025e0ba6aaa6d586b9e0b59",
+            "home.example.com",
+            Family::V4,
+        )
+        .await
+        .unwrap();
+    assert_eq!(record.ip, IpAddr::from([192, 0, 2, 1]));
+
+    // The ID of another domain's record, or of the A record of one that wants its AAAA record, is
+    // as good as gone
+    for (id, domain, family) in [
+        ("deleted", "home.example.com", Family::V4),
+        (
+            "372e67954025e0ba6aaa6d586b9e0b59",
+            "www.example.com",
+            Family::V4,
+        ),
+        (
+            "372e67954025e0ba6aaa6d586b9e0b59",
+            "home.example.com",
+            Family::V6,
+        ),
+    ] {
+        let result = handler(&server)
+            .get_record_by_id(ZONE_ID, id, domain, family)
+            .await;
+        assert!(
+            matches!(result, Err(CloudflareError::RecordNotFound(_))),
+            "Expected the record to be gone, got {result:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_set_txt_record() {
+    let server = MockServer::start().await;
+    for (name, result) in [
+        ("_cdu.home.example.com", json!([])),
+        (
+            "_cdu.www.example.com",
+            json!([{ "id": "txt-1", "type": "TXT", "name": "_cdu.www.example.com", "content": "\"old\"" }]),
+        ),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+            .and(query_param("type", "TXT"))
+            .and(query_param("name", name))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "errors": [],
+                "result": result,
+            })))
+            .mount(&server)
+            .await;
+    }
+    let written = ResponseTemplate::new(200).set_body_json(json!({
+        "success": true,
+        "errors": [],
+        "result": {},
+    }));
+    Mock::given(method("POST"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+        .and(body_partial_json(json!({
+            "type": "TXT",
+            "name": "_cdu.home.example.com",
+            "content": "\"updated=now\"",
+        })))
+        .respond_with(written.clone())
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records/txt-1")))
+        .and(body_partial_json(json!({ "content": "\"updated=now\"" })))
+        .respond_with(written)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    for name in ["_cdu.home.example.com", "_cdu.www.example.com"] {
+        handler(&server)
+            .set_txt_record(ZONE_ID, name, "updated=now")
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_api_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/user/tokens/verify"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": 9109, "message": "Invalid access token" }],
+            "result": null,
+        })))
+        .mount(&server)
+        .await;
+
+    let error = handler(&server).verify_token().await.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            CloudflareError::Api {
+                code: Some(9109),
+                ..
+            }
+        ),
+        "Expected an error of the API, got {error:?}"
+    );
+    assert_eq!(error.category(), Some(Category::Authentication));
+}
+
+#[tokio::test]
+async fn test_malformed_json() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}")))
+        .respond_with(ResponseTemplate::new(502).set_body_string("<html>Bad gateway</html>"))
+        .mount(&server)
+        .await;
+
+    let error = handler(&server).get_zone(ZONE_ID).await.unwrap_err();
+    assert!(
+        matches!(error, CloudflareError::Json { status, .. } if status.as_u16() == 502),
+        "Expected the response not to be JSON, got {error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_retry() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}")))
+        .respond_with(ResponseTemplate::new(503).set_body_string("Service unavailable"))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": { "id": ZONE_ID, "name": "example.com", "status": "active" },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let zone = handler(&server)
+        .with_retry(Policy {
+            attempts: 3,
+            delay: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+        })
+        .get_zone(ZONE_ID)
+        .await
+        .unwrap();
+    assert_eq!(zone.name, "example.com");
+}
+
+#[tokio::test]
+async fn test_list_zones_pages() {
+    let server = MockServer::start().await;
+    for page in 1..=2 {
+        Mock::given(method("GET"))
+            .and(path("/zones"))
+            .and(query_param("page", page.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "errors": [],
+                "result": [{ "id": format!("zone{page}"), "name": format!("example{page}.com"), "status": "active" }],
+                "result_info": { "page": page, "per_page": 50, "total_pages": 2 },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let zones = handler(&server).list_zones().await.unwrap();
+    assert_eq!(
+        zones
+            .iter()
+            .map(|zone| zone.name.as_str())
+            .collect::<Vec<_>>(),
+        ["example1.com", "example2.com"]
+    );
+}
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+        .respond_with(
+            ResponseTemplate::new(429)
+                .insert_header("retry-after", "60")
+                .set_body_json(json!({
+                    "success": false,
+                    "errors": [{ "code": 10013, "message": "Rate limited" }],
+                })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(429).set_body_string("Too many requests"))
+        .mount(&server)
+        .await;
+
+    let error = handler(&server).list_a_records(ZONE_ID).await.unwrap_err();
+    assert_eq!(error.category(), Some(Category::RateLimit));
+
+    let record = ARecord {
+        id: String::from("372e67954025e0ba6aaa6d586b9e0b59"),
+        ip: IpAddr::from([192, 0, 2, 2]),
+        proxied: None,
+        ttl: None,
+    };
+    let error = handler(&server)
+        .set_a_record(ZONE_ID, &record, "home.example.com")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, CloudflareError::Update { code: None, body, .. } if body == "Too many requests"),
+        "Expected the update to be refused, got {error:?}"
+    );
+    assert_eq!(error.category(), Some(Category::RateLimit));
+}
+
+#[tokio::test]
+async fn test_set_api_key() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/user/tokens/verify"))
+        .and(header("Authorization", "Bearer fallback"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": { "id": "ed17574386854bf78a67040be0a770b0", "status": "active" },
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user/tokens/verify"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": 1000, "message": "Invalid API Token" }],
+            "result": null,
+        })))
+        .mount(&server)
+        .await;
+
+    let mut handler = handler(&server);
+    let error = handler.verify_token().await.unwrap_err();
+    assert_eq!(error.category(), Some(Category::Authentication));
+
+    handler.set_api_key("fallback").unwrap();
+    handler.verify_token().await.unwrap();
+    assert!(handler.set_api_key("new\nline").is_err());
+}
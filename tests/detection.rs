@@ -0,0 +1,74 @@
+//! Asks servers that answer as the ones that tell the outside IP would, to check which answers
+//! are taken.
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use cdu::network::{self, DetectionError};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// How long the servers get to answer, which the slow one takes longer than.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+async fn server(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn test_detect_from() {
+    let client = network::client(network::DEFAULT_TIMEOUT);
+    let down = server(ResponseTemplate::new(503).set_body_string("Service unavailable")).await;
+    let slow = server(
+        ResponseTemplate::new(200)
+            .set_body_string("192.0.2.3\n")
+            .set_delay(Duration::from_secs(5)),
+    )
+    .await;
+    let up = server(ResponseTemplate::new(200).set_body_string("192.0.2.1\n")).await;
+
+    let ip = network::ask(&client, &up.uri(), TIMEOUT).await.unwrap();
+    assert_eq!(ip, Ipv4Addr::new(192, 0, 2, 1));
+    let result = network::ask(&client, &down.uri(), TIMEOUT).await;
+    assert!(
+        matches!(result, Err(DetectionError::NotAnIp { .. })),
+        "Expected the answer not to be an IP, got {result:?}"
+    );
+    let result = network::ask(&client, &slow.uri(), TIMEOUT).await;
+    assert!(
+        matches!(result, Err(DetectionError::Request { .. })),
+        "Expected the request to time out, got {result:?}"
+    );
+
+    let (ip, source) = network::detect_from(
+        &client,
+        &[down.uri().as_str(), &slow.uri(), &up.uri()],
+        TIMEOUT,
+    )
+    .await
+    .unwrap();
+    assert_eq!(ip, Ipv4Addr::new(192, 0, 2, 1));
+    assert_eq!(source, up.uri());
+
+    let result = network::detect_from(&client, &[down.uri().as_str(), &slow.uri()], TIMEOUT).await;
+    assert!(
+        matches!(result, Err(DetectionError::AllFailed)),
+        "Expected every server to fail, got {result:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_detect_outside_ipv6() {
+    let client = network::client(network::DEFAULT_TIMEOUT);
+    let up = server(ResponseTemplate::new(200).set_body_string("2001:db8::1\n")).await;
+
+    let (ip, source) = network::detect_outside_ipv6(&client, Some(&up.uri()), TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(ip, "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(source, up.uri());
+}
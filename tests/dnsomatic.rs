@@ -0,0 +1,50 @@
+//! Passes the outside IP on to a server that answers as DNS-O-Matic would, to check what's sent
+//! and what's made of the answers.
+use std::net::IpAddr;
+
+use cdu::dnsomatic::Account;
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn account(server: &MockServer, password: &str) -> Account {
+    Account {
+        url: format!("{}/nic/update", server.uri()),
+        ..Account::parse(&format!("me:{password}")).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_update() {
+    let server = MockServer::start().await;
+    // me:secret
+    Mock::given(method("GET"))
+        .and(path("/nic/update"))
+        .and(header("Authorization", "Basic bWU6c2VjcmV0"))
+        .and(query_param("hostname", "all.dnsomatic.com"))
+        .and(query_param("myip", "192.0.2.1"))
+        .and(query_param("wildcard", "NOCHG"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string("good 192.0.2.1\nnochg 192.0.2.1\n"),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/nic/update"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("badauth"))
+        .mount(&server)
+        .await;
+
+    let client = reqwest::Client::new();
+    let ip = IpAddr::from([192, 0, 2, 1]);
+    account(&server, "secret")
+        .update(&client, ip)
+        .await
+        .unwrap();
+
+    let error = account(&server, "wrong")
+        .update(&client, ip)
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "DNS-O-Matic answered badauth");
+}
@@ -0,0 +1,112 @@
+//! Makes a plan and applies it against a server that answers as the Cloudflare API would, to check
+//! that nothing is changed once the A records have drifted from the plan.
+use std::net::IpAddr;
+
+use cdu::cloudflare::Handler;
+use cdu::plan::{Change, Plan};
+use cdu::retry::Policy;
+use serde_json::json;
+use wiremock::matchers::{body_partial_json, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+fn handler(server: &MockServer) -> Handler {
+    Handler::try_new("token")
+        .unwrap()
+        .with_api_url(&server.uri())
+        .with_retry(Policy::NEVER)
+}
+
+async fn mount_record(server: &MockServer, name: &str, id: &str, ip: &str) {
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+        .and(query_param("name", name))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": [{
+                "id": id,
+                "type": "A",
+                "name": name,
+                "content": ip,
+                "proxied": false,
+                "ttl": 300,
+            }],
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_plan_apply() {
+    let server = MockServer::start().await;
+    mount_record(&server, "home.example.com", "record-1", "192.0.2.1").await;
+    mount_record(&server, "www.example.com", "record-2", "192.0.2.2").await;
+    Mock::given(method("PATCH"))
+        .and(path(format!("/zones/{ZONE_ID}/dns_records/record-1")))
+        .and(body_partial_json(json!({ "content": "192.0.2.2" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": {
+                "id": "record-1",
+                "type": "A",
+                "name": "home.example.com",
+                "content": "192.0.2.2",
+                "proxied": false,
+                "ttl": 300,
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let domains = [
+        String::from("home.example.com"),
+        String::from("www.example.com"),
+    ];
+    let plan = Plan::create(
+        &handler(&server),
+        ZONE_ID,
+        &domains,
+        &[],
+        IpAddr::from([192, 0, 2, 2]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        plan.changes,
+        [Change {
+            domain: String::from("home.example.com"),
+            zone_id: String::from(ZONE_ID),
+            record_id: String::from("record-1"),
+            old_ip: IpAddr::from([192, 0, 2, 1]),
+            new_ip: IpAddr::from([192, 0, 2, 2]),
+        }]
+    );
+    assert_eq!(
+        plan.changes[0].to_string(),
+        "home.example.com: 192.0.2.1 -> 192.0.2.2"
+    );
+
+    // It's read back the same way it's saved
+    let saved = serde_json::to_string(&plan).unwrap();
+    let plan = serde_json::from_str::<Plan>(&saved).unwrap();
+    plan.apply(&handler(&server)).await.unwrap();
+
+    // An A record that points elsewhere by now is left alone, like the rest
+    let mut drifted = plan.clone();
+    drifted.changes[0].old_ip = IpAddr::from([192, 0, 2, 9]);
+    assert_eq!(
+        drifted.drifts(&handler(&server)).await.unwrap(),
+        ["home.example.com: points at 192.0.2.1 instead of 192.0.2.9"]
+    );
+    assert!(drifted.apply(&handler(&server)).await.is_err());
+
+    drifted.changes[0].record_id = String::from("record-0");
+    assert_eq!(
+        drifted.drifts(&handler(&server)).await.unwrap(),
+        ["home.example.com: the A record was replaced"]
+    );
+}
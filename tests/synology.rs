@@ -0,0 +1,141 @@
+//! Runs cdu as a custom DDNS provider of Synology DSM against a server that answers as the
+//! Cloudflare API would, to check what DSM is told.
+use std::net::Ipv4Addr;
+
+use cdu::synology::{self, Response};
+use serde_json::json;
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+async fn run(
+    server: &MockServer,
+    username: &str,
+    password: &str,
+    hostname: &str,
+    ip: &str,
+) -> Response {
+    synology::run(
+        username,
+        password,
+        hostname,
+        ip,
+        &server.uri(),
+        reqwest::Client::new(),
+    )
+    .await
+}
+
+#[tokio::test]
+#[allow(clippy::too_many_lines)]
+async fn test_run() {
+    let server = MockServer::start().await;
+    Mock::given(header("Authorization", "Bearer bad-token"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": 10000, "message": "Authentication error" }],
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": [{ "id": ZONE_ID, "name": "example.com", "status": "active" }],
+            "result_info": { "page": 1, "per_page": 50, "total_pages": 1 },
+        })))
+        .mount(&server)
+        .await;
+    for (name, result) in [
+        (
+            "home.example.com",
+            json!([{
+                "id": "372e67954025e0ba6aaa6d586b9e0b59",
+                "type": "A",
+                "name": "home.example.com",
+                "content": "192.0.2.1",
+                "proxied": false,
+                "ttl": 300,
+            }]),
+        ),
+        ("www.example.com", json!([])),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(format!("/zones/{ZONE_ID}/dns_records")))
+            .and(query_param("name", name))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "errors": [],
+                "result": result,
+            })))
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("PATCH"))
+        .and(path(format!(
+            "/zones/{ZONE_ID}/dns_records/372e67954025e0ba6aaa6d586b9e0b59"
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "result": {
+                "id": "372e67954025e0ba6aaa6d586b9e0b59",
+                "type": "A",
+                "name": "home.example.com",
+                "content": "192.0.2.2",
+                "proxied": false,
+                "ttl": 300,
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let good = run(
+        &server,
+        "example.com",
+        "token",
+        "home.example.com",
+        "192.0.2.2",
+    )
+    .await;
+    assert_eq!(good, Response::Good(Ipv4Addr::new(192, 0, 2, 2)));
+    assert_eq!(good.to_string(), "good 192.0.2.2");
+    assert_eq!(
+        run(&server, ZONE_ID, "token", "home.example.com", "192.0.2.1").await,
+        Response::NoChange(Ipv4Addr::new(192, 0, 2, 1))
+    );
+    assert_eq!(
+        run(&server, ZONE_ID, "token", "www.example.com", "192.0.2.2").await,
+        Response::NoHost
+    );
+    assert_eq!(
+        run(
+            &server,
+            "example.net",
+            "token",
+            "home.example.net",
+            "192.0.2.2"
+        )
+        .await,
+        Response::NoHost
+    );
+    assert_eq!(
+        run(
+            &server,
+            ZONE_ID,
+            "bad-token",
+            "home.example.com",
+            "192.0.2.2"
+        )
+        .await,
+        Response::BadAuth
+    );
+    assert_eq!(
+        run(&server, ZONE_ID, "token", "home.example.com", "2001:db8::1").await,
+        Response::BadParam
+    );
+    assert_eq!(Response::ServerError.to_string(), "911");
+}
@@ -0,0 +1,80 @@
+//! Reads secrets from a server that answers as Vault would.
+use std::time::Duration;
+
+use cdu::vault::{Auth, Reference, Vault};
+use reqwest::{Client, Url};
+use serde_json::json;
+use wiremock::matchers::{body_json, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn vault_at(server: &MockServer, auth: Auth) -> Vault {
+    Vault::new(
+        Client::new(),
+        Url::parse(&server.uri()).unwrap(),
+        auth,
+        Duration::from_secs(5),
+    )
+}
+
+async fn mount_secret(server: &MockServer, token: &str) {
+    Mock::given(method("GET"))
+        .and(path("/v1/secret/data/cdu/home"))
+        .and(header("X-Vault-Token", token))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {
+                "data": { "api_key": "cloudflare-token", "ttl": 60 },
+                "metadata": { "version": 3 },
+            },
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_read_with_token() {
+    let server = MockServer::start().await;
+    mount_secret(&server, "root").await;
+    let vault = vault_at(&server, Auth::Token(String::from("root")));
+
+    let reference = "secret/cdu/home#api_key".parse::<Reference>().unwrap();
+    assert_eq!(reference.path, "cdu/home");
+    assert_eq!(vault.read(&reference).await.unwrap(), "cloudflare-token");
+
+    let missing = "secret/cdu/home#zone_id".parse().unwrap();
+    let error = vault.read(&missing).await.unwrap_err();
+    assert!(error.to_string().contains("isn't in Vault"), "{error}");
+    let number = "secret/cdu/home#ttl".parse().unwrap();
+    assert!(vault.read(&number).await.is_err());
+
+    let forbidden = vault_at(&server, Auth::Token(String::from("wrong")));
+    assert!(forbidden.read(&reference).await.is_err());
+
+    assert!("secret#api_key".parse::<Reference>().is_err());
+    assert!("secret/cdu".parse::<Reference>().is_err());
+}
+
+#[tokio::test]
+async fn test_read_with_approle() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/auth/approle/login"))
+        .and(body_json(
+            json!({ "role_id": "role", "secret_id": "secret" }),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "auth": { "client_token": "from-approle", "lease_duration": 3600 },
+        })))
+        .mount(&server)
+        .await;
+    mount_secret(&server, "from-approle").await;
+
+    let vault = vault_at(
+        &server,
+        Auth::AppRole {
+            role_id: String::from("role"),
+            secret_id: String::from("secret"),
+        },
+    );
+    let reference = "secret/cdu/home#api_key".parse().unwrap();
+    assert_eq!(vault.read(&reference).await.unwrap(), "cloudflare-token");
+}